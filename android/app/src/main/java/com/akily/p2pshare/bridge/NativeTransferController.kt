@@ -30,12 +30,30 @@ class NativeTransferController private constructor(
         return payload.toBridgeEvent()
     }
 
+    override fun pollEvents(max: Int): List<BridgeEvent> {
+        val payload = RustBindings.nativePollEvents(handle, max) ?: return emptyList()
+        val array = JSONArray(payload)
+        return List(array.length()) { index -> array.getJSONObject(index).toBridgeEvent() }
+    }
+
+    override fun stats(): TransferStats? {
+        val json = JSONObject(RustBindings.nativeStats(handle) ?: return null)
+        return TransferStats(
+            bytesTransferred = json.optLong("bytes_transferred"),
+            bytesTotal = json.optLong("bytes_total"),
+            startedAtMs = json.optLong("started_at_ms"),
+            updatedAtMs = json.optLong("updated_at_ms"),
+        )
+    }
+
     override fun cancel() {
         RustBindings.nativeCancel(handle)
     }
 
-    private fun String.toBridgeEvent(): BridgeEvent {
-        val json = JSONObject(this)
+    private fun String.toBridgeEvent(): BridgeEvent = JSONObject(this).toBridgeEvent()
+
+    private fun JSONObject.toBridgeEvent(): BridgeEvent {
+        val json = this
         return BridgeEvent(
             kind = json.optString("kind"),
             message = json.optNullableString("message"),
@@ -48,6 +66,7 @@ class NativeTransferController private constructor(
             latencyMs = if (json.has("latency_ms") && !json.isNull("latency_ms")) json.optDouble("latency_ms") else null,
             contentKind = json.optNullableString("content_kind"),
             itemCount = if (json.has("item_count") && !json.isNull("item_count")) json.optLong("item_count") else null,
+            sessionId = if (json.has("session_id") && !json.isNull("session_id")) json.optLong("session_id") else null,
         )
     }
 
@@ -22,9 +22,56 @@ object RustBindings {
     @JvmStatic
     external fun nativeStartReceiveListen(handle: Long, outputDir: String)
 
+    /** Queue a wait-mode send behind the running transfer; returns its session id (0 on error). */
+    @JvmStatic
+    external fun nativeEnqueueSendWait(handle: Long, filePathsJson: String): Long
+
+    @JvmStatic
+    external fun nativeEnqueueSendToTicket(handle: Long, filePathsJson: String, ticket: String): Long
+
+    @JvmStatic
+    external fun nativeEnqueueReceiveTarget(handle: Long, target: String, outputDir: String): Long
+
+    @JvmStatic
+    external fun nativeEnqueueReceiveListen(handle: Long, outputDir: String): Long
+
     @JvmStatic
     external fun nativePollEvent(handle: Long): String?
 
+    @JvmStatic
+    external fun nativePollEvents(handle: Long, max: Int): String?
+
+    /** Byte counters of the running transfer as JSON; cheap enough to call every frame. */
+    @JvmStatic
+    external fun nativeStats(handle: Long): String?
+
+    /** Features of the native library as JSON flags, e.g. to hide unsupported options. */
+    @JvmStatic
+    external fun nativeCapabilities(): String?
+
+    /**
+     * A pasted ticket as JSON, whose `kind` is `direct_only`, `relay_only` or `mixed`;
+     * null when the ticket is invalid.
+     */
+    @JvmStatic
+    external fun nativeTicketInfo(ticket: String): String?
+
+    /** Cancel the running transfer and every queued one. */
     @JvmStatic
     external fun nativeCancel(handle: Long)
+
+    /** Cancel only the running transfer; the next queued one starts. */
+    @JvmStatic
+    external fun nativeCancelCurrent(handle: Long)
+
+    /** Cancel one running or queued job by session id; false when it is unknown or finished. */
+    @JvmStatic
+    external fun nativeCancelJob(handle: Long, sessionId: Long): Boolean
+
+    /**
+     * Jobs as a JSON array of `session_id` and `state` (`waiting`, `connecting`,
+     * `transferring` or `done`); safe to call from the main thread while jobs run.
+     */
+    @JvmStatic
+    external fun nativeListJobs(handle: Long): String?
 }
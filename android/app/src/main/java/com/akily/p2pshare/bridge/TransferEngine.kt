@@ -15,6 +15,15 @@ data class BridgeEvent(
     val latencyMs: Double? = null,
     val contentKind: String? = null,
     val itemCount: Long? = null,
+    val sessionId: Long? = null,
+)
+
+/** Byte counters sampled from the running (or last) transfer. */
+data class TransferStats(
+    val bytesTransferred: Long,
+    val bytesTotal: Long,
+    val startedAtMs: Long,
+    val updatedAtMs: Long,
 )
 
 interface TransferEngine {
@@ -25,6 +34,12 @@ interface TransferEngine {
     fun startReceiveTarget(target: String, outputDir: String)
     fun startReceiveListen(outputDir: String)
     fun pollEvent(): BridgeEvent?
+
+    /** Drain up to [max] queued events in one call. */
+    fun pollEvents(max: Int): List<BridgeEvent> = generateSequence { pollEvent() }.take(max).toList()
+
+    /** Current byte counters, or null when the engine does not track them. */
+    fun stats(): TransferStats? = null
     fun cancel()
 }
 
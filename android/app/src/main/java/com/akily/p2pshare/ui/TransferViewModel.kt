@@ -28,6 +28,9 @@ import kotlinx.coroutines.launch
 import kotlinx.coroutines.withContext
 import java.io.File
 
+/** Events drained from the engine per poll. */
+private const val POLL_BATCH_SIZE = 64
+
 class TransferViewModel(app: Application) : AndroidViewModel(app) {
     private val engine: TransferEngine = TransferEngineFactory.create()
     private val prefs = PrefsStore(app)
@@ -234,15 +237,17 @@ class TransferViewModel(app: Application) : AndroidViewModel(app) {
         pollGeneration += 1
         val generation = pollGeneration
         pollJob = viewModelScope.launch {
-            while (isActive) {
-                val event = engine.pollEvent()
-                if (event != null) {
+            polling@ while (isActive) {
+                val events = engine.pollEvents(POLL_BATCH_SIZE)
+                if (events.isEmpty()) {
+                    delay(80)
+                    continue
+                }
+                for (event in events) {
                     val shouldStop = applyEvent(event)
                     if (shouldStop) {
-                        break
+                        break@polling
                     }
-                } else {
-                    delay(80)
                 }
             }
             if (pollGeneration == generation) {
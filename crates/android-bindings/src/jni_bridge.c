@@ -15,7 +15,28 @@ extern void p2pshare_controller_start_receive_target(
 );
 extern void p2pshare_controller_start_receive_listen(uint64_t handle, const char *output_dir);
 extern const char *p2pshare_controller_poll_event_json(uint64_t handle);
+extern const char *p2pshare_controller_poll_events_json(uint64_t handle, uint32_t max);
+extern const char *p2pshare_controller_drain_remaining_json(uint64_t handle);
+extern uint64_t p2pshare_controller_enqueue_send_wait(uint64_t handle, const char *file_paths_json);
+extern uint64_t p2pshare_controller_enqueue_send_to_ticket(
+    uint64_t handle,
+    const char *file_paths_json,
+    const char *ticket
+);
+extern uint64_t p2pshare_controller_enqueue_receive_target(
+    uint64_t handle,
+    const char *target,
+    const char *output_dir
+);
+extern uint64_t p2pshare_controller_enqueue_receive_listen(uint64_t handle, const char *output_dir);
+extern const char *p2pshare_controller_stats_json(uint64_t handle);
+extern const char *p2pshare_capabilities_json(void);
+extern const char *p2pshare_ticket_info_json(const char *ticket);
+extern const char *p2pshare_join_ticket_parts(const char *parts_json);
 extern void p2pshare_controller_cancel(uint64_t handle);
+extern void p2pshare_controller_cancel_current(uint64_t handle);
+extern int32_t p2pshare_controller_cancel_job(uint64_t handle, uint64_t session_id);
+extern const char *p2pshare_controller_list_jobs_json(uint64_t handle);
 extern void p2pshare_free_cstring(const char *ptr);
 
 static jlong native_create_controller(JNIEnv *env, jclass clazz) {
@@ -105,6 +126,91 @@ static void native_start_receive_listen(
     (*env)->ReleaseStringUTFChars(env, output_dir, output);
 }
 
+static jlong native_enqueue_send_wait(
+    JNIEnv *env,
+    jclass clazz,
+    jlong handle,
+    jstring file_paths_json
+) {
+    (void) clazz;
+    if (file_paths_json == NULL) return 0;
+    const char *paths = (*env)->GetStringUTFChars(env, file_paths_json, NULL);
+    if (paths == NULL) return 0;
+    uint64_t id = p2pshare_controller_enqueue_send_wait((uint64_t) handle, paths);
+    (*env)->ReleaseStringUTFChars(env, file_paths_json, paths);
+    return (jlong) id;
+}
+
+static jlong native_enqueue_send_to_ticket(
+    JNIEnv *env,
+    jclass clazz,
+    jlong handle,
+    jstring file_paths_json,
+    jstring ticket
+) {
+    (void) clazz;
+    if (file_paths_json == NULL || ticket == NULL) return 0;
+
+    const char *paths = (*env)->GetStringUTFChars(env, file_paths_json, NULL);
+    if (paths == NULL) return 0;
+
+    const char *ticket_str = (*env)->GetStringUTFChars(env, ticket, NULL);
+    if (ticket_str == NULL) {
+        (*env)->ReleaseStringUTFChars(env, file_paths_json, paths);
+        return 0;
+    }
+
+    uint64_t id = p2pshare_controller_enqueue_send_to_ticket((uint64_t) handle, paths, ticket_str);
+
+    (*env)->ReleaseStringUTFChars(env, ticket, ticket_str);
+    (*env)->ReleaseStringUTFChars(env, file_paths_json, paths);
+    return (jlong) id;
+}
+
+static jlong native_enqueue_receive_target(
+    JNIEnv *env,
+    jclass clazz,
+    jlong handle,
+    jstring target,
+    jstring output_dir
+) {
+    (void) clazz;
+    if (target == NULL || output_dir == NULL) return 0;
+
+    const char *target_str = (*env)->GetStringUTFChars(env, target, NULL);
+    if (target_str == NULL) return 0;
+
+    const char *output = (*env)->GetStringUTFChars(env, output_dir, NULL);
+    if (output == NULL) {
+        (*env)->ReleaseStringUTFChars(env, target, target_str);
+        return 0;
+    }
+
+    uint64_t id = p2pshare_controller_enqueue_receive_target((uint64_t) handle, target_str, output);
+
+    (*env)->ReleaseStringUTFChars(env, output_dir, output);
+    (*env)->ReleaseStringUTFChars(env, target, target_str);
+    return (jlong) id;
+}
+
+static jlong native_enqueue_receive_listen(
+    JNIEnv *env,
+    jclass clazz,
+    jlong handle,
+    jstring output_dir
+) {
+    (void) clazz;
+    if (output_dir == NULL) return 0;
+
+    const char *output = (*env)->GetStringUTFChars(env, output_dir, NULL);
+    if (output == NULL) return 0;
+
+    uint64_t id = p2pshare_controller_enqueue_receive_listen((uint64_t) handle, output);
+
+    (*env)->ReleaseStringUTFChars(env, output_dir, output);
+    return (jlong) id;
+}
+
 static jstring native_poll_event(JNIEnv *env, jclass clazz, jlong handle) {
     (void) clazz;
     const char *json = p2pshare_controller_poll_event_json((uint64_t) handle);
@@ -117,12 +223,119 @@ static jstring native_poll_event(JNIEnv *env, jclass clazz, jlong handle) {
     return out;
 }
 
+static jstring native_poll_events(JNIEnv *env, jclass clazz, jlong handle, jint max) {
+    (void) clazz;
+    if (max <= 0) return NULL;
+    const char *json = p2pshare_controller_poll_events_json((uint64_t) handle, (uint32_t) max);
+    if (json == NULL) {
+        return NULL;
+    }
+
+    jstring out = (*env)->NewStringUTF(env, json);
+    p2pshare_free_cstring(json);
+    return out;
+}
+
+static jstring native_drain_remaining(JNIEnv *env, jclass clazz, jlong handle) {
+    (void) clazz;
+    const char *json = p2pshare_controller_drain_remaining_json((uint64_t) handle);
+    if (json == NULL) {
+        return NULL;
+    }
+
+    jstring out = (*env)->NewStringUTF(env, json);
+    p2pshare_free_cstring(json);
+    return out;
+}
+
+static jstring native_stats(JNIEnv *env, jclass clazz, jlong handle) {
+    (void) clazz;
+    const char *json = p2pshare_controller_stats_json((uint64_t) handle);
+    if (json == NULL) {
+        return NULL;
+    }
+
+    jstring out = (*env)->NewStringUTF(env, json);
+    p2pshare_free_cstring(json);
+    return out;
+}
+
+static jstring native_capabilities(JNIEnv *env, jclass clazz) {
+    (void) clazz;
+    const char *json = p2pshare_capabilities_json();
+    if (json == NULL) {
+        return NULL;
+    }
+
+    jstring out = (*env)->NewStringUTF(env, json);
+    p2pshare_free_cstring(json);
+    return out;
+}
+
+static jstring native_ticket_info(JNIEnv *env, jclass clazz, jstring ticket) {
+    (void) clazz;
+    if (ticket == NULL) return NULL;
+    const char *ticket_str = (*env)->GetStringUTFChars(env, ticket, NULL);
+    if (ticket_str == NULL) return NULL;
+    const char *json = p2pshare_ticket_info_json(ticket_str);
+    (*env)->ReleaseStringUTFChars(env, ticket, ticket_str);
+    if (json == NULL) {
+        return NULL;
+    }
+
+    jstring out = (*env)->NewStringUTF(env, json);
+    p2pshare_free_cstring(json);
+    return out;
+}
+
+static jstring native_join_ticket_parts(JNIEnv *env, jclass clazz, jstring parts_json) {
+    (void) clazz;
+    if (parts_json == NULL) return NULL;
+    const char *parts = (*env)->GetStringUTFChars(env, parts_json, NULL);
+    if (parts == NULL) return NULL;
+    const char *ticket = p2pshare_join_ticket_parts(parts);
+    (*env)->ReleaseStringUTFChars(env, parts_json, parts);
+    if (ticket == NULL) {
+        return NULL;
+    }
+
+    jstring out = (*env)->NewStringUTF(env, ticket);
+    p2pshare_free_cstring(ticket);
+    return out;
+}
+
 static void native_cancel(JNIEnv *env, jclass clazz, jlong handle) {
     (void) env;
     (void) clazz;
     p2pshare_controller_cancel((uint64_t) handle);
 }
 
+static void native_cancel_current(JNIEnv *env, jclass clazz, jlong handle) {
+    (void) env;
+    (void) clazz;
+    p2pshare_controller_cancel_current((uint64_t) handle);
+}
+
+static jboolean native_cancel_job(JNIEnv *env, jclass clazz, jlong handle, jlong session_id) {
+    (void) env;
+    (void) clazz;
+    return p2pshare_controller_cancel_job((uint64_t) handle, (uint64_t) session_id)
+        ? JNI_TRUE
+        : JNI_FALSE;
+}
+
+static jstring native_list_jobs(JNIEnv *env, jclass clazz, jlong handle) {
+    (void) clazz;
+    const char *json = p2pshare_controller_list_jobs_json((uint64_t) handle);
+    if (json == NULL) {
+        return NULL;
+    }
+
+    jstring out = (*env)->NewStringUTF(env, json);
+    p2pshare_free_cstring(json);
+    return out;
+}
+
 int p2pshare_jni_register(JavaVM *vm) {
     JNIEnv *env = NULL;
     if ((*vm)->GetEnv(vm, (void **) &env, JNI_VERSION_1_6) != JNI_OK) {
@@ -140,8 +353,21 @@ int p2pshare_jni_register(JavaVM *vm) {
         {"nativeStartSendToTicket", "(JLjava/lang/String;Ljava/lang/String;)V", (void *) native_start_send_to_ticket},
         {"nativeStartReceiveTarget", "(JLjava/lang/String;Ljava/lang/String;)V", (void *) native_start_receive_target},
         {"nativeStartReceiveListen", "(JLjava/lang/String;)V", (void *) native_start_receive_listen},
+        {"nativeEnqueueSendWait", "(JLjava/lang/String;)J", (void *) native_enqueue_send_wait},
+        {"nativeEnqueueSendToTicket", "(JLjava/lang/String;Ljava/lang/String;)J", (void *) native_enqueue_send_to_ticket},
+        {"nativeEnqueueReceiveTarget", "(JLjava/lang/String;Ljava/lang/String;)J", (void *) native_enqueue_receive_target},
+        {"nativeEnqueueReceiveListen", "(JLjava/lang/String;)J", (void *) native_enqueue_receive_listen},
         {"nativePollEvent", "(J)Ljava/lang/String;", (void *) native_poll_event},
+        {"nativePollEvents", "(JI)Ljava/lang/String;", (void *) native_poll_events},
+        {"nativeDrainRemaining", "(J)Ljava/lang/String;", (void *) native_drain_remaining},
+        {"nativeStats", "(J)Ljava/lang/String;", (void *) native_stats},
+        {"nativeCapabilities", "()Ljava/lang/String;", (void *) native_capabilities},
+        {"nativeTicketInfo", "(Ljava/lang/String;)Ljava/lang/String;", (void *) native_ticket_info},
+        {"nativeJoinTicketParts", "(Ljava/lang/String;)Ljava/lang/String;", (void *) native_join_ticket_parts},
         {"nativeCancel", "(J)V", (void *) native_cancel},
+        {"nativeCancelCurrent", "(J)V", (void *) native_cancel_current},
+        {"nativeCancelJob", "(JJ)Z", (void *) native_cancel_job},
+        {"nativeListJobs", "(J)Ljava/lang/String;", (void *) native_list_jobs},
     };
 
     if ((*env)->RegisterNatives(
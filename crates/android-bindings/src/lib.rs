@@ -1,81 +1,137 @@
-use std::collections::VecDeque;
+use std::collections::{BTreeMap, VecDeque};
 #[cfg(target_os = "android")]
 use std::ffi::c_void;
 use std::ffi::{c_char, CStr, CString};
+use std::future::Future;
 use std::path::PathBuf;
-use std::sync::{Arc, Mutex};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, Weak};
 
 use anyhow::Result;
-use p2p_share_core::events::{
-    ConnectionPathKind, TransferContentKind, TransferEvent, TransferEventSink,
-};
-use serde::{Deserialize, Serialize};
-use tokio::runtime::{Builder, Runtime};
+use p2p_share_core::event_record::map_event;
+pub use p2p_share_core::event_record::TransferEventRecord;
+use p2p_share_core::events::{TransferEvent, TransferEventSink};
+use p2p_share_core::options::TransferOptions;
+use p2p_share_core::receiver::{Canceled, ReceiveOptions};
+use p2p_share_core::sender::SendOptions;
+use p2p_share_core::stats::TransferStats;
+pub use p2p_share_core::stats::TransferStatsSnapshot;
+use serde::Serialize;
+use tokio::runtime::{Builder, Handle, Runtime};
 use tokio::task::JoinHandle;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct TransferEventRecord {
-    pub kind: String,
-    pub message: Option<String>,
-    pub value: Option<String>,
-    pub done: Option<u64>,
-    pub total: Option<u64>,
-    pub file_name: Option<String>,
-    pub size_bytes: Option<u64>,
-    pub saved_path: Option<String>,
-    pub latency_ms: Option<f64>,
-    pub content_kind: Option<String>,
-    pub item_count: Option<u64>,
-}
-
-impl TransferEventRecord {
-    fn status(message: impl Into<String>) -> Self {
-        Self {
-            kind: "status".to_string(),
-            message: Some(message.into()),
-            value: None,
-            done: None,
-            total: None,
-            file_name: None,
-            size_bytes: None,
-            saved_path: None,
-            latency_ms: None,
-            content_kind: None,
-            item_count: None,
+type EventQueue = Arc<Mutex<VecDeque<TransferEventRecord>>>;
+type Job = Pin<Box<dyn Future<Output = Result<()>> + Send>>;
+
+struct QueueSink {
+    queue: EventQueue,
+    session_id: u64,
+    /// Weak, since the queued job owns this sink.
+    jobs: Weak<Mutex<JobQueue>>,
+    /// Set once the first progress event has marked the job transferring.
+    transferring: AtomicBool,
+}
+
+impl TransferEventSink for QueueSink {
+    fn on_event(&self, event: TransferEvent) {
+        if event.is_terminal() && !self.finish() {
+            return;
+        }
+        if matches!(event, TransferEvent::Progress { .. })
+            && !self.transferring.swap(true, Ordering::Relaxed)
+        {
+            if let Some(jobs) = self.jobs.upgrade() {
+                if let Ok(mut jobs) = jobs.lock() {
+                    jobs.set_state(self.session_id, JobState::Transferring);
+                }
+            }
         }
+        push_event(&self.queue, tagged(map_event(event), self.session_id));
     }
+}
 
-    fn error(code: impl Into<String>, message: impl Into<String>) -> Self {
-        Self {
-            kind: "error".to_string(),
-            message: Some(message.into()),
-            value: Some(code.into()),
-            done: None,
-            total: None,
-            file_name: None,
-            size_bytes: None,
-            saved_path: None,
-            latency_ms: None,
-            content_kind: None,
-            item_count: None,
-        }
+impl QueueSink {
+    /// Mark the job done for its terminal event. False when it already
+    /// ended, so a later `Completed` or `Error` is dropped and consumers
+    /// see exactly one.
+    fn finish(&self) -> bool {
+        let Some(jobs) = self.jobs.upgrade() else {
+            return true;
+        };
+        let Ok(mut jobs) = jobs.lock() else {
+            return true;
+        };
+        jobs.set_state(self.session_id, JobState::Done)
     }
 }
 
-struct QueueSink {
-    queue: Arc<Mutex<VecDeque<TransferEventRecord>>>,
+/// Where a job is, as reported by [`TransferController::list_jobs`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobState {
+    /// Queued behind the running job.
+    Waiting,
+    /// Running, but no file data has moved yet.
+    Connecting,
+    Transferring,
+    /// Finished, failed or canceled.
+    Done,
 }
 
-impl TransferEventSink for QueueSink {
-    fn on_event(&self, event: TransferEvent) {
-        push_event(&self.queue, map_event(event));
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct JobInfo {
+    pub session_id: u64,
+    pub state: JobState,
+}
+
+/// Finished jobs still listed by [`TransferController::list_jobs`]; older
+/// ones are forgotten.
+const DONE_JOBS_KEPT: usize = 16;
+
+/// Transfers waiting to run one after another, and the one running now.
+#[derive(Default)]
+struct JobQueue {
+    next_id: u64,
+    pending: VecDeque<(u64, Job)>,
+    current: Option<(u64, JoinHandle<()>)>,
+    states: BTreeMap<u64, JobState>,
+}
+
+impl JobQueue {
+    /// Record `state` for job `id`. A finished job stays done, and only the
+    /// last [`DONE_JOBS_KEPT`] of them are kept. Returns whether this call
+    /// finished the job, which is when its terminal event is due.
+    fn set_state(&mut self, id: u64, state: JobState) -> bool {
+        let Some(current) = self.states.get_mut(&id) else {
+            return false;
+        };
+        if *current == JobState::Done {
+            return false;
+        }
+        *current = state;
+        if state != JobState::Done {
+            return false;
+        }
+        let done: Vec<u64> = self
+            .states
+            .iter()
+            .filter(|(_, state)| **state == JobState::Done)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in done.iter().take(done.len().saturating_sub(DONE_JOBS_KEPT)) {
+            self.states.remove(id);
+        }
+        true
     }
 }
 
 pub struct TransferController {
     runtime: Runtime,
-    queue: Arc<Mutex<VecDeque<TransferEventRecord>>>,
-    task: Mutex<Option<JoinHandle<()>>>,
+    queue: EventQueue,
+    jobs: Arc<Mutex<JobQueue>>,
+    /// Shared by every job; reset when each transfer's data starts.
+    stats: Arc<TransferStats>,
 }
 
 impl TransferController {
@@ -89,60 +145,102 @@ impl TransferController {
         Self {
             runtime,
             queue: Arc::new(Mutex::new(VecDeque::new())),
-            task: Mutex::new(None),
+            jobs: Arc::new(Mutex::new(JobQueue::default())),
+            stats: Arc::new(TransferStats::new()),
         }
     }
 
     pub fn start_send_wait(&self, file_paths: Vec<String>) {
-        let file_paths: Vec<PathBuf> = file_paths.into_iter().map(PathBuf::from).collect();
-        let queue = self.queue.clone();
-        self.start_task(async move {
-            p2p_share_core::sender::run_paths_with_sink(
-                &file_paths,
-                Some(Arc::new(QueueSink { queue }) as Arc<dyn TransferEventSink>),
-            )
-            .await
-        });
+        self.cancel();
+        self.enqueue_send_wait(file_paths);
     }
 
     pub fn start_send_to_ticket(&self, file_paths: Vec<String>, ticket: impl Into<String>) {
+        self.cancel();
+        self.enqueue_send_to_ticket(file_paths, ticket);
+    }
+
+    pub fn start_receive_target(&self, target: impl Into<String>, output_dir: impl Into<String>) {
+        self.cancel();
+        self.enqueue_receive_target(target, output_dir);
+    }
+
+    pub fn start_receive_listen(&self, output_dir: impl Into<String>) {
+        self.cancel();
+        self.enqueue_receive_listen(output_dir);
+    }
+
+    /// Queue a wait-mode send behind any running transfer. Returns the job's
+    /// session id, which tags all of its events.
+    pub fn enqueue_send_wait(&self, file_paths: Vec<String>) -> u64 {
+        let file_paths: Vec<PathBuf> = file_paths.into_iter().map(PathBuf::from).collect();
+        let options = self.send_options();
+        self.enqueue(|sink| async move {
+            p2p_share_core::sender::run_paths_with_options(&file_paths, Some(sink), &options).await
+        })
+    }
+
+    pub fn enqueue_send_to_ticket(
+        &self,
+        file_paths: Vec<String>,
+        ticket: impl Into<String>,
+    ) -> u64 {
         let file_paths: Vec<PathBuf> = file_paths.into_iter().map(PathBuf::from).collect();
         let ticket = ticket.into();
-        let queue = self.queue.clone();
-        self.start_task(async move {
-            p2p_share_core::sender::run_reverse_paths_with_sink(
+        let options = self.send_options();
+        self.enqueue(|sink| async move {
+            p2p_share_core::sender::run_reverse_paths_with_options(
                 &file_paths,
                 &ticket,
-                Some(Arc::new(QueueSink { queue }) as Arc<dyn TransferEventSink>),
+                Some(sink),
+                &options,
             )
             .await
-        });
+        })
     }
 
-    pub fn start_receive_target(&self, target: impl Into<String>, output_dir: impl Into<String>) {
+    pub fn enqueue_receive_target(
+        &self,
+        target: impl Into<String>,
+        output_dir: impl Into<String>,
+    ) -> u64 {
         let target = target.into();
         let output_dir = PathBuf::from(output_dir.into());
-        let queue = self.queue.clone();
-        self.start_task(async move {
-            p2p_share_core::receiver::run_with_sink(
+        let options = self.receive_options();
+        self.enqueue(|sink| async move {
+            p2p_share_core::receiver::run_with_options(
                 &target,
                 output_dir.as_path(),
-                Some(Arc::new(QueueSink { queue }) as Arc<dyn TransferEventSink>),
+                Some(sink),
+                &options,
             )
-            .await
-        });
+            .await?;
+            Ok(())
+        })
     }
 
-    pub fn start_receive_listen(&self, output_dir: impl Into<String>) {
+    pub fn enqueue_receive_listen(&self, output_dir: impl Into<String>) -> u64 {
         let output_dir = PathBuf::from(output_dir.into());
-        let queue = self.queue.clone();
-        self.start_task(async move {
-            p2p_share_core::receiver::run_listen_with_sink(
+        let options = self.receive_options();
+        self.enqueue(|sink| async move {
+            p2p_share_core::receiver::run_listen_with_options(
                 output_dir.as_path(),
-                Some(Arc::new(QueueSink { queue }) as Arc<dyn TransferEventSink>),
+                Some(sink),
+                &options,
             )
-            .await
-        });
+            .await?;
+            Ok(())
+        })
+    }
+
+    /// Byte counters of the running transfer, or of the last one when
+    /// nothing is running.
+    pub fn stats(&self) -> TransferStatsSnapshot {
+        self.stats.snapshot()
+    }
+
+    pub fn stats_json(&self) -> Option<String> {
+        serde_json::to_string(&self.stats()).ok()
     }
 
     pub fn poll_event(&self) -> Option<TransferEventRecord> {
@@ -155,40 +253,225 @@ impl TransferController {
             .and_then(|evt| serde_json::to_string(&evt).ok())
     }
 
+    /// Drain up to `max` queued events in order.
+    pub fn poll_events(&self, max: usize) -> Vec<TransferEventRecord> {
+        let Ok(mut queue) = self.queue.lock() else {
+            return Vec::new();
+        };
+        let count = max.min(queue.len());
+        queue.drain(..count).collect()
+    }
+
+    /// Up to `max` queued events as a JSON array, or `None` when the queue
+    /// is empty.
+    pub fn poll_events_json(&self, max: usize) -> Option<String> {
+        let events = self.poll_events(max);
+        if events.is_empty() {
+            return None;
+        }
+        serde_json::to_string(&events).ok()
+    }
+
+    /// Every queued event in order, for a UI tearing down that will not
+    /// poll again. After [`cancel`](Self::cancel) this includes the
+    /// terminal `error` event of each canceled job.
+    pub fn drain_remaining(&self) -> Vec<TransferEventRecord> {
+        self.poll_events(usize::MAX)
+    }
+
+    /// [`drain_remaining`](Self::drain_remaining) as a JSON array, or
+    /// `None` when the queue is empty.
+    pub fn drain_remaining_json(&self) -> Option<String> {
+        self.poll_events_json(usize::MAX)
+    }
+
+    /// Cancel the running transfer and drop every queued one.
     pub fn cancel(&self) {
-        if let Ok(mut task) = self.task.lock() {
-            if let Some(handle) = task.take() {
-                handle.abort();
-                push_event(
-                    &self.queue,
-                    TransferEventRecord::status("Transfer canceled by user."),
-                );
+        if let Ok(mut jobs) = self.jobs.lock() {
+            let pending: Vec<u64> = jobs.pending.drain(..).map(|(id, _)| id).collect();
+            for id in pending {
+                cancel_queued(&mut jobs, id, &self.queue);
             }
+            abort_current(&mut jobs, &self.queue);
+        }
+    }
+
+    /// Cancel one job, running or queued. When it was running the next
+    /// queued one starts. Returns false for unknown or finished jobs.
+    pub fn cancel_job(&self, session_id: u64) -> bool {
+        let Ok(mut jobs) = self.jobs.lock() else {
+            return false;
+        };
+        if jobs
+            .current
+            .as_ref()
+            .is_some_and(|(id, _)| *id == session_id)
+        {
+            abort_current(&mut jobs, &self.queue);
+            start_next(&mut jobs, &self.jobs, &self.queue, self.runtime.handle());
+            return true;
+        }
+        let Some(index) = jobs.pending.iter().position(|(id, _)| *id == session_id) else {
+            return false;
+        };
+        jobs.pending.remove(index);
+        cancel_queued(&mut jobs, session_id, &self.queue);
+        true
+    }
+
+    /// Running, queued and recently finished jobs in the order they were
+    /// enqueued.
+    pub fn list_jobs(&self) -> Vec<JobInfo> {
+        let Ok(jobs) = self.jobs.lock() else {
+            return Vec::new();
+        };
+        jobs.states
+            .iter()
+            .map(|(id, state)| JobInfo {
+                session_id: *id,
+                state: *state,
+            })
+            .collect()
+    }
+
+    pub fn list_jobs_json(&self) -> Option<String> {
+        serde_json::to_string(&self.list_jobs()).ok()
+    }
+
+    /// Cancel only the running transfer; the next queued one starts.
+    pub fn cancel_current(&self) {
+        if let Ok(mut jobs) = self.jobs.lock() {
+            abort_current(&mut jobs, &self.queue);
+            start_next(&mut jobs, &self.jobs, &self.queue, self.runtime.handle());
         }
     }
 
-    fn start_task<F>(&self, fut: F)
+    fn transfer_options(&self) -> TransferOptions {
+        TransferOptions::builder().stats(self.stats.clone()).build()
+    }
+
+    fn send_options(&self) -> SendOptions {
+        self.transfer_options().into()
+    }
+
+    fn receive_options(&self) -> ReceiveOptions {
+        self.transfer_options().into()
+    }
+
+    fn enqueue<F>(&self, build: impl FnOnce(Arc<dyn TransferEventSink>) -> F) -> u64
     where
-        F: std::future::Future<Output = Result<()>> + Send + 'static,
+        F: Future<Output = Result<()>> + Send + 'static,
     {
-        self.cancel();
+        let Ok(mut jobs) = self.jobs.lock() else {
+            return 0;
+        };
+        jobs.next_id += 1;
+        let id = jobs.next_id;
+        let sink = Arc::new(QueueSink {
+            queue: self.queue.clone(),
+            session_id: id,
+            jobs: Arc::downgrade(&self.jobs),
+            transferring: AtomicBool::new(false),
+        });
+        jobs.pending.push_back((id, Box::pin(build(sink))));
+        jobs.states.insert(id, JobState::Waiting);
+        if jobs.current.is_some() {
+            push_event(
+                &self.queue,
+                tagged(TransferEventRecord::status("Transfer queued."), id),
+            );
+        }
+        start_next(&mut jobs, &self.jobs, &self.queue, self.runtime.handle());
+        id
+    }
+}
 
-        let queue = self.queue.clone();
-        push_event(&queue, TransferEventRecord::status("Transfer started."));
+fn tagged(mut event: TransferEventRecord, session_id: u64) -> TransferEventRecord {
+    event.session_id = Some(session_id);
+    event
+}
 
-        let task = self.runtime.spawn(async move {
-            if let Err(err) = fut.await {
+fn abort_current(jobs: &mut JobQueue, queue: &EventQueue) {
+    if let Some((id, handle)) = jobs.current.take() {
+        handle.abort();
+        if jobs.set_state(id, JobState::Done) {
+            push_event(
+                queue,
+                tagged(
+                    TransferEventRecord::status("Transfer canceled by user."),
+                    id,
+                ),
+            );
+            push_event(queue, tagged(canceled_event(), id));
+        }
+    }
+}
+
+/// Mark job `id`, already taken off the pending queue, as canceled.
+fn cancel_queued(jobs: &mut JobQueue, id: u64, queue: &EventQueue) {
+    if jobs.set_state(id, JobState::Done) {
+        push_event(
+            queue,
+            tagged(TransferEventRecord::status("Queued transfer canceled."), id),
+        );
+        push_event(queue, tagged(canceled_event(), id));
+    }
+}
+
+/// Terminal event of a job canceled through the controller.
+fn canceled_event() -> TransferEventRecord {
+    TransferEventRecord::error("canceled", Canceled.to_string())
+}
+
+/// Start the next queued job when none is running. The job clears itself
+/// and starts its successor when it finishes; `jobs` stays locked while it
+/// is spawned so that cannot happen before it is recorded as current.
+fn start_next(
+    jobs: &mut JobQueue,
+    shared: &Arc<Mutex<JobQueue>>,
+    queue: &EventQueue,
+    runtime: &Handle,
+) {
+    if jobs.current.is_some() {
+        return;
+    }
+    let Some((id, job)) = jobs.pending.pop_front() else {
+        return;
+    };
+    jobs.set_state(id, JobState::Connecting);
+    push_event(
+        queue,
+        tagged(TransferEventRecord::status("Transfer started."), id),
+    );
+
+    let shared_jobs = shared.clone();
+    let events = queue.clone();
+    let spawner = runtime.clone();
+    let task = runtime.spawn(async move {
+        let result = job.await;
+        if let Ok(mut jobs) = shared_jobs.lock() {
+            // A job that failed without an `error` event of its own, such
+            // as on an invalid ticket, gets a generic one.
+            if let (true, Err(err)) = (jobs.set_state(id, JobState::Done), &result) {
                 push_event(
-                    &queue,
-                    TransferEventRecord::error("transfer_error", format!("{:#}", err)),
+                    &events,
+                    tagged(
+                        TransferEventRecord::error("transfer_error", format!("{:#}", err)),
+                        id,
+                    ),
                 );
             }
-        });
-
-        if let Ok(mut current) = self.task.lock() {
-            *current = Some(task);
+            if jobs
+                .current
+                .as_ref()
+                .is_some_and(|(current, _)| *current == id)
+            {
+                jobs.current = None;
+            }
+            start_next(&mut jobs, &shared_jobs, &events, &spawner);
         }
-    }
+    });
+    jobs.current = Some((id, task));
 }
 
 impl Default for TransferController {
@@ -201,112 +484,29 @@ pub fn bindings_version() -> &'static str {
     env!("CARGO_PKG_VERSION")
 }
 
-fn push_event(queue: &Arc<Mutex<VecDeque<TransferEventRecord>>>, event: TransferEventRecord) {
-    if let Ok(mut q) = queue.lock() {
-        q.push_back(event);
-    }
+/// Features of the bundled core, as reported by `p2p-share capabilities`.
+pub fn capabilities_json() -> Option<String> {
+    serde_json::to_string(&p2p_share_core::capabilities::capabilities()).ok()
 }
 
-fn map_event(event: TransferEvent) -> TransferEventRecord {
-    match event {
-        TransferEvent::Status(message) => TransferEventRecord::status(message),
-        TransferEvent::Ticket(ticket) => TransferEventRecord {
-            kind: "ticket".to_string(),
-            message: None,
-            value: Some(ticket),
-            done: None,
-            total: None,
-            file_name: None,
-            size_bytes: None,
-            saved_path: None,
-            latency_ms: None,
-            content_kind: None,
-            item_count: None,
-        },
-        TransferEvent::QrPayload(payload) => TransferEventRecord {
-            kind: "qr_payload".to_string(),
-            message: None,
-            value: Some(payload),
-            done: None,
-            total: None,
-            file_name: None,
-            size_bytes: None,
-            saved_path: None,
-            latency_ms: None,
-            content_kind: None,
-            item_count: None,
-        },
-        TransferEvent::HandshakeCode(code) => TransferEventRecord {
-            kind: "handshake_code".to_string(),
-            message: None,
-            value: Some(code),
-            done: None,
-            total: None,
-            file_name: None,
-            size_bytes: None,
-            saved_path: None,
-            latency_ms: None,
-            content_kind: None,
-            item_count: None,
-        },
-        TransferEvent::Progress { done, total } => TransferEventRecord {
-            kind: "progress".to_string(),
-            message: None,
-            value: None,
-            done: Some(done),
-            total: Some(total),
-            file_name: None,
-            size_bytes: None,
-            saved_path: None,
-            latency_ms: None,
-            content_kind: None,
-            item_count: None,
-        },
-        TransferEvent::ConnectionPath { kind, latency_ms } => {
-            let (value, message) = match kind {
-                ConnectionPathKind::Direct(addr) => (Some("direct".to_string()), Some(addr)),
-                ConnectionPathKind::Relay(url) => (Some("relay".to_string()), Some(url)),
-                ConnectionPathKind::Mixed {
-                    udp_addr,
-                    relay_url,
-                } => (
-                    Some("mixed".to_string()),
-                    Some(format!("udp: {}, relay: {}", udp_addr, relay_url)),
-                ),
-                ConnectionPathKind::None => (Some("none".to_string()), None),
-            };
+/// What a pasted ticket contains and which transports it enables, as
+/// printed by `p2p-share ticket inspect --json`. `None` for invalid tickets.
+pub fn ticket_info_json(ticket: &str) -> Option<String> {
+    let info = p2p_share_core::ticket::inspect(ticket).ok()?;
+    serde_json::to_string(&info).ok()
+}
 
-            TransferEventRecord {
-                kind: "connection_path".to_string(),
-                message,
-                value,
-                done: None,
-                total: None,
-                file_name: None,
-                size_bytes: None,
-                saved_path: None,
-                latency_ms,
-                content_kind: None,
-                item_count: None,
-            }
-        }
-        TransferEvent::Completed(result) => TransferEventRecord {
-            kind: "completed".to_string(),
-            message: None,
-            value: None,
-            done: None,
-            total: None,
-            file_name: Some(result.file_name),
-            size_bytes: Some(result.size_bytes),
-            saved_path: result.saved_path.map(|p| p.display().to_string()),
-            latency_ms: None,
-            content_kind: Some(match result.content_kind {
-                TransferContentKind::File => "file".to_string(),
-                TransferContentKind::Bundle => "bundle".to_string(),
-            }),
-            item_count: Some(result.item_count),
-        },
-        TransferEvent::Error { code, message } => TransferEventRecord::error(code, message),
+/// The ticket put back together from the scanned QR codes of a split
+/// ticket, given as a JSON array of `p2psh-part:` strings in any order.
+/// `None` while parts are missing or when they do not belong together.
+pub fn join_ticket_parts(parts_json: &str) -> Option<String> {
+    let parts: Vec<String> = serde_json::from_str(parts_json).ok()?;
+    p2p_share_core::ticket::join(&parts).ok()
+}
+
+fn push_event(queue: &EventQueue, event: TransferEventRecord) {
+    if let Ok(mut q) = queue.lock() {
+        q.push_back(event);
     }
 }
 
@@ -407,6 +607,82 @@ pub extern "C" fn p2pshare_controller_start_receive_listen(handle: u64, output_d
     });
 }
 
+/// Queue a wait-mode send. Returns its session id, or 0 when the request
+/// was invalid.
+#[no_mangle]
+pub extern "C" fn p2pshare_controller_enqueue_send_wait(
+    handle: u64,
+    file_paths_json: *const c_char,
+) -> u64 {
+    let Some(file_paths_json) = cstr_to_string(file_paths_json) else {
+        return 0;
+    };
+    let Ok(file_paths) = serde_json::from_str::<Vec<String>>(&file_paths_json) else {
+        return 0;
+    };
+    let mut id = 0;
+    with_controller(handle, |controller| {
+        id = controller.enqueue_send_wait(file_paths)
+    });
+    id
+}
+
+#[no_mangle]
+pub extern "C" fn p2pshare_controller_enqueue_send_to_ticket(
+    handle: u64,
+    file_paths_json: *const c_char,
+    ticket: *const c_char,
+) -> u64 {
+    let Some(file_paths_json) = cstr_to_string(file_paths_json) else {
+        return 0;
+    };
+    let Some(ticket) = cstr_to_string(ticket) else {
+        return 0;
+    };
+    let Ok(file_paths) = serde_json::from_str::<Vec<String>>(&file_paths_json) else {
+        return 0;
+    };
+    let mut id = 0;
+    with_controller(handle, |controller| {
+        id = controller.enqueue_send_to_ticket(file_paths, ticket)
+    });
+    id
+}
+
+#[no_mangle]
+pub extern "C" fn p2pshare_controller_enqueue_receive_target(
+    handle: u64,
+    target: *const c_char,
+    output_dir: *const c_char,
+) -> u64 {
+    let Some(target) = cstr_to_string(target) else {
+        return 0;
+    };
+    let Some(output_dir) = cstr_to_string(output_dir) else {
+        return 0;
+    };
+    let mut id = 0;
+    with_controller(handle, |controller| {
+        id = controller.enqueue_receive_target(target, output_dir)
+    });
+    id
+}
+
+#[no_mangle]
+pub extern "C" fn p2pshare_controller_enqueue_receive_listen(
+    handle: u64,
+    output_dir: *const c_char,
+) -> u64 {
+    let Some(output_dir) = cstr_to_string(output_dir) else {
+        return 0;
+    };
+    let mut id = 0;
+    with_controller(handle, |controller| {
+        id = controller.enqueue_receive_listen(output_dir)
+    });
+    id
+}
+
 #[no_mangle]
 pub extern "C" fn p2pshare_controller_poll_event_json(handle: u64) -> *mut c_char {
     let mut out: Option<String> = None;
@@ -421,11 +697,143 @@ pub extern "C" fn p2pshare_controller_poll_event_json(handle: u64) -> *mut c_cha
     }
 }
 
+/// Batched variant of `p2pshare_controller_poll_event_json`: returns a JSON
+/// array of up to `max` events, or null when none are queued. Free the
+/// result with `p2pshare_free_cstring`.
+#[no_mangle]
+pub extern "C" fn p2pshare_controller_poll_events_json(handle: u64, max: u32) -> *mut c_char {
+    let mut out: Option<String> = None;
+    with_controller(handle, |controller| {
+        out = controller.poll_events_json(max as usize);
+    });
+    match out {
+        Some(json) => CString::new(json)
+            .map(CString::into_raw)
+            .unwrap_or(std::ptr::null_mut()),
+        None => std::ptr::null_mut(),
+    }
+}
+
+/// Every queued event as a JSON array, or null when none are queued; see
+/// [`TransferController::drain_remaining`]. Call on teardown, after
+/// `p2pshare_controller_cancel`. Free the result with
+/// `p2pshare_free_cstring`.
+#[no_mangle]
+pub extern "C" fn p2pshare_controller_drain_remaining_json(handle: u64) -> *mut c_char {
+    let mut out: Option<String> = None;
+    with_controller(handle, |controller| {
+        out = controller.drain_remaining_json();
+    });
+    match out {
+        Some(json) => CString::new(json)
+            .map(CString::into_raw)
+            .unwrap_or(std::ptr::null_mut()),
+        None => std::ptr::null_mut(),
+    }
+}
+
+/// Snapshot of the controller's byte counters as a JSON object with
+/// `bytes_transferred`, `bytes_total`, `started_at_ms` and `updated_at_ms`.
+/// Cheap enough to call every frame. Free the result with
+/// `p2pshare_free_cstring`.
+#[no_mangle]
+pub extern "C" fn p2pshare_controller_stats_json(handle: u64) -> *mut c_char {
+    let mut out: Option<String> = None;
+    with_controller(handle, |controller| {
+        out = controller.stats_json();
+    });
+    match out {
+        Some(json) => CString::new(json)
+            .map(CString::into_raw)
+            .unwrap_or(std::ptr::null_mut()),
+        None => std::ptr::null_mut(),
+    }
+}
+
+/// Features this library supports as a JSON object of flags, the same one
+/// `p2p-share capabilities --json` prints. Needs no controller. Free the
+/// result with `p2pshare_free_cstring`.
+#[no_mangle]
+pub extern "C" fn p2pshare_capabilities_json() -> *mut c_char {
+    match capabilities_json() {
+        Some(json) => CString::new(json)
+            .map(CString::into_raw)
+            .unwrap_or(std::ptr::null_mut()),
+        None => std::ptr::null_mut(),
+    }
+}
+
+/// Inspect a ticket before connecting, e.g. to warn that a relay-only
+/// ticket may be slow. Returns a JSON object whose `kind` is `direct_only`,
+/// `relay_only` or `mixed`, or null when the ticket is invalid. Free the
+/// result with `p2pshare_free_cstring`.
+#[no_mangle]
+pub extern "C" fn p2pshare_ticket_info_json(ticket: *const c_char) -> *mut c_char {
+    let Some(ticket) = cstr_to_string(ticket) else {
+        return std::ptr::null_mut();
+    };
+    match ticket_info_json(&ticket) {
+        Some(json) => CString::new(json)
+            .map(CString::into_raw)
+            .unwrap_or(std::ptr::null_mut()),
+        None => std::ptr::null_mut(),
+    }
+}
+
+/// Join the parts of a ticket split across several QR codes; see
+/// [`join_ticket_parts`]. Returns the ticket, or null while parts are
+/// missing. Free the result with `p2pshare_free_cstring`.
+#[no_mangle]
+pub extern "C" fn p2pshare_join_ticket_parts(parts_json: *const c_char) -> *mut c_char {
+    let Some(parts_json) = cstr_to_string(parts_json) else {
+        return std::ptr::null_mut();
+    };
+    match join_ticket_parts(&parts_json) {
+        Some(ticket) => CString::new(ticket)
+            .map(CString::into_raw)
+            .unwrap_or(std::ptr::null_mut()),
+        None => std::ptr::null_mut(),
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn p2pshare_controller_cancel(handle: u64) {
     with_controller(handle, TransferController::cancel);
 }
 
+#[no_mangle]
+pub extern "C" fn p2pshare_controller_cancel_current(handle: u64) {
+    with_controller(handle, TransferController::cancel_current);
+}
+
+/// Cancel the job with `session_id`, running or queued. Returns 1 when it
+/// was found and 0 otherwise.
+#[no_mangle]
+pub extern "C" fn p2pshare_controller_cancel_job(handle: u64, session_id: u64) -> i32 {
+    let mut found = false;
+    with_controller(handle, |controller| {
+        found = controller.cancel_job(session_id);
+    });
+    i32::from(found)
+}
+
+/// Jobs as a JSON array of `{"session_id", "state"}` objects, where `state`
+/// is `waiting`, `connecting`, `transferring` or `done`. Safe to call from
+/// any thread while jobs run. Free the result with `p2pshare_free_cstring`.
+#[no_mangle]
+pub extern "C" fn p2pshare_controller_list_jobs_json(handle: u64) -> *mut c_char {
+    let mut out: Option<String> = None;
+    with_controller(handle, |controller| {
+        out = controller.list_jobs_json();
+    });
+    match out {
+        Some(json) => CString::new(json)
+            .map(CString::into_raw)
+            .unwrap_or(std::ptr::null_mut()),
+        None => std::ptr::null_mut(),
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn p2pshare_free_cstring(ptr: *const c_char) {
     if ptr.is_null() {
@@ -436,3 +844,173 @@ pub extern "C" fn p2pshare_free_cstring(ptr: *const c_char) {
         let _ = CString::from_raw(ptr as *mut c_char);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use p2p_share_core::events::TransferEvent;
+
+    use super::{JobInfo, JobState, TransferController, TransferEventRecord};
+
+    /// Poll until an event matching `pred` arrives, collecting everything seen.
+    fn wait_for(
+        controller: &TransferController,
+        seen: &mut Vec<TransferEventRecord>,
+        pred: impl Fn(&TransferEventRecord) -> bool,
+    ) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while Instant::now() < deadline {
+            let batch = controller.poll_events(64);
+            let found = batch.iter().any(&pred);
+            seen.extend(batch);
+            if found {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        panic!("timed out; saw {:?}", seen);
+    }
+
+    fn started(seen: &[TransferEventRecord], id: u64) -> bool {
+        seen.iter().any(|event| {
+            event.session_id == Some(id) && event.message.as_deref() == Some("Transfer started.")
+        })
+    }
+
+    #[test]
+    fn queued_jobs_run_one_after_another() {
+        let controller = TransferController::new();
+        let (release, wait) = tokio::sync::oneshot::channel::<()>();
+        let first = controller.enqueue(|_| async move {
+            let _ = wait.await;
+            Ok(())
+        });
+        let second = controller.enqueue(|_| async { anyhow::bail!("second failed") });
+
+        let mut seen = Vec::new();
+        std::thread::sleep(Duration::from_millis(50));
+        seen.extend(controller.poll_events(64));
+        assert!(started(&seen, first));
+        assert!(!started(&seen, second), "second ran before first finished");
+
+        release.send(()).unwrap();
+        wait_for(&controller, &mut seen, |event| {
+            event.kind == "error" && event.session_id == Some(second)
+        });
+        assert!(started(&seen, second));
+    }
+
+    #[test]
+    fn cancel_current_moves_on_but_cancel_clears_the_queue() {
+        let controller = TransferController::new();
+        let first = controller.enqueue(|_| std::future::pending());
+        let second = controller.enqueue(|_| std::future::pending());
+        let third = controller.enqueue(|_| std::future::pending());
+
+        controller.cancel_current();
+        let mut seen = controller.poll_events(64);
+        assert!(seen.iter().any(|event| event.session_id == Some(first)
+            && event.message.as_deref() == Some("Transfer canceled by user.")));
+        assert!(started(&seen, second));
+
+        controller.cancel();
+        seen.extend(controller.poll_events(64));
+        assert!(!started(&seen, third));
+        assert!(seen.iter().any(|event| event.session_id == Some(third)
+            && event.message.as_deref() == Some("Queued transfer canceled.")));
+        assert!(controller.jobs.lock().unwrap().current.is_none());
+    }
+
+    #[test]
+    fn jobs_can_be_listed_and_canceled_one_by_one() {
+        let controller = TransferController::new();
+        let first = controller.enqueue(|sink| async move {
+            sink.on_event(TransferEvent::Progress {
+                done: 1,
+                total: Some(2),
+            });
+            std::future::pending().await
+        });
+        let second = controller.enqueue(|_| std::future::pending());
+        let third = controller.enqueue(|_| std::future::pending());
+        let job = |session_id, state| JobInfo { session_id, state };
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while controller.list_jobs()[0].state != JobState::Transferring {
+            assert!(Instant::now() < deadline, "{:?}", controller.list_jobs());
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(
+            controller.list_jobs(),
+            [
+                job(first, JobState::Transferring),
+                job(second, JobState::Waiting),
+                job(third, JobState::Waiting),
+            ]
+        );
+
+        assert!(controller.cancel_job(third));
+        assert!(controller.cancel_job(first));
+        assert_eq!(
+            controller.list_jobs(),
+            [
+                job(first, JobState::Done),
+                job(second, JobState::Connecting),
+                job(third, JobState::Done),
+            ]
+        );
+        assert!(!controller.cancel_job(first));
+        assert!(!controller.cancel_job(third + 1));
+        let json = controller.list_jobs_json().unwrap();
+        assert!(json.contains(r#""state":"connecting""#), "{json}");
+    }
+
+    #[test]
+    fn every_job_ends_with_one_terminal_event() {
+        let controller = TransferController::new();
+        let failed = controller.enqueue(|sink| async move {
+            sink.on_event(TransferEvent::Error {
+                code: "connection_lost".to_string(),
+                message: "the connection to the peer was lost".to_string(),
+                partial_path: None,
+            });
+            anyhow::bail!("the connection to the peer was lost")
+        });
+        let mut seen = Vec::new();
+        wait_for(&controller, &mut seen, |event| {
+            event.session_id == Some(failed) && event.kind == "error"
+        });
+        let running = controller.enqueue(|_| std::future::pending());
+        let queued = controller.enqueue(|_| std::future::pending());
+        wait_for(&controller, &mut seen, |event| {
+            event.session_id == Some(running)
+                && event.message.as_deref() == Some("Transfer started.")
+        });
+
+        controller.cancel();
+        seen.extend(controller.drain_remaining());
+        assert!(controller.drain_remaining().is_empty());
+        assert_eq!(controller.drain_remaining_json(), None);
+
+        for id in [failed, running, queued] {
+            let terminal: Vec<&str> = seen
+                .iter()
+                .filter(|event| event.session_id == Some(id) && event.kind == "error")
+                .map(|event| event.value.as_deref().unwrap_or_default())
+                .collect();
+            let expected = if id == failed {
+                "connection_lost"
+            } else {
+                "canceled"
+            };
+            assert_eq!(terminal, [expected], "job {id}: {seen:?}");
+            let last = seen
+                .iter()
+                .rev()
+                .find(|event| event.session_id == Some(id))
+                .unwrap();
+            assert_eq!(last.kind, "error");
+        }
+    }
+}
@@ -0,0 +1,443 @@
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, Context, Result};
+use clap::parser::ValueSource;
+use clap::ArgMatches;
+use p2p_share_core::code::CodeFormat;
+use p2p_share_core::endpoint::{RelayRegion, RelayUrl};
+use p2p_share_core::output_template::OutputTemplate;
+use p2p_share_core::paths;
+use p2p_share_core::route::Route;
+use p2p_share_core::sender::AdvertiseMode;
+use toml_edit::{DocumentMut, Item, TableLike};
+
+use super::{parse_pipeline_depth, Command, RelayArgs};
+
+/// File name of the config inside the p2p-share config directory.
+const CONFIG_FILE_NAME: &str = "config.toml";
+
+const TOP_LEVEL_KEYS: &[&str] = &[
+    "relay_region",
+    "relay_url",
+    "code_format",
+    "code_qr",
+    "history",
+    "send",
+    "receive",
+];
+const SEND_KEYS: &[&str] = &["pipeline_depth", "hash_cache", "advertise"];
+const RECEIVE_KEYS: &[&str] = &[
+    "output",
+    "yes",
+    "confirm_timeout",
+    "output_template",
+    "route",
+    "dedup",
+    "write_checksum",
+    "keep_going",
+];
+
+/// Defaults for `send` and `receive` flags, read from `config.toml`:
+///
+/// ```toml
+/// relay_url = ["https://relay.example.com"]
+/// code_format = "words"
+/// history = true
+///
+/// [send]
+/// hash_cache = true
+///
+/// [receive]
+/// output = "~/Downloads"
+/// route = ["image/*=~/Pictures"]
+/// ```
+///
+/// Top-level keys apply to both commands. A flag given on the command line
+/// always wins over the file; unknown keys are rejected so typos surface.
+#[derive(Debug, Default)]
+pub struct Config {
+    relay_region: Option<RelayRegion>,
+    relay_url: Vec<RelayUrl>,
+    code_format: Option<CodeFormat>,
+    code_qr: Option<bool>,
+    history: Option<bool>,
+    send: SendConfig,
+    receive: ReceiveConfig,
+}
+
+#[derive(Debug, Default)]
+struct SendConfig {
+    pipeline_depth: Option<usize>,
+    hash_cache: Option<bool>,
+    advertise: Option<AdvertiseMode>,
+}
+
+#[derive(Debug, Default)]
+struct ReceiveConfig {
+    output: Option<PathBuf>,
+    yes: Option<bool>,
+    confirm_timeout: Option<u64>,
+    output_template: Option<OutputTemplate>,
+    route: Vec<Route>,
+    dedup: Option<bool>,
+    write_checksum: Option<bool>,
+    keep_going: Option<bool>,
+}
+
+/// Default config location, if the platform config directory is known.
+pub fn default_path() -> Option<PathBuf> {
+    paths::app_config_dir().map(|dir| dir.join(CONFIG_FILE_NAME))
+}
+
+impl Config {
+    /// Load `path` (`--config`), or the default location when `None`. Only a
+    /// missing default file is fine: it means no defaults.
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let (path, explicit) = match path {
+            Some(path) => (path.to_path_buf(), true),
+            None => match default_path() {
+                Some(path) => (path, false),
+                None => return Ok(Self::default()),
+            },
+        };
+        let text = match std::fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound && !explicit => {
+                return Ok(Self::default())
+            }
+            Err(err) => {
+                return Err(err).with_context(|| format!("failed to read {}", path.display()))
+            }
+        };
+        text.parse()
+            .with_context(|| format!("invalid config file {}", path.display()))
+    }
+
+    /// Fill in every flag of `command` that was not given on the command
+    /// line. `matches` are the parsed arguments `command` came from.
+    pub fn apply(&self, command: &mut Command, matches: &ArgMatches) {
+        match command {
+            Command::Send(args) => {
+                let Some(matches) = matches.subcommand_matches("send") else {
+                    return;
+                };
+                self.apply_relay(&mut args.relay, matches);
+                fill(
+                    &mut args.code_format,
+                    &self.code_format,
+                    matches,
+                    "code_format",
+                );
+                fill(&mut args.code_qr, &self.code_qr, matches, "code_qr");
+                fill(&mut args.history, &self.history, matches, "history");
+                let send = &self.send;
+                fill(
+                    &mut args.pipeline_depth,
+                    &send.pipeline_depth,
+                    matches,
+                    "pipeline_depth",
+                );
+                fill(
+                    &mut args.hash_cache,
+                    &send.hash_cache,
+                    matches,
+                    "hash_cache",
+                );
+                fill(&mut args.advertise, &send.advertise, matches, "advertise");
+            }
+            Command::Receive(args) => {
+                let Some(matches) = matches.subcommand_matches("receive") else {
+                    return;
+                };
+                self.apply_relay(&mut args.relay, matches);
+                fill(
+                    &mut args.code_format,
+                    &self.code_format,
+                    matches,
+                    "code_format",
+                );
+                fill(&mut args.code_qr, &self.code_qr, matches, "code_qr");
+                fill(&mut args.history, &self.history, matches, "history");
+                let receive = &self.receive;
+                fill(&mut args.output, &receive.output, matches, "output");
+                fill(&mut args.yes, &receive.yes, matches, "yes");
+                fill(
+                    &mut args.confirm_timeout,
+                    &receive.confirm_timeout,
+                    matches,
+                    "confirm_timeout",
+                );
+                if unset(matches, "output_template") && receive.output_template.is_some() {
+                    args.output_template = receive.output_template.clone();
+                }
+                if unset(matches, "route") && !receive.route.is_empty() {
+                    args.route = receive.route.clone();
+                }
+                fill(&mut args.dedup, &receive.dedup, matches, "dedup");
+                fill(
+                    &mut args.write_checksum,
+                    &receive.write_checksum,
+                    matches,
+                    "write_checksum",
+                );
+                fill(
+                    &mut args.keep_going,
+                    &receive.keep_going,
+                    matches,
+                    "keep_going",
+                );
+            }
+            _ => {}
+        }
+    }
+
+    /// The relay flags conflict with each other, so either one on the
+    /// command line replaces both config keys.
+    fn apply_relay(&self, relay: &mut RelayArgs, matches: &ArgMatches) {
+        if !unset(matches, "relay_region") || !unset(matches, "relay_url") {
+            return;
+        }
+        if self.relay_region.is_some() {
+            relay.relay_region = self.relay_region;
+        } else if !self.relay_url.is_empty() {
+            relay.relay_url = self.relay_url.clone();
+        }
+    }
+}
+
+impl FromStr for Config {
+    type Err = anyhow::Error;
+
+    fn from_str(text: &str) -> Result<Self> {
+        let doc: DocumentMut = text.parse()?;
+        let root = doc.as_table();
+        check_keys(root, None, TOP_LEVEL_KEYS)?;
+        let relay_region = parsed(root, None, "relay_region")?;
+        let relay_url = strings(root, None, "relay_url")?
+            .iter()
+            .map(|url| p2p_share_core::endpoint::parse_relay_url(url))
+            .collect::<Result<Vec<_>>>()?;
+        if relay_region.is_some() && !relay_url.is_empty() {
+            bail!("relay_region and relay_url cannot both be set");
+        }
+        let mut config = Config {
+            relay_region,
+            relay_url,
+            code_format: parsed(root, None, "code_format")?,
+            code_qr: boolean(root, None, "code_qr")?,
+            history: boolean(root, None, "history")?,
+            ..Config::default()
+        };
+
+        if let Some(send) = section(root, "send")? {
+            check_keys(send, Some("send"), SEND_KEYS)?;
+            let pipeline_depth = integer::<u64>(send, Some("send"), "pipeline_depth")?
+                .map(|depth| parse_pipeline_depth(&depth.to_string()).map_err(|err| anyhow!(err)))
+                .transpose()?;
+            config.send = SendConfig {
+                pipeline_depth,
+                hash_cache: boolean(send, Some("send"), "hash_cache")?,
+                advertise: parsed(send, Some("send"), "advertise")?,
+            };
+        }
+
+        if let Some(receive) = section(root, "receive")? {
+            let name = Some("receive");
+            check_keys(receive, name, RECEIVE_KEYS)?;
+            config.receive = ReceiveConfig {
+                output: string(receive, name, "output")?.map(paths::expand_home),
+                yes: boolean(receive, name, "yes")?,
+                confirm_timeout: integer(receive, name, "confirm_timeout")?,
+                output_template: parsed(receive, name, "output_template")?,
+                route: strings(receive, name, "route")?
+                    .iter()
+                    .map(|route| route.parse())
+                    .collect::<Result<Vec<_>>>()?,
+                dedup: boolean(receive, name, "dedup")?,
+                write_checksum: boolean(receive, name, "write_checksum")?,
+                keep_going: boolean(receive, name, "keep_going")?,
+            };
+        }
+        Ok(config)
+    }
+}
+
+/// Whether flag `id` was left to its default rather than typed by the user.
+fn unset(matches: &ArgMatches, id: &str) -> bool {
+    matches.value_source(id) != Some(ValueSource::CommandLine)
+}
+
+fn fill<T: Clone>(slot: &mut T, value: &Option<T>, matches: &ArgMatches, id: &str) {
+    if let Some(value) = value {
+        if unset(matches, id) {
+            *slot = value.clone();
+        }
+    }
+}
+
+fn key_name(section: Option<&str>, key: &str) -> String {
+    match section {
+        Some(section) => format!("{}.{}", section, key),
+        None => key.to_string(),
+    }
+}
+
+fn check_keys(table: &dyn TableLike, section: Option<&str>, known: &[&str]) -> Result<()> {
+    for (key, _) in table.iter() {
+        if !known.contains(&key) {
+            bail!(
+                "unknown key '{}' (expected one of: {})",
+                key_name(section, key),
+                known.join(", ")
+            );
+        }
+    }
+    Ok(())
+}
+
+fn section<'a>(root: &'a dyn TableLike, name: &str) -> Result<Option<&'a dyn TableLike>> {
+    match root.get(name) {
+        None => Ok(None),
+        Some(item) => item
+            .as_table_like()
+            .map(Some)
+            .ok_or_else(|| anyhow!("{} must be a table", name)),
+    }
+}
+
+fn value<'a>(
+    table: &'a dyn TableLike,
+    section: Option<&str>,
+    key: &str,
+    expected: &str,
+    get: impl FnOnce(&'a Item) -> Option<&'a str>,
+) -> Result<Option<&'a str>> {
+    match table.get(key) {
+        None => Ok(None),
+        Some(item) => get(item)
+            .map(Some)
+            .ok_or_else(|| anyhow!("{} must be {}", key_name(section, key), expected)),
+    }
+}
+
+fn string<'a>(
+    table: &'a dyn TableLike,
+    section: Option<&str>,
+    key: &str,
+) -> Result<Option<&'a str>> {
+    value(table, section, key, "a string", Item::as_str)
+}
+
+fn parsed<T>(table: &dyn TableLike, section: Option<&str>, key: &str) -> Result<Option<T>>
+where
+    T: FromStr<Err = anyhow::Error>,
+{
+    string(table, section, key)?
+        .map(|text| {
+            text.parse()
+                .with_context(|| format!("invalid {}", key_name(section, key)))
+        })
+        .transpose()
+}
+
+fn boolean(table: &dyn TableLike, section: Option<&str>, key: &str) -> Result<Option<bool>> {
+    match table.get(key) {
+        None => Ok(None),
+        Some(item) => item
+            .as_bool()
+            .map(Some)
+            .ok_or_else(|| anyhow!("{} must be true or false", key_name(section, key))),
+    }
+}
+
+fn integer<T: TryFrom<i64>>(
+    table: &dyn TableLike,
+    section: Option<&str>,
+    key: &str,
+) -> Result<Option<T>> {
+    match table.get(key) {
+        None => Ok(None),
+        Some(item) => item
+            .as_integer()
+            .and_then(|number| T::try_from(number).ok())
+            .map(Some)
+            .ok_or_else(|| anyhow!("{} must be a positive integer", key_name(section, key))),
+    }
+}
+
+/// A string or an array of strings, for keys that mirror repeatable flags.
+fn strings<'a>(table: &'a dyn TableLike, section: Option<&str>, key: &str) -> Result<Vec<&'a str>> {
+    let Some(item) = table.get(key) else {
+        return Ok(Vec::new());
+    };
+    if let Some(text) = item.as_str() {
+        return Ok(vec![text]);
+    }
+    item.as_array()
+        .and_then(|array| array.iter().map(|value| value.as_str()).collect())
+        .ok_or_else(|| {
+            anyhow!(
+                "{} must be a string or an array of strings",
+                key_name(section, key)
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use clap::{CommandFactory, FromArgMatches};
+
+    use super::Config;
+    use crate::{Cli, Command, ReceiveArgs};
+
+    fn receive_with(config: &str, args: &[&str]) -> ReceiveArgs {
+        let config: Config = config.parse().expect("config");
+        let matches = Cli::command().try_get_matches_from(args).expect("parse");
+        let mut cli = Cli::from_arg_matches(&matches).expect("cli");
+        config.apply(&mut cli.command, &matches);
+        match cli.command {
+            Command::Receive(args) => args,
+            _ => panic!("expected receive"),
+        }
+    }
+
+    #[test]
+    fn config_fills_in_flags_left_out() {
+        let config = r#"
+            code_format = "words"
+            code_qr = true
+            history = true
+
+            [receive]
+            output = "/srv/inbox"
+            dedup = true
+            route = "jpg=/srv/pictures"
+        "#;
+        let args = receive_with(config, &["p2p-share", "receive", "--qr"]);
+        assert_eq!(args.output, PathBuf::from("/srv/inbox"));
+        assert!(args.code_qr);
+        assert!(args.dedup);
+        assert_eq!(args.route.len(), 1);
+        assert_eq!(args.code_format, "words".parse().unwrap());
+        assert!(args.history);
+
+        let args = receive_with(config, &["p2p-share", "receive", "--qr", "-o", "here"]);
+        assert_eq!(args.output, PathBuf::from("here"));
+        assert!(args.dedup);
+    }
+
+    #[test]
+    fn unknown_keys_and_bad_values_are_rejected() {
+        let err = "[receive]\noutptu = \"x\"".parse::<Config>().unwrap_err();
+        assert!(err.to_string().contains("receive.outptu"), "{err:#}");
+        assert!("verbose = true".parse::<Config>().is_err());
+        assert!("[send]\npipeline_depth = 0".parse::<Config>().is_err());
+        assert!("[send]\nhash_cache = \"yes\"".parse::<Config>().is_err());
+        assert!("code_format = \"morse\"".parse::<Config>().is_err());
+        assert!("receive = 1".parse::<Config>().is_err());
+        assert!("".parse::<Config>().is_ok());
+    }
+}
@@ -0,0 +1,54 @@
+use std::fs::File;
+use std::io::{self, Write};
+use std::sync::{Mutex, OnceLock};
+
+/// Descriptor `--event-fd` opened, shared by every JSON event line. Unset
+/// when events go to stdout.
+static EVENT_FILE: OnceLock<Mutex<File>> = OnceLock::new();
+
+/// Send JSON event lines to descriptor `fd` from now on. Falls back to
+/// stdout, with a warning, when `fd` is not open in this process.
+pub fn redirect_events(fd: i32) {
+    match open_fd(fd) {
+        Some(file) => {
+            let _ = EVENT_FILE.set(Mutex::new(file));
+        }
+        None => eprintln!(
+            "Warning: --event-fd {} is not an open file descriptor; writing events to stdout.",
+            fd
+        ),
+    }
+}
+
+/// Write one JSON event line to the event output.
+pub fn write_event_line(json: &str) {
+    if let Some(file) = EVENT_FILE.get() {
+        if let Ok(mut file) = file.lock() {
+            let _ = writeln!(file, "{json}");
+            let _ = file.flush();
+            return;
+        }
+    }
+    let mut out = io::stdout().lock();
+    let _ = writeln!(out, "{json}");
+    let _ = out.flush();
+}
+
+#[cfg(unix)]
+fn open_fd(fd: i32) -> Option<File> {
+    use std::os::fd::FromRawFd;
+
+    // SAFETY: F_GETFD only reads descriptor flags; it fails with EBADF when
+    // `fd` is not open.
+    if fd < 0 || unsafe { libc::fcntl(fd, libc::F_GETFD) } == -1 {
+        return None;
+    }
+    // SAFETY: `fd` is open and was handed to us for events; nothing else in
+    // this process owns it.
+    Some(unsafe { File::from_raw_fd(fd) })
+}
+
+#[cfg(not(unix))]
+fn open_fd(_fd: i32) -> Option<File> {
+    None
+}
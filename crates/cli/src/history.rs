@@ -0,0 +1,330 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use clap::{Args, ValueEnum};
+use p2p_share_core::events::{TransferEvent, TransferEventSink};
+use p2p_share_core::paths;
+use serde::{Deserialize, Serialize};
+
+/// File name of the history inside the p2p-share config directory.
+const HISTORY_FILE_NAME: &str = "history.jsonl";
+
+/// Characters of a node id shown in `p2p-share history`, as many as iroh's
+/// short form.
+const SHORT_PEER_LEN: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Send,
+    Receive,
+}
+
+/// One completed transfer, a line of the history file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+    /// Seconds since the Unix epoch when the transfer completed.
+    pub timestamp: u64,
+    pub direction: Direction,
+    /// Node id of the other device; `None` for plain TCP transfers.
+    pub peer: Option<String>,
+    pub file_name: String,
+    pub size_bytes: u64,
+    /// Hex-encoded blake3 hash of the data, when known.
+    pub blake3: Option<String>,
+    /// From connecting to the peer until the transfer completed.
+    pub duration_ms: u64,
+    /// Where a received file or folder was saved.
+    pub path: Option<PathBuf>,
+}
+
+/// Which entries `p2p-share history` lists.
+#[derive(Debug, Clone, Default, Args)]
+pub struct Filter {
+    /// Only transfers in this direction.
+    #[arg(long, value_enum)]
+    pub direction: Option<Direction>,
+
+    /// Only transfers with this peer; a prefix of its node id is enough.
+    #[arg(long, value_name = "NODE_ID")]
+    pub peer: Option<String>,
+
+    /// Only files whose name contains TEXT, ignoring case.
+    #[arg(long, value_name = "TEXT")]
+    pub name: Option<String>,
+
+    /// Only the last N matching transfers.
+    #[arg(long, value_name = "N")]
+    pub limit: Option<usize>,
+}
+
+impl Filter {
+    fn matches(&self, entry: &Entry) -> bool {
+        self.direction
+            .is_none_or(|direction| direction == entry.direction)
+            && self.peer.as_deref().is_none_or(|peer| {
+                entry
+                    .peer
+                    .as_deref()
+                    .is_some_and(|id| id.starts_with(&peer.to_ascii_lowercase()))
+            })
+            && self.name.as_deref().is_none_or(|name| {
+                entry
+                    .file_name
+                    .to_lowercase()
+                    .contains(&name.to_lowercase())
+            })
+    }
+
+    /// The entries this filter lets through, oldest first.
+    pub fn apply(&self, entries: Vec<Entry>) -> Vec<Entry> {
+        let mut entries: Vec<Entry> = entries
+            .into_iter()
+            .filter(|entry| self.matches(entry))
+            .collect();
+        if let Some(limit) = self.limit {
+            entries.drain(..entries.len().saturating_sub(limit));
+        }
+        entries
+    }
+}
+
+/// Default history location, if the platform config directory is known.
+pub fn default_path() -> Option<PathBuf> {
+    paths::app_config_dir().map(|dir| dir.join(HISTORY_FILE_NAME))
+}
+
+/// Every entry in the history at `path`, oldest first. A missing file is an
+/// empty history; lines that do not parse are skipped.
+pub fn load(path: &Path) -> Result<Vec<Entry>> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err).with_context(|| format!("failed to read {}", path.display())),
+    };
+    Ok(text
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect())
+}
+
+fn append(path: &Path, entry: &Entry) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("cannot create {}", parent.display()))?;
+    }
+    let mut line = serde_json::to_vec(entry)?;
+    line.push(b'\n');
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(&line))
+        .with_context(|| format!("cannot write {}", path.display()))
+}
+
+/// `sink` with every completed transfer also appended to the history file,
+/// or `sink` unchanged when `enabled` is false.
+pub fn record(
+    sink: Option<Arc<dyn TransferEventSink>>,
+    enabled: bool,
+    direction: Direction,
+) -> Option<Arc<dyn TransferEventSink>> {
+    match default_path() {
+        Some(path) if enabled => Some(Arc::new(HistorySink::new(sink, path, direction))),
+        _ => sink,
+    }
+}
+
+/// Forwards events to an inner sink, if any, and writes an [`Entry`] for
+/// every `Completed` event. Passive without an inner sink, so the terminal
+/// output stays the same.
+struct HistorySink {
+    inner: Option<Arc<dyn TransferEventSink>>,
+    path: PathBuf,
+    direction: Direction,
+    created: Instant,
+    current: Mutex<Current>,
+}
+
+/// What is known about the transfer in progress.
+#[derive(Default)]
+struct Current {
+    peer: Option<String>,
+    started: Option<Instant>,
+}
+
+impl HistorySink {
+    fn new(inner: Option<Arc<dyn TransferEventSink>>, path: PathBuf, direction: Direction) -> Self {
+        Self {
+            inner,
+            path,
+            direction,
+            created: Instant::now(),
+            current: Mutex::new(Current::default()),
+        }
+    }
+
+    fn observe(&self, event: &TransferEvent) {
+        let mut current = self.current.lock().unwrap_or_else(|err| err.into_inner());
+        match event {
+            TransferEvent::PeerIdentified { node_id, .. } => {
+                current.peer = Some(node_id.clone());
+                current.started = Some(Instant::now());
+            }
+            TransferEvent::Progress { .. } if current.started.is_none() => {
+                current.started = Some(Instant::now());
+            }
+            TransferEvent::Completed(completed) => {
+                let Current { peer, started } = std::mem::take(&mut *current);
+                let entry = Entry {
+                    timestamp: SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .map(|d| d.as_secs())
+                        .unwrap_or_default(),
+                    direction: self.direction,
+                    peer,
+                    file_name: completed.file_name.clone(),
+                    size_bytes: completed.size_bytes,
+                    blake3: completed.blake3.clone(),
+                    duration_ms: started.unwrap_or(self.created).elapsed().as_millis() as u64,
+                    path: completed.saved_path.clone(),
+                };
+                if let Err(err) = append(&self.path, &entry) {
+                    eprintln!("Warning: transfer not recorded in history: {:#}", err);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl TransferEventSink for HistorySink {
+    fn on_event(&self, event: TransferEvent) {
+        self.observe(&event);
+        if let Some(inner) = &self.inner {
+            inner.on_event(event);
+        }
+    }
+
+    fn passive(&self) -> bool {
+        self.inner.as_ref().is_none_or(|inner| inner.passive())
+    }
+}
+
+/// One line of `p2p-share history` for `entry`.
+pub fn describe(entry: &Entry) -> String {
+    let format = time::macros::format_description!("[year]-[month]-[day] [hour]:[minute]:[second]");
+    let when = time::OffsetDateTime::from_unix_timestamp(entry.timestamp as i64)
+        .ok()
+        .and_then(|when| when.format(&format).ok())
+        .unwrap_or_else(|| entry.timestamp.to_string());
+    let peer = entry
+        .peer
+        .as_deref()
+        .map(|peer| &peer[..peer.len().min(SHORT_PEER_LEN)])
+        .unwrap_or("tcp");
+    let (arrow, preposition) = match entry.direction {
+        Direction::Send => ("sent", "to"),
+        Direction::Receive => ("received", "from"),
+    };
+    let mut line = format!(
+        "{} UTC  {} {} ({}) {} {}",
+        when,
+        arrow,
+        entry.file_name,
+        p2p_share_core::protocol::human_bytes(entry.size_bytes),
+        preposition,
+        peer
+    );
+    if let Some(path) = &entry.path {
+        line.push_str(&format!(" -> {}", path.display()));
+    }
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    use p2p_share_core::events::{
+        TransferCompleted, TransferContentKind, TransferEvent, TransferEventSink,
+    };
+
+    use super::{load, Direction, Filter, HistorySink};
+
+    #[test]
+    fn completed_transfers_are_appended_and_filtered() {
+        let path = std::env::temp_dir().join(format!(
+            "p2p-share-history-{}/history.jsonl",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        for (direction, peer, name) in [
+            (Direction::Receive, Some("3f9a1b2c4d"), "Holiday.JPG"),
+            (Direction::Send, None, "notes.txt"),
+            (Direction::Receive, Some("77aa00ff11"), "report.pdf"),
+        ] {
+            let sink = HistorySink::new(None, path.clone(), direction);
+            assert!(sink.passive());
+            if let Some(peer) = peer {
+                sink.on_event(TransferEvent::PeerIdentified {
+                    node_id: format!("{}{}", peer, "0".repeat(54)),
+                    short: peer.to_string(),
+                });
+            }
+            sink.on_event(TransferEvent::Completed(TransferCompleted {
+                file_name: name.to_string(),
+                size_bytes: 42,
+                saved_path: (direction == Direction::Receive)
+                    .then(|| PathBuf::from("/inbox").join(name)),
+                content_kind: TransferContentKind::File,
+                item_count: 1,
+                mime: None,
+                compression_level: None,
+                blake3: Some("ab".repeat(32)),
+            }));
+        }
+
+        let entries = load(&path).unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].peer.as_deref().map(str::len), Some(64));
+        assert_eq!(entries[1].peer, None);
+        assert_eq!(entries[1].path, None);
+
+        let received = Filter {
+            direction: Some(Direction::Receive),
+            ..Filter::default()
+        };
+        assert_eq!(received.apply(entries.clone()).len(), 2);
+        let by_peer = Filter {
+            peer: Some("3F9A".to_string()),
+            ..Filter::default()
+        };
+        assert_eq!(by_peer.apply(entries.clone())[0].file_name, "Holiday.JPG");
+        let by_name = Filter {
+            name: Some("holiday".to_string()),
+            ..Filter::default()
+        };
+        assert_eq!(by_name.apply(entries.clone()).len(), 1);
+        let last = Filter {
+            limit: Some(1),
+            ..Filter::default()
+        };
+        assert_eq!(last.apply(entries)[0].file_name, "report.pdf");
+
+        let forwarding = HistorySink::new(
+            Some(Arc::new(|_: TransferEvent| {})),
+            path.clone(),
+            Direction::Send,
+        );
+        assert!(!forwarding.passive());
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
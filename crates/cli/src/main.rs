@@ -1,15 +1,43 @@
 use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use std::{io, io::Write};
 
-use anyhow::{anyhow, Result};
-use clap::{Parser, Subcommand};
-use p2p_share_core::events::{
-    ConnectionPathKind, TransferContentKind, TransferEvent, TransferEventSink,
+use anyhow::{anyhow, bail, Context, Result};
+use clap::{Args, CommandFactory, FromArgMatches, Parser, Subcommand};
+use p2p_share_core::at_rest::AtRestKey;
+use p2p_share_core::code::CodeFormat;
+use p2p_share_core::compression;
+use p2p_share_core::diagnostics::DoctorReport;
+use p2p_share_core::endpoint::{
+    RelayPolicy, RelayRegion, RelaySelection, RelayUrl, DEFAULT_MAX_STREAMS,
 };
-use serde::Serialize;
+use p2p_share_core::event_record::{
+    map_event, SchemaVersion, TransferEventRecord, TRANSFER_EVENT_SCHEMA_VERSION,
+};
+use p2p_share_core::events::{TerminalSink, TransferEvent, TransferEventSink};
+use p2p_share_core::failure::{self, FailureKind};
+use p2p_share_core::hash_cache::HashCache;
+use p2p_share_core::inbox::InboxPolicy;
+use p2p_share_core::options::TransferOptions;
+use p2p_share_core::output_template::OutputTemplate;
+use p2p_share_core::receiver::{
+    ChecksumMismatch, IdleExit, ListenQueue, ReceiveOptions, DEFAULT_LISTEN_BACKLOG,
+};
+use p2p_share_core::route::Route;
+use p2p_share_core::sender::{
+    AdvertiseMode, SendOptions, VerifyReport, DEFAULT_ACK_TIMEOUT, DEFAULT_PIPELINE_DEPTH,
+    DEFAULT_WAIT_FOR_TIMEOUT,
+};
+use p2p_share_core::tcp::TcpTuning;
+use p2p_share_core::ticket::{self, TicketKind};
+use tokio::sync::watch;
 
-const TRANSFER_EVENT_SCHEMA_VERSION: &str = "1.1.0";
+mod config;
+mod event_fd;
+mod history;
+mod prompt;
+mod reveal;
 
 /// p2p-share — simple peer-to-peer file transfer.
 ///
@@ -23,6 +51,29 @@ struct Cli {
     #[arg(long, global = true)]
     json: bool,
 
+    /// Emit --json events in an older schema version (e.g. 1.0.0), leaving
+    /// out fields and event kinds added since.
+    #[arg(
+        long,
+        global = true,
+        value_name = "VERSION",
+        default_value_t,
+        requires = "json"
+    )]
+    schema_version: SchemaVersion,
+
+    /// Write --json events to this file descriptor (e.g. 3) instead of
+    /// stdout, keeping stdout free for data and stderr for logs. Falls back
+    /// to stdout when the descriptor is not open; Unix only.
+    #[arg(long, global = true, value_name = "FD", requires = "json")]
+    event_fd: Option<i32>,
+
+    /// Read defaults for send and receive flags from this TOML file instead
+    /// of `config.toml` in the p2p-share config directory. Flags given on
+    /// the command line win.
+    #[arg(long, global = true, value_name = "PATH")]
+    config: Option<PathBuf>,
+
     #[command(subcommand)]
     command: Command,
 }
@@ -30,156 +81,680 @@ struct Cli {
 #[derive(Debug, Subcommand)]
 enum Command {
     /// Send one or more files to another device.
-    Send {
-        /// One or more file paths to send.
-        #[arg(required = true)]
-        files: Vec<PathBuf>,
-
-        /// Connect to a waiting receiver instead of waiting for one.
-        /// Use the ticket shown by `p2p-share receive --qr`.
-        #[arg(long)]
-        to: Option<String>,
-    },
+    Send(SendArgs),
 
     /// Receive a file from another device.
-    Receive {
-        /// Connection ticket (shown by the sender) or ip:port for direct LAN.
-        /// Not required when using --qr.
-        target: Option<String>,
-
-        /// Directory to save the received file in.
-        #[arg(short, long, default_value = ".")]
-        output: PathBuf,
-
-        /// Listen mode: create an endpoint, display a QR code, and wait for
-        /// a sender to connect with `p2p-share send --to <ticket>`.
-        /// Useful when the sender is a phone and typing long tickets is impractical.
-        #[arg(long)]
-        qr: bool,
-    },
+    Receive(ReceiveArgs),
+
+    /// Check relay connectivity, local addresses and ticket filtering, and
+    /// try dialing this machine through its own ticket.
+    Doctor,
+
+    /// Re-check a file against a blake3 hash, e.g. one logged by a transfer.
+    Verify(VerifyArgs),
 
     /// Print machine-readable version metadata.
     Version,
+
+    /// List the features this build supports, for frontends that hide
+    /// unsupported options.
+    Capabilities,
+
+    /// Inspect connection tickets.
+    #[command(subcommand)]
+    Ticket(TicketCommand),
+
+    /// List the transfers recorded with `--history`, oldest first.
+    History(history::Filter),
 }
 
-#[derive(Debug, Clone, Serialize)]
-struct TransferEventRecord {
-    kind: String,
-    message: Option<String>,
-    value: Option<String>,
-    schema_version: String,
-    done: Option<u64>,
-    total: Option<u64>,
-    file_name: Option<String>,
-    size_bytes: Option<u64>,
-    saved_path: Option<String>,
-    latency_ms: Option<f64>,
-    content_kind: Option<TransferContentKind>,
-    item_count: Option<u64>,
-}
-
-impl TransferEventRecord {
-    fn base(kind: impl Into<String>) -> Self {
-        Self {
-            kind: kind.into(),
-            message: None,
-            value: None,
-            schema_version: TRANSFER_EVENT_SCHEMA_VERSION.to_string(),
-            done: None,
-            total: None,
-            file_name: None,
-            size_bytes: None,
-            saved_path: None,
-            latency_ms: None,
-            content_kind: None,
-            item_count: None,
+#[derive(Debug, Subcommand)]
+enum TicketCommand {
+    /// Show a ticket's node id and addresses, and whether it connects
+    /// directly, through a relay, or both.
+    Inspect {
+        /// Ticket, in `p2psh:` or `p2pshare://` form.
+        ticket: String,
+    },
+}
+
+#[derive(Debug, Args)]
+struct SendArgs {
+    /// One or more file paths to send.
+    #[arg(required = true)]
+    files: Vec<PathBuf>,
+
+    /// Connect to a waiting receiver instead of waiting for one.
+    /// Use the ticket shown by `p2p-share receive --qr`.
+    #[arg(long)]
+    to: Option<String>,
+
+    /// One part of a --to ticket shown across several QR codes by
+    /// `receive --qr --multi-qr`. Repeat for every part, in any order.
+    #[arg(long, value_name = "PART", conflicts_with = "to")]
+    to_part: Vec<String>,
+
+    /// Connect to a receiver listening with `p2p-share receive --tcp-listen`
+    /// over plain TCP (ip:port), without relays or NAT traversal.
+    #[arg(long, value_name = "ADDR", conflicts_with_all = ["to", "to_part", "uri", "ticket_only"])]
+    tcp: Option<String>,
+
+    /// Number of chunks buffered between the read, encrypt and write
+    /// stages. 1 disables pipelining.
+    #[arg(long, default_value_t = DEFAULT_PIPELINE_DEPTH, value_parser = parse_pipeline_depth)]
+    pipeline_depth: usize,
+
+    /// Update the progress bar and emit progress events at most this
+    /// often, e.g. `250ms` or `1s` (at least 10ms). By default every chunk
+    /// is reported.
+    #[arg(long, value_name = "DURATION", value_parser = parse_progress_interval)]
+    progress_interval: Option<Duration>,
+
+    /// Do not watch for connection path changes during the transfer; only
+    /// the final path summary is printed.
+    #[arg(long)]
+    no_path_watch: bool,
+
+    /// Concurrent QUIC streams a peer may open on one connection; further
+    /// opens are refused. Each transfer uses one stream.
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = DEFAULT_MAX_STREAMS,
+        value_parser = parse_max_streams
+    )]
+    max_streams: u32,
+
+    /// Look for the --to receiver on the local network first (it must
+    /// listen with `receive --qr --lan`) and connect to it directly.
+    #[arg(long, requires = "to")]
+    lan: bool,
+
+    /// When the connection drops mid-transfer, keep retrying for this long
+    /// (e.g. `60s`) and resume where the receiver left off. Off by default.
+    #[arg(long, value_name = "DURATION", value_parser = parse_idle_duration)]
+    reconnect_window: Option<Duration>,
+
+    /// Number each chunk so the receiver stops at the first missing or
+    /// misordered one instead of at the final checksum. Ignored by
+    /// receivers that do not support it.
+    #[arg(long)]
+    sequence_chunks: bool,
+
+    /// Send a hash of every 1 MiB up front so the receiver stops at the
+    /// first corrupted segment, naming its offset, instead of at the final
+    /// checksum. Costs an extra pass over archives and disables the hash
+    /// cache. Ignored by receivers that do not support it.
+    #[arg(long)]
+    verify_chunks: bool,
+
+    /// Send only the files of a folder that a `receive --sync` receiver
+    /// does not already have unchanged (same size and blake3). Receivers
+    /// that do not sync get every file.
+    #[arg(long)]
+    sync: bool,
+
+    /// Give up with `ack_timeout` when the receiver has not accepted and
+    /// opened its file after this long, e.g. `30s` (default `5m`).
+    #[arg(long, value_name = "DURATION", value_parser = parse_idle_duration)]
+    ack_timeout: Option<Duration>,
+
+    /// Wait until N receivers have connected with the ticket, then send to
+    /// all of them at once so they finish together.
+    #[arg(
+        long,
+        value_name = "N",
+        value_parser = clap::value_parser!(u16).range(1..),
+        conflicts_with_all = ["to", "tcp", "ticket_only"]
+    )]
+    wait_for: Option<u16>,
+
+    /// With --wait-for, start without the stragglers this long after the
+    /// first receiver connected, e.g. `2m` (default `5m`).
+    #[arg(
+        long,
+        value_name = "DURATION",
+        value_parser = parse_idle_duration,
+        requires = "wait_for"
+    )]
+    wait_timeout: Option<Duration>,
+
+    /// Make the ticket expire this long after it is printed, e.g. `10m`.
+    /// The sender stops waiting then; receivers with a clock that is far
+    /// off get a warning instead of a spurious expiry.
+    #[arg(
+        long,
+        value_name = "DURATION",
+        value_parser = parse_idle_duration,
+        conflicts_with_all = ["to", "tcp"]
+    )]
+    ticket_ttl: Option<Duration>,
+
+    /// How far the clock of the device that issued an expiring ticket may
+    /// be off from this one before the ticket counts as expired, e.g. `10m`
+    /// (default `5m`).
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    clock_skew: Option<Duration>,
+
+    /// Remember file hashes in the config directory so re-sending an
+    /// unchanged file skips rehashing.
+    #[arg(long)]
+    hash_cache: bool,
+
+    /// Append the completed transfer to the history in the config
+    /// directory, listed by `p2p-share history`.
+    #[arg(long)]
+    history: bool,
+
+    /// Threads to hash a single file of 16 MiB or more on (default: every core).
+    /// `1` streams it through a single hasher.
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u16).range(1..))]
+    hash_threads: Option<u16>,
+
+    /// Compress data with zstd at this level: 1 is fastest, for a LAN,
+    /// 19 smallest, for a slow link. Receivers that cannot decompress get
+    /// the data raw; so do chunks that do not shrink.
+    #[arg(
+        long,
+        value_name = "LEVEL",
+        value_parser = clap::value_parser!(i32).range(
+            compression::MIN_LEVEL as i64..=compression::MAX_LEVEL as i64
+        )
+    )]
+    compress_level: Option<i32>,
+
+    /// Compress a folder or `--archive` send as one zstd stream at this
+    /// level before sending, which shrinks many small files further than
+    /// `--compress-level`. Receivers that cannot extract it get the plain
+    /// archive.
+    #[arg(
+        long,
+        value_name = "LEVEL",
+        value_parser = clap::value_parser!(i32).range(
+            compression::MIN_LEVEL as i64..=compression::MAX_LEVEL as i64
+        ),
+        conflicts_with_all = ["compress_level", "follow"]
+    )]
+    archive_level: Option<i32>,
+
+    /// Addresses to put in the ticket when waiting for a receiver:
+    /// full, relay-only or direct-only.
+    #[arg(
+        long,
+        value_name = "MODE",
+        default_value = "full",
+        conflicts_with = "to"
+    )]
+    advertise: AdvertiseMode,
+
+    /// Show the ticket as a p2pshare:// deep link and put the link in the
+    /// QR code, so scanning it opens the app.
+    #[arg(long, conflicts_with = "to")]
+    uri: bool,
+
+    /// When the ticket is too long for one QR code, split it across
+    /// several instead of showing its relay-only form. The receiver scans
+    /// them all, or passes each to `receive --part`.
+    #[arg(long, conflicts_with_all = ["to", "to_part", "tcp"])]
+    multi_qr: bool,
+
+    /// Print the ticket and QR code, then exit without waiting for a
+    /// receiver. The ticket stops working once the command exits.
+    #[arg(long, conflicts_with = "to")]
+    ticket_only: bool,
+
+    /// Send everything as one tar archive that the receiver extracts, even
+    /// a single file. Directories are always archived recursively.
+    #[arg(long)]
+    archive: bool,
+
+    /// Send the targets of symbolic links, including links inside
+    /// directories, instead of rejecting them.
+    #[arg(long)]
+    follow_symlinks: bool,
+
+    /// Skip files that cannot be read instead of aborting a multi-file
+    /// send; each is reported and the rest are sent.
+    #[arg(long)]
+    keep_going: bool,
+
+    /// Keep sending bytes appended to the file, like `tail -f`, until
+    /// Ctrl-C. The receiver checks a hash sent at stop time instead of one
+    /// announced up front, and must run with --accept-streaming.
+    #[arg(long, conflicts_with_all = ["archive", "ticket_only"])]
+    follow: bool,
+
+    /// How to show the verification code: hex (the default) or words,
+    /// optionally with a length such as `words:4`. Both peers show the same
+    /// code when they use the same format.
+    #[arg(long, value_name = "FORMAT", default_value_t)]
+    code_format: CodeFormat,
+
+    /// Also show the verification code as a QR code, to compare the two
+    /// screens at a glance or scan one device's code with the other.
+    #[arg(long)]
+    code_qr: bool,
+
+    #[command(flatten)]
+    relay: RelayArgs,
+
+    #[command(flatten)]
+    tcp_tuning: TcpArgs,
+}
+
+#[derive(Debug, Args)]
+struct VerifyArgs {
+    /// File to hash.
+    path: PathBuf,
+
+    /// Expected blake3 hash in hex.
+    #[arg(long, value_name = "HASH")]
+    blake3: String,
+
+    /// Threads to hash a file of 16 MiB or more on (default: every core).
+    /// `1` streams it through a single hasher.
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u16).range(1..))]
+    hash_threads: Option<u16>,
+}
+
+fn parse_pipeline_depth(value: &str) -> Result<usize, String> {
+    let depth: usize = value
+        .parse()
+        .map_err(|_| format!("invalid pipeline depth: {value}"))?;
+    if !(1..=64).contains(&depth) {
+        return Err("pipeline depth must be between 1 and 64".to_string());
+    }
+    Ok(depth)
+}
+
+fn parse_queue_size(value: &str) -> Result<usize, String> {
+    let size: usize = value
+        .parse()
+        .map_err(|_| format!("invalid count: {value}"))?;
+    if !(1..=64).contains(&size) {
+        return Err("count must be between 1 and 64".to_string());
+    }
+    Ok(size)
+}
+
+fn parse_max_streams(value: &str) -> Result<u32, String> {
+    let streams: u32 = value
+        .parse()
+        .map_err(|_| format!("invalid stream count: {value}"))?;
+    if !(1..=64).contains(&streams) {
+        return Err("stream count must be between 1 and 64".to_string());
+    }
+    Ok(streams)
+}
+
+/// Parse a duration such as `250ms`, `90s`, `10m` or `2h`; a bare number
+/// is seconds.
+fn parse_duration(value: &str) -> Result<Duration, String> {
+    let (digits, unit) = match value.find(|c: char| !c.is_ascii_digit()) {
+        Some(index) => value.split_at(index),
+        None => (value, "s"),
+    };
+    let count: u64 = digits
+        .parse()
+        .map_err(|_| format!("invalid duration: {value}"))?;
+    match unit {
+        "ms" => Ok(Duration::from_millis(count)),
+        "s" => Ok(Duration::from_secs(count)),
+        "m" => Ok(Duration::from_secs(count.saturating_mul(60))),
+        "h" => Ok(Duration::from_secs(count.saturating_mul(3600))),
+        _ => Err(format!("invalid duration: {value} (use ms, s, m or h)")),
+    }
+}
+
+fn parse_idle_duration(value: &str) -> Result<Duration, String> {
+    let duration = parse_duration(value)?;
+    if duration.is_zero() {
+        return Err("duration must be longer than zero".to_string());
+    }
+    Ok(duration)
+}
+
+/// Shortest accepted `--progress-interval`.
+const MIN_PROGRESS_INTERVAL: Duration = Duration::from_millis(10);
+
+fn parse_progress_interval(value: &str) -> Result<Duration, String> {
+    let interval = parse_duration(value)?;
+    if interval < MIN_PROGRESS_INTERVAL {
+        return Err("progress interval must be at least 10ms".to_string());
+    }
+    Ok(interval)
+}
+
+/// Home relay override shared by `send` and `receive`.
+#[derive(Debug, Args)]
+struct RelayArgs {
+    /// Only use the default relay of one region (na, eu or ap).
+    #[arg(long, value_name = "REGION", conflicts_with = "relay_url")]
+    relay_region: Option<RelayRegion>,
+
+    /// Only use this relay server (http or https URL). Repeat to list
+    /// several; the one with the lowest measured latency is used.
+    #[arg(long, value_name = "URL", value_parser = parse_relay_url)]
+    relay_url: Vec<RelayUrl>,
+
+    /// Pin the --relay-url/--relay-region relays: never fall back to the
+    /// default relays, and abort if the connection goes through any other
+    /// relay.
+    #[arg(long)]
+    strict_host: bool,
+
+    /// Warn whenever the connection goes through a relay. The data stays
+    /// end-to-end encrypted; this is for setups that must know.
+    #[arg(long, conflicts_with = "no_path_watch")]
+    warn_on_relay: bool,
+
+    /// Like --warn-on-relay, but abort when the connection is not direct
+    /// within 10 seconds of connecting or falls back to a relay later.
+    #[arg(long, conflicts_with_all = ["warn_on_relay", "no_path_watch"])]
+    no_relay: bool,
+}
+
+impl RelayArgs {
+    fn selection(&self) -> RelaySelection {
+        match (self.relay_region, self.relay_url.as_slice()) {
+            (Some(region), _) => RelaySelection::Region(region),
+            (None, []) => RelaySelection::Auto,
+            (None, [url]) => RelaySelection::Url(url.clone()),
+            (None, urls) => RelaySelection::Urls(urls.to_vec()),
         }
     }
 
-    fn status(message: impl Into<String>) -> Self {
-        let mut record = Self::base("status");
-        record.message = Some(message.into());
-        record
+    fn policy(&self) -> RelayPolicy {
+        match (self.warn_on_relay, self.no_relay) {
+            (_, true) => RelayPolicy::Deny,
+            (true, false) => RelayPolicy::Warn,
+            (false, false) => RelayPolicy::Allow,
+        }
+    }
+
+    /// Whether to pin the selected relays. Checked after `config.toml` is
+    /// applied, since the relays to pin may come from there.
+    fn strict_host(&self) -> Result<bool> {
+        if self.strict_host && self.selection() == RelaySelection::Auto {
+            bail!("--strict-host needs --relay-url or --relay-region to pin");
+        }
+        Ok(self.strict_host)
     }
+}
+
+/// Socket tuning for plain TCP transfers, shared by `send` and `receive`.
+#[derive(Debug, Args)]
+struct TcpArgs {
+    /// Leave Nagle's algorithm on for plain TCP transfers instead of
+    /// setting TCP_NODELAY.
+    #[arg(long)]
+    tcp_nagle: bool,
 
-    fn error(code: impl Into<String>, message: impl Into<String>) -> Self {
-        let mut record = Self::base("error");
-        record.message = Some(message.into());
-        record.value = Some(code.into());
-        record
+    /// Socket send buffer size for plain TCP transfers, in bytes.
+    #[arg(long, value_name = "BYTES")]
+    tcp_send_buffer: Option<u32>,
+
+    /// Socket receive buffer size for plain TCP transfers, in bytes.
+    #[arg(long, value_name = "BYTES")]
+    tcp_recv_buffer: Option<u32>,
+}
+
+impl TcpArgs {
+    fn tuning(&self) -> TcpTuning {
+        TcpTuning {
+            nodelay: !self.tcp_nagle,
+            send_buffer: self.tcp_send_buffer,
+            recv_buffer: self.tcp_recv_buffer,
+        }
     }
 }
 
-#[derive(Default)]
+fn parse_relay_url(value: &str) -> Result<RelayUrl, String> {
+    p2p_share_core::endpoint::parse_relay_url(value).map_err(|err| format!("{:#}", err))
+}
+
+#[derive(Debug, Args)]
+struct ReceiveArgs {
+    /// Connection ticket (shown by the sender) or ip:port for direct LAN.
+    /// Not required when using --qr.
+    target: Option<String>,
+
+    /// One part of a ticket shown across several QR codes by
+    /// `send --multi-qr`. Repeat for every part, in any order.
+    #[arg(long, value_name = "PART", conflicts_with_all = ["target", "qr"])]
+    part: Vec<String>,
+
+    /// Directory to save the received file in.
+    #[arg(short, long, default_value = ".")]
+    output: PathBuf,
+
+    /// Listen mode: create an endpoint, display a QR code, and wait for
+    /// a sender to connect with `p2p-share send --to <ticket>`.
+    /// Useful when the sender is a phone and typing long tickets is impractical.
+    #[arg(long)]
+    qr: bool,
+
+    /// Listen for a sender on a plain TCP port instead of iroh, e.g. `:9000`.
+    /// The sender connects with `p2p-share send --tcp <ip>:9000`.
+    #[arg(long, value_name = "ADDR", conflicts_with_all = ["qr", "target", "part"])]
+    tcp_listen: Option<String>,
+
+    /// JSON inbox policy with accept/reject rules for listen mode
+    /// (peer, extension, size, target subfolder).
+    #[arg(long, requires = "qr")]
+    inbox: Option<PathBuf>,
+
+    /// Show the listen ticket as a p2pshare:// deep link and put the link in
+    /// the QR code, so scanning it opens the app.
+    #[arg(long, requires = "qr")]
+    uri: bool,
+
+    /// When the listen ticket is too long for one QR code, split it across
+    /// several instead of showing its relay-only form. The sender scans
+    /// them all, or passes each to `send --to-part`.
+    #[arg(long, requires = "qr")]
+    multi_qr: bool,
+
+    /// Keep listening after the first transfer and receive from every
+    /// sender that connects, until interrupted.
+    #[arg(long, requires = "qr")]
+    keep_listening: bool,
+
+    /// Transfers received at the same time with --keep-listening.
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 1,
+        requires = "keep_listening",
+        value_parser = parse_queue_size
+    )]
+    max_concurrent: usize,
+
+    /// Senders allowed to wait for a free slot with --keep-listening;
+    /// more are turned away as busy.
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = DEFAULT_LISTEN_BACKLOG,
+        requires = "keep_listening",
+        value_parser = parse_queue_size
+    )]
+    backlog: usize,
+
+    /// Stop listening when no sender connects for this long, e.g. `90s`,
+    /// `10m` or `2h`; with --keep-listening the timer restarts after each
+    /// transfer. Exits with status 3.
+    #[arg(long, value_name = "DURATION", requires = "qr", value_parser = parse_idle_duration)]
+    idle_exit: Option<Duration>,
+
+    /// Update the progress bar and emit progress events at most this
+    /// often, e.g. `250ms` or `1s` (at least 10ms). By default every chunk
+    /// is reported.
+    #[arg(long, value_name = "DURATION", value_parser = parse_progress_interval)]
+    progress_interval: Option<Duration>,
+
+    /// Do not watch for connection path changes during the transfer; only
+    /// the final path summary is printed.
+    #[arg(long)]
+    no_path_watch: bool,
+
+    /// Concurrent QUIC streams a peer may open on one connection; further
+    /// opens are refused. Each transfer uses one stream.
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = DEFAULT_MAX_STREAMS,
+        value_parser = parse_max_streams
+    )]
+    max_streams: u32,
+
+    /// Keep the `.part` file encrypted on disk until the transfer is
+    /// verified. The passphrase is read from P2P_SHARE_AT_REST_PASSPHRASE,
+    /// or asked for on the terminal.
+    #[arg(long)]
+    encrypt_at_rest: bool,
+
+    /// Answer LAN probes from `send --to <ticket> --lan`, so senders on the
+    /// same network connect directly (UDP port 47820).
+    #[arg(long, requires = "qr")]
+    lan: bool,
+
+    /// When the connection drops mid-transfer, keep the `.part` file and
+    /// wait this long (e.g. `60s`) for the sender to come back and resume.
+    /// Off by default.
+    #[arg(long, value_name = "DURATION", value_parser = parse_idle_duration)]
+    reconnect_window: Option<Duration>,
+
+    /// How far the clock of the device that issued an expiring ticket may
+    /// be off from this one before the ticket counts as expired, e.g. `10m`
+    /// (default `5m`).
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    clock_skew: Option<Duration>,
+
+    /// Name saved files from a pattern, e.g. `{date}_{sender}_{name}`.
+    /// Placeholders: {name}, {stem}, {ext}, {date}, {time} (UTC), {sender},
+    /// {size}; write `{{` or `}}` for a literal brace.
+    #[arg(long, value_name = "TEMPLATE")]
+    output_template: Option<OutputTemplate>,
+
+    /// Save single files of one type elsewhere, e.g. `jpg=~/Pictures` or
+    /// `image/*=~/Pictures`. Repeatable; the first match wins and other
+    /// files go to --output.
+    #[arg(long, value_name = "PATTERN=DIR")]
+    route: Vec<Route>,
+
+    /// Skip files of a multi-file transfer that cannot be written instead
+    /// of discarding the whole batch; each is reported.
+    #[arg(long)]
+    keep_going: bool,
+
+    /// Write the verified hash to `<file>.blake3` next to the received
+    /// file, in the format `b3sum --check` reads. Single files only.
+    #[arg(long)]
+    write_checksum: bool,
+
+    /// Skip a file when the output directory already holds one with the
+    /// same content, instead of saving another numbered copy. Single
+    /// files only.
+    #[arg(long)]
+    dedup: bool,
+
+    /// Let a `send --sync` sender skip the files the output directory
+    /// already has, and merge its folder into the output directory,
+    /// replacing changed files, instead of saving a new folder.
+    #[arg(long)]
+    sync: bool,
+
+    /// Ask the sender for the first KIB kibibytes of a single file before
+    /// accepting it, reported as a `preview` event (at most 1024).
+    #[arg(long, value_name = "KIB", value_parser = clap::value_parser!(u64).range(1..=1024))]
+    preview: Option<u64>,
+
+    /// Unpack a received .tar, .tar.zst or .zip file into the output folder
+    /// once its checksum is verified, numbering files whose names are
+    /// taken. An archive that fails to extract is kept.
+    #[arg(long)]
+    extract: bool,
+
+    /// Accept live files from `send --follow`, whose size is unknown until
+    /// the sender stops. Inbox size limits apply to the bytes received so
+    /// far.
+    #[arg(long)]
+    accept_streaming: bool,
+
+    /// Show the received file in the system file manager when done.
+    #[arg(long)]
+    reveal: bool,
+
+    /// Append each completed transfer to the history in the config
+    /// directory, listed by `p2p-share history`.
+    #[arg(long)]
+    history: bool,
+
+    /// Accept incoming transfers without asking. Prompts are only shown in
+    /// human mode; --json always accepts.
+    #[arg(short, long)]
+    yes: bool,
+
+    /// Seconds to wait for an answer to the accept prompt before declining.
+    #[arg(
+        long,
+        value_name = "SECS",
+        default_value_t = 60,
+        conflicts_with = "yes"
+    )]
+    confirm_timeout: u64,
+
+    /// How to show the verification code: hex (the default) or words,
+    /// optionally with a length such as `words:4`. Both peers show the same
+    /// code when they use the same format.
+    #[arg(long, value_name = "FORMAT", default_value_t)]
+    code_format: CodeFormat,
+
+    /// Also show the verification code as a QR code, to compare the two
+    /// screens at a glance or scan one device's code with the other.
+    #[arg(long)]
+    code_qr: bool,
+
+    #[command(flatten)]
+    relay: RelayArgs,
+
+    #[command(flatten)]
+    tcp_tuning: TcpArgs,
+}
+
 struct StdoutJsonSink {
     write_lock: Mutex<()>,
+    schema: SchemaVersion,
+}
+
+impl StdoutJsonSink {
+    fn new(schema: SchemaVersion) -> Self {
+        Self {
+            write_lock: Mutex::new(()),
+            schema,
+        }
+    }
 }
 
 impl TransferEventSink for StdoutJsonSink {
     fn on_event(&self, event: TransferEvent) {
         let _guard = self.write_lock.lock();
-        emit_json_line(&map_event(event));
-    }
-}
-
-fn emit_json_line(event: &TransferEventRecord) {
-    if let Ok(json) = serde_json::to_string(event) {
-        let mut out = io::stdout().lock();
-        let _ = writeln!(out, "{json}");
-        let _ = out.flush();
-    }
-}
-
-fn map_event(event: TransferEvent) -> TransferEventRecord {
-    match event {
-        TransferEvent::Status(message) => TransferEventRecord::status(message),
-        TransferEvent::Ticket(ticket) => TransferEventRecord {
-            value: Some(ticket),
-            ..TransferEventRecord::base("ticket")
-        },
-        TransferEvent::QrPayload(payload) => TransferEventRecord {
-            value: Some(payload),
-            ..TransferEventRecord::base("qr_payload")
-        },
-        TransferEvent::HandshakeCode(code) => TransferEventRecord {
-            value: Some(code),
-            ..TransferEventRecord::base("handshake_code")
-        },
-        TransferEvent::Progress { done, total } => TransferEventRecord {
-            done: Some(done),
-            total: Some(total),
-            ..TransferEventRecord::base("progress")
-        },
-        TransferEvent::ConnectionPath { kind, latency_ms } => {
-            let (value, message) = match kind {
-                ConnectionPathKind::Direct(addr) => (Some("direct".to_string()), Some(addr)),
-                ConnectionPathKind::Relay(url) => (Some("relay".to_string()), Some(url)),
-                ConnectionPathKind::Mixed {
-                    udp_addr,
-                    relay_url,
-                } => (
-                    Some("mixed".to_string()),
-                    Some(format!("udp: {udp_addr}, relay: {relay_url}")),
-                ),
-                ConnectionPathKind::None => (Some("none".to_string()), None),
-            };
+        emit_json_line(&map_event(event), self.schema);
+    }
+}
 
-            TransferEventRecord {
-                message,
-                value,
-                latency_ms,
-                ..TransferEventRecord::base("connection_path")
-            }
+fn emit_json_line(event: &TransferEventRecord, schema: SchemaVersion) {
+    let json = if schema == SchemaVersion::CURRENT {
+        serde_json::to_string(event)
+    } else {
+        match event.to_versioned_json(schema) {
+            Some(value) => serde_json::to_string(&value),
+            None => return,
         }
-        TransferEvent::Completed(result) => TransferEventRecord {
-            file_name: Some(result.file_name),
-            size_bytes: Some(result.size_bytes),
-            saved_path: result.saved_path.map(|p| p.display().to_string()),
-            content_kind: Some(result.content_kind),
-            item_count: Some(result.item_count),
-            ..TransferEventRecord::base("completed")
-        },
-        TransferEvent::Error { code, message } => TransferEventRecord::error(code, message),
+    };
+    if let Ok(json) = json {
+        event_fd::write_event_line(&json);
     }
 }
 
@@ -194,6 +769,217 @@ fn emit_version_json() -> Result<()> {
     Ok(())
 }
 
+fn emit_capabilities_json() -> Result<()> {
+    let payload = p2p_share_core::capabilities::capabilities();
+    let mut out = io::stdout().lock();
+    writeln!(out, "{}", serde_json::to_string(&payload)?)?;
+    out.flush()?;
+    Ok(())
+}
+
+fn print_capabilities() {
+    let caps = p2p_share_core::capabilities::capabilities();
+    let flag = |supported: bool| if supported { "yes" } else { "no" };
+    let protocols: Vec<String> = caps
+        .protocol_versions
+        .iter()
+        .map(|version| version.to_string())
+        .collect();
+    println!("version:          {}", caps.version);
+    println!("event schema:     {}", caps.schema_version);
+    println!("protocols:        {}", protocols.join(", "));
+    println!("multi-file:       {}", flag(caps.multi_file));
+    println!("archive:          {}", flag(caps.archive));
+    println!("keep-going:       {}", flag(caps.keep_going));
+    println!("follow:           {}", flag(caps.follow));
+    println!("tcp:              {}", flag(caps.tcp));
+    println!("keep-listening:   {}", flag(caps.keep_listening));
+    println!("checksum sidecar: {}", flag(caps.checksum_sidecar));
+    println!("dedup:            {}", flag(caps.dedup));
+    println!("code formats:     {}", caps.code_formats.join(", "));
+    println!("resume:           {}", flag(caps.resume));
+    println!("compression:      {}", flag(caps.compression));
+    println!("zstd archive:     {}", flag(caps.zstd_archive));
+    println!("sequence numbers: {}", flag(caps.sequence_numbers));
+    println!("verify chunks:    {}", flag(caps.verify_chunks));
+    println!("sync:             {}", flag(caps.sync));
+    println!("preview:          {}", flag(caps.preview));
+    println!("extract:          {}", caps.extract.join(", "));
+}
+
+fn emit_ticket_json(command: &TicketCommand) -> Result<()> {
+    let TicketCommand::Inspect { ticket } = command;
+    let mut payload = serde_json::to_value(ticket::inspect(ticket)?)?;
+    payload["schema_version"] = TRANSFER_EVENT_SCHEMA_VERSION.into();
+    let mut out = io::stdout().lock();
+    writeln!(out, "{}", serde_json::to_string(&payload)?)?;
+    out.flush()?;
+    Ok(())
+}
+
+/// Recorded transfers `filter` lets through; none without a config
+/// directory.
+fn history_entries(filter: &history::Filter) -> Result<Vec<history::Entry>> {
+    let entries = match history::default_path() {
+        Some(path) => history::load(&path)?,
+        None => Vec::new(),
+    };
+    Ok(filter.apply(entries))
+}
+
+fn emit_history_json(filter: &history::Filter) -> Result<()> {
+    let payload = serde_json::json!({
+        "schema_version": TRANSFER_EVENT_SCHEMA_VERSION,
+        "transfers": history_entries(filter)?,
+    });
+    let mut out = io::stdout().lock();
+    writeln!(out, "{}", serde_json::to_string(&payload)?)?;
+    out.flush()?;
+    Ok(())
+}
+
+fn print_history(filter: &history::Filter) -> Result<()> {
+    let entries = history_entries(filter)?;
+    if entries.is_empty() {
+        eprintln!("No transfers recorded. Pass --history to send or receive to keep a log.");
+    }
+    for entry in &entries {
+        println!("{}", history::describe(entry));
+    }
+    Ok(())
+}
+
+fn print_ticket(command: &TicketCommand) -> Result<()> {
+    let TicketCommand::Inspect { ticket } = command;
+    let info = ticket::inspect(ticket)?;
+    println!("Node id: {}", info.node_id);
+    println!("Relay: {}", info.relay_url.as_deref().unwrap_or("none"));
+    println!("Direct addresses:");
+    if info.direct_addresses.is_empty() {
+        println!("  (none)");
+    }
+    for addr in &info.direct_addresses {
+        println!("  {}", addr);
+    }
+    let transport = match info.kind {
+        TicketKind::DirectOnly => "direct only (fails if the devices cannot reach each other)",
+        TicketKind::RelayOnly => "relay only (may be slow)",
+        TicketKind::Mixed => "direct and relay",
+    };
+    println!("Transport: {}", transport);
+    Ok(())
+}
+
+async fn emit_doctor_json() -> Result<()> {
+    let report = p2p_share_core::diagnostics::run_doctor().await?;
+    let mut payload = serde_json::to_value(&report)?;
+    payload["schema_version"] = TRANSFER_EVENT_SCHEMA_VERSION.into();
+    let mut out = io::stdout().lock();
+    writeln!(out, "{}", serde_json::to_string(&payload)?)?;
+    out.flush()?;
+    Ok(())
+}
+
+async fn emit_verify_json(args: &VerifyArgs) -> Result<()> {
+    let report = p2p_share_core::sender::verify_file(
+        &args.path,
+        &args.blake3,
+        args.hash_threads.map(usize::from),
+    )
+    .await?;
+    let mut payload = serde_json::to_value(&report)?;
+    payload["schema_version"] = TRANSFER_EVENT_SCHEMA_VERSION.into();
+    let mut out = io::stdout().lock();
+    writeln!(out, "{}", serde_json::to_string(&payload)?)?;
+    out.flush()?;
+    if !report.matches {
+        return Err(mismatch(report));
+    }
+    Ok(())
+}
+
+/// The error a `verify` of a file that does not match fails with.
+fn mismatch(report: VerifyReport) -> anyhow::Error {
+    anyhow::Error::new(ChecksumMismatch {
+        expected: report.expected,
+        actual: report.actual,
+    })
+    .context(report.path)
+}
+
+fn print_doctor_report(report: &DoctorReport) {
+    println!("Node id: {}", report.node_id);
+    match &report.relay_url {
+        Some(url) => println!("Relay: connected ({})", url),
+        None => println!("Relay: not connected (only direct/LAN connections will work)"),
+    }
+
+    println!("Local addresses:");
+    if report.addresses.is_empty() {
+        println!("  (none discovered)");
+    }
+    for addr in &report.addresses {
+        let verdict = if addr.advertised {
+            "advertised"
+        } else {
+            "filtered from ticket"
+        };
+        println!("  {} [{}] - {}", addr.addr, addr.origin, verdict);
+    }
+    println!("Public addresses:");
+    if report.public_addresses.is_empty() {
+        println!("  (none observed; the relay may be unreachable)");
+    }
+    for addr in &report.public_addresses {
+        println!("  {}", addr);
+    }
+    println!("Ticket length: {} chars", report.ticket_len);
+
+    let dial = &report.self_dial;
+    if dial.ok {
+        let path = dial.connection_path.as_deref().unwrap_or("unknown");
+        let latency = dial
+            .latency_ms
+            .map(|ms| format!("{:.1}ms", ms))
+            .unwrap_or_else(|| "unknown".to_string());
+        println!("Self-dial: ok via {}, latency {}", path, latency);
+    } else {
+        println!(
+            "Self-dial: failed ({})",
+            dial.error.as_deref().unwrap_or("unknown error")
+        );
+    }
+}
+
+/// Environment variable holding the `--encrypt-at-rest` passphrase.
+const AT_REST_PASSPHRASE_ENV: &str = "P2P_SHARE_AT_REST_PASSPHRASE";
+
+fn at_rest_key() -> Result<AtRestKey> {
+    let passphrase = match std::env::var(AT_REST_PASSPHRASE_ENV) {
+        Ok(passphrase) => passphrase,
+        Err(_) => prompt::read_passphrase("Passphrase for the encrypted .part file: ")
+            .with_context(|| {
+                format!(
+                    "cannot ask for the --encrypt-at-rest passphrase; set {}",
+                    AT_REST_PASSPHRASE_ENV
+                )
+            })?,
+    };
+    if passphrase.is_empty() {
+        bail!("the --encrypt-at-rest passphrase is empty");
+    }
+    AtRestKey::derive(&passphrase)
+}
+
+/// The ticket put back together from the parts given with `--part` or
+/// `--to-part`, or `None` when there are none.
+fn joined_ticket(parts: &[String]) -> Result<Option<String>> {
+    if parts.is_empty() {
+        return Ok(None);
+    }
+    ticket::join(parts).map(Some)
+}
+
 fn missing_target_error() -> anyhow::Error {
     anyhow!(
         "either provide a <TARGET> ticket/address, or use --qr to listen.\n\n\
@@ -201,153 +987,371 @@ fn missing_target_error() -> anyhow::Error {
     )
 }
 
+/// Where `--hash-cache` keeps its hashes. Without a config directory the
+/// flag could not do anything, so that is an error rather than a no-op.
+fn hash_cache_path(enabled: bool) -> Result<Option<PathBuf>> {
+    if !enabled {
+        return Ok(None);
+    }
+    HashCache::default_path().map(Some).ok_or_else(|| {
+        anyhow!("--hash-cache needs a config directory, but none could be determined from the environment")
+    })
+}
+
+async fn run_send(args: SendArgs, sink: Option<Arc<dyn TransferEventSink>>) -> Result<()> {
+    let sink = history::record(sink, args.history, history::Direction::Send);
+    let options = SendOptions {
+        transfer: TransferOptions::builder()
+            .relay(args.relay.selection())
+            .strict_host(args.relay.strict_host()?)
+            .relay_policy(args.relay.policy())
+            .code_format(args.code_format)
+            .code_qr(args.code_qr)
+            .tcp(args.tcp_tuning.tuning())
+            .progress_interval(args.progress_interval)
+            .no_path_watch(args.no_path_watch)
+            .max_streams(args.max_streams)
+            .lan(args.lan)
+            .reconnect_window(args.reconnect_window)
+            .clock_skew(args.clock_skew.unwrap_or(ticket::DEFAULT_CLOCK_SKEW))
+            .build(),
+        pipeline_depth: args.pipeline_depth,
+        advertise: args.advertise,
+        hash_cache: hash_cache_path(args.hash_cache)?,
+        ticket_uri: args.uri,
+        multi_qr: args.multi_qr,
+        archive: args.archive,
+        follow_symlinks: args.follow_symlinks,
+        keep_going: args.keep_going,
+        follow: args.follow.then(follow_until_ctrl_c),
+        sequence_chunks: args.sequence_chunks,
+        verify_chunks: args.verify_chunks,
+        sync: args.sync,
+        ack_timeout: Some(args.ack_timeout.unwrap_or(DEFAULT_ACK_TIMEOUT)),
+        hash_threads: args.hash_threads.map(usize::from),
+        wait_for: args.wait_for.map(usize::from),
+        wait_for_timeout: Some(args.wait_timeout.unwrap_or(DEFAULT_WAIT_FOR_TIMEOUT)),
+        compress_level: args.compress_level,
+        archive_level: args.archive_level,
+        ticket_ttl: args.ticket_ttl,
+    };
+    if args.ticket_only {
+        for file in &args.files {
+            if !file.exists() {
+                bail!("{} does not exist", file.display());
+            }
+        }
+        p2p_share_core::sender::issue_ticket(sink, &options).await?;
+        return Ok(());
+    }
+    if let Some(addr) = args.tcp.as_deref() {
+        return p2p_share_core::sender::run_tcp_paths_with_options(
+            &args.files,
+            addr,
+            sink,
+            &options,
+        )
+        .await;
+    }
+    let joined = joined_ticket(&args.to_part)?;
+    match joined.as_deref().or(args.to.as_deref()) {
+        None => p2p_share_core::sender::run_paths_with_options(&args.files, sink, &options).await,
+        Some(ticket) => {
+            p2p_share_core::sender::run_reverse_paths_with_options(
+                &args.files,
+                ticket,
+                sink,
+                &options,
+            )
+            .await
+        }
+    }
+}
+
+/// Stop signal for `send --follow`: the first Ctrl-C finishes the transfer,
+/// a second one exits immediately.
+fn follow_until_ctrl_c() -> watch::Receiver<bool> {
+    let (stop_tx, stop) = watch::channel(false);
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_err() {
+            return;
+        }
+        eprintln!("\nStopping; finishing the transfer (Ctrl-C again to abort).");
+        let _ = stop_tx.send(true);
+        if tokio::signal::ctrl_c().await.is_ok() {
+            std::process::exit(130);
+        }
+    });
+    stop
+}
+
+fn receive_options(args: &ReceiveArgs, interactive: bool) -> Result<ReceiveOptions> {
+    let inbox = args.inbox.as_deref().map(InboxPolicy::load).transpose()?;
+    let confirm = (interactive && !args.yes)
+        .then(|| prompt::terminal_confirm(Duration::from_secs(args.confirm_timeout)));
+    Ok(ReceiveOptions {
+        transfer: TransferOptions::builder()
+            .relay(args.relay.selection())
+            .strict_host(args.relay.strict_host()?)
+            .relay_policy(args.relay.policy())
+            .code_format(args.code_format)
+            .code_qr(args.code_qr)
+            .tcp(args.tcp_tuning.tuning())
+            .progress_interval(args.progress_interval)
+            .no_path_watch(args.no_path_watch)
+            .max_streams(args.max_streams)
+            .lan(args.lan)
+            .reconnect_window(args.reconnect_window)
+            .clock_skew(args.clock_skew.unwrap_or(ticket::DEFAULT_CLOCK_SKEW))
+            .build(),
+        inbox,
+        confirm,
+        ticket_uri: args.uri,
+        multi_qr: args.multi_qr,
+        max_name_collisions: None,
+        disk_write_retries: None,
+        output_template: args.output_template.clone(),
+        keep_going: args.keep_going,
+        write_checksum: args.write_checksum,
+        routes: args.route.clone(),
+        cancel: None,
+        keep_partial: false,
+        dedup: args.dedup,
+        idle_exit: args.idle_exit,
+        encrypt_at_rest: args.encrypt_at_rest.then(at_rest_key).transpose()?,
+        sync: args.sync,
+        preview: args.preview.map(|kib| kib * 1024),
+        rename: None,
+        extract: args.extract,
+        accept_streaming: args.accept_streaming,
+    })
+}
+
+async fn run_receive(args: ReceiveArgs, sink: Option<Arc<dyn TransferEventSink>>) -> Result<()> {
+    let options = receive_options(&args, sink.is_none())?;
+    let sink = history::record(sink, args.history, history::Direction::Receive);
+    let joined = joined_ticket(&args.part)?;
+    let target = joined.as_deref().or(args.target.as_deref());
+    let completed = match (args.tcp_listen.as_deref(), args.qr, target) {
+        (Some(listen_addr), _, _) => {
+            p2p_share_core::receiver::run_tcp_listen_with_options(
+                listen_addr,
+                &args.output,
+                sink,
+                &options,
+            )
+            .await?
+        }
+        (None, true, _) if args.keep_listening => {
+            let queue = ListenQueue {
+                workers: args.max_concurrent,
+                backlog: args.backlog,
+            };
+            return p2p_share_core::receiver::run_listen_queue_with_options(
+                &args.output,
+                sink,
+                &options,
+                queue,
+            )
+            .await;
+        }
+        (None, true, _) => {
+            p2p_share_core::receiver::run_listen_with_options(&args.output, sink, &options).await?
+        }
+        (None, false, Some(target)) => {
+            p2p_share_core::receiver::run_with_options(target, &args.output, sink, &options).await?
+        }
+        (None, false, None) => return Err(missing_target_error()),
+    };
+
+    if args.reveal {
+        if let Some(path) = &completed.saved_path {
+            reveal::reveal_in_file_manager(path);
+        }
+    }
+    Ok(())
+}
+
 async fn run_human(command: Command) -> Result<()> {
     match command {
         Command::Version => {
             println!("{}", env!("CARGO_PKG_VERSION"));
             Ok(())
         }
-        Command::Send { files, to: None } => p2p_share_core::sender::run_paths(&files).await,
-        Command::Send {
-            files,
-            to: Some(ticket),
-        } => p2p_share_core::sender::run_reverse_paths(&files, &ticket).await,
-        Command::Receive {
-            target: _,
-            output,
-            qr: true,
-        } => p2p_share_core::receiver::run_listen(&output).await,
-        Command::Receive {
-            target: Some(target),
-            output,
-            qr: false,
-        } => p2p_share_core::receiver::run(&target, &output).await,
-        Command::Receive {
-            target: None,
-            output: _,
-            qr: false,
-        } => Err(missing_target_error()),
-    }
-}
-
-async fn run_json(command: Command) -> Result<()> {
+        Command::Capabilities => {
+            print_capabilities();
+            Ok(())
+        }
+        Command::Ticket(command) => print_ticket(&command),
+        Command::History(filter) => print_history(&filter),
+        Command::Send(args) => run_send(args, None).await,
+        Command::Receive(args) => run_receive(args, None).await,
+        Command::Doctor => {
+            eprintln!("Running connectivity diagnostics...");
+            let report = p2p_share_core::diagnostics::run_doctor().await?;
+            print_doctor_report(&report);
+            Ok(())
+        }
+        Command::Verify(args) => {
+            let report = p2p_share_core::sender::verify_file(
+                &args.path,
+                &args.blake3,
+                args.hash_threads.map(usize::from),
+            )
+            .await?;
+            if !report.matches {
+                return Err(mismatch(report));
+            }
+            println!("Checksum verified (blake3): {}", report.path);
+            Ok(())
+        }
+    }
+}
+
+async fn run_json(command: Command, schema: SchemaVersion) -> Result<()> {
     if let Command::Version = &command {
         return emit_version_json();
     }
+    if let Command::Capabilities = &command {
+        return emit_capabilities_json();
+    }
+    if let Command::Ticket(command) = &command {
+        return emit_ticket_json(command);
+    }
+    if let Command::History(filter) = &command {
+        return emit_history_json(filter);
+    }
+    if let Command::Doctor = &command {
+        return emit_doctor_json().await;
+    }
+    if let Command::Verify(args) = &command {
+        return emit_verify_json(args).await;
+    }
 
-    let sink: Arc<dyn TransferEventSink> = Arc::new(StdoutJsonSink::default());
-    emit_json_line(&TransferEventRecord::status("Transfer started."));
+    let terminal = Arc::new(TerminalSink::new(Arc::new(StdoutJsonSink::new(schema))));
+    let sink: Arc<dyn TransferEventSink> = terminal.clone();
+    emit_json_line(&TransferEventRecord::status("Transfer started."), schema);
 
     let result = match command {
-        Command::Version => unreachable!("handled above"),
-        Command::Send { files, to: None } => {
-            p2p_share_core::sender::run_paths_with_sink(&files, Some(sink.clone())).await
-        }
-        Command::Send {
-            files,
-            to: Some(ticket),
-        } => {
-            p2p_share_core::sender::run_reverse_paths_with_sink(&files, &ticket, Some(sink.clone()))
-                .await
-        }
-        Command::Receive {
-            target: _,
-            output,
-            qr: true,
-        } => p2p_share_core::receiver::run_listen_with_sink(&output, Some(sink.clone())).await,
-        Command::Receive {
-            target: Some(target),
-            output,
-            qr: false,
-        } => p2p_share_core::receiver::run_with_sink(&target, &output, Some(sink.clone())).await,
-        Command::Receive {
-            target: None,
-            output: _,
-            qr: false,
-        } => Err(missing_target_error()),
+        Command::Version
+        | Command::Capabilities
+        | Command::Ticket(_)
+        | Command::History(_)
+        | Command::Doctor
+        | Command::Verify(_) => unreachable!("handled above"),
+        Command::Send(args) => run_send(args, Some(sink.clone())).await,
+        Command::Receive(args) => run_receive(args, Some(sink.clone())).await,
     };
 
-    if let Err(err) = &result {
-        emit_json_line(&TransferEventRecord::error(
-            "transfer_error",
-            format!("{:#}", err),
-        ));
+    match &result {
+        // Hitting --idle-exit already reported its status event.
+        Err(err) if err.is::<IdleExit>() => {}
+        result => terminal.finish(result),
     }
 
     result
 }
 
+/// Exit status for a failure no other status covers.
+const EXIT_FAILURE: i32 = 1;
+
+/// Exit status for invalid input: bad flags or config, an invalid or
+/// expired ticket, a path that cannot be sent. Matches clap's status for
+/// usage errors.
+const EXIT_USAGE: i32 = 2;
+
+/// Exit status when `receive --idle-exit` stops listening without a sender;
+/// not a failure, but distinct from a completed transfer.
+const EXIT_IDLE: i32 = 3;
+
+const EXIT_NETWORK: i32 = 4;
+const EXIT_INTEGRITY: i32 = 5;
+const EXIT_REJECTED: i32 = 6;
+const EXIT_TIMEOUT: i32 = 7;
+
+/// Exit status for `err`, listed in the README.
+fn exit_code(err: &anyhow::Error) -> i32 {
+    if err.is::<IdleExit>() {
+        return EXIT_IDLE;
+    }
+    match failure::classify(err) {
+        FailureKind::Usage => EXIT_USAGE,
+        FailureKind::Network => EXIT_NETWORK,
+        FailureKind::Integrity => EXIT_INTEGRITY,
+        FailureKind::Rejected => EXIT_REJECTED,
+        FailureKind::Timeout => EXIT_TIMEOUT,
+        FailureKind::Other => EXIT_FAILURE,
+    }
+}
+
 #[tokio::main]
 async fn main() {
-    let cli = Cli::parse();
+    let matches = Cli::command().get_matches();
+    let mut cli = Cli::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+    match config::Config::load(cli.config.as_deref()) {
+        Ok(config) => config.apply(&mut cli.command, &matches),
+        Err(err) => {
+            eprintln!("Error: {:#}", err);
+            std::process::exit(EXIT_USAGE);
+        }
+    }
+    if let Some(fd) = cli.event_fd {
+        event_fd::redirect_events(fd);
+    }
 
     let result = if cli.json {
-        run_json(cli.command).await
+        run_json(cli.command, cli.schema_version).await
     } else {
         run_human(cli.command).await
     };
 
     if let Err(e) = result {
-        eprintln!("Error: {:#}", e);
-        std::process::exit(1);
+        let code = exit_code(&e);
+        if code != EXIT_IDLE {
+            eprintln!("Error: {:#}", e);
+        }
+        std::process::exit(code);
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{map_event, missing_target_error, Cli, Command, TRANSFER_EVENT_SCHEMA_VERSION};
+    use super::{exit_code, joined_ticket, missing_target_error, Cli, Command, TicketCommand};
     use clap::Parser;
-    use p2p_share_core::events::{
-        ConnectionPathKind, TransferCompleted, TransferContentKind, TransferEvent,
-    };
+    use p2p_share_core::code::CodeFormat;
+    use p2p_share_core::endpoint::{RelayPolicy, RelayRegion, RelaySelection};
+    use p2p_share_core::event_record::SchemaVersion;
+    use p2p_share_core::receiver::{IdleExit, DEFAULT_LISTEN_BACKLOG};
+    use p2p_share_core::sender::Rejected;
+    use p2p_share_core::tcp::TcpTuning;
+    use p2p_share_core::ticket;
     use std::path::PathBuf;
+    use std::time::Duration;
 
     #[test]
-    fn map_event_progress_keeps_counts() {
-        let record = map_event(TransferEvent::Progress {
-            done: 16,
-            total: 64,
-        });
-        assert_eq!(record.kind, "progress");
-        assert_eq!(record.schema_version, TRANSFER_EVENT_SCHEMA_VERSION);
-        assert_eq!(record.done, Some(16));
-        assert_eq!(record.total, Some(64));
-        assert!(record.message.is_none());
-    }
-
-    #[test]
-    fn map_event_connection_path_mixed_formats_message() {
-        let record = map_event(TransferEvent::ConnectionPath {
-            kind: ConnectionPathKind::Mixed {
-                udp_addr: "192.168.1.2:4000".to_string(),
-                relay_url: "https://relay.example".to_string(),
-            },
-            latency_ms: Some(21.5),
-        });
-        assert_eq!(record.kind, "connection_path");
-        assert_eq!(record.value.as_deref(), Some("mixed"));
-        assert_eq!(
-            record.message.as_deref(),
-            Some("udp: 192.168.1.2:4000, relay: https://relay.example")
+    fn schema_version_flag_requires_json() {
+        let cli = Cli::try_parse_from([
+            "p2p-share",
+            "--json",
+            "--schema-version",
+            "1.0.0",
+            "version",
+        ])
+        .expect("parse");
+        assert_eq!(cli.schema_version, SchemaVersion::V1_0);
+        let cli = Cli::try_parse_from(["p2p-share", "--json", "version"]).expect("parse");
+        assert_eq!(cli.schema_version, SchemaVersion::CURRENT);
+        assert!(
+            Cli::try_parse_from(["p2p-share", "--schema-version", "1.0.0", "version"]).is_err()
         );
-        assert_eq!(record.latency_ms, Some(21.5));
-    }
-
-    #[test]
-    fn map_event_completed_includes_saved_path() {
-        let record = map_event(TransferEvent::Completed(TransferCompleted {
-            file_name: "demo.txt".to_string(),
-            size_bytes: 42,
-            saved_path: Some(PathBuf::from("/tmp/demo.txt")),
-            content_kind: TransferContentKind::Bundle,
-            item_count: 3,
-        }));
-        assert_eq!(record.kind, "completed");
-        assert_eq!(record.file_name.as_deref(), Some("demo.txt"));
-        assert_eq!(record.size_bytes, Some(42));
-        assert_eq!(record.saved_path.as_deref(), Some("/tmp/demo.txt"));
-        assert_eq!(record.content_kind, Some(TransferContentKind::Bundle));
-        assert_eq!(record.item_count, Some(3));
+        assert!(Cli::try_parse_from([
+            "p2p-share",
+            "--json",
+            "--schema-version",
+            "0.9.0",
+            "version"
+        ])
+        .is_err());
     }
 
     #[test]
@@ -358,15 +1362,415 @@ mod tests {
         assert!(msg.contains("p2p-share receive --qr"));
     }
 
+    #[test]
+    fn ticket_parts_are_joined_into_the_target() {
+        let ticket = format!("p2psh:{}", "a".repeat(1500));
+        let parts = ticket::split(&ticket, 600);
+        let cli = Cli::try_parse_from(
+            [
+                vec!["p2p-share", "receive"],
+                parts.iter().flat_map(|part| ["--part", part]).collect(),
+            ]
+            .concat(),
+        )
+        .expect("parse");
+        match cli.command {
+            Command::Receive(args) => {
+                assert_eq!(joined_ticket(&args.part).unwrap(), Some(ticket));
+            }
+            other => panic!("unexpected command: {:?}", other),
+        }
+        assert_eq!(joined_ticket(&[]).unwrap(), None);
+        assert!(joined_ticket(&parts[..1]).is_err());
+
+        assert!(
+            Cli::try_parse_from(["p2p-share", "receive", "p2psh:x", "--part", &parts[0]]).is_err()
+        );
+        assert!(Cli::try_parse_from(["p2p-share", "receive", "--multi-qr"]).is_err());
+        assert!(Cli::try_parse_from(["p2p-share", "send", "a.txt", "--multi-qr"]).is_ok());
+    }
+
     #[test]
     fn send_command_accepts_multiple_files() {
         let cli = Cli::try_parse_from(["p2p-share", "send", "a.txt", "b.txt"]).expect("parse");
         match cli.command {
-            Command::Send { files, to } => {
-                assert_eq!(files, vec![PathBuf::from("a.txt"), PathBuf::from("b.txt")]);
-                assert!(to.is_none());
+            Command::Send(args) => {
+                assert_eq!(
+                    args.files,
+                    vec![PathBuf::from("a.txt"), PathBuf::from("b.txt")]
+                );
+                assert!(args.to.is_none());
+            }
+            other => panic!("unexpected command: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn strict_host_needs_relays_to_pin() {
+        let relay = |args: &[&str]| {
+            let cli = Cli::try_parse_from([&["p2p-share", "send", "a.txt"], args].concat())
+                .expect("parse");
+            match cli.command {
+                Command::Send(args) => args.relay,
+                other => panic!("unexpected command: {:?}", other),
+            }
+        };
+        assert!(relay(&["--strict-host"]).strict_host().is_err());
+        assert!(!relay(&["--relay-region", "eu"]).strict_host().unwrap());
+        assert!(relay(&["--relay-region", "eu", "--strict-host"])
+            .strict_host()
+            .unwrap());
+    }
+
+    #[test]
+    fn relay_flags_pick_a_relay_policy() {
+        let policy = |args: &[&str]| {
+            Cli::try_parse_from([&["p2p-share", "receive", "--qr"], args].concat()).map(|cli| {
+                match cli.command {
+                    Command::Receive(args) => args.relay.policy(),
+                    other => panic!("unexpected command: {:?}", other),
+                }
+            })
+        };
+        assert_eq!(policy(&[]).unwrap(), RelayPolicy::Allow);
+        assert_eq!(policy(&["--warn-on-relay"]).unwrap(), RelayPolicy::Warn);
+        assert_eq!(policy(&["--no-relay"]).unwrap(), RelayPolicy::Deny);
+        assert!(policy(&["--no-relay", "--warn-on-relay"]).is_err());
+        assert!(policy(&["--no-relay", "--no-path-watch"]).is_err());
+    }
+
+    #[test]
+    fn ticket_only_conflicts_with_to() {
+        let cli =
+            Cli::try_parse_from(["p2p-share", "send", "a.txt", "--ticket-only"]).expect("parse");
+        match cli.command {
+            Command::Send(args) => assert!(args.ticket_only),
+            other => panic!("unexpected command: {:?}", other),
+        }
+        assert!(Cli::try_parse_from([
+            "p2p-share",
+            "send",
+            "a.txt",
+            "--ticket-only",
+            "--to",
+            "p2psh:abc"
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn uri_flag_applies_to_published_tickets_only() {
+        let cli = Cli::try_parse_from(["p2p-share", "send", "a.txt", "--uri"]).expect("parse");
+        match cli.command {
+            Command::Send(args) => assert!(args.uri),
+            other => panic!("unexpected command: {:?}", other),
+        }
+        assert!(Cli::try_parse_from(["p2p-share", "send", "a.txt", "--uri", "--to", "x"]).is_err());
+        assert!(Cli::try_parse_from(["p2p-share", "receive", "p2psh:abc", "--uri"]).is_err());
+        assert!(Cli::try_parse_from(["p2p-share", "receive", "--qr", "--uri"]).is_ok());
+    }
+
+    #[test]
+    fn verify_command_requires_a_hash() {
+        let cli = Cli::try_parse_from(["p2p-share", "verify", "a.bin", "--blake3", "abc"])
+            .expect("parse");
+        match cli.command {
+            Command::Verify(args) => {
+                assert_eq!(args.path, PathBuf::from("a.bin"));
+                assert_eq!(args.blake3, "abc");
+            }
+            other => panic!("unexpected command: {:?}", other),
+        }
+        assert!(Cli::try_parse_from(["p2p-share", "verify", "a.bin"]).is_err());
+    }
+
+    #[test]
+    fn ticket_inspect_takes_one_ticket() {
+        let cli =
+            Cli::try_parse_from(["p2p-share", "ticket", "inspect", "p2psh:abc"]).expect("parse");
+        match cli.command {
+            Command::Ticket(TicketCommand::Inspect { ticket }) => assert_eq!(ticket, "p2psh:abc"),
+            other => panic!("unexpected command: {:?}", other),
+        }
+        assert!(Cli::try_parse_from(["p2p-share", "ticket", "inspect"]).is_err());
+    }
+
+    #[test]
+    fn tcp_modes_exclude_iroh_modes() {
+        let cli =
+            Cli::try_parse_from(["p2p-share", "receive", "--tcp-listen", ":9000"]).expect("parse");
+        match cli.command {
+            Command::Receive(args) => assert_eq!(args.tcp_listen.as_deref(), Some(":9000")),
+            other => panic!("unexpected command: {:?}", other),
+        }
+        assert!(
+            Cli::try_parse_from(["p2p-share", "receive", "--tcp-listen", ":9000", "--qr"]).is_err()
+        );
+        let cli = Cli::try_parse_from(["p2p-share", "send", "a.txt", "--tcp", "10.0.0.2:9000"])
+            .expect("parse");
+        match cli.command {
+            Command::Send(args) => assert_eq!(args.tcp.as_deref(), Some("10.0.0.2:9000")),
+            other => panic!("unexpected command: {:?}", other),
+        }
+        assert!(Cli::try_parse_from([
+            "p2p-share",
+            "send",
+            "a.txt",
+            "--tcp",
+            "10.0.0.2:9000",
+            "--to",
+            "p2psh:abc"
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn event_fd_requires_json() {
+        assert!(Cli::try_parse_from(["p2p-share", "--event-fd", "3", "receive", "--qr"]).is_err());
+        let cli =
+            Cli::try_parse_from(["p2p-share", "--json", "--event-fd", "3", "receive", "--qr"])
+                .expect("parse");
+        assert_eq!(cli.event_fd, Some(3));
+    }
+
+    #[test]
+    fn idle_exit_takes_a_duration_in_listen_mode() {
+        let cli = Cli::try_parse_from(["p2p-share", "receive", "--qr", "--idle-exit", "10m"])
+            .expect("parse");
+        match cli.command {
+            Command::Receive(args) => assert_eq!(args.idle_exit, Some(Duration::from_secs(600))),
+            _ => panic!("expected receive"),
+        }
+        for value in ["90", "90s"] {
+            let cli = Cli::try_parse_from(["p2p-share", "receive", "--qr", "--idle-exit", value])
+                .expect("parse");
+            match cli.command {
+                Command::Receive(args) => assert_eq!(args.idle_exit, Some(Duration::from_secs(90))),
+                _ => panic!("expected receive"),
+            }
+        }
+        for value in ["0m", "10d", "m"] {
+            assert!(
+                Cli::try_parse_from(["p2p-share", "receive", "--qr", "--idle-exit", value])
+                    .is_err()
+            );
+        }
+        assert!(
+            Cli::try_parse_from(["p2p-share", "receive", "p2psh:abc", "--idle-exit", "1m"])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn progress_interval_has_a_floor() {
+        let cli = Cli::try_parse_from([
+            "p2p-share",
+            "send",
+            "file.txt",
+            "--progress-interval",
+            "250ms",
+        ])
+        .expect("parse");
+        match cli.command {
+            Command::Send(args) => {
+                assert_eq!(args.progress_interval, Some(Duration::from_millis(250)))
+            }
+            _ => panic!("expected send"),
+        }
+        let cli = Cli::try_parse_from(["p2p-share", "receive", "--qr", "--progress-interval", "1"])
+            .expect("parse");
+        match cli.command {
+            Command::Receive(args) => {
+                assert_eq!(args.progress_interval, Some(Duration::from_secs(1)))
+            }
+            _ => panic!("expected receive"),
+        }
+        for value in ["5ms", "0", "fast"] {
+            assert!(Cli::try_parse_from([
+                "p2p-share",
+                "receive",
+                "--qr",
+                "--progress-interval",
+                value
+            ])
+            .is_err());
+        }
+    }
+
+    #[test]
+    fn queue_limits_require_keep_listening() {
+        assert!(Cli::try_parse_from(["p2p-share", "receive", "--qr", "--backlog", "2"]).is_err());
+        assert!(
+            Cli::try_parse_from(["p2p-share", "receive", "p2psh:abc", "--keep-listening"]).is_err()
+        );
+        let cli = Cli::try_parse_from([
+            "p2p-share",
+            "receive",
+            "--qr",
+            "--keep-listening",
+            "--max-concurrent",
+            "2",
+        ])
+        .expect("parse");
+        match cli.command {
+            Command::Receive(args) => {
+                assert_eq!(args.max_concurrent, 2);
+                assert_eq!(args.backlog, DEFAULT_LISTEN_BACKLOG);
+            }
+            other => panic!("unexpected command: {:?}", other),
+        }
+        assert!(Cli::try_parse_from([
+            "p2p-share",
+            "receive",
+            "--qr",
+            "--keep-listening",
+            "--backlog",
+            "0"
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn code_format_defaults_to_hex() {
+        let cli = Cli::try_parse_from(["p2p-share", "send", "a.txt"]).expect("parse");
+        match cli.command {
+            Command::Send(args) => assert_eq!(args.code_format, CodeFormat::Hex { groups: 2 }),
+            other => panic!("unexpected command: {:?}", other),
+        }
+        let cli = Cli::try_parse_from(["p2p-share", "receive", "--qr", "--code-format", "words:4"])
+            .expect("parse");
+        match cli.command {
+            Command::Receive(args) => assert_eq!(args.code_format, CodeFormat::Words { count: 4 }),
+            other => panic!("unexpected command: {:?}", other),
+        }
+        assert!(
+            Cli::try_parse_from(["p2p-share", "send", "a.txt", "--code-format", "emoji"]).is_err()
+        );
+    }
+
+    #[test]
+    fn tcp_sockets_set_nodelay_unless_nagle_is_requested() {
+        let cli = Cli::try_parse_from(["p2p-share", "send", "--tcp", "10.0.0.2:9000", "a.txt"])
+            .expect("parse");
+        match cli.command {
+            Command::Send(args) => assert_eq!(args.tcp_tuning.tuning(), TcpTuning::default()),
+            other => panic!("unexpected command: {:?}", other),
+        }
+        let cli = Cli::try_parse_from([
+            "p2p-share",
+            "receive",
+            "--tcp-listen",
+            ":9000",
+            "--tcp-nagle",
+            "--tcp-recv-buffer",
+            "1048576",
+        ])
+        .expect("parse");
+        match cli.command {
+            Command::Receive(args) => {
+                let tuning = args.tcp_tuning.tuning();
+                assert!(!tuning.nodelay);
+                assert_eq!(tuning.recv_buffer, Some(1048576));
+                assert_eq!(tuning.send_buffer, None);
+            }
+            other => panic!("unexpected command: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn inbox_flag_requires_listen_mode() {
+        assert!(
+            Cli::try_parse_from(["p2p-share", "receive", "p2psh:abc", "--inbox", "p.json"])
+                .is_err()
+        );
+        let cli = Cli::try_parse_from(["p2p-share", "receive", "--qr", "--inbox", "p.json"])
+            .expect("parse");
+        match cli.command {
+            Command::Receive(args) => {
+                assert!(args.qr);
+                assert_eq!(args.inbox, Some(PathBuf::from("p.json")));
             }
             other => panic!("unexpected command: {:?}", other),
         }
     }
+
+    #[test]
+    fn failures_map_to_documented_exit_codes() {
+        assert_eq!(exit_code(&IdleExit.into()), 3);
+        assert_eq!(
+            exit_code(&p2p_share_core::ticket::deserialize("nonsense").unwrap_err()),
+            2
+        );
+        let rejected = Rejected {
+            reason: "declined by receiver".to_string(),
+        };
+        assert_eq!(exit_code(&rejected.into()), 6);
+        assert_eq!(exit_code(&anyhow::anyhow!("disk on fire")), 1);
+    }
+
+    #[test]
+    fn yes_flag_conflicts_with_confirm_timeout() {
+        let cli = Cli::try_parse_from(["p2p-share", "receive", "p2psh:abc", "-y"]).expect("parse");
+        match cli.command {
+            Command::Receive(args) => assert!(args.yes),
+            other => panic!("unexpected command: {:?}", other),
+        }
+        assert!(Cli::try_parse_from([
+            "p2p-share",
+            "receive",
+            "p2psh:abc",
+            "--yes",
+            "--confirm-timeout",
+            "5"
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn relay_flags_are_validated_and_exclusive() {
+        let cli = Cli::try_parse_from(["p2p-share", "send", "a.txt", "--relay-region", "eu"])
+            .expect("parse");
+        match cli.command {
+            Command::Send(args) => assert_eq!(
+                args.relay.selection(),
+                RelaySelection::Region(RelayRegion::Europe)
+            ),
+            other => panic!("unexpected command: {:?}", other),
+        }
+        assert!(
+            Cli::try_parse_from(["p2p-share", "send", "a.txt", "--relay-region", "mars"]).is_err()
+        );
+        assert!(
+            Cli::try_parse_from(["p2p-share", "receive", "--qr", "--relay-url", "ftp://x"])
+                .is_err()
+        );
+        assert!(Cli::try_parse_from([
+            "p2p-share",
+            "receive",
+            "--qr",
+            "--relay-region",
+            "na",
+            "--relay-url",
+            "https://relay.example.com",
+        ])
+        .is_err());
+
+        let cli = Cli::try_parse_from([
+            "p2p-share",
+            "receive",
+            "--qr",
+            "--relay-url",
+            "https://a.example.com",
+            "--relay-url",
+            "https://b.example.com",
+        ])
+        .expect("parse");
+        match cli.command {
+            Command::Receive(args) => assert!(matches!(
+                args.relay.selection(),
+                RelaySelection::Urls(urls) if urls.len() == 2
+            )),
+            other => panic!("unexpected command: {:?}", other),
+        }
+    }
 }
@@ -0,0 +1,175 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::sync::mpsc;
+use std::time::Duration;
+
+use p2p_share_core::events::TransferContentKind;
+use p2p_share_core::protocol::human_bytes;
+use p2p_share_core::receiver::{AcceptHandler, IncomingOffer};
+
+/// Accept handler that asks on the controlling terminal before each
+/// transfer and declines when no answer arrives within `timeout`.
+pub fn terminal_confirm(timeout: Duration) -> AcceptHandler {
+    AcceptHandler::new(move |offer| confirm_on_terminal(offer, timeout))
+}
+
+fn confirm_on_terminal(offer: &IncomingOffer, timeout: Duration) -> bool {
+    // Read from the terminal itself so piped stdin is never consumed.
+    let terminal = match open_terminal() {
+        Ok(terminal) => terminal,
+        Err(err) => {
+            eprintln!(
+                "Cannot open the terminal for confirmation ({}); declining. \
+                 Pass --yes to accept automatically.",
+                err
+            );
+            return false;
+        }
+    };
+
+    eprintln!();
+    match offer.content_kind {
+        TransferContentKind::File if offer.streaming => {
+            eprintln!("  Incoming live file: {} (size unknown)", offer.file_name)
+        }
+        TransferContentKind::File => eprintln!(
+            "  Incoming file: {} ({})",
+            offer.file_name,
+            human_bytes(offer.size_bytes)
+        ),
+        TransferContentKind::Bundle => eprintln!(
+            "  Incoming files: {} ({} files, {})",
+            offer.file_name,
+            offer.item_count,
+            human_bytes(offer.size_bytes)
+        ),
+    }
+    if !offer.streaming {
+        eprintln!("  blake3: {}", offer.blake3);
+    }
+    if let Some(peer) = &offer.peer {
+        eprintln!("  From:   {}", peer);
+    }
+    if let Some(path) = &offer.connection_path {
+        eprintln!("  Path:   {}", path);
+    }
+    if let Some(preview) = &offer.preview {
+        eprintln!("  Preview: first {}", human_bytes(preview.len() as u64));
+    }
+    eprint!(
+        "Accept this transfer? [y/N] (declines in {}s) ",
+        timeout.as_secs()
+    );
+    let _ = io::stderr().flush();
+
+    let (tx, rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        let mut answer = String::new();
+        let _ = BufReader::new(terminal).read_line(&mut answer);
+        let _ = tx.send(answer);
+    });
+
+    match rx.recv_timeout(timeout) {
+        Ok(answer) => is_yes(&answer),
+        Err(_) => {
+            eprintln!();
+            eprintln!("No answer within {}s; declining.", timeout.as_secs());
+            false
+        }
+    }
+}
+
+/// Ask for a passphrase on the controlling terminal, without echoing it
+/// where the platform allows.
+pub fn read_passphrase(prompt: &str) -> io::Result<String> {
+    let terminal = open_terminal()?;
+    eprint!("{}", prompt);
+    let _ = io::stderr().flush();
+    let echo = EchoOff::new(&terminal);
+    let mut line = String::new();
+    BufReader::new(&terminal).read_line(&mut line)?;
+    drop(echo);
+    eprintln!();
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+/// Turns terminal echo off until dropped.
+#[cfg(unix)]
+struct EchoOff {
+    fd: std::os::fd::RawFd,
+    saved: Option<libc::termios>,
+}
+
+#[cfg(unix)]
+impl EchoOff {
+    fn new(terminal: &File) -> Self {
+        use std::os::fd::AsRawFd;
+
+        let fd = terminal.as_raw_fd();
+        let mut attrs = std::mem::MaybeUninit::<libc::termios>::uninit();
+        // SAFETY: `fd` is an open terminal and `attrs` is only read once
+        // tcgetattr has filled it in.
+        let saved = unsafe {
+            if libc::tcgetattr(fd, attrs.as_mut_ptr()) != 0 {
+                None
+            } else {
+                let saved = attrs.assume_init();
+                let mut quiet = saved;
+                quiet.c_lflag &= !libc::ECHO;
+                libc::tcsetattr(fd, libc::TCSANOW, &quiet);
+                Some(saved)
+            }
+        };
+        Self { fd, saved }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for EchoOff {
+    fn drop(&mut self) {
+        if let Some(saved) = &self.saved {
+            // SAFETY: restores the attributes read from the same terminal.
+            unsafe {
+                libc::tcsetattr(self.fd, libc::TCSANOW, saved);
+            }
+        }
+    }
+}
+
+#[cfg(not(unix))]
+struct EchoOff;
+
+#[cfg(not(unix))]
+impl EchoOff {
+    fn new(_terminal: &File) -> Self {
+        Self
+    }
+}
+
+#[cfg(windows)]
+fn open_terminal() -> io::Result<File> {
+    File::open("CONIN$")
+}
+
+#[cfg(not(windows))]
+fn open_terminal() -> io::Result<File> {
+    File::open("/dev/tty")
+}
+
+fn is_yes(answer: &str) -> bool {
+    matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::is_yes;
+
+    #[test]
+    fn only_explicit_yes_accepts() {
+        assert!(is_yes("y\n"));
+        assert!(is_yes(" YES "));
+        assert!(!is_yes("\n"));
+        assert!(!is_yes("no"));
+        assert!(!is_yes("yep"));
+    }
+}
@@ -0,0 +1,58 @@
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// Show `path` in the platform file manager (selected where supported,
+/// otherwise by opening its folder). Best-effort: problems are reported on
+/// stderr and never fail the transfer.
+pub fn reveal_in_file_manager(path: &Path) {
+    let Some(mut command) = reveal_command(path) else {
+        eprintln!(
+            "No desktop session detected; not opening a file manager for {}",
+            path.display()
+        );
+        return;
+    };
+
+    let spawned = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn();
+    if let Err(err) = spawned {
+        eprintln!("Could not open file manager: {}", err);
+    }
+}
+
+#[cfg(target_os = "windows")]
+fn reveal_command(path: &Path) -> Option<Command> {
+    let mut select = std::ffi::OsString::from("/select,");
+    select.push(path);
+    let mut command = Command::new("explorer");
+    command.arg(select);
+    Some(command)
+}
+
+#[cfg(target_os = "macos")]
+fn reveal_command(path: &Path) -> Option<Command> {
+    let mut command = Command::new("open");
+    command.arg("-R").arg(path);
+    Some(command)
+}
+
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+fn reveal_command(path: &Path) -> Option<Command> {
+    let has_display = std::env::var_os("DISPLAY").is_some_and(|v| !v.is_empty())
+        || std::env::var_os("WAYLAND_DISPLAY").is_some_and(|v| !v.is_empty());
+    if !has_display {
+        return None;
+    }
+
+    // xdg-open has no "select" mode; open the containing folder instead.
+    let folder = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."));
+    let mut command = Command::new("xdg-open");
+    command.arg(folder);
+    Some(command)
+}
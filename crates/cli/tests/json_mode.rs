@@ -54,3 +54,61 @@ fn json_mode_emits_status_and_error_events_for_invalid_receive_invocation() {
         "expected help examples in error message"
     );
 }
+
+#[test]
+fn capabilities_json_is_a_single_object_of_flags() {
+    let output = Command::new(env!("CARGO_BIN_EXE_p2p-share"))
+        .args(["capabilities", "--json"])
+        .output()
+        .expect("failed to run p2p-share");
+    assert!(output.status.success(), "capabilities should succeed");
+
+    let events = parse_json_events(&output.stdout);
+    assert_eq!(events.len(), 1, "expected exactly one JSON object");
+    let caps = &events[0];
+    assert_eq!(caps.get("multi_file").and_then(Value::as_bool), Some(true));
+    assert!(caps
+        .get("protocol_versions")
+        .and_then(Value::as_array)
+        .is_some_and(|versions| !versions.is_empty()));
+}
+
+#[cfg(unix)]
+#[test]
+fn event_fd_moves_json_events_off_stdout() {
+    let dir = std::env::temp_dir().join(format!("p2p-share-event-fd-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).expect("create temp dir");
+    let events_path = dir.join("events.jsonl");
+    let script = format!(
+        "exec \"$0\" --json --event-fd 3 receive 3>\"{}\"",
+        events_path.display()
+    );
+    let output = Command::new("sh")
+        .args(["-c", &script, env!("CARGO_BIN_EXE_p2p-share")])
+        .output()
+        .expect("failed to run p2p-share");
+    assert!(!output.status.success());
+    assert!(
+        parse_json_events(&output.stdout).is_empty(),
+        "stdout should carry no events"
+    );
+
+    let events = parse_json_events(&std::fs::read(&events_path).expect("read events"));
+    assert!(events
+        .iter()
+        .any(|evt| evt.get("kind").and_then(Value::as_str) == Some("status")));
+    assert!(events
+        .iter()
+        .any(|evt| evt.get("kind").and_then(Value::as_str) == Some("error")));
+    let _ = std::fs::remove_dir_all(dir);
+
+    // A descriptor that is not open falls back to stdout.
+    let output = Command::new(env!("CARGO_BIN_EXE_p2p-share"))
+        .args(["--json", "--event-fd", "97", "receive"])
+        .output()
+        .expect("failed to run p2p-share");
+    assert!(parse_json_events(&output.stdout)
+        .iter()
+        .any(|evt| evt.get("kind").and_then(Value::as_str) == Some("error")));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--event-fd 97"));
+}
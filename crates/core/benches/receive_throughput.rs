@@ -0,0 +1,95 @@
+//! End-to-end throughput of a single-file transfer over loopback TCP, which
+//! exercises the receive path's overlapped network reads and disk writes.
+//!
+//! Run with `cargo bench -p p2p-share-core --bench receive_throughput`.
+
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use p2p_share_core::receiver::{self, ReceiveOptions};
+use p2p_share_core::sender::{self, SendOptions};
+
+const FILE_SIZE: usize = 64 * 1024 * 1024;
+
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("p2p-share-bench-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// A loopback port nothing is listening on right now.
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+/// Send `source` to a fresh TCP listener, check the received hash and size,
+/// and remove the received file so the next run does not collide with it.
+async fn transfer_once(source: &Path, output_dir: &Path, expected: &str) -> Duration {
+    let listen_addr = format!("127.0.0.1:{}", free_port());
+    let receive_options = ReceiveOptions::default();
+    let send_options = SendOptions::default();
+
+    let start = Instant::now();
+    let receive =
+        receiver::run_tcp_listen_with_options(&listen_addr, output_dir, None, &receive_options);
+    let send = async {
+        // Give the listener a moment to bind before dialing it.
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        sender::run_tcp_paths_with_options(
+            &[source.to_path_buf()],
+            &listen_addr,
+            None,
+            &send_options,
+        )
+        .await
+    };
+    let (completed, sent) = tokio::join!(receive, send);
+    let elapsed = start.elapsed();
+
+    sent.unwrap();
+    let completed = completed.unwrap();
+    assert_eq!(completed.size_bytes, FILE_SIZE as u64);
+    assert_eq!(completed.blake3.as_deref(), Some(expected));
+    std::fs::remove_file(completed.saved_path.unwrap()).unwrap();
+    elapsed
+}
+
+fn receive_throughput(c: &mut Criterion) {
+    let source_dir = scratch_dir("source");
+    let output_dir = scratch_dir("output");
+    let source = source_dir.join("payload.bin");
+    let data: Vec<u8> = (0..FILE_SIZE).map(|i| (i * 31 % 251) as u8).collect();
+    std::fs::write(&source, &data).unwrap();
+    let expected = blake3::hash(&data).to_hex().to_string();
+    drop(data);
+
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let mut group = c.benchmark_group("receive");
+    group.throughput(Throughput::Bytes(FILE_SIZE as u64));
+    group.sample_size(10);
+    group.bench_function("tcp_loopback_64mib", |b| {
+        b.iter_custom(|iters| {
+            runtime.block_on(async {
+                let mut total = Duration::ZERO;
+                for _ in 0..iters {
+                    total += transfer_once(&source, &output_dir, &expected).await;
+                }
+                total
+            })
+        })
+    });
+    group.finish();
+
+    let _ = std::fs::remove_dir_all(&source_dir);
+    let _ = std::fs::remove_dir_all(&output_dir);
+}
+
+criterion_group!(benches, receive_throughput);
+criterion_main!(benches);
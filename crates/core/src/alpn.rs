@@ -0,0 +1,276 @@
+use std::fmt;
+
+use anyhow::{Context, Result};
+use iroh::endpoint::Connection;
+
+use crate::crypto;
+
+/// Forward mode, version 1: the connector receives the file.
+pub const ALPN_V1: &[u8] = b"p2p-share/1";
+
+/// Reverse mode, version 1: the connector sends the file.
+pub const ALPN_REVERSE_V1: &[u8] = b"p2p-share/1-reverse";
+
+/// Forward mode, version 2: as version 1, with the Noise handshake bound to
+/// the ALPN by a prologue.
+pub const ALPN_V2: &[u8] = b"p2p-share/2";
+
+/// Wire protocol versions this build speaks, newest first. Listening
+/// endpoints advertise all of them; connectors offer all of them and the
+/// accepting side picks.
+pub const SUPPORTED_VERSIONS: &[u32] = &[2, 1];
+
+/// Transport named in the Noise prologue of iroh connections.
+const TRANSPORT: &str = "iroh";
+
+const ALPN_PREFIX: &str = "p2p-share/";
+const REVERSE_SUFFIX: &str = "-reverse";
+
+/// Which side of the connection sends the file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlpnRole {
+    /// The accepting side sends (`send` waiting for `receive <ticket>`).
+    Forward,
+    /// The connecting side sends (`send --to` against `receive --qr`).
+    Reverse,
+}
+
+/// ALPN agreed on for a connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NegotiatedAlpn {
+    pub version: u32,
+    pub role: AlpnRole,
+}
+
+impl NegotiatedAlpn {
+    /// Parse an ALPN identifier, returning `None` for unknown protocols and
+    /// unsupported versions.
+    pub fn parse(alpn: &[u8]) -> Option<Self> {
+        let alpn = std::str::from_utf8(alpn).ok()?.strip_prefix(ALPN_PREFIX)?;
+        let (version, role) = match alpn.strip_suffix(REVERSE_SUFFIX) {
+            Some(version) => (version, AlpnRole::Reverse),
+            None => (alpn, AlpnRole::Forward),
+        };
+        let version: u32 = version.parse().ok()?;
+        SUPPORTED_VERSIONS
+            .contains(&version)
+            .then_some(Self { version, role })
+    }
+
+    /// The ALPN negotiated on an established connection.
+    pub fn from_connection(conn: &Connection) -> Result<Self> {
+        let alpn = conn.alpn().context("connection has no negotiated ALPN")?;
+        Self::parse(&alpn).with_context(|| {
+            format!(
+                "peer negotiated unsupported protocol '{}'",
+                String::from_utf8_lossy(&alpn)
+            )
+        })
+    }
+
+    /// This ALPN, if it names `role`; [`RoleMismatch`] otherwise, such as
+    /// for a receiver that dialed a listener waiting for a sender.
+    pub fn expect_role(self, role: AlpnRole) -> Result<Self, RoleMismatch> {
+        if self.role == role {
+            Ok(self)
+        } else {
+            Err(RoleMismatch {
+                expected: role,
+                negotiated: self,
+            })
+        }
+    }
+
+    pub fn to_bytes(self) -> Vec<u8> {
+        self.to_string().into_bytes()
+    }
+
+    /// Noise prologue for this protocol. It binds the ALPNs offered for the
+    /// role as well as the one picked, so an offer rewritten on the path
+    /// fails the handshake. The connecting side offers every version it
+    /// supports; those up to the negotiated one are what any two peers that
+    /// settled on it both offered. Version 1 predates prologues and keeps
+    /// an empty one so older peers can still connect; a downgrade to it is
+    /// caught by [`check_downgrade`](Self::check_downgrade) instead.
+    pub fn noise_prologue(self) -> Vec<u8> {
+        if self.version < 2 {
+            return Vec::new();
+        }
+        let offered: Vec<Vec<u8>> = SUPPORTED_VERSIONS
+            .iter()
+            .filter(|&&version| version <= self.version)
+            .map(|&version| {
+                NegotiatedAlpn {
+                    version,
+                    role: self.role,
+                }
+                .to_bytes()
+            })
+            .collect();
+        crypto::prologue(TRANSPORT, &offered, &self.to_bytes())
+    }
+
+    /// Check this ALPN against the versions the peer listed in its
+    /// handshake payload, which the handshake authenticates: settling on an
+    /// older version than both sides speak means the offer was tampered
+    /// with. Peers that list none predate the check and are trusted.
+    pub fn check_downgrade(self, peer_versions: &[u32]) -> Result<Self, Downgraded> {
+        let common = peer_versions
+            .iter()
+            .copied()
+            .filter(|version| SUPPORTED_VERSIONS.contains(version))
+            .max();
+        match common {
+            Some(common) if common > self.version => Err(Downgraded {
+                negotiated: self,
+                common,
+            }),
+            _ => Ok(self),
+        }
+    }
+}
+
+impl fmt::Display for NegotiatedAlpn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let suffix = match self.role {
+            AlpnRole::Forward => "",
+            AlpnRole::Reverse => REVERSE_SUFFIX,
+        };
+        write!(f, "{}{}{}", ALPN_PREFIX, self.version, suffix)
+    }
+}
+
+/// Error for a connection whose ALPN names the other [`AlpnRole`]: both
+/// peers would wait to receive, or both would send.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RoleMismatch {
+    pub expected: AlpnRole,
+    pub negotiated: NegotiatedAlpn,
+}
+
+impl fmt::Display for RoleMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let expected = match self.expected {
+            AlpnRole::Forward => "forward",
+            AlpnRole::Reverse => "reverse",
+        };
+        write!(
+            f,
+            "peer negotiated '{}', but this side expects a {} mode connection",
+            self.negotiated, expected
+        )
+    }
+}
+
+impl std::error::Error for RoleMismatch {}
+
+/// Error for a connection that negotiated an older protocol version than
+/// both peers speak, which only happens when someone on the path removed
+/// the newer ALPNs from the offer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Downgraded {
+    pub negotiated: NegotiatedAlpn,
+    /// Newest version both peers speak.
+    pub common: u32,
+}
+
+impl fmt::Display for Downgraded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "negotiated '{}' although both peers speak version {}; the connection was tampered with",
+            self.negotiated, self.common
+        )
+    }
+}
+
+impl std::error::Error for Downgraded {}
+
+/// Every supported ALPN for `role`, newest first.
+pub fn supported_alpns(role: AlpnRole) -> Vec<Vec<u8>> {
+    SUPPORTED_VERSIONS
+        .iter()
+        .map(|&version| NegotiatedAlpn { version, role }.to_bytes())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{supported_alpns, AlpnRole, NegotiatedAlpn, ALPN_REVERSE_V1, ALPN_V1, ALPN_V2};
+    use crate::crypto;
+
+    #[test]
+    fn v1_identifiers_parse_and_round_trip() {
+        let forward = NegotiatedAlpn::parse(ALPN_V1).expect("forward");
+        assert_eq!(forward.version, 1);
+        assert_eq!(forward.role, AlpnRole::Forward);
+        assert_eq!(forward.to_bytes(), ALPN_V1);
+
+        let reverse = NegotiatedAlpn::parse(ALPN_REVERSE_V1).expect("reverse");
+        assert_eq!(reverse.role, AlpnRole::Reverse);
+        assert_eq!(reverse.to_string(), "p2p-share/1-reverse");
+
+        assert_eq!(
+            supported_alpns(AlpnRole::Forward),
+            vec![ALPN_V2.to_vec(), ALPN_V1.to_vec()]
+        );
+    }
+
+    #[test]
+    fn only_version_2_and_later_bind_a_prologue() {
+        let v1 = NegotiatedAlpn::parse(ALPN_V1).unwrap();
+        assert!(v1.noise_prologue().is_empty());
+        let v2 = NegotiatedAlpn::parse(ALPN_V2).unwrap();
+        let reverse = NegotiatedAlpn {
+            role: AlpnRole::Reverse,
+            ..v2
+        };
+        assert!(!v2.noise_prologue().is_empty());
+        assert_ne!(v2.noise_prologue(), reverse.noise_prologue());
+    }
+
+    #[test]
+    fn prologues_bind_the_offer_and_the_transport() {
+        let v2 = NegotiatedAlpn::parse(ALPN_V2).unwrap();
+        let offered = [ALPN_V2.to_vec(), ALPN_V1.to_vec()];
+        assert_eq!(
+            v2.noise_prologue(),
+            crypto::prologue("iroh", &offered, ALPN_V2)
+        );
+        assert_ne!(
+            v2.noise_prologue(),
+            crypto::prologue("iroh", &offered[..1], ALPN_V2)
+        );
+    }
+
+    #[test]
+    fn a_downgrade_to_an_older_common_version_is_caught() {
+        let v1 = NegotiatedAlpn::parse(ALPN_V1).unwrap();
+        let err = v1.check_downgrade(&[2, 1]).unwrap_err();
+        assert_eq!(err.common, 2);
+        assert!(err.to_string().contains("p2p-share/1"), "{err}");
+        // Older peers list nothing or only version 1.
+        assert_eq!(v1.check_downgrade(&[]), Ok(v1));
+        assert_eq!(v1.check_downgrade(&[1]), Ok(v1));
+        // Versions this build does not speak do not count.
+        let v2 = NegotiatedAlpn::parse(ALPN_V2).unwrap();
+        assert_eq!(v2.check_downgrade(&[7, 2, 1]), Ok(v2));
+    }
+
+    #[test]
+    fn a_mismatched_role_is_rejected() {
+        let forward = NegotiatedAlpn::parse(ALPN_V2).unwrap();
+        assert_eq!(forward.expect_role(AlpnRole::Forward), Ok(forward));
+        let err = forward.expect_role(AlpnRole::Reverse).unwrap_err();
+        assert_eq!(err.expected, AlpnRole::Reverse);
+        assert!(err.to_string().contains("p2p-share/2"));
+    }
+
+    #[test]
+    fn unknown_protocols_and_versions_are_rejected() {
+        assert!(NegotiatedAlpn::parse(b"p2p-share/99").is_none());
+        assert!(NegotiatedAlpn::parse(b"p2p-share/x").is_none());
+        assert!(NegotiatedAlpn::parse(b"p2p-share/doctor").is_none());
+        assert!(NegotiatedAlpn::parse(b"other/1").is_none());
+    }
+}
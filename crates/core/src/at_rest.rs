@@ -0,0 +1,270 @@
+use std::fmt;
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use anyhow::{bail, Context as _, Result};
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// First bytes of an encrypted `.part` file.
+const MAGIC: &[u8; 8] = b"P2PSREST";
+const SALT_LEN: usize = 16;
+const FILE_ID_LEN: usize = 8;
+const HEADER_LEN: usize = MAGIC.len() + SALT_LEN + FILE_ID_LEN;
+/// Plaintext bytes sealed per frame. Flushing seals a shorter one.
+const FRAME_LEN: usize = 64 * 1024;
+const TAG_LEN: usize = 16;
+const KEY_LEN: usize = 32;
+/// Argon2id memory cost in KiB; with [`ARGON2_PASSES`] and one lane the
+/// OWASP minimum.
+const ARGON2_MEMORY_KIB: u32 = 19 * 1024;
+const ARGON2_PASSES: u32 = 2;
+
+/// A key for keeping a receive's `.part` file encrypted on disk, derived
+/// from a passphrase with Argon2id and a fresh random salt.
+#[derive(Clone)]
+pub struct AtRestKey {
+    key: [u8; KEY_LEN],
+    salt: [u8; SALT_LEN],
+}
+
+impl AtRestKey {
+    pub fn derive(passphrase: &str) -> Result<Self> {
+        let mut salt = [0u8; SALT_LEN];
+        getrandom::fill(&mut salt).map_err(|err| anyhow::anyhow!("no randomness: {}", err))?;
+        let params = Params::new(ARGON2_MEMORY_KIB, ARGON2_PASSES, 1, Some(KEY_LEN))
+            .map_err(|err| anyhow::anyhow!("invalid Argon2id parameters: {}", err))?;
+        Self::derive_with(passphrase, salt, params)
+    }
+
+    fn derive_with(passphrase: &str, salt: [u8; SALT_LEN], params: Params) -> Result<Self> {
+        let mut key = [0u8; KEY_LEN];
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(passphrase.as_bytes(), &salt, &mut key)
+            .map_err(|err| anyhow::anyhow!("cannot derive the encryption key: {}", err))?;
+        Ok(Self { key, salt })
+    }
+
+    fn cipher(&self) -> ChaCha20Poly1305 {
+        ChaCha20Poly1305::new(Key::from_slice(&self.key))
+    }
+}
+
+impl fmt::Debug for AtRestKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AtRestKey").finish_non_exhaustive()
+    }
+}
+
+/// Seals everything written through it with ChaCha20-Poly1305 before it
+/// reaches `inner`. The output is a header (magic, salt, random file id)
+/// followed by frames of a little-endian `u32` length and the ciphertext;
+/// each frame's nonce is the file id and the frame counter.
+pub(crate) struct AtRestWriter<W> {
+    inner: W,
+    cipher: ChaCha20Poly1305,
+    file_id: [u8; FILE_ID_LEN],
+    counter: u32,
+    plain: Vec<u8>,
+    /// Encoded bytes not yet accepted by `inner`.
+    out: Vec<u8>,
+    written: usize,
+}
+
+impl<W> AtRestWriter<W> {
+    pub fn new(inner: W, key: &AtRestKey) -> Result<Self> {
+        let mut file_id = [0u8; FILE_ID_LEN];
+        getrandom::fill(&mut file_id).map_err(|err| anyhow::anyhow!("no randomness: {}", err))?;
+        let mut out = Vec::with_capacity(HEADER_LEN);
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&key.salt);
+        out.extend_from_slice(&file_id);
+        Ok(Self {
+            inner,
+            cipher: key.cipher(),
+            file_id,
+            counter: 0,
+            plain: Vec::with_capacity(FRAME_LEN),
+            out,
+            written: 0,
+        })
+    }
+
+    fn seal(&mut self) -> io::Result<()> {
+        let nonce = frame_nonce(&self.file_id, self.counter);
+        self.counter = self
+            .counter
+            .checked_add(1)
+            .ok_or_else(|| io::Error::other("too many frames for one encrypted file"))?;
+        let sealed = self
+            .cipher
+            .encrypt(&nonce, self.plain.as_slice())
+            .map_err(|_| io::Error::other("could not encrypt the partial download"))?;
+        self.plain.clear();
+        self.out
+            .extend_from_slice(&(sealed.len() as u32).to_le_bytes());
+        self.out.extend_from_slice(&sealed);
+        Ok(())
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AtRestWriter<W> {
+    fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.written < self.out.len() {
+            let n = ready!(Pin::new(&mut self.inner).poll_write(cx, &self.out[self.written..]))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.written += n;
+        }
+        self.out.clear();
+        self.written = 0;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for AtRestWriter<W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_drain(cx))?;
+        let take = (FRAME_LEN - this.plain.len()).min(buf.len());
+        this.plain.extend_from_slice(&buf[..take]);
+        if this.plain.len() == FRAME_LEN {
+            this.seal()?;
+        }
+        Poll::Ready(Ok(take))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_drain(cx))?;
+        if !this.plain.is_empty() {
+            this.seal()?;
+            ready!(this.poll_drain(cx))?;
+        }
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(self.as_mut().poll_flush(cx))?;
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+/// Decrypt a file written by [`AtRestWriter`] into `writer`, returning the
+/// number of plaintext bytes.
+pub(crate) async fn decrypt<R, W>(key: &AtRestKey, reader: &mut R, writer: &mut W) -> Result<u64>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut header = [0u8; HEADER_LEN];
+    reader
+        .read_exact(&mut header)
+        .await
+        .context("encrypted partial download is truncated")?;
+    if &header[..MAGIC.len()] != MAGIC {
+        bail!("not an encrypted partial download");
+    }
+    if header[MAGIC.len()..MAGIC.len() + SALT_LEN] != key.salt {
+        bail!("partial download was encrypted with a different key");
+    }
+    let file_id: [u8; FILE_ID_LEN] = header[MAGIC.len() + SALT_LEN..]
+        .try_into()
+        .expect("header holds a file id");
+
+    let cipher = key.cipher();
+    let mut total = 0u64;
+    let mut sealed = Vec::new();
+    for counter in 0u32.. {
+        let mut len = [0u8; 4];
+        let first = reader.read(&mut len).await?;
+        if first == 0 {
+            break;
+        }
+        reader
+            .read_exact(&mut len[first..])
+            .await
+            .context("encrypted partial download is truncated")?;
+        let len = u32::from_le_bytes(len) as usize;
+        if !(TAG_LEN..=FRAME_LEN + TAG_LEN).contains(&len) {
+            bail!("encrypted partial download is corrupt");
+        }
+        sealed.resize(len, 0);
+        reader
+            .read_exact(&mut sealed)
+            .await
+            .context("encrypted partial download is truncated")?;
+        let plain = cipher
+            .decrypt(&frame_nonce(&file_id, counter), sealed.as_slice())
+            .map_err(|_| anyhow::anyhow!("encrypted partial download failed authentication"))?;
+        writer.write_all(&plain).await?;
+        total += plain.len() as u64;
+    }
+    writer.flush().await?;
+    Ok(total)
+}
+
+fn frame_nonce(file_id: &[u8; FILE_ID_LEN], counter: u32) -> Nonce {
+    let mut nonce = [0u8; 12];
+    nonce[..FILE_ID_LEN].copy_from_slice(file_id);
+    nonce[FILE_ID_LEN..].copy_from_slice(&counter.to_le_bytes());
+    *Nonce::from_slice(&nonce)
+}
+
+#[cfg(test)]
+pub(crate) fn test_key(passphrase: &str) -> AtRestKey {
+    let cheap = Params::new(64, 1, 1, Some(KEY_LEN)).expect("valid Argon2id parameters");
+    AtRestKey::derive_with(passphrase, [7; SALT_LEN], cheap).expect("key")
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncWriteExt;
+
+    use super::{decrypt, test_key, AtRestWriter, FRAME_LEN};
+
+    #[tokio::test]
+    async fn sealed_files_decrypt_only_with_their_key() {
+        let key = test_key("correct horse");
+        let data: Vec<u8> = (0..FRAME_LEN * 2 + 1234).map(|i| (i % 251) as u8).collect();
+        let mut sealed = Vec::new();
+        {
+            let mut writer = AtRestWriter::new(&mut sealed, &key).unwrap();
+            writer.write_all(&data[..100]).await.unwrap();
+            // A flush mid-frame seals a short frame; the rest still follows.
+            writer.flush().await.unwrap();
+            writer.write_all(&data[100..]).await.unwrap();
+            writer.flush().await.unwrap();
+        }
+        assert!(!sealed
+            .windows(64)
+            .any(|window| data.windows(64).next() == Some(window)));
+
+        let mut plain = Vec::new();
+        let len = decrypt(&key, &mut sealed.as_slice(), &mut plain)
+            .await
+            .unwrap();
+        assert_eq!(len, data.len() as u64);
+        assert_eq!(plain, data);
+
+        let wrong = test_key("battery staple");
+        assert!(decrypt(&wrong, &mut sealed.as_slice(), &mut Vec::new())
+            .await
+            .is_err());
+
+        let mut tampered = sealed.clone();
+        let last = tampered.len() - 1;
+        tampered[last] ^= 1;
+        assert!(decrypt(&key, &mut tampered.as_slice(), &mut Vec::new())
+            .await
+            .is_err());
+    }
+}
@@ -1,5 +1,6 @@
 use std::collections::HashSet;
 use std::fs::File;
+use std::io::{BufWriter, Seek, Write};
 use std::path::{Component, Path, PathBuf};
 use std::time::{SystemTime, UNIX_EPOCH};
 
@@ -8,6 +9,10 @@ use tar::{Archive, Builder};
 use time::macros::format_description;
 use time::OffsetDateTime;
 
+use crate::events::TransferEvent;
+use crate::manifest::{self, Manifest};
+use crate::sanitize;
+
 pub const BUNDLE_EXTENSION: &str = ".p2pshare-bundle.tar";
 
 #[derive(Debug, Clone)]
@@ -15,17 +20,174 @@ pub struct BundleBuild {
     pub bundle_path: PathBuf,
     pub logical_name: String,
     pub item_count: u64,
+    /// blake3 of the archive, computed while it was written.
+    pub blake3: String,
+    /// Files left out of the archive in keep-going mode.
+    pub failures: Vec<FileFailure>,
+    /// Files left out because a sync receiver already has them unchanged,
+    /// as [`manifest::entry_name`] paths.
+    pub unchanged: Vec<String>,
+}
+
+/// Outcome of unpacking a bundle.
+#[derive(Debug, Clone)]
+pub struct BundleExtract {
+    pub item_count: u64,
+    /// Files that could not be written in keep-going mode.
+    pub failures: Vec<FileFailure>,
+}
+
+/// One file skipped in keep-going mode. When creating a bundle, `index`
+/// counts the files in walk order, skipped ones included; when extracting,
+/// it is the archive position used by [`TransferEvent::FileStarted`].
+#[derive(Debug, Clone)]
+pub struct FileFailure {
+    pub index: u64,
+    pub name: String,
+    pub message: String,
+}
+
+/// Archive `paths`. With `keep_going`, files that cannot be opened are
+/// skipped and recorded in [`BundleBuild::failures`] instead of failing
+/// the bundle; it still fails when nothing could be added. Symbolic links
+/// are archived as their target with `follow_symlinks`, and treated like
+/// FIFOs, sockets and devices, which cannot be archived, without it. A
+/// followed link back to a directory it is inside of fails like an
+/// unreadable file.
+pub async fn create_bundle(
+    paths: &[PathBuf],
+    keep_going: bool,
+    follow_symlinks: bool,
+) -> Result<BundleBuild> {
+    create_sync_bundle(paths, keep_going, follow_symlinks, Manifest::default()).await
 }
 
-pub async fn create_bundle(paths: &[PathBuf]) -> Result<BundleBuild> {
+/// Like [`create_bundle`], but leave out the files `have` lists with the
+/// same size and hash, recording them in [`BundleBuild::unchanged`]. Only
+/// files whose size matches are hashed.
+pub async fn create_sync_bundle(
+    paths: &[PathBuf],
+    keep_going: bool,
+    follow_symlinks: bool,
+    have: Manifest,
+) -> Result<BundleBuild> {
     let input_paths = paths.to_vec();
-    tokio::task::spawn_blocking(move || create_bundle_blocking(&input_paths)).await?
+    tokio::task::spawn_blocking(move || {
+        create_bundle_blocking(&input_paths, keep_going, follow_symlinks, have)
+    })
+    .await?
+}
+
+/// Top-level names of the archive [`create_bundle`] builds from `paths`.
+pub fn archive_roots(paths: &[PathBuf]) -> Result<Vec<String>> {
+    let mut used_names = HashSet::new();
+    paths
+        .iter()
+        .map(|path| {
+            let base_name = path
+                .file_name()
+                .context("path has no file name")?
+                .to_string_lossy()
+                .to_string();
+            Ok(dedupe_file_name(&base_name, &mut used_names))
+        })
+        .collect()
+}
+
+/// What kind of file `file_type` is, for messages about files that are
+/// neither regular files nor directories.
+pub(crate) fn file_type_name(file_type: &std::fs::FileType) -> &'static str {
+    if file_type.is_symlink() {
+        return "symbolic link";
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileTypeExt;
+
+        if file_type.is_fifo() {
+            return "FIFO";
+        }
+        if file_type.is_socket() {
+            return "socket";
+        }
+        if file_type.is_block_device() {
+            return "block device";
+        }
+        if file_type.is_char_device() {
+            return "character device";
+        }
+    }
+    "special file"
+}
+
+/// Unpack a bundle into `output_dir`. With `keep_going`, files that cannot
+/// be written are skipped and recorded in [`BundleExtract::failures`].
+/// Invalid or unsafe entries always fail the extraction.
+pub async fn extract_bundle(
+    bundle_path: &Path,
+    output_dir: &Path,
+    keep_going: bool,
+) -> Result<BundleExtract> {
+    let bundle_path = bundle_path.to_path_buf();
+    let output_dir = output_dir.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        extract_bundle_blocking(&bundle_path, &output_dir, keep_going)
+    })
+    .await?
 }
 
-pub async fn extract_bundle(bundle_path: &Path, output_dir: &Path) -> Result<u64> {
+/// Like [`extract_bundle`], for a bundle compressed by [`compress_bundle`].
+pub async fn extract_zstd_bundle(
+    bundle_path: &Path,
+    output_dir: &Path,
+    keep_going: bool,
+) -> Result<BundleExtract> {
     let bundle_path = bundle_path.to_path_buf();
     let output_dir = output_dir.to_path_buf();
-    tokio::task::spawn_blocking(move || extract_bundle_blocking(&bundle_path, &output_dir)).await?
+    tokio::task::spawn_blocking(move || {
+        extract_zstd_bundle_blocking(&bundle_path, &output_dir, keep_going)
+    })
+    .await?
+}
+
+/// Compress the bundle at `bundle_path` as one zstd stream at `level` into
+/// a `.zst` file next to it, returning that path and the blake3 of the
+/// compressed data. One stream finds the redundancy across many small
+/// files that compressing frame by frame misses.
+#[cfg(feature = "compression")]
+pub async fn compress_bundle(bundle_path: &Path, level: i32) -> Result<(PathBuf, String)> {
+    let mut compressed_path = bundle_path.as_os_str().to_owned();
+    compressed_path.push(".zst");
+    let compressed_path = PathBuf::from(compressed_path);
+    let source_path = bundle_path.to_path_buf();
+    let output_path = compressed_path.clone();
+    let compressed = tokio::task::spawn_blocking(move || -> Result<String> {
+        let mut source = File::open(&source_path)
+            .with_context(|| format!("failed to open bundle {}", source_path.display()))?;
+        let file = File::create(&output_path)
+            .with_context(|| format!("failed to create {}", output_path.display()))?;
+        let mut writer = HashingWriter {
+            inner: BufWriter::new(file),
+            hasher: blake3::Hasher::new(),
+        };
+        zstd::stream::copy_encode(&mut source, &mut writer, level)
+            .context("failed to compress bundle")?;
+        writer.flush().context("failed to compress bundle")?;
+        Ok(writer.hasher.finalize().to_hex().to_string())
+    })
+    .await?;
+    match compressed {
+        Ok(hash) => Ok((compressed_path, hash)),
+        Err(err) => {
+            let _ = tokio::fs::remove_file(&compressed_path).await;
+            Err(err)
+        }
+    }
+}
+
+#[cfg(not(feature = "compression"))]
+pub async fn compress_bundle(_bundle_path: &Path, _level: i32) -> Result<(PathBuf, String)> {
+    bail!("this build cannot compress bundles")
 }
 
 pub fn logical_name_from_wire_name(name: &str) -> String {
@@ -34,7 +196,12 @@ pub fn logical_name_from_wire_name(name: &str) -> String {
         .to_string()
 }
 
-fn create_bundle_blocking(paths: &[PathBuf]) -> Result<BundleBuild> {
+fn create_bundle_blocking(
+    paths: &[PathBuf],
+    keep_going: bool,
+    follow_symlinks: bool,
+    have: Manifest,
+) -> Result<BundleBuild> {
     if paths.is_empty() {
         bail!("at least one file is required");
     }
@@ -45,91 +212,552 @@ fn create_bundle_blocking(paths: &[PathBuf]) -> Result<BundleBuild> {
 
     let file = File::create(&bundle_path)
         .with_context(|| format!("failed to create bundle file {}", bundle_path.display()))?;
-    let mut builder = Builder::new(file);
-    let mut used_names = HashSet::new();
+    let mut builder = Builder::new(HashingWriter {
+        inner: BufWriter::new(file),
+        hasher: blake3::Hasher::new(),
+    });
+    let mut walk = BundleWalk {
+        keep_going,
+        follow_symlinks,
+        have,
+        ..BundleWalk::default()
+    };
+
+    for (path, archive_name) in paths.iter().zip(archive_roots(paths)?) {
+        append_tree(&mut builder, path, Path::new(&archive_name), &mut walk)?;
+    }
+    if walk.item_count == 0 && !walk.failures.is_empty() {
+        let _ = std::fs::remove_file(&bundle_path);
+        bail!(
+            "none of the {} files could be read: {}",
+            walk.failures.len(),
+            walk.failures[0].message
+        );
+    }
 
-    for path in paths {
-        let metadata =
-            std::fs::metadata(path).with_context(|| format!("cannot access {}", path.display()))?;
-        if !metadata.is_file() {
-            bail!("{} is not a regular file", path.display());
+    let mut writer = builder
+        .into_inner()
+        .context("failed to finalize bundle archive")?;
+    writer
+        .inner
+        .flush()
+        .context("failed to finalize bundle archive")?;
+
+    Ok(BundleBuild {
+        bundle_path,
+        logical_name,
+        item_count: walk.item_count,
+        blake3: writer.hasher.finalize().to_hex().to_string(),
+        failures: walk.failures,
+        unchanged: walk.unchanged,
+    })
+}
+
+/// Progress of the walk over the paths being bundled.
+#[derive(Debug, Default)]
+struct BundleWalk {
+    keep_going: bool,
+    follow_symlinks: bool,
+    /// Regular files added to the archive.
+    item_count: u64,
+    /// Files visited so far, added or skipped.
+    visited: u64,
+    failures: Vec<FileFailure>,
+    /// What a sync receiver already has.
+    have: Manifest,
+    unchanged: Vec<String>,
+    /// Directories being walked, from the root down to the current one,
+    /// so a followed link back to one of them is caught as a cycle.
+    ancestors: Vec<DirId>,
+}
+
+/// What identifies a directory however it was reached: the device and
+/// inode on Unix, the fully resolved path elsewhere.
+#[cfg(unix)]
+type DirId = (u64, u64);
+#[cfg(not(unix))]
+type DirId = PathBuf;
+
+#[cfg(unix)]
+fn dir_id(_path: &Path, metadata: &std::fs::Metadata) -> std::io::Result<DirId> {
+    use std::os::unix::fs::MetadataExt;
+
+    Ok((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn dir_id(path: &Path, _metadata: &std::fs::Metadata) -> std::io::Result<DirId> {
+    std::fs::canonicalize(path)
+}
+
+impl BundleWalk {
+    /// Record `err` for `archive_name` and carry on in keep-going mode;
+    /// otherwise fail the bundle with it.
+    fn skip(&mut self, archive_name: &Path, err: anyhow::Error) -> Result<()> {
+        if !self.keep_going {
+            return Err(err);
         }
+        self.failures.push(FileFailure {
+            index: self.visited,
+            name: archive_name.to_string_lossy().to_string(),
+            message: format!("{:#}", err),
+        });
+        self.visited += 1;
+        Ok(())
+    }
 
-        let base_name = path
-            .file_name()
-            .context("path has no file name")?
-            .to_string_lossy()
-            .to_string();
-        let archive_name = dedupe_file_name(&base_name, &mut used_names);
+    /// Whether the sync receiver already has `file` as `archive_name`,
+    /// recording it if so. Rewinds `file` when it was read.
+    fn already_synced(&mut self, archive_name: &Path, size: u64, file: &mut File) -> Result<bool> {
+        let name = manifest::entry_name(archive_name);
+        let Some(entry) = self.have.get(&name).filter(|entry| entry.size == size) else {
+            return Ok(false);
+        };
+        let mut hasher = blake3::Hasher::new();
+        std::io::copy(file, &mut hasher)?;
+        file.rewind()?;
+        if !hasher
+            .finalize()
+            .to_hex()
+            .eq_ignore_ascii_case(&entry.blake3)
+        {
+            return Ok(false);
+        }
+        self.unchanged.push(name);
+        self.visited += 1;
+        Ok(true)
+    }
+}
 
+/// Add `path` to the archive as `archive_name`, recursing into directories
+/// in name order so the layout is deterministic.
+fn append_tree<W: Write>(
+    builder: &mut Builder<W>,
+    path: &Path,
+    archive_name: &Path,
+    walk: &mut BundleWalk,
+) -> Result<()> {
+    let metadata = if walk.follow_symlinks {
+        std::fs::metadata(path)
+    } else {
+        std::fs::symlink_metadata(path)
+    };
+    let metadata = match metadata {
+        Ok(metadata) => metadata,
+        Err(err) => {
+            let err = anyhow::Error::new(err).context(format!("cannot access {}", path.display()));
+            return walk.skip(archive_name, err);
+        }
+    };
+    if metadata.is_file() {
+        // Opened before anything is written, so an unreadable file can be
+        // skipped without leaving a dangling header in the archive.
+        let mut file = match File::open(path) {
+            Ok(file) => file,
+            Err(err) => {
+                let err =
+                    anyhow::Error::new(err).context(format!("cannot read {}", path.display()));
+                return walk.skip(archive_name, err);
+            }
+        };
+        match walk.already_synced(archive_name, metadata.len(), &mut file) {
+            Ok(true) => return Ok(()),
+            Ok(false) => {}
+            Err(err) => {
+                let err = err.context(format!("cannot read {}", path.display()));
+                return walk.skip(archive_name, err);
+            }
+        }
         builder
-            .append_path_with_name(path, &archive_name)
+            .append_file(archive_name, &mut file)
             .with_context(|| format!("failed to add {} to bundle", path.display()))?;
+        walk.item_count += 1;
+        walk.visited += 1;
+        return Ok(());
     }
+    if !metadata.is_dir() {
+        let err = anyhow::anyhow!(
+            "{} is a {}, not a regular file or directory",
+            path.display(),
+            file_type_name(&metadata.file_type())
+        );
+        return walk.skip(archive_name, err);
+    }
+
+    let id = if walk.follow_symlinks {
+        match dir_id(path, &metadata) {
+            Ok(id) if walk.ancestors.contains(&id) => {
+                let err = anyhow::anyhow!(
+                    "{} links back to a directory it is inside of",
+                    path.display()
+                );
+                return walk.skip(archive_name, err);
+            }
+            Ok(id) => Some(id),
+            Err(err) => {
+                let err =
+                    anyhow::Error::new(err).context(format!("cannot access {}", path.display()));
+                return walk.skip(archive_name, err);
+            }
+        }
+    } else {
+        None
+    };
 
+    let children = std::fs::read_dir(path)
+        .and_then(|entries| {
+            entries
+                .map(|entry| entry.map(|entry| entry.file_name()))
+                .collect::<std::io::Result<Vec<_>>>()
+        })
+        .with_context(|| format!("cannot read directory {}", path.display()));
+    let mut children = match children {
+        Ok(children) => children,
+        Err(err) => return walk.skip(archive_name, err),
+    };
     builder
-        .finish()
-        .context("failed to finalize bundle archive")?;
+        .append_dir(archive_name, path)
+        .with_context(|| format!("failed to add {} to bundle", path.display()))?;
+    children.sort();
+    let followed = id.is_some();
+    walk.ancestors.extend(id);
+    for child in children {
+        append_tree(
+            builder,
+            &path.join(&child),
+            &archive_name.join(&child),
+            walk,
+        )?;
+    }
+    if followed {
+        walk.ancestors.pop();
+    }
+    Ok(())
+}
 
-    Ok(BundleBuild {
-        bundle_path,
-        logical_name,
-        item_count: paths.len() as u64,
-    })
+/// Writer that hashes everything written through it, so a bundle needs no
+/// second read pass to be hashed before sending.
+struct HashingWriter<W> {
+    inner: W,
+    hasher: blake3::Hasher,
 }
 
-fn extract_bundle_blocking(bundle_path: &Path, output_dir: &Path) -> Result<u64> {
-    std::fs::create_dir_all(output_dir)
-        .with_context(|| format!("failed to create {}", output_dir.display()))?;
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
 
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn extract_bundle_blocking(
+    bundle_path: &Path,
+    output_dir: &Path,
+    keep_going: bool,
+) -> Result<BundleExtract> {
     let file = File::open(bundle_path)
         .with_context(|| format!("failed to open bundle {}", bundle_path.display()))?;
-    let mut archive = Archive::new(file);
+    extract_archive(file, output_dir, keep_going)
+}
+
+#[cfg(feature = "compression")]
+fn extract_zstd_bundle_blocking(
+    bundle_path: &Path,
+    output_dir: &Path,
+    keep_going: bool,
+) -> Result<BundleExtract> {
+    let file = File::open(bundle_path)
+        .with_context(|| format!("failed to open bundle {}", bundle_path.display()))?;
+    let decoder = zstd::stream::read::Decoder::new(file).context("failed to read bundle")?;
+    extract_archive(decoder, output_dir, keep_going)
+}
+
+#[cfg(not(feature = "compression"))]
+fn extract_zstd_bundle_blocking(
+    _bundle_path: &Path,
+    _output_dir: &Path,
+    _keep_going: bool,
+) -> Result<BundleExtract> {
+    bail!("received a compressed bundle, but this build cannot decompress")
+}
+
+fn extract_archive(
+    reader: impl std::io::Read,
+    output_dir: &Path,
+    keep_going: bool,
+) -> Result<BundleExtract> {
+    std::fs::create_dir_all(output_dir)
+        .with_context(|| format!("failed to create {}", output_dir.display()))?;
+
+    let mut archive = Archive::new(reader);
     let mut item_count = 0u64;
+    let mut failures = Vec::new();
+    let mut file_index = 0u64;
     let mut seen = HashSet::new();
 
     for entry in archive.entries().context("failed to read bundle entries")? {
         let mut entry = entry.context("failed to read bundle entry")?;
-        if entry.header().entry_type().is_dir() {
-            continue;
-        }
-        if !entry.header().entry_type().is_file() {
+        let entry_type = entry.header().entry_type();
+        if !entry_type.is_dir() && !entry_type.is_file() {
             bail!("bundle contains unsupported entry type");
         }
 
         let path = entry.path().context("bundle entry path is invalid")?;
-        let file_name = sanitize_bundle_entry_path(&path)?;
-        if !seen.insert(file_name.clone()) {
-            bail!("bundle contains duplicate entry {}", file_name);
+        let relative = sanitize_bundle_entry_path(&path)?;
+        let dest = sanitize::extended_length_path(&output_dir.join(&relative));
+        if entry_type.is_dir() {
+            std::fs::create_dir_all(&dest)
+                .with_context(|| format!("failed to create {}", dest.display()))?;
+            continue;
+        }
+        if !seen.insert(relative.clone()) {
+            bail!("bundle contains duplicate entry {}", relative.display());
         }
+        let index = file_index;
+        file_index += 1;
 
-        let dest = output_dir.join(&file_name);
-        entry
-            .unpack(&dest)
-            .with_context(|| format!("failed to unpack {}", dest.display()))?;
-        item_count += 1;
+        let unpacked = dest
+            .parent()
+            .map_or(Ok(()), |parent| {
+                std::fs::create_dir_all(parent)
+                    .with_context(|| format!("failed to create {}", parent.display()))
+            })
+            .and_then(|()| {
+                entry
+                    .unpack(&dest)
+                    .with_context(|| format!("failed to unpack {}", dest.display()))
+            });
+        match unpacked {
+            Ok(_) => item_count += 1,
+            Err(err) if keep_going => {
+                let _ = std::fs::remove_file(&dest);
+                failures.push(FileFailure {
+                    index,
+                    name: relative.to_string_lossy().to_string(),
+                    message: format!("{:#}", err),
+                });
+            }
+            Err(err) => return Err(err),
+        }
     }
 
-    Ok(item_count)
+    Ok(BundleExtract {
+        item_count,
+        failures,
+    })
 }
 
-fn sanitize_bundle_entry_path(path: &Path) -> Result<String> {
-    let mut components = path.components();
-    let Some(first) = components.next() else {
-        bail!("bundle contains empty path");
-    };
-    if components.next().is_some() {
-        bail!("bundle contains nested path {}", path.display());
+/// Tar block size; headers and padded entry data are multiples of it.
+const TAR_BLOCK: u64 = 512;
+
+/// Position of one regular file's data inside a bundle archive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BundleEntry {
+    pub name: String,
+    pub data_offset: u64,
+    pub size: u64,
+}
+
+/// Turns the aggregate byte count of a streamed bundle into per-file
+/// events. The sender knows the layout up front; the receiver learns it by
+/// observing tar headers as the bytes arrive.
+#[derive(Debug, Default)]
+pub struct BundleTracker {
+    entries: Vec<BundleEntry>,
+    /// Index of the first entry that has not finished yet.
+    current: usize,
+    /// Whether `entries[current]` has been reported as started.
+    current_started: bool,
+    parser: Option<TarHeaderParser>,
+}
+
+impl BundleTracker {
+    pub fn from_layout(entries: Vec<BundleEntry>) -> Self {
+        Self {
+            entries,
+            ..Self::default()
+        }
     }
 
-    let Component::Normal(name) = first else {
-        bail!("bundle contains invalid path {}", path.display());
-    };
-    let file_name = name.to_string_lossy().trim().to_string();
-    if file_name.is_empty() || file_name == "." || file_name == ".." {
-        bail!("bundle contains invalid file name");
+    /// Tracker for a bundle whose layout is discovered from its bytes; feed
+    /// every chunk to [`BundleTracker::observe`] before calling `advance`.
+    pub fn streaming() -> Self {
+        Self {
+            parser: Some(TarHeaderParser::default()),
+            ..Self::default()
+        }
+    }
+
+    /// Fails on a tar header no real archive has, such as an entry size
+    /// that overflows when padded to [`TAR_BLOCK`].
+    pub fn observe(&mut self, chunk: &[u8]) -> Result<()> {
+        match &mut self.parser {
+            Some(parser) => parser.feed(chunk, &mut self.entries),
+            None => Ok(()),
+        }
+    }
+
+    /// Events for the files touched since the previous call, given that the
+    /// first `done` bytes of the archive have been transferred.
+    pub fn advance(&mut self, done: u64) -> Vec<TransferEvent> {
+        let mut events = Vec::new();
+        while let Some(entry) = self.entries.get(self.current) {
+            if done < entry.data_offset {
+                break;
+            }
+            let index = self.current as u64;
+            if !self.current_started {
+                events.push(TransferEvent::FileStarted {
+                    index,
+                    name: entry.name.clone(),
+                    size: entry.size,
+                });
+                self.current_started = true;
+            }
+            let file_done = (done - entry.data_offset).min(entry.size);
+            if entry.size > 0 {
+                events.push(TransferEvent::FileProgress {
+                    index,
+                    done: file_done,
+                    total: entry.size,
+                });
+            }
+            if file_done < entry.size {
+                break;
+            }
+            events.push(TransferEvent::FileFinished { index });
+            self.current += 1;
+            self.current_started = false;
+        }
+        events
+    }
+}
+
+/// Read the layout of the regular files in a bundle archive.
+pub fn bundle_layout(bundle_path: &Path) -> Result<Vec<BundleEntry>> {
+    let file = File::open(bundle_path)
+        .with_context(|| format!("failed to open bundle {}", bundle_path.display()))?;
+    let mut archive = Archive::new(file);
+    let mut entries = Vec::new();
+    for entry in archive.entries().context("failed to read bundle entries")? {
+        let entry = entry.context("failed to read bundle entry")?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        entries.push(BundleEntry {
+            name: entry.path()?.to_string_lossy().to_string(),
+            data_offset: entry.raw_file_position(),
+            size: entry.size(),
+        });
+    }
+    Ok(entries)
+}
+
+/// Incremental tar header reader used by [`BundleTracker::streaming`].
+#[derive(Debug, Default)]
+struct TarHeaderParser {
+    offset: u64,
+    header: Vec<u8>,
+    /// Bytes left in the current entry's padded data, after its header.
+    skip: u64,
+    /// Collects the payload of a GNU long-name entry.
+    long_name: Option<Vec<u8>>,
+    long_name_left: u64,
+    pending_name: Option<String>,
+    finished: bool,
+}
+
+impl TarHeaderParser {
+    fn feed(&mut self, mut chunk: &[u8], entries: &mut Vec<BundleEntry>) -> Result<()> {
+        while !chunk.is_empty() && !self.finished {
+            if self.skip > 0 {
+                let take = self.skip.min(chunk.len() as u64) as usize;
+                if let Some(name) = &mut self.long_name {
+                    let wanted = self.long_name_left.min(take as u64) as usize;
+                    name.extend_from_slice(&chunk[..wanted]);
+                    self.long_name_left -= wanted as u64;
+                }
+                chunk = &chunk[take..];
+                self.offset += take as u64;
+                self.skip -= take as u64;
+                if self.skip == 0 {
+                    if let Some(name) = self.long_name.take() {
+                        let name = name.split(|&b| b == 0).next().unwrap_or_default();
+                        self.pending_name = Some(String::from_utf8_lossy(name).to_string());
+                    }
+                }
+                continue;
+            }
+
+            let take = (TAR_BLOCK as usize - self.header.len()).min(chunk.len());
+            self.header.extend_from_slice(&chunk[..take]);
+            chunk = &chunk[take..];
+            self.offset += take as u64;
+            if self.header.len() < TAR_BLOCK as usize {
+                continue;
+            }
+
+            let block = std::mem::take(&mut self.header);
+            if block.iter().all(|&b| b == 0) {
+                self.finished = true;
+                break;
+            }
+            let header = tar::Header::from_byte_slice(&block);
+            let size = header.entry_size().unwrap_or(0);
+            let Some(skip) = size.div_ceil(TAR_BLOCK).checked_mul(TAR_BLOCK) else {
+                self.finished = true;
+                bail!(
+                    "bundle entry at offset {} has an impossible size",
+                    self.offset
+                );
+            };
+            self.skip = skip;
+            let entry_type = header.entry_type();
+            if entry_type.is_gnu_longname() {
+                self.long_name = Some(Vec::new());
+                self.long_name_left = size;
+            } else if entry_type.is_file() {
+                let name = self.pending_name.take().unwrap_or_else(|| {
+                    header
+                        .path()
+                        .map(|path| path.to_string_lossy().to_string())
+                        .unwrap_or_default()
+                });
+                entries.push(BundleEntry {
+                    name,
+                    data_offset: self.offset,
+                    size,
+                });
+            } else {
+                self.pending_name = None;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Relative destination of a bundle entry. Every component must be a plain
+/// name, so entries cannot escape the output directory.
+fn sanitize_bundle_entry_path(path: &Path) -> Result<PathBuf> {
+    let mut relative = PathBuf::new();
+    for component in path.components() {
+        let Component::Normal(name) = component else {
+            bail!("bundle contains invalid path {}", path.display());
+        };
+        let file_name = name.to_string_lossy().trim().to_string();
+        if file_name.is_empty() || file_name == "." || file_name == ".." {
+            bail!("bundle contains invalid file name");
+        }
+        relative.push(sanitize::sanitize_file_name(&file_name));
+    }
+    if relative.as_os_str().is_empty() {
+        bail!("bundle contains empty path");
     }
-    Ok(file_name)
+    Ok(relative)
 }
 
 fn bundle_logical_name() -> Result<String> {
@@ -177,9 +805,11 @@ mod tests {
     use std::time::{SystemTime, UNIX_EPOCH};
 
     use super::{
-        create_bundle_blocking, dedupe_file_name, extract_bundle_blocking,
-        logical_name_from_wire_name,
+        bundle_layout, create_bundle_blocking, dedupe_file_name, extract_bundle_blocking,
+        logical_name_from_wire_name, BundleTracker,
     };
+    use crate::events::TransferEvent;
+    use crate::manifest::Manifest;
 
     #[test]
     fn dedupe_file_name_adds_numeric_suffixes() {
@@ -206,11 +836,20 @@ mod tests {
         fs::write(source_dir.join("a.txt"), "alpha").expect("write a");
         fs::write(source_dir.join("b.txt"), "beta").expect("write b");
 
-        let build = create_bundle_blocking(&[source_dir.join("a.txt"), source_dir.join("b.txt")])
-            .expect("create bundle");
+        let build = create_bundle_blocking(
+            &[source_dir.join("a.txt"), source_dir.join("b.txt")],
+            false,
+            false,
+            Manifest::default(),
+        )
+        .expect("create bundle");
+
+        let archive = fs::read(&build.bundle_path).expect("read bundle");
+        assert_eq!(build.blake3, blake3::hash(&archive).to_hex().to_string());
 
-        let count =
-            extract_bundle_blocking(&build.bundle_path, &output_dir).expect("extract bundle");
+        let count = extract_bundle_blocking(&build.bundle_path, &output_dir, false)
+            .expect("extract bundle")
+            .item_count;
         assert_eq!(count, 2);
         assert_eq!(
             fs::read_to_string(output_dir.join("a.txt")).expect("read a"),
@@ -236,11 +875,17 @@ mod tests {
         fs::write(left.join("same.txt"), "left").expect("write left");
         fs::write(right.join("same.txt"), "right").expect("write right");
 
-        let build = create_bundle_blocking(&[left.join("same.txt"), right.join("same.txt")])
-            .expect("create bundle");
+        let build = create_bundle_blocking(
+            &[left.join("same.txt"), right.join("same.txt")],
+            false,
+            false,
+            Manifest::default(),
+        )
+        .expect("create bundle");
 
-        let count =
-            extract_bundle_blocking(&build.bundle_path, &output_dir).expect("extract bundle");
+        let count = extract_bundle_blocking(&build.bundle_path, &output_dir, false)
+            .expect("extract bundle")
+            .item_count;
         assert_eq!(count, 2);
         assert_eq!(
             fs::read_to_string(output_dir.join("same.txt")).expect("read first"),
@@ -255,6 +900,285 @@ mod tests {
         let _ = fs::remove_dir_all(root);
     }
 
+    #[test]
+    fn directory_bundles_keep_their_tree() {
+        let root = temp_test_dir("bundle-directory");
+        let tree = root.join("photos");
+        fs::create_dir_all(tree.join("2024/empty")).expect("create tree");
+        fs::write(tree.join("cover.jpg"), "cover").expect("write cover");
+        fs::write(tree.join("2024/beach.jpg"), "beach").expect("write beach");
+        let output_dir = root.join("out");
+
+        let build = create_bundle_blocking(
+            std::slice::from_ref(&tree),
+            false,
+            false,
+            Manifest::default(),
+        )
+        .expect("create bundle");
+        assert_eq!(build.item_count, 2);
+        let layout = bundle_layout(&build.bundle_path).expect("layout");
+        let names: Vec<_> = layout.iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(names, ["photos/2024/beach.jpg", "photos/cover.jpg"]);
+
+        let count = extract_bundle_blocking(&build.bundle_path, &output_dir, false)
+            .expect("extract bundle")
+            .item_count;
+        assert_eq!(count, 2);
+        assert_eq!(
+            fs::read_to_string(output_dir.join("photos/2024/beach.jpg")).expect("read beach"),
+            "beach"
+        );
+        assert!(output_dir.join("photos/2024/empty").is_dir());
+
+        let _ = fs::remove_file(build.bundle_path);
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn sync_bundles_leave_out_what_the_receiver_has() {
+        let root = temp_test_dir("bundle-sync");
+        let tree = root.join("photos");
+        fs::create_dir_all(tree.join("2024")).expect("create tree");
+        fs::write(tree.join("cover.jpg"), "cover").expect("write cover");
+        fs::write(tree.join("2024/beach.jpg"), "beach v2").expect("write beach");
+        fs::write(tree.join("2024/new.jpg"), "new").expect("write new");
+        let output_dir = root.join("out");
+        fs::create_dir_all(output_dir.join("photos/2024")).expect("create copy");
+        fs::write(output_dir.join("photos/cover.jpg"), "cover").expect("copy cover");
+        fs::write(output_dir.join("photos/2024/beach.jpg"), "beach v1").expect("copy beach");
+
+        let have = Manifest::build(&output_dir, &["photos".to_string()]);
+        assert_eq!(have.len(), 2);
+        let build = create_bundle_blocking(std::slice::from_ref(&tree), false, false, have)
+            .expect("create bundle");
+        assert_eq!(build.unchanged, ["photos/cover.jpg"]);
+        assert_eq!(build.item_count, 2);
+
+        extract_bundle_blocking(&build.bundle_path, &output_dir, false).expect("extract bundle");
+        assert_eq!(
+            fs::read_to_string(output_dir.join("photos/2024/beach.jpg")).expect("read beach"),
+            "beach v2"
+        );
+        assert!(output_dir.join("photos/2024/new.jpg").is_file());
+        assert!(output_dir.join("photos/cover.jpg").is_file());
+
+        let _ = fs::remove_file(build.bundle_path);
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_in_directories_are_archived_only_when_followed() {
+        let root = temp_test_dir("bundle-symlink");
+        let tree = root.join("docs");
+        fs::create_dir_all(&tree).expect("create tree");
+        fs::write(root.join("outside.txt"), "outside").expect("write target");
+        std::os::unix::fs::symlink(root.join("outside.txt"), tree.join("link.txt"))
+            .expect("symlink");
+
+        let err = create_bundle_blocking(
+            std::slice::from_ref(&tree),
+            false,
+            false,
+            Manifest::default(),
+        )
+        .expect_err("symlink rejected");
+        assert!(format!("{err:#}").contains("symbolic link"), "{err:#}");
+        let build = create_bundle_blocking(
+            std::slice::from_ref(&tree),
+            false,
+            true,
+            Manifest::default(),
+        )
+        .expect("create bundle");
+        assert_eq!(build.item_count, 1);
+
+        let _ = fs::remove_file(build.bundle_path);
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn followed_links_back_to_an_ancestor_are_reported_as_cycles() {
+        let root = temp_test_dir("bundle-cycle");
+        let tree = root.join("docs");
+        fs::create_dir_all(tree.join("inner")).expect("create tree");
+        fs::write(tree.join("inner/notes.txt"), "notes").expect("write file");
+        std::os::unix::fs::symlink(&tree, tree.join("inner/loop")).expect("symlink");
+        // A second link to a directory that is not an ancestor is no cycle.
+        std::os::unix::fs::symlink(tree.join("inner"), tree.join("again")).expect("symlink");
+
+        let err = create_bundle_blocking(
+            std::slice::from_ref(&tree),
+            false,
+            true,
+            Manifest::default(),
+        )
+        .expect_err("cycle rejected");
+        assert!(format!("{err:#}").contains("links back"), "{err:#}");
+
+        let build =
+            create_bundle_blocking(std::slice::from_ref(&tree), true, true, Manifest::default())
+                .expect("create bundle");
+        assert_eq!(build.item_count, 2);
+        assert_eq!(build.failures.len(), 2, "{:?}", build.failures);
+
+        let _ = fs::remove_file(build.bundle_path);
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn extraction_rejects_paths_that_escape_the_output_dir() {
+        let root = temp_test_dir("bundle-traversal");
+        let bundle_path = root.join("evil.tar");
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.as_old_mut().name[..9].copy_from_slice(b"../escape");
+        header.set_size(4);
+        header.set_entry_type(tar::EntryType::Regular);
+        header.set_cksum();
+        builder.append(&header, &b"evil"[..]).expect("append");
+        fs::write(&bundle_path, builder.into_inner().expect("finish")).expect("write tar");
+
+        let err = extract_bundle_blocking(&bundle_path, &root.join("out"), true).unwrap_err();
+        assert!(err.to_string().contains("invalid path"), "{err:#}");
+        assert!(!root.join("escape").exists());
+
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn keep_going_skips_files_that_cannot_be_read_or_written() {
+        let root = temp_test_dir("bundle-keep-going");
+        fs::write(root.join("a.txt"), "alpha").expect("write a");
+        let missing = root.join("missing.txt");
+        let paths = [missing.clone(), root.join("a.txt")];
+
+        assert!(create_bundle_blocking(&paths, false, false, Manifest::default()).is_err());
+        let build = create_bundle_blocking(&paths, true, false, Manifest::default())
+            .expect("create bundle");
+        assert_eq!(build.item_count, 1);
+        assert_eq!(build.failures.len(), 1);
+        assert_eq!(build.failures[0].index, 0);
+        assert_eq!(build.failures[0].name, "missing.txt");
+        assert!(create_bundle_blocking(&[missing], true, false, Manifest::default()).is_err());
+        let _ = fs::remove_file(build.bundle_path);
+
+        // `d/x.txt` cannot be written once `d` exists as a file.
+        let bundle_path = root.join("clash.tar");
+        let mut builder = tar::Builder::new(Vec::new());
+        for name in ["d", "d/x.txt", "e.txt"] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(1);
+            header.set_entry_type(tar::EntryType::Regular);
+            builder
+                .append_data(&mut header, name, &b"x"[..])
+                .expect("append");
+        }
+        fs::write(&bundle_path, builder.into_inner().expect("finish")).expect("write tar");
+
+        let output_dir = root.join("out");
+        assert!(extract_bundle_blocking(&bundle_path, &output_dir, false).is_err());
+        let _ = fs::remove_dir_all(&output_dir);
+        let extracted =
+            extract_bundle_blocking(&bundle_path, &output_dir, true).expect("extract bundle");
+        assert_eq!(extracted.item_count, 2);
+        assert_eq!(extracted.failures.len(), 1);
+        assert_eq!(extracted.failures[0].index, 1);
+        assert!(output_dir.join("e.txt").is_file());
+
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn streaming_tracker_matches_bundle_layout() {
+        let root = temp_test_dir("bundle-tracker");
+        let long_name = format!("{}.txt", "n".repeat(120));
+        fs::write(root.join("a.txt"), vec![b'a'; 700]).expect("write a");
+        fs::write(root.join("empty.txt"), "").expect("write empty");
+        fs::write(root.join(&long_name), "long").expect("write long");
+
+        let build = create_bundle_blocking(
+            &[
+                root.join("a.txt"),
+                root.join("empty.txt"),
+                root.join(&long_name),
+            ],
+            false,
+            false,
+            Manifest::default(),
+        )
+        .expect("create bundle");
+        let bytes = fs::read(&build.bundle_path).expect("read bundle");
+        let layout = bundle_layout(&build.bundle_path).expect("layout");
+        assert_eq!(layout.len(), 3);
+        assert_eq!(layout[2].name, long_name);
+
+        let mut sender = BundleTracker::from_layout(layout);
+        let mut receiver = BundleTracker::streaming();
+        let mut sent_events = Vec::new();
+        let mut received_events = Vec::new();
+        let mut done = 0u64;
+        for chunk in bytes.chunks(300) {
+            done += chunk.len() as u64;
+            sent_events.extend(sender.advance(done));
+            receiver.observe(chunk).expect("observe");
+            received_events.extend(receiver.advance(done));
+        }
+
+        let summary = |events: &[TransferEvent]| {
+            events
+                .iter()
+                .filter_map(|event| match event {
+                    TransferEvent::FileStarted { index, name, size } => {
+                        Some(format!("start {index} {name} {size}"))
+                    }
+                    TransferEvent::FileFinished { index } => Some(format!("finish {index}")),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(summary(&sent_events), summary(&received_events));
+        assert_eq!(
+            summary(&sent_events),
+            vec![
+                "start 0 a.txt 700".to_string(),
+                "finish 0".to_string(),
+                "start 1 empty.txt 0".to_string(),
+                "finish 1".to_string(),
+                format!("start 2 {long_name} 4"),
+                "finish 2".to_string(),
+            ]
+        );
+        assert!(sent_events.iter().any(|event| matches!(
+            event,
+            TransferEvent::FileProgress {
+                index: 0,
+                done: 700,
+                total: 700
+            }
+        )));
+
+        let _ = fs::remove_file(build.bundle_path);
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn streaming_tracker_rejects_an_overflowing_entry_size() {
+        let mut header = tar::Header::new_gnu();
+        header.set_path("huge.bin").expect("path");
+        header.set_entry_type(tar::EntryType::Regular);
+        // Written in base-256, as no octal field holds it.
+        header.set_size(u64::MAX);
+        header.set_cksum();
+
+        let mut receiver = BundleTracker::streaming();
+        assert!(receiver.observe(header.as_bytes()).is_err());
+        // The rest of such an archive is not parsed.
+        assert!(receiver.observe(&[0u8; 512]).is_ok());
+    }
+
     fn temp_test_dir(label: &str) -> PathBuf {
         let stamp = SystemTime::now()
             .duration_since(UNIX_EPOCH)
@@ -0,0 +1,136 @@
+use serde::Serialize;
+
+use crate::alpn;
+use crate::compression;
+use crate::crypto::{
+    FEATURE_COMPRESSION, FEATURE_DEDUP, FEATURE_PREVIEW, FEATURE_RESUME, FEATURE_SEQUENCE,
+    FEATURE_SYNC, FEATURE_VERIFY_CHUNKS, FEATURE_ZSTD_ARCHIVE,
+};
+use crate::event_record::TRANSFER_EVENT_SCHEMA_VERSION;
+use crate::extract;
+
+/// What this build can do, for frontends that hide UI a binary does not
+/// support. The CLI (`capabilities --json`) and the Android bindings both
+/// report [`capabilities`], so they always agree. New flags are only ever
+/// added; a frontend should treat a missing flag as `false`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Capabilities {
+    /// Version of the core transfer engine.
+    pub version: &'static str,
+    pub schema_version: &'static str,
+    /// Wire protocol versions this build speaks, newest first.
+    pub protocol_versions: &'static [u32],
+    /// Several files in one transfer, sent as a bundle.
+    pub multi_file: bool,
+    /// Directories, sent as a tar archive the receiver extracts.
+    pub archive: bool,
+    /// Skip files that fail instead of aborting a multi-file transfer.
+    pub keep_going: bool,
+    /// Stream a file that is still being written (`send --follow`).
+    pub follow: bool,
+    /// Plain TCP transfers on a LAN, without iroh.
+    pub tcp: bool,
+    /// Listen mode that serves several senders (`receive --keep-listening`).
+    pub keep_listening: bool,
+    /// `.blake3` sidecar files next to received files.
+    pub checksum_sidecar: bool,
+    /// Skipping files the receiver already has (`receive --dedup`).
+    pub dedup: bool,
+    /// Formats accepted by `--code-format`.
+    pub code_formats: &'static [&'static str],
+    /// Continue an interrupted transfer where it stopped.
+    pub resume: bool,
+    /// Compress data on the wire with zstd (`send --compress-level`), and
+    /// decompress it when receiving. Needs the `compression` feature.
+    pub compression: bool,
+    /// Compress a folder's archive as one zstd stream
+    /// (`send --archive-level`), and extract such archives when receiving.
+    /// Needs the `compression` feature.
+    pub zstd_archive: bool,
+    /// Number each data chunk so reordering is caught as it happens
+    /// (`send --sequence-chunks`).
+    pub sequence_numbers: bool,
+    /// Check each segment against a hash sent up front, so corruption
+    /// stops the transfer where it happens (`send --verify-chunks`).
+    pub verify_chunks: bool,
+    /// Send only the files of a folder the receiver does not already have
+    /// unchanged (`send --sync`, `receive --sync`).
+    pub sync: bool,
+    /// Show a receiver the first bytes of a file before it accepts the
+    /// rest (`receive --preview`).
+    pub preview: bool,
+    /// Archive formats a receiver unpacks with `receive --extract`, by
+    /// extension.
+    pub extract: &'static [&'static str],
+}
+
+/// Capabilities of this build.
+pub fn capabilities() -> Capabilities {
+    Capabilities {
+        version: env!("CARGO_PKG_VERSION"),
+        schema_version: TRANSFER_EVENT_SCHEMA_VERSION,
+        protocol_versions: alpn::SUPPORTED_VERSIONS,
+        multi_file: true,
+        archive: true,
+        keep_going: true,
+        follow: true,
+        tcp: true,
+        keep_listening: true,
+        checksum_sidecar: true,
+        dedup: true,
+        code_formats: &["hex", "words"],
+        resume: true,
+        compression: compression::SUPPORTED,
+        zstd_archive: compression::SUPPORTED,
+        sequence_numbers: true,
+        verify_chunks: true,
+        sync: true,
+        preview: true,
+        extract: extract::FORMATS,
+    }
+}
+
+/// [`capabilities`] as the `FEATURE_*` bits this build announces in its
+/// [`crate::crypto::HandshakePayload`].
+pub fn feature_bits() -> u64 {
+    let caps = capabilities();
+    [
+        (caps.dedup, FEATURE_DEDUP),
+        (caps.resume, FEATURE_RESUME),
+        (caps.compression, FEATURE_COMPRESSION),
+        (caps.zstd_archive, FEATURE_ZSTD_ARCHIVE),
+        (caps.sequence_numbers, FEATURE_SEQUENCE),
+        (caps.verify_chunks, FEATURE_VERIFY_CHUNKS),
+        (caps.sync, FEATURE_SYNC),
+        (caps.preview, FEATURE_PREVIEW),
+    ]
+    .into_iter()
+    .filter(|(supported, _)| *supported)
+    .fold(0, |bits, (_, bit)| bits | bit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{capabilities, feature_bits};
+    use crate::crypto::{FEATURE_DEDUP, FEATURE_RESUME, FEATURE_SEQUENCE};
+
+    #[test]
+    fn capabilities_serialize_as_flat_flags() {
+        let json = serde_json::to_value(capabilities()).unwrap();
+        assert_eq!(json["protocol_versions"][0], 2);
+        assert_eq!(json["multi_file"], true);
+        assert_eq!(json["resume"], true);
+        assert_eq!(json["code_formats"], serde_json::json!(["hex", "words"]));
+    }
+
+    #[test]
+    fn feature_bits_follow_the_flags() {
+        let caps = capabilities();
+        assert_eq!(feature_bits() & FEATURE_DEDUP != 0, caps.dedup);
+        assert_eq!(feature_bits() & FEATURE_RESUME != 0, caps.resume);
+        assert_eq!(
+            feature_bits() & FEATURE_SEQUENCE != 0,
+            caps.sequence_numbers
+        );
+    }
+}
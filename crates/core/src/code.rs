@@ -0,0 +1,189 @@
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{bail, Result};
+
+/// Most hex groups or words a verification code may have. The handshake
+/// hash has room for more, but longer codes stop being easy to compare.
+pub const MAX_CODE_LENGTH: usize = 8;
+
+/// How the verification code derived from the handshake hash is shown.
+/// Both peers derive it from the same hash, so peers using the same format
+/// always show the same code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CodeFormat {
+    /// Groups of four hex digits, `1a2b-3c4d` with two groups. Each group
+    /// carries 16 bits.
+    Hex { groups: usize },
+    /// Words from [`WORDS`], `maple-otter-quartz` with three words. Each
+    /// word carries 8 bits.
+    Words { count: usize },
+}
+
+impl Default for CodeFormat {
+    fn default() -> Self {
+        CodeFormat::Hex { groups: 2 }
+    }
+}
+
+impl FromStr for CodeFormat {
+    type Err = anyhow::Error;
+
+    /// Parses `hex`, `words`, or either with a length such as `words:4`.
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim().to_ascii_lowercase();
+        let (kind, length) = match s.split_once(':') {
+            Some((kind, length)) => {
+                let length: usize = length
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("invalid code length '{}'", length))?;
+                (kind, Some(length))
+            }
+            None => (s.as_str(), None),
+        };
+        if length.is_some_and(|length| !(1..=MAX_CODE_LENGTH).contains(&length)) {
+            bail!("code length must be between 1 and {}", MAX_CODE_LENGTH);
+        }
+        match kind {
+            "hex" => Ok(CodeFormat::Hex {
+                groups: length.unwrap_or(2),
+            }),
+            "words" => Ok(CodeFormat::Words {
+                count: length.unwrap_or(3),
+            }),
+            other => bail!(
+                "unknown code format '{}' (expected hex or words, optionally with :N)",
+                other
+            ),
+        }
+    }
+}
+
+impl fmt::Display for CodeFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CodeFormat::Hex { groups } => write!(f, "hex:{}", groups),
+            CodeFormat::Words { count } => write!(f, "words:{}", count),
+        }
+    }
+}
+
+/// Verification code of one handshake, ready to render in any
+/// [`CodeFormat`].
+#[derive(Debug, Clone)]
+pub struct VerificationCode {
+    handshake_hash: Vec<u8>,
+}
+
+impl VerificationCode {
+    pub(crate) fn new(handshake_hash: &[u8]) -> Self {
+        Self {
+            handshake_hash: handshake_hash.to_vec(),
+        }
+    }
+
+    /// The code as shown to users, taken from the start of the handshake
+    /// hash. Lengths above [`MAX_CODE_LENGTH`] are clamped.
+    pub fn render(&self, format: CodeFormat) -> String {
+        let parts: Vec<String> = match format {
+            CodeFormat::Hex { groups } => self
+                .handshake_hash
+                .chunks(2)
+                .take(groups.clamp(1, MAX_CODE_LENGTH))
+                .map(|group| group.iter().map(|b| format!("{:02x}", b)).collect())
+                .collect(),
+            CodeFormat::Words { count } => self
+                .handshake_hash
+                .iter()
+                .take(count.clamp(1, MAX_CODE_LENGTH))
+                .map(|&b| WORDS[b as usize].to_string())
+                .collect(),
+        };
+        parts.join("-")
+    }
+}
+
+impl fmt::Display for VerificationCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.render(CodeFormat::default()))
+    }
+}
+
+/// One word per byte value, chosen to be short and hard to mishear.
+pub const WORDS: [&str; 256] = [
+    "acorn", "acre", "agent", "alarm", "album", "alpine", "amber", "anchor", "angle", "apple",
+    "apron", "arch", "arena", "armor", "arrow", "aspen", "atlas", "attic", "autumn", "badge",
+    "bagel", "bamboo", "banjo", "barley", "basil", "basin", "beacon", "beach", "beaver", "berry",
+    "birch", "bison", "blade", "blanket", "bloom", "bonnet", "border", "bottle", "boulder",
+    "bramble", "breeze", "brick", "bridge", "brook", "broom", "bucket", "bugle", "butter",
+    "button", "cabin", "cactus", "camel", "candle", "canoe", "canyon", "carbon", "cargo", "carpet",
+    "castle", "cedar", "cello", "chalk", "cherry", "chess", "cider", "cinder", "circle", "citrus",
+    "clay", "cliff", "clock", "cloud", "clover", "cobalt", "comet", "copper", "coral", "cotton",
+    "cougar", "crane", "crater", "crown", "crystal", "cuckoo", "cumin", "daisy", "dawn", "delta",
+    "denim", "desert", "dingo", "dolphin", "donkey", "dragon", "drift", "drum", "dune", "eagle",
+    "ember", "emerald", "engine", "falcon", "feather", "fennel", "fern", "ferry", "fiddle",
+    "field", "flint", "forest", "fossil", "fox", "frost", "galaxy", "garden", "garnet", "gecko",
+    "ginger", "glacier", "glade", "globe", "goose", "granite", "grape", "gravel", "grove", "gull",
+    "hammer", "harbor", "harvest", "hazel", "heron", "hickory", "honey", "hornet", "husky",
+    "igloo", "indigo", "iris", "island", "ivory", "jade", "jasmine", "jelly", "jungle", "kayak",
+    "kettle", "kiwi", "koala", "ladder", "lagoon", "lantern", "lark", "lava", "lemon", "lily",
+    "lime", "linen", "lizard", "lobster", "lotus", "magnet", "mango", "maple", "marble", "meadow",
+    "melon", "meteor", "mint", "mirror", "moose", "moss", "motor", "mural", "nectar", "needle",
+    "nickel", "nutmeg", "oasis", "ocean", "olive", "onyx", "orbit", "orchid", "otter", "owl",
+    "oyster", "paddle", "panda", "paper", "parrot", "peach", "pebble", "pepper", "piano", "pilot",
+    "pine", "planet", "plum", "pocket", "pollen", "pony", "poppy", "prairie", "puffin", "pumpkin",
+    "quartz", "quill", "rabbit", "radar", "raven", "reef", "ribbon", "river", "robin", "rocket",
+    "rose", "ruby", "saddle", "sage", "salmon", "sandal", "satin", "scarf", "shadow", "shell",
+    "silver", "sky", "sparrow", "spice", "spruce", "squid", "star", "stone", "summit", "sun",
+    "swan", "thistle", "tiger", "timber", "toast", "topaz", "torch", "tulip", "tundra", "turtle",
+    "valley", "velvet", "violet", "walnut", "walrus", "willow", "window", "winter", "wolf",
+    "zebra",
+];
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::{CodeFormat, VerificationCode, WORDS};
+
+    fn code() -> VerificationCode {
+        VerificationCode::new(&[0x1a, 0x2b, 0x3c, 0x4d, 0x00, 0xff, 0x10, 0x20])
+    }
+
+    #[test]
+    fn default_format_is_the_classic_hex_code() {
+        assert_eq!(code().render(CodeFormat::default()), "1a2b-3c4d");
+        assert_eq!(code().to_string(), "1a2b-3c4d");
+        assert_eq!(
+            code().render(CodeFormat::Hex { groups: 3 }),
+            "1a2b-3c4d-00ff"
+        );
+    }
+
+    #[test]
+    fn words_take_one_byte_each() {
+        let rendered = code().render(CodeFormat::Words { count: 3 });
+        let expected = [WORDS[0x1a], WORDS[0x2b], WORDS[0x3c]].join("-");
+        assert_eq!(rendered, expected);
+        assert_eq!(WORDS.iter().collect::<HashSet<_>>().len(), WORDS.len());
+    }
+
+    #[test]
+    fn formats_parse_with_optional_lengths() {
+        assert_eq!(
+            "hex".parse::<CodeFormat>().unwrap(),
+            CodeFormat::Hex { groups: 2 }
+        );
+        assert_eq!(
+            "Words:4".parse::<CodeFormat>().unwrap(),
+            CodeFormat::Words { count: 4 }
+        );
+        assert_eq!(
+            "words:4".parse::<CodeFormat>().unwrap().to_string(),
+            "words:4"
+        );
+        assert!("words:0".parse::<CodeFormat>().is_err());
+        assert!("hex:9".parse::<CodeFormat>().is_err());
+        assert!("emoji".parse::<CodeFormat>().is_err());
+    }
+}
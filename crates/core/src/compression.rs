@@ -0,0 +1,94 @@
+use anyhow::{bail, Result};
+
+#[cfg(feature = "compression")]
+use crate::protocol::CHUNK_SIZE;
+
+/// Lowest zstd level `--compress-level` accepts.
+pub const MIN_LEVEL: i32 = 1;
+
+/// Highest zstd level `--compress-level` accepts. zstd goes to 22, but the
+/// levels above 19 need far more memory for little gain on 60 KiB chunks.
+pub const MAX_LEVEL: i32 = 19;
+
+/// Flag byte of a data frame whose data follows as is.
+const RAW: u8 = 0;
+
+/// Flag byte of a data frame whose data follows zstd-compressed.
+const ZSTD: u8 = 1;
+
+/// Bytes in front of the data of every frame of a compressed transfer.
+pub const FLAG_LEN: usize = 1;
+
+/// Whether this build can compress and decompress data frames; see the
+/// `compression` feature.
+pub const SUPPORTED: bool = cfg!(feature = "compression");
+
+/// Compress the data after the flag byte at `flag` in place, at zstd
+/// `level`, and set the flag. Data that does not get smaller, such as
+/// media or archives that are already compressed, is left raw, so a frame
+/// never grows by more than its flag byte.
+#[cfg_attr(not(feature = "compression"), allow(clippy::ptr_arg))]
+pub fn pack(frame: &mut Vec<u8>, flag: usize, level: i32) {
+    frame[flag] = RAW;
+    #[cfg(feature = "compression")]
+    {
+        let data = &frame[flag + FLAG_LEN..];
+        if let Ok(packed) = zstd::bulk::compress(data, level) {
+            if packed.len() < data.len() {
+                frame.truncate(flag + FLAG_LEN);
+                frame.extend_from_slice(&packed);
+                frame[flag] = ZSTD;
+            }
+        }
+    }
+    #[cfg(not(feature = "compression"))]
+    let _ = level;
+}
+
+/// The data of a frame written by [`pack`], with its flag byte removed.
+/// A compressed frame may hold at most
+/// [`CHUNK_SIZE`](crate::protocol::CHUNK_SIZE) bytes of data.
+pub fn unpack(mut frame: Vec<u8>) -> Result<Vec<u8>> {
+    match frame.first() {
+        Some(&RAW) => {
+            frame.drain(..FLAG_LEN);
+            Ok(frame)
+        }
+        #[cfg(feature = "compression")]
+        Some(&ZSTD) => zstd::bulk::decompress(&frame[FLAG_LEN..], CHUNK_SIZE)
+            .map_err(|err| anyhow::anyhow!("corrupt compressed chunk: {}", err)),
+        #[cfg(not(feature = "compression"))]
+        Some(&ZSTD) => bail!("received a compressed chunk, but this build cannot decompress"),
+        Some(flag) => bail!("unknown chunk compression flag {}", flag),
+        None => bail!("compressed chunk has no flag byte"),
+    }
+}
+
+#[cfg(all(test, feature = "compression"))]
+mod tests {
+    use super::{pack, unpack, FLAG_LEN};
+
+    #[test]
+    fn packed_frames_unpack_to_their_data() {
+        let text: Vec<u8> = b"all work and no play makes jack a dull boy\n"
+            .iter()
+            .copied()
+            .cycle()
+            .take(60 * 1024)
+            .collect();
+        let mut frame = [vec![0u8; FLAG_LEN], text.clone()].concat();
+        pack(&mut frame, 0, 3);
+        assert!(frame.len() < text.len() / 10, "{} bytes", frame.len());
+        assert_eq!(unpack(frame).unwrap(), text);
+
+        let mut noise = vec![0u8; 4096];
+        blake3::Hasher::new().finalize_xof().fill(&mut noise);
+        let mut frame = [vec![0u8; FLAG_LEN], noise.clone()].concat();
+        pack(&mut frame, 0, 19);
+        assert_eq!(frame.len(), noise.len() + FLAG_LEN);
+        assert_eq!(unpack(frame).unwrap(), noise);
+
+        assert!(unpack(vec![7, 1, 2]).is_err());
+        assert!(unpack(vec![1, 0xde, 0xad]).is_err());
+    }
+}
@@ -1,7 +1,13 @@
 use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
 use snow::{Builder, TransportState};
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
+use crate::alpn;
+use crate::capabilities;
+use crate::code::VerificationCode;
+use crate::endpoint;
+
 /// Noise protocol pattern: NN (no static keys, ephemeral-only).
 /// Cipher: ChaChaPoly.  DH: 25519.  Hash: BLAKE2s.
 const NOISE_PATTERN: &str = "Noise_NN_25519_ChaChaPoly_BLAKE2s";
@@ -9,60 +15,177 @@ const NOISE_PATTERN: &str = "Noise_NN_25519_ChaChaPoly_BLAKE2s";
 /// Maximum Noise transport message (ciphertext) size.
 const NOISE_MAX_MSG: usize = 65535;
 
+/// Domain separator at the start of every prologue.
+const PROLOGUE_LABEL: &[u8] = b"p2p-share noise prologue";
+
+/// Bytes both peers mix into the handshake transcript, binding it to the
+/// `transport` it runs over, the ALPN identifiers `offered` for the
+/// connection, the `negotiated` one among them (which names the version
+/// and role) and to [`NOISE_PATTERN`] (which names the cipher). If the
+/// peers disagree on any of them, or someone on the path rewrote them, the
+/// handshake fails.
+pub fn prologue(transport: &str, offered: &[Vec<u8>], negotiated: &[u8]) -> Vec<u8> {
+    [
+        PROLOGUE_LABEL,
+        b"\0",
+        transport.as_bytes(),
+        b"\0",
+        &offered.join(&b","[..]),
+        b"\0",
+        negotiated,
+        b"\0",
+        NOISE_PATTERN.as_bytes(),
+    ]
+    .concat()
+}
+
 // ─── Handshake ──────────────────────────────────────────────────────────────
 
+/// [`HandshakePayload::features`] bit: answers a duplicate with
+/// [`crate::protocol::ALREADY_HAVE_ACK`] (`receive --dedup`) and understands
+/// that answer as a sender.
+pub const FEATURE_DEDUP: u64 = 1 << 0;
+/// [`HandshakePayload::features`] bit: continues interrupted transfers.
+pub const FEATURE_RESUME: u64 = 1 << 1;
+/// [`HandshakePayload::features`] bit: decompresses
+/// [`crate::protocol::FileHeader::compression_level`] data frames.
+pub const FEATURE_COMPRESSION: u64 = 1 << 2;
+/// [`HandshakePayload::features`] bit: understands
+/// [`crate::protocol::FileHeader::sequenced`] transfers.
+pub const FEATURE_SEQUENCE: u64 = 1 << 3;
+/// [`HandshakePayload::features`] bit: checks
+/// [`crate::protocol::FileHeader::segment_size`] segment hashes.
+pub const FEATURE_VERIFY_CHUNKS: u64 = 1 << 4;
+/// [`HandshakePayload::features`] bit: answers a
+/// [`crate::protocol::SYNC_PREFIX`] request with a manifest or a refusal.
+pub const FEATURE_SYNC: u64 = 1 << 5;
+/// [`HandshakePayload::features`] bit: answers a
+/// [`crate::protocol::HeaderAck::Preview`] with the head of the file.
+pub const FEATURE_PREVIEW: u64 = 1 << 6;
+/// [`HandshakePayload::features`] bit: extracts
+/// [`crate::protocol::FileHeader::zstd_archive`] bundles.
+pub const FEATURE_ZSTD_ARCHIVE: u64 = 1 << 7;
+
+/// Application metadata each peer sends inside its handshake message, so
+/// version and feature negotiation costs no extra round trip. Encoded as
+/// JSON; unknown fields are ignored so either side can add some.
+///
+/// The initiator's payload travels before any key is agreed. It is bound
+/// to the handshake hash, so tampering fails the handshake, but it is not
+/// confidential and must not carry secrets.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HandshakePayload {
+    /// Core version of the peer.
+    #[serde(default)]
+    pub version: String,
+    /// `FEATURE_*` bits the peer supports.
+    #[serde(default)]
+    pub features: u64,
+    /// `FEATURE_*` bits the peer would like to use for this transfer.
+    #[serde(default)]
+    pub wants: u64,
+    /// Wire protocol versions the peer speaks, see
+    /// [`alpn::SUPPORTED_VERSIONS`]. Lets a peer notice an ALPN downgrade
+    /// to version 1, whose empty prologue binds nothing.
+    #[serde(default)]
+    pub protocols: Vec<u32>,
+}
+
+impl HandshakePayload {
+    /// The payload describing this build, see [`capabilities::feature_bits`].
+    pub fn local() -> Self {
+        Self {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            features: capabilities::feature_bits(),
+            wants: 0,
+            protocols: alpn::SUPPORTED_VERSIONS.to_vec(),
+        }
+    }
+
+    pub fn supports(&self, feature: u64) -> bool {
+        self.features & feature == feature
+    }
+
+    fn encode(&self) -> Result<Vec<u8>> {
+        Ok(serde_json::to_vec(self)?)
+    }
+
+    /// `None` for the empty payload of peers that predate this, and for
+    /// anything that does not parse; the metadata is advisory.
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        if bytes.is_empty() {
+            return None;
+        }
+        serde_json::from_slice(bytes).ok()
+    }
+}
+
 /// Perform the Noise NN handshake as the **initiator** (the receiver/client).
-/// Returns the transport state and a verification code.
+/// Both sides must pass the same `prologue`, see [`prologue`]; an empty one
+/// is the legacy version 1 handshake. `payload` goes into our handshake
+/// message, see [`HandshakePayload`].
+/// Returns the transport state, the verification code to show and the
+/// peer's payload, or `None` when it sent none.
 pub async fn handshake_initiator<R, W>(
     reader: &mut R,
     writer: &mut W,
-) -> Result<(TransportState, String)>
+    prologue: &[u8],
+    payload: &HandshakePayload,
+) -> Result<(TransportState, VerificationCode, Option<HandshakePayload>)>
 where
     R: AsyncRead + Unpin,
     W: AsyncWrite + Unpin,
 {
-    let builder = Builder::new(NOISE_PATTERN.parse()?);
+    let builder = Builder::new(NOISE_PATTERN.parse()?).prologue(prologue);
     let mut handshake = builder.build_initiator()?;
 
     let mut buf = vec![0u8; NOISE_MAX_MSG];
 
     // -> e  (initiator sends ephemeral public key)
-    let len = handshake.write_message(&[], &mut buf)?;
+    let len = handshake.write_message(&payload.encode()?, &mut buf)?;
     send_frame(writer, &buf[..len]).await?;
 
     // <- e, ee  (responder replies)
     let frame = recv_frame(reader).await?;
-    handshake.read_message(&frame, &mut buf)?;
+    let len = handshake
+        .read_message(&frame, &mut buf)
+        .context("handshake failed: the peer speaks a different protocol version or cipher")?;
+    let peer = HandshakePayload::decode(&buf[..len]);
 
     let hash = handshake.get_handshake_hash().to_vec();
     let transport = handshake
         .into_transport_mode()
         .context("failed to enter transport mode")?;
 
-    Ok((transport, verification_code(&hash)))
+    Ok((transport, VerificationCode::new(&hash), peer))
 }
 
 /// Perform the Noise NN handshake as the **responder** (the sender/server).
-/// Returns the transport state and a verification code.
+/// `prologue` is as for [`handshake_initiator`]. A mismatch surfaces on the
+/// initiator, and here when the first transport message fails to decrypt.
+/// Returns as [`handshake_initiator`] does.
 pub async fn handshake_responder<R, W>(
     reader: &mut R,
     writer: &mut W,
-) -> Result<(TransportState, String)>
+    prologue: &[u8],
+    payload: &HandshakePayload,
+) -> Result<(TransportState, VerificationCode, Option<HandshakePayload>)>
 where
     R: AsyncRead + Unpin,
     W: AsyncWrite + Unpin,
 {
-    let builder = Builder::new(NOISE_PATTERN.parse()?);
+    let builder = Builder::new(NOISE_PATTERN.parse()?).prologue(prologue);
     let mut handshake = builder.build_responder()?;
 
     let mut buf = vec![0u8; NOISE_MAX_MSG];
 
     // -> e  (read initiator's ephemeral public key)
     let frame = recv_frame(reader).await?;
-    handshake.read_message(&frame, &mut buf)?;
+    let len = handshake.read_message(&frame, &mut buf)?;
+    let peer = HandshakePayload::decode(&buf[..len]);
 
     // <- e, ee  (respond with our ephemeral key)
-    let len = handshake.write_message(&[], &mut buf)?;
+    let len = handshake.write_message(&payload.encode()?, &mut buf)?;
     send_frame(writer, &buf[..len]).await?;
 
     let hash = handshake.get_handshake_hash().to_vec();
@@ -70,24 +193,21 @@ where
         .into_transport_mode()
         .context("failed to enter transport mode")?;
 
-    Ok((transport, verification_code(&hash)))
-}
-
-// ─── Verification code ──────────────────────────────────────────────────────
-
-/// Derive a short human-readable verification code from the handshake hash.
-/// Format: `xxxx-xxxx` (8 hex chars from the first 4 bytes of the hash).
-fn verification_code(handshake_hash: &[u8]) -> String {
-    let a = hex::encode(&handshake_hash[..2]);
-    let b = hex::encode(&handshake_hash[2..4]);
-    format!("{}-{}", a, b)
+    Ok((transport, VerificationCode::new(&hash), peer))
 }
 
-/// Tiny hex encoder (avoids adding a `hex` crate dependency).
-mod hex {
-    pub fn encode(bytes: &[u8]) -> String {
-        bytes.iter().map(|b| format!("{:02x}", b)).collect()
-    }
+/// A transport state shared with no peer, to hold the place of a real one
+/// while that is moved to another thread.
+pub(crate) fn placeholder_transport() -> Result<TransportState> {
+    let mut initiator = Builder::new(NOISE_PATTERN.parse()?).build_initiator()?;
+    let mut responder = Builder::new(NOISE_PATTERN.parse()?).build_responder()?;
+    let mut message = [0u8; 64];
+    let mut payload = [0u8; 64];
+    let len = initiator.write_message(&[], &mut message)?;
+    responder.read_message(&message[..len], &mut payload)?;
+    let len = responder.write_message(&[], &mut message)?;
+    initiator.read_message(&message[..len], &mut payload)?;
+    Ok(initiator.into_transport_mode()?)
 }
 
 // ─── Encrypted framing ─────────────────────────────────────────────────────
@@ -99,9 +219,25 @@ pub async fn encrypted_write<W: AsyncWrite + Unpin>(
     transport: &mut TransportState,
     plaintext: &[u8],
 ) -> Result<()> {
+    let ciphertext = encrypt(transport, plaintext)?;
+    send_frame(writer, &ciphertext).await
+}
+
+/// Encrypt `plaintext` into a ciphertext message without sending it.
+/// Messages must be sent in the order they were encrypted.
+pub fn encrypt(transport: &mut TransportState, plaintext: &[u8]) -> Result<Vec<u8>> {
     let mut ciphertext = vec![0u8; plaintext.len() + 16]; // 16-byte AEAD tag
     let len = transport.write_message(plaintext, &mut ciphertext)?;
-    send_frame(writer, &ciphertext[..len]).await
+    ciphertext.truncate(len);
+    Ok(ciphertext)
+}
+
+/// Send a ciphertext produced by [`encrypt`] as a length-prefixed frame.
+pub async fn write_ciphertext<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    ciphertext: &[u8],
+) -> Result<()> {
+    send_frame(writer, ciphertext).await
 }
 
 /// Read a length-prefixed encrypted frame, decrypt it, return the plaintext.
@@ -118,27 +254,241 @@ pub async fn encrypted_read<R: AsyncRead + Unpin>(
 
 // ─── Raw framing helpers ────────────────────────────────────────────────────
 
+/// Error a frame could not be sent or read with because the connection
+/// dropped: the peer reset it, timed out or went away partway through a
+/// frame. Wraps the I/O error as its source. Reported as an `Error` event
+/// with code `connection_lost`.
+#[derive(Debug)]
+pub struct ConnectionLost;
+
+impl std::fmt::Display for ConnectionLost {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("the connection to the peer was lost")
+    }
+}
+
+impl std::error::Error for ConnectionLost {}
+
+/// `err` from the stream of a frame, marked [`ConnectionLost`] when it is
+/// the connection dropping rather than any other I/O failure.
+fn frame_error(err: std::io::Error) -> anyhow::Error {
+    let err = anyhow::Error::new(err);
+    if endpoint::is_connection_lost(&err) {
+        err.context(ConnectionLost)
+    } else {
+        err
+    }
+}
+
 /// Send a length-prefixed frame: [4-byte BE len][data].
 async fn send_frame<W: AsyncWrite + Unpin>(writer: &mut W, data: &[u8]) -> Result<()> {
     if data.len() > NOISE_MAX_MSG {
         bail!("frame too large: {} bytes", data.len());
     }
     let len_bytes = (data.len() as u32).to_be_bytes();
-    writer.write_all(&len_bytes).await?;
-    writer.write_all(data).await?;
-    writer.flush().await?;
+    writer.write_all(&len_bytes).await.map_err(frame_error)?;
+    writer.write_all(data).await.map_err(frame_error)?;
+    writer.flush().await.map_err(frame_error)?;
     Ok(())
 }
 
 /// Read a length-prefixed frame.
 async fn recv_frame<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Vec<u8>> {
     let mut len_bytes = [0u8; 4];
-    reader.read_exact(&mut len_bytes).await?;
+    reader
+        .read_exact(&mut len_bytes)
+        .await
+        .map_err(frame_error)?;
     let len = u32::from_be_bytes(len_bytes) as usize;
     if len > NOISE_MAX_MSG {
         bail!("frame too large: {} bytes", len);
     }
     let mut buf = vec![0u8; len];
-    reader.read_exact(&mut buf).await?;
+    reader.read_exact(&mut buf).await.map_err(frame_error)?;
     Ok(buf)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+    use super::{
+        encrypted_read, encrypted_write, handshake_initiator, handshake_responder, prologue,
+        recv_frame, send_frame, ConnectionLost, HandshakePayload, FEATURE_DEDUP, FEATURE_RESUME,
+    };
+
+    /// Handshake over an in-memory duplex, then send one message from the
+    /// responder to the initiator.
+    async fn handshake_with(
+        initiator_prologue: &[u8],
+        responder_prologue: &[u8],
+    ) -> anyhow::Result<Vec<u8>> {
+        let (left, right) = tokio::io::duplex(64 * 1024);
+        let (mut left_read, mut left_write) = tokio::io::split(left);
+        let (mut right_read, mut right_write) = tokio::io::split(right);
+        let payload = HandshakePayload::default();
+        let responder = async {
+            let (mut transport, _, _) = handshake_responder(
+                &mut left_read,
+                &mut left_write,
+                responder_prologue,
+                &payload,
+            )
+            .await?;
+            encrypted_write(&mut left_write, &mut transport, b"hello").await
+        };
+        let initiator = async {
+            let (mut transport, _, _) = handshake_initiator(
+                &mut right_read,
+                &mut right_write,
+                initiator_prologue,
+                &payload,
+            )
+            .await?;
+            encrypted_read(&mut right_read, &mut transport).await
+        };
+        let (responded, received) = tokio::join!(responder, initiator);
+        responded?;
+        received
+    }
+
+    #[tokio::test]
+    async fn matching_prologues_complete_the_handshake() {
+        let prologue = prologue("iroh", &[b"p2p-share/2".to_vec()], b"p2p-share/2");
+        let received = handshake_with(&prologue, &prologue)
+            .await
+            .expect("handshake");
+        assert_eq!(received, b"hello");
+    }
+
+    #[tokio::test]
+    async fn handshake_payloads_reach_the_other_side() {
+        let (left, right) = tokio::io::duplex(64 * 1024);
+        let (mut left_read, mut left_write) = tokio::io::split(left);
+        let (mut right_read, mut right_write) = tokio::io::split(right);
+        let sender = HandshakePayload {
+            version: "9.9.9".to_string(),
+            features: FEATURE_DEDUP | FEATURE_RESUME,
+            wants: FEATURE_RESUME,
+            protocols: vec![2, 1],
+        };
+        let prologue = prologue("iroh", &[b"p2p-share/2".to_vec()], b"p2p-share/2");
+        let local = HandshakePayload::local();
+        let (responded, initiated) = tokio::join!(
+            handshake_responder(&mut left_read, &mut left_write, &prologue, &sender),
+            handshake_initiator(&mut right_read, &mut right_write, &prologue, &local),
+        );
+        let (_, _, from_receiver) = responded.expect("responder");
+        let (_, _, from_sender) = initiated.expect("initiator");
+        assert_eq!(from_receiver, Some(HandshakePayload::local()));
+        let from_sender = from_sender.expect("payload");
+        assert!(from_sender.supports(FEATURE_DEDUP));
+        assert_eq!(from_sender, sender);
+
+        // Peers from before payloads existed send nothing.
+        assert_eq!(HandshakePayload::decode(&[]), None);
+        assert_eq!(
+            HandshakePayload::decode(br#"{"version":"1.0.0","future":true}"#),
+            Some(HandshakePayload {
+                version: "1.0.0".to_string(),
+                ..HandshakePayload::default()
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn mismatched_prologues_fail_the_handshake() {
+        let offered = [b"p2p-share/2".to_vec(), b"p2p-share/1".to_vec()];
+        let expected = prologue("iroh", &offered, b"p2p-share/2");
+        let err = handshake_with(&expected, &prologue("iroh", &offered, b"p2p-share/3"))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("handshake failed"), "{err:#}");
+        // A rewritten offer or another transport fails the same way.
+        let stripped = prologue("iroh", &offered[..1], b"p2p-share/2");
+        assert!(handshake_with(&expected, &stripped).await.is_err());
+        let tcp = prologue("tcp", &offered, b"p2p-share/2");
+        assert!(handshake_with(&expected, &tcp).await.is_err());
+        assert!(handshake_with(&expected, &[]).await.is_err());
+    }
+
+    /// Lets `left` bytes through, then fails every call with `kind`.
+    struct FailingStream {
+        left: usize,
+        kind: std::io::ErrorKind,
+    }
+
+    impl AsyncRead for FailingStream {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<std::io::Result<()>> {
+            if self.left == 0 {
+                return Poll::Ready(Err(self.kind.into()));
+            }
+            let n = self.left.min(buf.remaining());
+            buf.put_slice(&vec![0u8; n]);
+            self.left -= n;
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    impl AsyncWrite for FailingStream {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<std::io::Result<usize>> {
+            if self.left == 0 {
+                return Poll::Ready(Err(self.kind.into()));
+            }
+            let n = self.left.min(buf.len());
+            self.left -= n;
+            Poll::Ready(Ok(n))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[tokio::test]
+    async fn streams_dropping_mid_frame_are_lost_connections() {
+        use std::io::ErrorKind;
+
+        // The stream ends halfway through the length prefix.
+        let mut reader = FailingStream {
+            left: 2,
+            kind: ErrorKind::UnexpectedEof,
+        };
+        let err = recv_frame(&mut reader).await.unwrap_err();
+        assert!(err.is::<ConnectionLost>(), "{err:#}");
+
+        let mut writer = FailingStream {
+            left: 10,
+            kind: ErrorKind::ConnectionReset,
+        };
+        let err = send_frame(&mut writer, &[7u8; 100]).await.unwrap_err();
+        assert!(err.is::<ConnectionLost>(), "{err:#}");
+        assert_eq!(
+            err.downcast_ref::<std::io::Error>().map(|err| err.kind()),
+            Some(ErrorKind::ConnectionReset)
+        );
+
+        // Other I/O failures are reported as they are.
+        let mut writer = FailingStream {
+            left: 2,
+            kind: ErrorKind::PermissionDenied,
+        };
+        let err = send_frame(&mut writer, b"data").await.unwrap_err();
+        assert!(!err.is::<ConnectionLost>(), "{err:#}");
+    }
+}
@@ -0,0 +1,162 @@
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use iroh::{Endpoint, NodeAddr, Watcher as _};
+use serde::Serialize;
+
+use crate::endpoint;
+use crate::ticket;
+
+/// ALPN used only by the diagnostics self-dial.
+const DOCTOR_ALPN: &[u8] = b"p2p-share/doctor";
+
+/// How long to wait for local address discovery.
+const ADDRESS_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long the self-dial may take before it is reported as failed.
+const SELF_DIAL_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Connectivity report produced by [`run_doctor`].
+#[derive(Debug, Clone, Serialize)]
+pub struct DoctorReport {
+    pub node_id: String,
+    pub relay_connected: bool,
+    pub relay_url: Option<String>,
+    pub addresses: Vec<AddressReport>,
+    /// Addresses this node appears as from outside its NAT, as observed
+    /// through the relay or a port mapping. Never part of the ticket filter.
+    pub public_addresses: Vec<String>,
+    pub ticket_len: usize,
+    pub self_dial: SelfDialReport,
+}
+
+/// One locally discovered direct address.
+#[derive(Debug, Clone, Serialize)]
+pub struct AddressReport {
+    pub addr: String,
+    /// How iroh discovered the address (`local`, `qad`, `portmap`, ...).
+    pub origin: String,
+    /// Whether the address survives ticket filtering.
+    pub advertised: bool,
+}
+
+/// Outcome of dialing this node from a second local endpoint using the
+/// filtered ticket.
+#[derive(Debug, Clone, Serialize)]
+pub struct SelfDialReport {
+    pub ok: bool,
+    pub connection_path: Option<String>,
+    pub latency_ms: Option<f64>,
+    pub error: Option<String>,
+}
+
+/// Build an endpoint, wait for the relay, inspect the local addresses and
+/// the ticket filtering applied to them, then dial the endpoint from a
+/// second one through the generated ticket.
+pub async fn run_doctor() -> Result<DoctorReport> {
+    let ep = Endpoint::builder()
+        .alpns(vec![DOCTOR_ALPN.to_vec()])
+        .bind()
+        .await
+        .context("failed to create iroh endpoint")?;
+
+    let (relay_url, addresses, public_addresses, ticket_str, self_dial) =
+        endpoint::close_after(&ep, async {
+            let relay_url = endpoint::wait_for_home_relay(&ep, endpoint::RELAY_TIMEOUT).await;
+            let direct = tokio::time::timeout(ADDRESS_TIMEOUT, ep.direct_addresses().initialized())
+                .await
+                .unwrap_or_default();
+
+            let addresses = direct
+                .iter()
+                .map(|direct| AddressReport {
+                    addr: direct.addr.to_string(),
+                    origin: direct.typ.to_string(),
+                    advertised: ticket::is_useful_address(&direct.addr),
+                })
+                .collect();
+            let public_addresses = direct
+                .iter()
+                .filter(|direct| endpoint::is_public_origin(direct.typ))
+                .map(|direct| direct.addr.to_string())
+                .collect();
+
+            let node_addr = NodeAddr::from_parts(
+                ep.node_id(),
+                relay_url.clone(),
+                direct.iter().map(|direct| direct.addr),
+            );
+            let ticket_str = ticket::serialize(&node_addr)?;
+            let self_dial = self_dial(&ep, &ticket_str).await;
+            anyhow::Ok((
+                relay_url,
+                addresses,
+                public_addresses,
+                ticket_str,
+                self_dial,
+            ))
+        })
+        .await?;
+
+    Ok(DoctorReport {
+        node_id: ep.node_id().to_string(),
+        relay_connected: relay_url.is_some(),
+        relay_url: relay_url.map(|url| url.to_string()),
+        addresses,
+        public_addresses,
+        ticket_len: ticket_str.len(),
+        self_dial,
+    })
+}
+
+async fn self_dial(target: &Endpoint, ticket_str: &str) -> SelfDialReport {
+    let failed = |error: String| SelfDialReport {
+        ok: false,
+        connection_path: None,
+        latency_ms: None,
+        error: Some(error),
+    };
+
+    let addr = match ticket::deserialize(ticket_str) {
+        Ok(addr) => addr,
+        Err(err) => return failed(format!("{:#}", err)),
+    };
+    let dialer = match Endpoint::builder().bind().await {
+        Ok(ep) => ep,
+        Err(err) => return failed(format!("failed to create dialer endpoint: {}", err)),
+    };
+
+    let acceptor = target.clone();
+    let accept_task = tokio::spawn(async move {
+        let incoming = acceptor.accept().await?;
+        let conn = incoming.accept().ok()?.await.ok()?;
+        conn.closed().await;
+        Some(())
+    });
+
+    let started = Instant::now();
+    let dial = tokio::time::timeout(SELF_DIAL_TIMEOUT, dialer.connect(addr, DOCTOR_ALPN)).await;
+    let report = match dial {
+        Ok(Ok(conn)) => {
+            let info = dialer.remote_info(target.node_id());
+            let latency = info
+                .as_ref()
+                .and_then(|info| info.latency)
+                .unwrap_or_else(|| started.elapsed());
+            let report = SelfDialReport {
+                ok: true,
+                connection_path: info.map(|info| info.conn_type.to_string()),
+                latency_ms: Some(latency.as_secs_f64() * 1000.0),
+                error: None,
+            };
+            conn.close(0u8.into(), b"done");
+            report
+        }
+        Ok(Err(err)) => failed(format!("{:#}", err)),
+        Err(_) => failed(format!("timed out after {}s", SELF_DIAL_TIMEOUT.as_secs())),
+    };
+
+    dialer.close().await;
+    accept_task.abort();
+    report
+}
@@ -0,0 +1,1249 @@
+use std::fmt;
+use std::future::Future;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+use iroh::defaults::prod;
+use iroh::endpoint::{
+    ConnectOptions, ConnectWithOptsError, Connection, ConnectionError, ConnectionType,
+    DirectAddrType, Incoming, ReadError, TransportConfig, VarInt, WriteError,
+};
+use iroh::{Endpoint, NodeAddr, NodeId, RelayMap, RelayMode, RelayNode, Watcher as _};
+use n0_future::StreamExt;
+
+pub use iroh::RelayUrl;
+
+use crate::alpn::{self, AlpnRole, NegotiatedAlpn};
+use crate::crypto;
+use crate::events::TransferEvent;
+
+/// How long endpoint setup waits for a home relay before continuing with
+/// direct/LAN connectivity only.
+pub const RELAY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long ticket generation waits for the first address discovery before
+/// publishing whatever addresses are known.
+pub const NODE_ADDR_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long a transfer under [`RelayPolicy::Deny`] waits after connecting
+/// for the path to the peer to become direct.
+pub const DIRECT_WAIT: Duration = Duration::from_secs(10);
+
+/// How long probing several configured relays may delay endpoint setup.
+/// Relays that have not answered by then are treated as unreachable.
+pub const RELAY_PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Default number of bidirectional streams a peer may have open at once on
+/// one connection. Every transfer runs over a single stream.
+pub const DEFAULT_MAX_STREAMS: u32 = 1;
+
+/// Keep-alive interval iroh uses by default, kept when the transport
+/// config is replaced.
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// One of the default n0 relay regions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RelayRegion {
+    NorthAmerica,
+    Europe,
+    AsiaPacific,
+}
+
+impl RelayRegion {
+    fn relay_node(self) -> RelayNode {
+        match self {
+            RelayRegion::NorthAmerica => prod::default_na_relay_node(),
+            RelayRegion::Europe => prod::default_eu_relay_node(),
+            RelayRegion::AsiaPacific => prod::default_ap_relay_node(),
+        }
+    }
+}
+
+impl FromStr for RelayRegion {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "na" => Ok(RelayRegion::NorthAmerica),
+            "eu" => Ok(RelayRegion::Europe),
+            "ap" => Ok(RelayRegion::AsiaPacific),
+            other => bail!("unknown relay region '{}' (expected na, eu or ap)", other),
+        }
+    }
+}
+
+impl fmt::Display for RelayRegion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let code = match self {
+            RelayRegion::NorthAmerica => "na",
+            RelayRegion::Europe => "eu",
+            RelayRegion::AsiaPacific => "ap",
+        };
+        f.write_str(code)
+    }
+}
+
+/// Which relay servers an endpoint may pick its home relay from.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum RelaySelection {
+    /// Let iroh pick the lowest-latency default relay.
+    #[default]
+    Auto,
+    /// Only use the default relay of one region.
+    Region(RelayRegion),
+    /// Only use an explicit relay server.
+    Url(RelayUrl),
+    /// Only use these relay servers, preferring the one that answers a
+    /// latency probe fastest.
+    Urls(Vec<RelayUrl>),
+}
+
+impl RelaySelection {
+    fn relay_mode(&self) -> RelayMode {
+        match self {
+            RelaySelection::Auto => RelayMode::Default,
+            RelaySelection::Region(region) => {
+                RelayMode::Custom(RelayMap::from(region.relay_node()))
+            }
+            RelaySelection::Url(url) => RelayMode::Custom(RelayMap::from(url.clone())),
+            RelaySelection::Urls(urls) => {
+                RelayMode::Custom(urls.iter().cloned().collect::<RelayMap>())
+            }
+        }
+    }
+
+    /// Whether `url` is one of the selected relays. Every relay is allowed
+    /// by [`RelaySelection::Auto`].
+    pub fn contains(&self, url: &RelayUrl) -> bool {
+        match self {
+            RelaySelection::Auto => true,
+            RelaySelection::Region(region) => region.relay_node().url == *url,
+            RelaySelection::Url(pinned) => pinned == url,
+            RelaySelection::Urls(urls) => urls.contains(url),
+        }
+    }
+}
+
+impl fmt::Display for RelaySelection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RelaySelection::Auto => f.write_str("automatic"),
+            RelaySelection::Region(region) => {
+                write!(f, "region {} ({})", region, region.relay_node().url)
+            }
+            RelaySelection::Url(url) => write!(f, "{}", url),
+            RelaySelection::Urls(urls) => {
+                let urls: Vec<_> = urls.iter().map(|url| url.to_string()).collect();
+                f.write_str(&urls.join(", "))
+            }
+        }
+    }
+}
+
+/// Parse and validate a user-supplied relay URL.
+pub fn parse_relay_url(value: &str) -> Result<RelayUrl> {
+    let url: RelayUrl = value
+        .trim()
+        .parse()
+        .with_context(|| format!("invalid relay URL '{}'", value.trim()))?;
+    if !matches!(url.scheme(), "http" | "https") {
+        bail!("relay URL must use http or https: {}", url);
+    }
+    if url.host_str().is_none() {
+        bail!("relay URL has no host: {}", url);
+    }
+    Ok(url)
+}
+
+/// QUIC transport settings that let a peer open at most `max_streams`
+/// (at least one) bidirectional streams at once. Opening more waits for
+/// stream credit the endpoint never grants while they are open, and
+/// unidirectional streams, which the protocol does not use, are refused.
+fn transport_config(max_streams: u32) -> TransportConfig {
+    let mut config = TransportConfig::default();
+    config
+        .keep_alive_interval(Some(KEEP_ALIVE_INTERVAL))
+        .max_concurrent_bidi_streams(VarInt::from_u32(max_streams.max(1)))
+        .max_concurrent_uni_streams(VarInt::from_u32(0));
+    config
+}
+
+/// Bind an endpoint accepting `alpns` that uses the selected relays and
+/// lets each peer open up to `max_streams` concurrent streams.
+pub async fn bind(
+    alpns: Vec<Vec<u8>>,
+    relay: &RelaySelection,
+    max_streams: u32,
+) -> Result<Endpoint> {
+    Endpoint::builder()
+        .alpns(alpns)
+        .relay_mode(relay.relay_mode())
+        .transport_config(transport_config(max_streams))
+        .bind()
+        .await
+        .context("failed to create iroh endpoint")
+}
+
+/// The relay an endpoint connected to as its home relay.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HomeRelay {
+    pub url: RelayUrl,
+    /// Round-trip time measured by [`fastest_relay`], when the relay was
+    /// picked by probing.
+    pub latency: Option<Duration>,
+}
+
+/// Bind an endpoint and wait for its home relay. With several explicit
+/// relays, the fastest to answer within [`RELAY_PROBE_TIMEOUT`] is used.
+/// When an explicit relay selection is unreachable within
+/// [`RELAY_TIMEOUT`], `report` is told and the endpoint is rebound with the
+/// default relays, unless `strict` pins the selection.
+pub async fn bind_with_home_relay(
+    alpns: Vec<Vec<u8>>,
+    relay: &RelaySelection,
+    max_streams: u32,
+    strict: bool,
+    mut report: impl FnMut(String),
+) -> Result<(Endpoint, Option<HomeRelay>)> {
+    let mut relay = relay.clone();
+    let mut probed = None;
+    if let RelaySelection::Urls(urls) = &relay {
+        report(format!("Probing {} relays...", urls.len()));
+        match fastest_relay(urls, RELAY_PROBE_TIMEOUT).await {
+            Some((url, latency)) => {
+                report(format!(
+                    "Fastest relay: {} ({:.1}ms)",
+                    url,
+                    latency.as_secs_f64() * 1000.0
+                ));
+                probed = Some(HomeRelay {
+                    url: url.clone(),
+                    latency: Some(latency),
+                });
+                relay = RelaySelection::Url(url);
+            }
+            None => report(
+                "Warning: no relay answered the latency probe; trying all of them.".to_string(),
+            ),
+        }
+    }
+    if relay != RelaySelection::Auto {
+        report(format!("Using relay {}", relay));
+    }
+    let ep = bind(alpns.clone(), &relay, max_streams).await?;
+
+    report("Connecting to relay...".to_string());
+    let home_relay = wait_for_home_relay(&ep, RELAY_TIMEOUT).await.map(|url| {
+        probed
+            .filter(|probed| probed.url == url)
+            .unwrap_or(HomeRelay { url, latency: None })
+    });
+    if home_relay.is_some() || relay == RelaySelection::Auto {
+        return Ok((ep, home_relay));
+    }
+    if strict {
+        report(format!(
+            "Warning: relay {} unreachable (timed out). Not falling back: the relay is pinned.",
+            relay
+        ));
+        return Ok((ep, None));
+    }
+
+    report(format!(
+        "Warning: relay {} unreachable (timed out). Falling back to default relays.",
+        relay
+    ));
+    ep.close().await;
+    let ep = bind(alpns, &RelaySelection::Auto, max_streams).await?;
+    let home_relay = wait_for_home_relay(&ep, RELAY_TIMEOUT)
+        .await
+        .map(|url| HomeRelay { url, latency: None });
+    Ok((ep, home_relay))
+}
+
+/// The [`TransferEvent::RelayStatus`] reporting `home_relay`, the outcome of
+/// waiting for an endpoint's home relay.
+pub fn relay_status(home_relay: Option<&HomeRelay>) -> TransferEvent {
+    TransferEvent::RelayStatus {
+        connected: home_relay.is_some(),
+        url: home_relay.map(|relay| relay.url.to_string()),
+        latency_ms: home_relay
+            .and_then(|relay| relay.latency)
+            .map(|latency| latency.as_secs_f64() * 1000.0),
+    }
+}
+
+/// Wait in the background for the home relay of an endpoint bound with
+/// [`bind`] and pass its [`relay_status`] to `report`, so a dialing side
+/// reports it too without holding up the dial. Nothing is reported if the
+/// returned guard is dropped first.
+pub(crate) fn report_home_relay(
+    ep: &Endpoint,
+    report: impl FnOnce(TransferEvent) + Send + 'static,
+) -> AbortOnDrop {
+    let ep = ep.clone();
+    AbortOnDrop::new(tokio::spawn(async move {
+        let home_relay = wait_for_home_relay(&ep, RELAY_TIMEOUT)
+            .await
+            .map(|url| HomeRelay { url, latency: None });
+        report(relay_status(home_relay.as_ref()));
+    }))
+}
+
+/// Time a TCP handshake with each relay's host, concurrently, and return
+/// the fastest. A handshake is a cheap stand-in for relay latency that
+/// needs no relay protocol. `None` when no relay answered within
+/// `timeout`.
+pub async fn fastest_relay(urls: &[RelayUrl], timeout: Duration) -> Option<(RelayUrl, Duration)> {
+    let probes = urls.iter().map(|url| async move {
+        let latency = tokio::time::timeout(timeout, probe_relay(url))
+            .await
+            .ok()??;
+        Some((url.clone(), latency))
+    });
+    n0_future::join_all(probes)
+        .await
+        .into_iter()
+        .flatten()
+        .min_by_key(|(_, latency)| *latency)
+}
+
+async fn probe_relay(url: &RelayUrl) -> Option<Duration> {
+    let host = url.host_str()?;
+    let port = url.port_or_known_default()?;
+    // Resolve first so DNS time does not count as latency.
+    let addr = tokio::net::lookup_host((host, port)).await.ok()?.next()?;
+    let started = Instant::now();
+    tokio::net::TcpStream::connect(addr).await.ok()?;
+    Some(started.elapsed())
+}
+
+/// Await `work`, then close `ep` whether it succeeded or failed, so no
+/// error path leaves the endpoint and its relay connection running until
+/// it happens to be dropped. A caller that cancels by dropping this future
+/// or aborting its task, as the Android bindings do, still gets the
+/// endpoint closed: the close then runs on a task of its own.
+pub async fn close_after<T>(ep: &Endpoint, work: impl Future<Output = T>) -> T {
+    let mut guard = CloseOnDrop(Some(ep.clone()));
+    let output = work.await;
+    guard.0 = None;
+    ep.close().await;
+    output
+}
+
+/// Closes the endpoint when [`close_after`] is dropped before `work` ended.
+struct CloseOnDrop(Option<Endpoint>);
+
+impl Drop for CloseOnDrop {
+    fn drop(&mut self) {
+        let Some(ep) = self.0.take() else {
+            return;
+        };
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            runtime.spawn(async move { ep.close().await });
+        }
+    }
+}
+
+/// Application close code a single-shot listener uses to turn away senders
+/// that connect while it is already receiving.
+pub(crate) const BUSY_CLOSE_CODE: u32 = 2;
+
+/// Close reason sent with [`BUSY_CLOSE_CODE`].
+pub(crate) const BUSY_CLOSE_REASON: &[u8] = b"receiver busy";
+
+/// Accept and immediately close every further connection to `ep` with
+/// [`BUSY_CLOSE_CODE`], telling `report` about each, until the returned
+/// guard is dropped. Accepting first lets the peer see the reason instead
+/// of timing out.
+pub(crate) fn reject_while_busy(
+    ep: &Endpoint,
+    mut report: impl FnMut(String) + Send + 'static,
+) -> AbortOnDrop {
+    let ep = ep.clone();
+    AbortOnDrop::new(tokio::spawn(async move {
+        while let Some(incoming) = ep.accept().await {
+            if let Some(peer) = reject_busy(incoming).await {
+                report(format!(
+                    "Warning: rejected a second sender ({}); a transfer is already in progress.",
+                    peer
+                ));
+            }
+        }
+    }))
+}
+
+/// Accept `incoming` and close it straight away with [`BUSY_CLOSE_CODE`].
+/// Returns the peer's short node id, or `None` if the connection failed
+/// before it could be told.
+pub(crate) async fn reject_busy(incoming: Incoming) -> Option<String> {
+    let conn = incoming.accept().ok()?.await.ok()?;
+    conn.close(VarInt::from_u32(BUSY_CLOSE_CODE), BUSY_CLOSE_REASON);
+    Some(
+        conn.remote_node_id()
+            .map(|node_id| node_id.fmt_short().to_string())
+            .unwrap_or_else(|_| "unknown".to_string()),
+    )
+}
+
+/// Whether `err` is a listener turning us away with [`BUSY_CLOSE_CODE`].
+pub(crate) fn is_busy_close(err: &ConnectionError) -> bool {
+    matches!(
+        err,
+        ConnectionError::ApplicationClosed(close)
+            if close.error_code == VarInt::from_u32(BUSY_CLOSE_CODE)
+    )
+}
+
+/// Application close code of a transfer stopped under
+/// [`RelayPolicy::Deny`] because its path fell back to a relay.
+pub(crate) const RELAY_CLOSE_CODE: u32 = 3;
+
+/// Close reason sent with [`RELAY_CLOSE_CODE`].
+pub(crate) const RELAY_CLOSE_REASON: &[u8] = b"relay not allowed";
+
+/// Status reported when a transfer drops inside a reconnect window.
+pub(crate) const RECONNECT_STATUS: &str = "Connection lost, waiting to resume...";
+
+/// Pause between reconnection attempts of the connecting side.
+pub(crate) const RECONNECT_RETRY_DELAY: Duration = Duration::from_secs(2);
+
+/// Whether `err` is an established transfer stream dropping: an idle
+/// timeout, a reset, or the peer vanishing mid-frame. A peer closing the
+/// connection on purpose, and failures to connect in the first place, are
+/// not.
+pub(crate) fn is_connection_lost(err: &anyhow::Error) -> bool {
+    fn lost(err: &ConnectionError) -> bool {
+        matches!(err, ConnectionError::TimedOut | ConnectionError::Reset)
+    }
+    err.chain().any(|cause| {
+        let stream_err = match cause.downcast_ref::<std::io::Error>() {
+            Some(io_err)
+                if matches!(
+                    io_err.kind(),
+                    std::io::ErrorKind::UnexpectedEof
+                        | std::io::ErrorKind::ConnectionReset
+                        | std::io::ErrorKind::ConnectionAborted
+                        | std::io::ErrorKind::BrokenPipe
+                ) =>
+            {
+                return true
+            }
+            Some(io_err) => match io_err.get_ref() {
+                Some(inner) => inner as &(dyn std::error::Error + 'static),
+                None => return false,
+            },
+            None => cause,
+        };
+        match (
+            stream_err.downcast_ref::<ReadError>(),
+            stream_err.downcast_ref::<WriteError>(),
+        ) {
+            (Some(ReadError::ConnectionLost(err)), _) => lost(err),
+            (_, Some(WriteError::ConnectionLost(err))) => lost(err),
+            _ => false,
+        }
+    })
+}
+
+/// Whether `err` is an attempt to reach the peer that failed before any
+/// transfer started: no route to it, or a handshake that timed out or was
+/// reset. A peer turning the connection down on purpose is not.
+fn is_connect_failure(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| {
+        if cause.is::<ConnectWithOptsError>() {
+            return true;
+        }
+        if let Some(err) = cause.downcast_ref::<ConnectionError>() {
+            return matches!(err, ConnectionError::TimedOut | ConnectionError::Reset);
+        }
+        cause.downcast_ref::<std::io::Error>().is_some_and(|err| {
+            matches!(
+                err.kind(),
+                std::io::ErrorKind::ConnectionRefused
+                    | std::io::ErrorKind::TimedOut
+                    | std::io::ErrorKind::HostUnreachable
+                    | std::io::ErrorKind::NetworkUnreachable
+            )
+        })
+    })
+}
+
+/// How long a dropped transfer may take to come back. The window starts
+/// at each lost connection; failed attempts to reconnect do not restart it.
+#[derive(Debug)]
+pub(crate) struct ReconnectWindow {
+    window: Option<Duration>,
+    lost_at: Option<Instant>,
+}
+
+impl ReconnectWindow {
+    pub fn new(window: Option<Duration>) -> Self {
+        Self {
+            window,
+            lost_at: None,
+        }
+    }
+
+    /// Whether to try again after `err`: a lost connection, or after one a
+    /// failed attempt to reconnect, while the window lasts. Any other error,
+    /// such as a cancel, a decline or a checksum mismatch, ends the
+    /// transfer. `report` is told when `err` is a fresh connection loss.
+    pub fn retry(&mut self, err: &anyhow::Error, report: impl FnOnce(&str)) -> bool {
+        if self.window.is_none() {
+            return false;
+        }
+        if is_connection_lost(err) {
+            report(RECONNECT_STATUS);
+            self.lost_at = Some(Instant::now());
+        } else if self.lost_at.is_none() || !is_connect_failure(err) {
+            return false;
+        }
+        self.remaining().is_some_and(|left| !left.is_zero())
+    }
+
+    /// Time left to reconnect in; `None` while no connection has been lost.
+    pub fn remaining(&self) -> Option<Duration> {
+        let window = self.window?;
+        Some(window.saturating_sub(self.lost_at?.elapsed()))
+    }
+}
+
+/// Aborts a background task, such as a connection path watcher, when
+/// dropped, so it cannot outlive the transfer it reports on.
+#[derive(Debug)]
+pub(crate) struct AbortOnDrop(tokio::task::JoinHandle<()>);
+
+impl AbortOnDrop {
+    pub fn new(handle: tokio::task::JoinHandle<()>) -> Self {
+        Self(handle)
+    }
+}
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Error for dialing a ticket that leads back to the dialing endpoint,
+/// such as a sender given its own ticket. Both ends would wait for the
+/// other's role, so the handshake would hang instead of failing.
+#[derive(Debug)]
+pub struct SelfConnection;
+
+impl fmt::Display for SelfConnection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("this ticket belongs to this device; use the ticket shown on the other device")
+    }
+}
+
+impl std::error::Error for SelfConnection {}
+
+/// The `self_connection` error event for `err`, if it is a
+/// [`SelfConnection`].
+pub(crate) fn self_connection_event(err: &anyhow::Error) -> Option<TransferEvent> {
+    err.chain()
+        .any(|cause| cause.is::<SelfConnection>())
+        .then(|| TransferEvent::Error {
+            code: "self_connection".to_string(),
+            message: SelfConnection.to_string(),
+            partial_path: None,
+        })
+}
+
+/// The `connection_lost` error event for `err`, if a frame could not be
+/// sent or read because the connection dropped
+/// ([`crypto::ConnectionLost`]).
+pub(crate) fn connection_lost_event(err: &anyhow::Error) -> Option<TransferEvent> {
+    err.chain()
+        .any(|cause| cause.is::<crypto::ConnectionLost>())
+        .then(|| TransferEvent::Error {
+            code: "connection_lost".to_string(),
+            message: format!("{:#}", err),
+            partial_path: None,
+        })
+}
+
+/// Error for a peer that could not be reached at all, wrapping why.
+#[derive(Debug)]
+pub struct ConnectFailed {
+    /// `sender`, `receiver` or the address dialed.
+    pub peer: String,
+}
+
+impl ConnectFailed {
+    pub(crate) fn to(peer: impl Into<String>) -> Self {
+        Self { peer: peer.into() }
+    }
+}
+
+impl fmt::Display for ConnectFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "failed to connect to {}", self.peer)
+    }
+}
+
+impl std::error::Error for ConnectFailed {}
+
+/// Error for a connection that goes through a relay outside the pinned
+/// selection while strict host checking is on.
+#[derive(Debug)]
+pub struct UnexpectedRelay {
+    pub relay: RelayUrl,
+    pub pinned: RelaySelection,
+}
+
+impl fmt::Display for UnexpectedRelay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "connection uses relay {}, not the pinned relay {}",
+            self.relay, self.pinned
+        )
+    }
+}
+
+impl std::error::Error for UnexpectedRelay {}
+
+impl UnexpectedRelay {
+    /// The `unexpected_relay` error event for this error.
+    pub(crate) fn event(&self) -> TransferEvent {
+        TransferEvent::Error {
+            code: "unexpected_relay".to_string(),
+            message: self.to_string(),
+            partial_path: None,
+        }
+    }
+}
+
+/// With `strict`, fail unless `ep`'s home relay and the relay on its path
+/// to `remote`, if any, are both in `pinned`.
+pub(crate) fn check_relay_pin(
+    ep: &Endpoint,
+    pinned: &RelaySelection,
+    strict: bool,
+    remote: NodeId,
+) -> Result<(), UnexpectedRelay> {
+    if !strict {
+        return Ok(());
+    }
+    match ep
+        .home_relay()
+        .get()
+        .into_iter()
+        .chain(path_relay(ep, remote))
+        .find(|url| !pinned.contains(url))
+    {
+        Some(relay) => Err(UnexpectedRelay {
+            relay,
+            pinned: pinned.clone(),
+        }),
+        None => Ok(()),
+    }
+}
+
+/// The relay on `ep`'s path to `remote`, if the path uses one.
+fn path_relay(ep: &Endpoint, remote: NodeId) -> Option<RelayUrl> {
+    ep.remote_info(remote)
+        .and_then(|info| relay_of(&info.conn_type).cloned())
+}
+
+/// The relay a path of type `conn_type` goes through, if any.
+fn relay_of(conn_type: &ConnectionType) -> Option<&RelayUrl> {
+    match conn_type {
+        ConnectionType::Relay(url) | ConnectionType::Mixed(_, url) => Some(url),
+        ConnectionType::Direct(_) | ConnectionType::None => None,
+    }
+}
+
+/// How a transfer treats a path to the peer that goes through a relay.
+/// The data is end-to-end encrypted either way; this is for setups that
+/// must know about, or avoid, third-party relays.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RelayPolicy {
+    /// Use relays as needed, without comment.
+    #[default]
+    Allow,
+    /// Report a warning `Status` whenever the path goes through a relay.
+    Warn,
+    /// Warn, and stop the transfer with [`RelayUsed`] when the path is not
+    /// direct within [`DIRECT_WAIT`] of connecting or falls back to a relay
+    /// later. Falling back is only noticed while the path is watched (see
+    /// [`crate::options::TransferOptions::no_path_watch`]).
+    Deny,
+}
+
+/// Error for a transfer stopped under [`RelayPolicy::Deny`] because its
+/// path went through `relay`.
+#[derive(Debug)]
+pub struct RelayUsed {
+    pub relay: RelayUrl,
+}
+
+impl fmt::Display for RelayUsed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "the connection goes through relay {} and relays are not allowed",
+            self.relay
+        )
+    }
+}
+
+impl std::error::Error for RelayUsed {}
+
+impl RelayUsed {
+    /// The `relay_used` error event for this error.
+    pub(crate) fn event(&self) -> TransferEvent {
+        TransferEvent::Error {
+            code: "relay_used".to_string(),
+            message: self.to_string(),
+            partial_path: None,
+        }
+    }
+}
+
+fn relay_warning(relay: &RelayUrl) -> String {
+    format!(
+        "Warning: traffic passes through relay {} (still end-to-end encrypted).",
+        relay
+    )
+}
+
+/// Apply `policy` once connected to `remote`: tell `report` if the path
+/// goes through a relay and, under [`RelayPolicy::Deny`], wait up to
+/// [`DIRECT_WAIT`] for it to become direct.
+pub(crate) async fn check_relay_policy(
+    ep: &Endpoint,
+    remote: NodeId,
+    policy: RelayPolicy,
+    mut report: impl FnMut(String),
+) -> Result<(), RelayUsed> {
+    if policy == RelayPolicy::Allow {
+        return Ok(());
+    }
+    let Some(relay) = path_relay(ep, remote) else {
+        return Ok(());
+    };
+    report(relay_warning(&relay));
+    if policy == RelayPolicy::Warn {
+        return Ok(());
+    }
+    report("Waiting for a direct connection...".to_string());
+    let direct = async {
+        let Some(watcher) = ep.conn_type(remote) else {
+            return false;
+        };
+        let mut updates = watcher.stream();
+        while let Some(conn_type) = updates.next().await {
+            if matches!(conn_type, ConnectionType::Direct(_)) {
+                return true;
+            }
+        }
+        false
+    };
+    match tokio::time::timeout(DIRECT_WAIT, direct).await {
+        Ok(true) => {
+            report("Connection is direct.".to_string());
+            Ok(())
+        }
+        _ => Err(RelayUsed {
+            relay: path_relay(ep, remote).unwrap_or(relay),
+        }),
+    }
+}
+
+/// Apply `policy` to the path to a peer changing to `conn_type` during a
+/// transfer. Returns the warning to report when the path now goes through
+/// a relay; under [`RelayPolicy::Deny`], also the error event, and `conn`
+/// is closed, which stops the transfer.
+pub(crate) fn enforce_relay_policy(
+    policy: RelayPolicy,
+    conn_type: &ConnectionType,
+    conn: &Connection,
+) -> Vec<TransferEvent> {
+    let Some(relay) = relay_of(conn_type).filter(|_| policy != RelayPolicy::Allow) else {
+        return Vec::new();
+    };
+    let mut events = vec![TransferEvent::Status(relay_warning(relay))];
+    if policy == RelayPolicy::Deny {
+        events.push(
+            RelayUsed {
+                relay: relay.clone(),
+            }
+            .event(),
+        );
+        conn.close(VarInt::from_u32(RELAY_CLOSE_CODE), RELAY_CLOSE_REASON);
+    }
+    events
+}
+
+/// The [`TransferEvent::PeerIdentified`] announcing `node_id`.
+pub(crate) fn peer_identified(node_id: NodeId) -> TransferEvent {
+    TransferEvent::PeerIdentified {
+        node_id: node_id.to_string(),
+        short: node_id.fmt_short().to_string(),
+    }
+}
+
+/// Whether an address discovered as `typ` is this node's public side of a
+/// NAT rather than a local interface address.
+pub(crate) fn is_public_origin(typ: DirectAddrType) -> bool {
+    matches!(
+        typ,
+        DirectAddrType::Qad | DirectAddrType::Qad4LocalPort | DirectAddrType::Portmapped
+    )
+}
+
+/// [`TransferEvent::PublicAddress`] events for the public addresses `ep`
+/// has discovered so far. Does not wait for discovery.
+pub(crate) fn public_addresses(ep: &Endpoint) -> Vec<TransferEvent> {
+    ep.direct_addresses()
+        .get()
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|direct| is_public_origin(direct.typ))
+        .map(|direct| TransferEvent::PublicAddress {
+            addr: direct.addr.to_string(),
+            origin: direct.typ.to_string(),
+        })
+        .collect()
+}
+
+/// Connect to `addr` offering every supported ALPN for `role` and return
+/// the connection with the version the peer picked. Fails with
+/// [`SelfConnection`] when `addr` turns out to be `ep` itself.
+pub async fn connect(
+    ep: &Endpoint,
+    addr: NodeAddr,
+    role: AlpnRole,
+) -> Result<(Connection, NegotiatedAlpn)> {
+    if addr.node_id == ep.node_id() {
+        return Err(SelfConnection.into());
+    }
+    let mut alpns = alpn::supported_alpns(role);
+    let primary = alpns.remove(0);
+    let options = ConnectOptions::new().with_additional_alpns(alpns);
+    let conn = ep.connect_with_opts(addr, &primary, options).await?.await?;
+    if conn.remote_node_id()? == ep.node_id() {
+        conn.close(0u8.into(), b"self connection");
+        return Err(SelfConnection.into());
+    }
+    let negotiated = NegotiatedAlpn::from_connection(&conn)?.expect_role(role)?;
+    Ok((conn, negotiated))
+}
+
+/// This endpoint's address for a ticket. Waits up to `timeout` for address
+/// discovery, which can stall on a fully offline machine, then tells
+/// `report` and falls back to the addresses known so far.
+pub async fn node_addr_with_timeout(
+    ep: &Endpoint,
+    timeout: Duration,
+    mut report: impl FnMut(String),
+) -> NodeAddr {
+    if let Ok(node_addr) = tokio::time::timeout(timeout, ep.node_addr().initialized()).await {
+        return node_addr;
+    }
+    report(
+        "Warning: address discovery timed out; the ticket only contains the addresses found so far."
+            .to_string(),
+    );
+    let relay_url = ep.home_relay().get().pop();
+    let direct = ep
+        .direct_addresses()
+        .get()
+        .unwrap_or_default()
+        .into_iter()
+        .map(|addr| addr.addr);
+    NodeAddr::from_parts(ep.node_id(), relay_url, direct)
+}
+
+/// Wait up to `timeout` for the endpoint to connect to its home relay.
+/// Returns `None` when no relay connected in time.
+pub async fn wait_for_home_relay(ep: &Endpoint, timeout: Duration) -> Option<RelayUrl> {
+    tokio::time::timeout(timeout, ep.home_relay().initialized())
+        .await
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bytes::Bytes;
+    use iroh::endpoint::{ConnectionError, ReadError};
+    use iroh::{Endpoint, RelayMode, Watcher as _};
+
+    use super::{
+        check_relay_pin, close_after, fastest_relay, is_busy_close, node_addr_with_timeout,
+        parse_relay_url, reject_while_busy, relay_status, self_connection_event, AbortOnDrop,
+        HomeRelay, ReconnectWindow, RelayRegion, RelaySelection, SelfConnection, UnexpectedRelay,
+    };
+    use crate::alpn::{self, AlpnRole};
+    use crate::events::TransferEvent;
+    use crate::sender::{self, SendOptions};
+
+    async fn local_sender() -> Endpoint {
+        Endpoint::builder()
+            .relay_mode(RelayMode::Disabled)
+            .alpns(alpn::supported_alpns(AlpnRole::Forward))
+            .bind()
+            .await
+            .expect("bind")
+    }
+
+    #[tokio::test]
+    async fn close_after_closes_the_endpoint_when_a_transfer_fails() {
+        let ep = local_sender().await;
+        let addr = ep.node_addr().initialized().await;
+        let options = SendOptions::default();
+        let send = close_after(
+            &ep,
+            sender::send_bytes_on_endpoint(
+                &ep,
+                "note.txt",
+                Bytes::from_static(b"hello"),
+                None,
+                &options,
+            ),
+        );
+        let peer = async {
+            let peer = Endpoint::builder()
+                .relay_mode(RelayMode::Disabled)
+                .bind()
+                .await
+                .expect("bind");
+            let (conn, _) = super::connect(&peer, addr, AlpnRole::Forward)
+                .await
+                .expect("connect");
+            // Hang up before the handshake, so the transfer itself fails.
+            conn.close(0u32.into(), b"gone");
+            peer
+        };
+        let (sent, peer) =
+            tokio::time::timeout(Duration::from_secs(10), async { tokio::join!(send, peer) })
+                .await
+                .expect("the transfer must fail rather than hang");
+        assert!(sent.is_err());
+        assert!(ep.is_closed());
+        peer.close().await;
+    }
+
+    #[tokio::test]
+    async fn close_after_closes_the_endpoint_when_the_transfer_is_aborted() {
+        let ep = local_sender().await;
+        let task = tokio::spawn({
+            let ep = ep.clone();
+            async move {
+                close_after(
+                    &ep,
+                    sender::send_bytes_on_endpoint(
+                        &ep,
+                        "note.txt",
+                        Bytes::from_static(b"hello"),
+                        None,
+                        &SendOptions::default(),
+                    ),
+                )
+                .await
+            }
+        });
+        // Let it start waiting for a receiver, then cancel it the way the
+        // Android bindings do.
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        task.abort();
+        assert!(task.await.unwrap_err().is_cancelled());
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !ep.is_closed() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("an aborted transfer must still close its endpoint");
+    }
+
+    #[tokio::test]
+    async fn dropping_the_guard_aborts_the_task() {
+        let (_keep, never) = tokio::sync::oneshot::channel::<()>();
+        let (tx, finished) = tokio::sync::oneshot::channel::<()>();
+        let guard = AbortOnDrop::new(tokio::spawn(async move {
+            let _tx = tx;
+            let _ = never.await;
+        }));
+        drop(guard);
+        // The task dropped its sender, so this resolves instead of hanging.
+        tokio::time::timeout(Duration::from_secs(5), finished)
+            .await
+            .expect("aborted task must stop")
+            .unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn node_addr_does_not_wait_forever_without_a_relay() {
+        let ep = Endpoint::builder()
+            .relay_mode(RelayMode::Disabled)
+            .bind()
+            .await
+            .expect("bind");
+        let node_addr = tokio::time::timeout(
+            Duration::from_secs(5),
+            node_addr_with_timeout(&ep, Duration::from_millis(1), |_| {}),
+        )
+        .await
+        .expect("fallback must not block");
+        assert_eq!(node_addr.node_id, ep.node_id());
+        assert!(node_addr.relay_url.is_none());
+        ep.close().await;
+    }
+
+    #[tokio::test]
+    async fn busy_listeners_close_extra_connections_with_a_reason() {
+        let local = |alpns| {
+            Endpoint::builder()
+                .relay_mode(RelayMode::Disabled)
+                .alpns(alpns)
+                .bind()
+        };
+        let listener = local(alpn::supported_alpns(AlpnRole::Reverse))
+            .await
+            .expect("bind");
+        let sender = local(Vec::new()).await.expect("bind");
+        let (tx, mut reports) = tokio::sync::mpsc::unbounded_channel();
+        let _busy = reject_while_busy(&listener, move |message| {
+            let _ = tx.send(message);
+        });
+
+        let addr = listener.node_addr().initialized().await;
+        let (conn, _) = super::connect(&sender, addr, AlpnRole::Reverse)
+            .await
+            .expect("connect");
+        let reason = tokio::time::timeout(Duration::from_secs(5), conn.closed())
+            .await
+            .expect("closed by the listener");
+        assert!(is_busy_close(&reason), "{reason:?}");
+        let report = reports.recv().await.expect("reported");
+        assert!(report.contains("already in progress"), "{report}");
+
+        sender.close().await;
+        listener.close().await;
+    }
+
+    #[tokio::test]
+    async fn streams_over_the_limit_are_refused() {
+        let listener = Endpoint::builder()
+            .relay_mode(RelayMode::Disabled)
+            .alpns(alpn::supported_alpns(AlpnRole::Reverse))
+            .transport_config(super::transport_config(1))
+            .bind()
+            .await
+            .expect("bind");
+        let sender = Endpoint::builder()
+            .relay_mode(RelayMode::Disabled)
+            .bind()
+            .await
+            .expect("bind");
+        let addr = listener.node_addr().initialized().await;
+        let accept = async {
+            let conn = listener
+                .accept()
+                .await
+                .expect("incoming")
+                .await
+                .expect("accept");
+            let (send, mut recv) = conn.accept_bi().await.expect("first stream");
+            let mut byte = [0u8; 1];
+            recv.read_exact(&mut byte).await.expect("read");
+            // Keep the stream open so it still counts against the limit.
+            (conn, send, recv)
+        };
+        let open = async {
+            let (conn, _) = super::connect(&sender, addr, AlpnRole::Reverse)
+                .await
+                .expect("connect");
+            let (mut first, _) = conn.open_bi().await.expect("first stream");
+            first.write_all(b"x").await.expect("write");
+            (conn, first)
+        };
+        let (_server, (conn, _first)) = tokio::join!(accept, open);
+
+        let second = tokio::time::timeout(Duration::from_secs(1), conn.open_bi()).await;
+        assert!(second.is_err(), "second stream opened over the limit");
+        let uni = tokio::time::timeout(Duration::from_secs(1), conn.open_uni()).await;
+        assert!(uni.is_err(), "unidirectional streams are not used");
+
+        sender.close().await;
+        listener.close().await;
+    }
+
+    #[tokio::test]
+    async fn dialing_our_own_ticket_is_rejected() {
+        let ep = Endpoint::builder()
+            .relay_mode(RelayMode::Disabled)
+            .alpns(alpn::supported_alpns(AlpnRole::Reverse))
+            .bind()
+            .await
+            .expect("bind");
+        let addr = ep.node_addr().initialized().await;
+        let ticket = crate::ticket::serialize(&addr).expect("ticket");
+        let addr = crate::ticket::deserialize(&ticket).expect("parse");
+
+        let err = tokio::time::timeout(
+            Duration::from_secs(5),
+            super::connect(&ep, addr, AlpnRole::Reverse),
+        )
+        .await
+        .expect("fails instead of hanging")
+        .expect_err("self connection");
+        assert!(err.is::<SelfConnection>(), "{err:#}");
+        let event = self_connection_event(&err.context("failed to connect")).expect("event");
+        assert!(matches!(
+            event,
+            TransferEvent::Error { code, .. } if code == "self_connection"
+        ));
+
+        ep.close().await;
+    }
+
+    #[test]
+    fn relay_regions_parse_case_insensitively() {
+        assert_eq!("EU".parse::<RelayRegion>().unwrap(), RelayRegion::Europe);
+        assert_eq!(
+            "na".parse::<RelayRegion>().unwrap(),
+            RelayRegion::NorthAmerica
+        );
+        assert!("mars".parse::<RelayRegion>().is_err());
+        let selection = RelaySelection::Region(RelayRegion::AsiaPacific);
+        assert!(selection.to_string().starts_with("region ap (https://"));
+    }
+
+    #[tokio::test]
+    async fn relay_probe_skips_relays_that_do_not_answer() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind");
+        let open = listener.local_addr().expect("addr").port();
+        let closed = {
+            let probe = std::net::TcpListener::bind("127.0.0.1:0").expect("bind");
+            probe.local_addr().expect("addr").port()
+        };
+        let urls = [
+            parse_relay_url(&format!("http://127.0.0.1:{closed}")).unwrap(),
+            parse_relay_url(&format!("http://127.0.0.1:{open}")).unwrap(),
+        ];
+        let (fastest, _) = fastest_relay(&urls, Duration::from_secs(2))
+            .await
+            .expect("one relay answers");
+        assert_eq!(fastest, urls[1]);
+        assert!(fastest_relay(&urls[..1], Duration::from_secs(2))
+            .await
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn strict_host_checking_rejects_relays_outside_the_pin() {
+        let pinned_url = parse_relay_url("https://relay.example.com").unwrap();
+        let other = parse_relay_url("https://other.example.com").unwrap();
+        let pinned = RelaySelection::Url(pinned_url.clone());
+        assert!(pinned.contains(&pinned_url));
+        assert!(!pinned.contains(&other));
+        assert!(RelaySelection::Auto.contains(&other));
+        let region = RelaySelection::Region(RelayRegion::Europe);
+        assert!(region.contains(&RelayRegion::Europe.relay_node().url));
+        assert!(!region.contains(&RelayRegion::NorthAmerica.relay_node().url));
+
+        let err = UnexpectedRelay {
+            relay: other,
+            pinned: pinned.clone(),
+        };
+        match err.event() {
+            TransferEvent::Error { code, message, .. } => {
+                assert_eq!(code, "unexpected_relay");
+                assert!(message.contains("other.example.com"), "{message}");
+            }
+            other => panic!("unexpected event: {other:?}"),
+        }
+
+        // Without a relay on either side there is nothing to reject.
+        let ep = Endpoint::builder()
+            .relay_mode(RelayMode::Disabled)
+            .bind()
+            .await
+            .expect("bind");
+        let remote = iroh::SecretKey::from_bytes(&[9u8; 32]).public();
+        assert!(check_relay_pin(&ep, &pinned, true, remote).is_ok());
+        ep.close().await;
+    }
+
+    #[test]
+    fn relay_status_reports_the_home_relay() {
+        let relay = HomeRelay {
+            url: "https://relay.example.com".parse().unwrap(),
+            latency: Some(Duration::from_millis(12)),
+        };
+        match relay_status(Some(&relay)) {
+            TransferEvent::RelayStatus {
+                connected,
+                url,
+                latency_ms,
+            } => {
+                assert!(connected);
+                assert_eq!(url, Some(relay.url.to_string()));
+                assert_eq!(latency_ms, Some(12.0));
+            }
+            other => panic!("unexpected event {:?}", other),
+        }
+        assert!(matches!(
+            relay_status(None),
+            TransferEvent::RelayStatus {
+                connected: false,
+                url: None,
+                latency_ms: None,
+            }
+        ));
+    }
+
+    #[test]
+    fn relay_urls_are_validated() {
+        assert!(parse_relay_url("https://relay.example.com").is_ok());
+        assert!(parse_relay_url("ftp://relay.example.com").is_err());
+        assert!(parse_relay_url("not a url").is_err());
+    }
+
+    #[test]
+    fn only_lost_connections_open_the_reconnect_window() {
+        let eof = || anyhow::Error::from(std::io::Error::from(std::io::ErrorKind::UnexpectedEof));
+        let timed_out = anyhow::Error::from(std::io::Error::other(ReadError::ConnectionLost(
+            ConnectionError::TimedOut,
+        )))
+        .context("reading a chunk");
+        let refused = anyhow::anyhow!("Transfer rejected: too large");
+        let unreachable = || {
+            anyhow::Error::from(std::io::Error::from(std::io::ErrorKind::ConnectionRefused))
+                .context("failed to connect")
+        };
+
+        let mut off = ReconnectWindow::new(None);
+        assert!(!off.retry(&eof(), |_| panic!("no window")));
+
+        let mut window = ReconnectWindow::new(Some(Duration::from_secs(60)));
+        assert!(!window.retry(&refused, |_| panic!("not a lost connection")));
+        assert_eq!(window.remaining(), None);
+        let mut reports = Vec::new();
+        assert!(window.retry(&timed_out, |message| reports.push(message.to_string())));
+        assert_eq!(reports, [super::RECONNECT_STATUS]);
+        // Failed reconnection attempts keep retrying inside the window, but
+        // nothing else does.
+        assert!(window.retry(&unreachable(), |_| panic!("not a fresh loss")));
+        assert!(!window.retry(&refused, |_| panic!("not a fresh loss")));
+        let canceled = anyhow::Error::from(crate::receiver::Canceled);
+        assert!(!window.retry(&canceled, |_| panic!("not a fresh loss")));
+
+        let mut fresh = ReconnectWindow::new(Some(Duration::from_secs(60)));
+        assert!(!fresh.retry(&unreachable(), |_| panic!("nothing was lost yet")));
+
+        let mut expired = ReconnectWindow::new(Some(Duration::ZERO));
+        assert!(!expired.retry(&eof(), |_| {}));
+    }
+}
@@ -0,0 +1,68 @@
+use crate::events::ConnectionPathKind;
+
+/// Throughput assumed for a direct path before any data has moved. Kept
+/// well below what a LAN does so the estimate errs on the long side.
+pub const DIRECT_BYTES_PER_SEC: f64 = 8.0 * 1024.0 * 1024.0;
+
+/// Throughput assumed when data goes through a relay, including paths that
+/// are still mixed and may never go direct.
+pub const RELAY_BYTES_PER_SEC: f64 = 1024.0 * 1024.0;
+
+/// Round trips a transfer waits on besides streaming: the header ack and
+/// the final `DONE`.
+const ROUND_TRIPS: f64 = 2.0;
+
+/// Rough time in seconds to send `bytes` over `path`, or `None` while no
+/// path is known. This is a best-effort guess made before streaming, not a
+/// live ETA.
+pub fn estimate_secs(
+    bytes: u64,
+    path: &ConnectionPathKind,
+    latency_ms: Option<f64>,
+) -> Option<f64> {
+    let rate = match path {
+        ConnectionPathKind::Direct(_) => DIRECT_BYTES_PER_SEC,
+        ConnectionPathKind::Relay(_) | ConnectionPathKind::Mixed { .. } => RELAY_BYTES_PER_SEC,
+        ConnectionPathKind::None => return None,
+    };
+    let round_trips = latency_ms.unwrap_or(0.0) / 1000.0 * ROUND_TRIPS;
+    Some(bytes as f64 / rate + round_trips)
+}
+
+/// Short human form of an estimate, such as `~2 min`.
+pub fn human_estimate(secs: f64) -> String {
+    if secs < 1.0 {
+        "under a second".to_string()
+    } else if secs < 90.0 {
+        format!("~{} s", secs.round() as u64)
+    } else if secs < 90.0 * 60.0 {
+        format!("~{} min", (secs / 60.0).round() as u64)
+    } else {
+        format!("~{:.1} h", secs / 3600.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{estimate_secs, human_estimate};
+    use crate::events::ConnectionPathKind;
+
+    #[test]
+    fn relay_paths_are_estimated_slower_than_direct_ones() {
+        let size = 64 * 1024 * 1024;
+        let direct = ConnectionPathKind::Direct("192.168.1.2:4000".to_string());
+        let relay = ConnectionPathKind::Relay("https://relay.example.com".to_string());
+        assert_eq!(estimate_secs(size, &direct, None), Some(8.0));
+        assert_eq!(estimate_secs(size, &direct, Some(500.0)), Some(9.0));
+        assert_eq!(estimate_secs(size, &relay, None), Some(64.0));
+        assert_eq!(estimate_secs(size, &ConnectionPathKind::None, None), None);
+    }
+
+    #[test]
+    fn estimates_are_rounded_for_display() {
+        assert_eq!(human_estimate(0.2), "under a second");
+        assert_eq!(human_estimate(42.4), "~42 s");
+        assert_eq!(human_estimate(130.0), "~2 min");
+        assert_eq!(human_estimate(9000.0), "~2.5 h");
+    }
+}
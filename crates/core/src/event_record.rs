@@ -0,0 +1,644 @@
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::estimate;
+use crate::events::{ConnectionPathKind, TransferContentKind, TransferEvent};
+
+/// Version of the JSON event contract shared by the CLI `--json` stream and
+/// the Android bindings. Same major version is compatible; bump the minor
+/// version and extend [`SCHEMA_ADDITIONS`] for every additive change.
+pub const TRANSFER_EVENT_SCHEMA_VERSION: &str = "1.15.0";
+
+/// A published version of the event schema that records can be projected
+/// into for consumers pinned to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SchemaVersion {
+    V1_0,
+    V1_1,
+    V1_2,
+    V1_3,
+    V1_4,
+    V1_5,
+    V1_6,
+    V1_7,
+    V1_8,
+    V1_9,
+    V1_10,
+    V1_11,
+    V1_12,
+    V1_13,
+    V1_14,
+    V1_15,
+}
+
+impl SchemaVersion {
+    pub const CURRENT: SchemaVersion = SchemaVersion::V1_15;
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SchemaVersion::V1_0 => "1.0.0",
+            SchemaVersion::V1_1 => "1.1.0",
+            SchemaVersion::V1_2 => "1.2.0",
+            SchemaVersion::V1_3 => "1.3.0",
+            SchemaVersion::V1_4 => "1.4.0",
+            SchemaVersion::V1_5 => "1.5.0",
+            SchemaVersion::V1_6 => "1.6.0",
+            SchemaVersion::V1_7 => "1.7.0",
+            SchemaVersion::V1_8 => "1.8.0",
+            SchemaVersion::V1_9 => "1.9.0",
+            SchemaVersion::V1_10 => "1.10.0",
+            SchemaVersion::V1_11 => "1.11.0",
+            SchemaVersion::V1_12 => "1.12.0",
+            SchemaVersion::V1_13 => "1.13.0",
+            SchemaVersion::V1_14 => "1.14.0",
+            SchemaVersion::V1_15 => TRANSFER_EVENT_SCHEMA_VERSION,
+        }
+    }
+}
+
+impl Default for SchemaVersion {
+    fn default() -> Self {
+        Self::CURRENT
+    }
+}
+
+impl FromStr for SchemaVersion {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.trim() {
+            "1.0.0" => Ok(SchemaVersion::V1_0),
+            "1.1.0" => Ok(SchemaVersion::V1_1),
+            "1.2.0" => Ok(SchemaVersion::V1_2),
+            "1.3.0" => Ok(SchemaVersion::V1_3),
+            "1.4.0" => Ok(SchemaVersion::V1_4),
+            "1.5.0" => Ok(SchemaVersion::V1_5),
+            "1.6.0" => Ok(SchemaVersion::V1_6),
+            "1.7.0" => Ok(SchemaVersion::V1_7),
+            "1.8.0" => Ok(SchemaVersion::V1_8),
+            "1.9.0" => Ok(SchemaVersion::V1_9),
+            "1.10.0" => Ok(SchemaVersion::V1_10),
+            "1.11.0" => Ok(SchemaVersion::V1_11),
+            "1.12.0" => Ok(SchemaVersion::V1_12),
+            "1.13.0" => Ok(SchemaVersion::V1_13),
+            "1.14.0" => Ok(SchemaVersion::V1_14),
+            "1.15.0" => Ok(SchemaVersion::V1_15),
+            other => anyhow::bail!(
+                "unsupported event schema version '{}' (expected 1.0.0 to 1.15.0)",
+                other
+            ),
+        }
+    }
+}
+
+impl fmt::Display for SchemaVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Event kinds and record fields each version added to the one before it.
+const SCHEMA_ADDITIONS: &[(SchemaVersion, &[&str], &[&str])] = &[
+    (SchemaVersion::V1_1, &[], &["content_kind", "item_count"]),
+    (
+        SchemaVersion::V1_2,
+        &[
+            "relay_status",
+            "file_started",
+            "file_progress",
+            "file_finished",
+        ],
+        &["file_index"],
+    ),
+    (SchemaVersion::V1_3, &[], &["session_id"]),
+    (SchemaVersion::V1_4, &["temp_file"], &[]),
+    (SchemaVersion::V1_5, &["file_error"], &[]),
+    (SchemaVersion::V1_6, &["accept_queue"], &["queue_depth"]),
+    (SchemaVersion::V1_7, &[], &["partial_path"]),
+    (SchemaVersion::V1_8, &["estimate"], &["est_secs"]),
+    (SchemaVersion::V1_9, &["peer_identified"], &[]),
+    (SchemaVersion::V1_10, &[], &["mime"]),
+    (SchemaVersion::V1_11, &["public_address"], &[]),
+    (SchemaVersion::V1_12, &["file_skipped"], &[]),
+    (SchemaVersion::V1_13, &["preview"], &[]),
+    (SchemaVersion::V1_14, &[], &["compression_level"]),
+    (SchemaVersion::V1_15, &[], &["blake3"]),
+];
+
+/// Flat, serializable form of a [`TransferEvent`]. Every field except
+/// `kind` and `schema_version` is optional; see `electron/docs/event-schema.md`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransferEventRecord {
+    pub kind: String,
+    pub message: Option<String>,
+    pub value: Option<String>,
+    pub schema_version: String,
+    pub done: Option<u64>,
+    pub total: Option<u64>,
+    pub file_name: Option<String>,
+    pub size_bytes: Option<u64>,
+    pub saved_path: Option<String>,
+    pub latency_ms: Option<f64>,
+    pub content_kind: Option<TransferContentKind>,
+    pub item_count: Option<u64>,
+    pub file_index: Option<u64>,
+    /// Job the event belongs to, when the producer runs several transfers
+    /// (the Android controller's queue).
+    pub session_id: Option<u64>,
+    /// Senders waiting for a free slot in keep-listening mode.
+    pub queue_depth: Option<u64>,
+    /// `.part` file a canceled receive kept so it can be resumed.
+    pub partial_path: Option<String>,
+    /// Estimated seconds a send will take (`estimate` events).
+    pub est_secs: Option<f64>,
+    /// Detected MIME type of a received file (`completed` events).
+    pub mime: Option<String>,
+    /// zstd level the data was compressed at on the wire (`completed`
+    /// events).
+    pub compression_level: Option<i32>,
+    /// Hex-encoded blake3 hash of the data (`completed` events).
+    pub blake3: Option<String>,
+}
+
+impl TransferEventRecord {
+    pub fn base(kind: impl Into<String>) -> Self {
+        Self {
+            kind: kind.into(),
+            message: None,
+            value: None,
+            schema_version: TRANSFER_EVENT_SCHEMA_VERSION.to_string(),
+            done: None,
+            total: None,
+            file_name: None,
+            size_bytes: None,
+            saved_path: None,
+            latency_ms: None,
+            content_kind: None,
+            item_count: None,
+            file_index: None,
+            session_id: None,
+            queue_depth: None,
+            partial_path: None,
+            est_secs: None,
+            mime: None,
+            compression_level: None,
+            blake3: None,
+        }
+    }
+
+    pub fn status(message: impl Into<String>) -> Self {
+        let mut record = Self::base("status");
+        record.message = Some(message.into());
+        record
+    }
+
+    pub fn error(code: impl Into<String>, message: impl Into<String>) -> Self {
+        let mut record = Self::base("error");
+        record.message = Some(message.into());
+        record.value = Some(code.into());
+        record
+    }
+}
+
+impl TransferEventRecord {
+    /// This record as JSON for a consumer of `version`: fields added after it
+    /// are left out, and `None` is returned for kinds it does not know.
+    pub fn to_versioned_json(&self, version: SchemaVersion) -> Option<serde_json::Value> {
+        let mut value = serde_json::to_value(self).ok()?;
+        let object = value.as_object_mut()?;
+        for (added_in, kinds, fields) in SCHEMA_ADDITIONS {
+            if *added_in <= version {
+                continue;
+            }
+            if kinds.contains(&self.kind.as_str()) {
+                return None;
+            }
+            for field in *fields {
+                object.remove(*field);
+            }
+        }
+        object.insert("schema_version".to_string(), version.as_str().into());
+        Some(value)
+    }
+}
+
+pub fn map_event(event: TransferEvent) -> TransferEventRecord {
+    match event {
+        TransferEvent::Status(message) => TransferEventRecord::status(message),
+        TransferEvent::Ticket(ticket) => TransferEventRecord {
+            value: Some(ticket),
+            ..TransferEventRecord::base("ticket")
+        },
+        TransferEvent::QrPayload(payload) => TransferEventRecord {
+            value: Some(payload),
+            ..TransferEventRecord::base("qr_payload")
+        },
+        TransferEvent::HandshakeCode(code) => TransferEventRecord {
+            value: Some(code),
+            ..TransferEventRecord::base("handshake_code")
+        },
+        TransferEvent::Progress { done, total } => TransferEventRecord {
+            done: Some(done),
+            total,
+            ..TransferEventRecord::base("progress")
+        },
+        TransferEvent::ConnectionPath { kind, latency_ms } => {
+            let (value, message) = match kind {
+                ConnectionPathKind::Direct(addr) => (Some("direct".to_string()), Some(addr)),
+                ConnectionPathKind::Relay(url) => (Some("relay".to_string()), Some(url)),
+                ConnectionPathKind::Mixed {
+                    udp_addr,
+                    relay_url,
+                } => (
+                    Some("mixed".to_string()),
+                    Some(format!("udp: {udp_addr}, relay: {relay_url}")),
+                ),
+                ConnectionPathKind::None => (Some("none".to_string()), None),
+            };
+
+            TransferEventRecord {
+                message,
+                value,
+                latency_ms,
+                ..TransferEventRecord::base("connection_path")
+            }
+        }
+        TransferEvent::PeerIdentified { node_id, short } => TransferEventRecord {
+            value: Some(node_id),
+            message: Some(short),
+            ..TransferEventRecord::base("peer_identified")
+        },
+        TransferEvent::PublicAddress { addr, origin } => TransferEventRecord {
+            value: Some(addr),
+            message: Some(origin),
+            ..TransferEventRecord::base("public_address")
+        },
+        TransferEvent::RelayStatus {
+            connected,
+            url,
+            latency_ms,
+        } => TransferEventRecord {
+            value: Some(relay_status_value(connected).to_string()),
+            message: url,
+            latency_ms,
+            ..TransferEventRecord::base("relay_status")
+        },
+        TransferEvent::FileStarted { index, name, size } => TransferEventRecord {
+            file_index: Some(index),
+            file_name: Some(name),
+            size_bytes: Some(size),
+            ..TransferEventRecord::base("file_started")
+        },
+        TransferEvent::FileProgress { index, done, total } => TransferEventRecord {
+            file_index: Some(index),
+            done: Some(done),
+            total: Some(total),
+            ..TransferEventRecord::base("file_progress")
+        },
+        TransferEvent::FileFinished { index } => TransferEventRecord {
+            file_index: Some(index),
+            ..TransferEventRecord::base("file_finished")
+        },
+        TransferEvent::TempFile { path } => TransferEventRecord {
+            value: Some(path.display().to_string()),
+            ..TransferEventRecord::base("temp_file")
+        },
+        TransferEvent::FileError {
+            index,
+            name,
+            code,
+            message,
+        } => TransferEventRecord {
+            file_index: Some(index),
+            file_name: Some(name),
+            value: Some(code),
+            message: Some(message),
+            ..TransferEventRecord::base("file_error")
+        },
+        TransferEvent::FileSkipped { name } => TransferEventRecord {
+            file_name: Some(name),
+            ..TransferEventRecord::base("file_skipped")
+        },
+        TransferEvent::Preview { bytes } => TransferEventRecord {
+            size_bytes: Some(bytes.len() as u64),
+            value: Some(data_encoding::BASE64.encode(&bytes)),
+            ..TransferEventRecord::base("preview")
+        },
+        TransferEvent::AcceptQueue { waiting, active } => TransferEventRecord {
+            queue_depth: Some(waiting),
+            message: Some(format!("{} waiting, {} receiving", waiting, active)),
+            ..TransferEventRecord::base("accept_queue")
+        },
+        TransferEvent::Estimate { bytes, est_secs } => TransferEventRecord {
+            size_bytes: Some(bytes),
+            est_secs,
+            message: est_secs.map(|secs| format!("{} (estimate)", estimate::human_estimate(secs))),
+            ..TransferEventRecord::base("estimate")
+        },
+        TransferEvent::Completed(result) => TransferEventRecord {
+            file_name: Some(result.file_name),
+            size_bytes: Some(result.size_bytes),
+            saved_path: result.saved_path.map(|p| p.display().to_string()),
+            content_kind: Some(result.content_kind),
+            item_count: Some(result.item_count),
+            mime: result.mime,
+            compression_level: result.compression_level,
+            blake3: result.blake3,
+            ..TransferEventRecord::base("completed")
+        },
+        TransferEvent::Error {
+            code,
+            message,
+            partial_path,
+        } => {
+            let mut record = TransferEventRecord::error(code, message);
+            record.partial_path = partial_path.map(|path| path.display().to_string());
+            record
+        }
+    }
+}
+
+fn relay_status_value(connected: bool) -> &'static str {
+    if connected {
+        "connected"
+    } else {
+        "unavailable"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::{map_event, SchemaVersion, TRANSFER_EVENT_SCHEMA_VERSION};
+    use crate::events::{
+        ConnectionPathKind, TransferCompleted, TransferContentKind, TransferEvent,
+    };
+
+    #[test]
+    fn map_event_progress_keeps_counts() {
+        let record = map_event(TransferEvent::Progress {
+            done: 16,
+            total: Some(64),
+        });
+        assert_eq!(record.kind, "progress");
+        assert_eq!(record.schema_version, TRANSFER_EVENT_SCHEMA_VERSION);
+        assert_eq!(record.done, Some(16));
+        assert_eq!(record.total, Some(64));
+        assert!(record.message.is_none());
+
+        let following = map_event(TransferEvent::Progress {
+            done: 16,
+            total: None,
+        });
+        assert_eq!(following.done, Some(16));
+        assert_eq!(following.total, None);
+    }
+
+    #[test]
+    fn map_event_relay_status_reports_availability() {
+        let record = map_event(TransferEvent::RelayStatus {
+            connected: false,
+            url: None,
+            latency_ms: None,
+        });
+        assert_eq!(record.kind, "relay_status");
+        assert_eq!(record.value.as_deref(), Some("unavailable"));
+        assert!(record.message.is_none());
+
+        let record = map_event(TransferEvent::RelayStatus {
+            connected: true,
+            url: Some("https://relay.example".to_string()),
+            latency_ms: Some(38.5),
+        });
+        assert_eq!(record.value.as_deref(), Some("connected"));
+        assert_eq!(record.message.as_deref(), Some("https://relay.example"));
+        assert_eq!(record.latency_ms, Some(38.5));
+    }
+
+    #[test]
+    fn map_event_file_started_carries_index_and_size() {
+        let record = map_event(TransferEvent::FileStarted {
+            index: 2,
+            name: "notes.txt".to_string(),
+            size: 42,
+        });
+        assert_eq!(record.kind, "file_started");
+        assert_eq!(record.file_index, Some(2));
+        assert_eq!(record.file_name.as_deref(), Some("notes.txt"));
+        assert_eq!(record.size_bytes, Some(42));
+
+        let record = map_event(TransferEvent::FileFinished { index: 2 });
+        assert_eq!(record.kind, "file_finished");
+        assert_eq!(record.file_index, Some(2));
+    }
+
+    #[test]
+    fn map_event_temp_file_carries_the_part_path() {
+        let record = map_event(TransferEvent::TempFile {
+            path: PathBuf::from("/tmp/demo.txt.part"),
+        });
+        assert_eq!(record.kind, "temp_file");
+        assert_eq!(record.value.as_deref(), Some("/tmp/demo.txt.part"));
+        assert!(record
+            .to_versioned_json(super::SchemaVersion::V1_3)
+            .is_none());
+    }
+
+    #[test]
+    fn map_event_file_error_carries_the_code() {
+        let record = map_event(TransferEvent::FileError {
+            index: 4,
+            name: "photos/locked.jpg".to_string(),
+            code: "unreadable".to_string(),
+            message: "permission denied".to_string(),
+        });
+        assert_eq!(record.kind, "file_error");
+        assert_eq!(record.file_index, Some(4));
+        assert_eq!(record.file_name.as_deref(), Some("photos/locked.jpg"));
+        assert_eq!(record.value.as_deref(), Some("unreadable"));
+        assert!(record
+            .to_versioned_json(super::SchemaVersion::V1_4)
+            .is_none());
+    }
+
+    #[test]
+    fn map_event_accept_queue_reports_the_depth() {
+        let record = map_event(TransferEvent::AcceptQueue {
+            waiting: 3,
+            active: 1,
+        });
+        assert_eq!(record.kind, "accept_queue");
+        assert_eq!(record.queue_depth, Some(3));
+        assert_eq!(record.message.as_deref(), Some("3 waiting, 1 receiving"));
+        assert!(record
+            .to_versioned_json(super::SchemaVersion::V1_5)
+            .is_none());
+    }
+
+    #[test]
+    fn map_event_estimate_is_labeled_as_one() {
+        let record = map_event(TransferEvent::Estimate {
+            bytes: 1024,
+            est_secs: Some(125.0),
+        });
+        assert_eq!(record.kind, "estimate");
+        assert_eq!(record.size_bytes, Some(1024));
+        assert_eq!(record.est_secs, Some(125.0));
+        assert_eq!(record.message.as_deref(), Some("~2 min (estimate)"));
+    }
+
+    #[test]
+    fn map_event_peer_identified_carries_both_forms() {
+        let record = map_event(TransferEvent::PeerIdentified {
+            node_id: "ab12cd34ef56".to_string(),
+            short: "ab12cd34ef".to_string(),
+        });
+        assert_eq!(record.kind, "peer_identified");
+        assert_eq!(record.value.as_deref(), Some("ab12cd34ef56"));
+        assert_eq!(record.message.as_deref(), Some("ab12cd34ef"));
+        assert!(record.to_versioned_json(SchemaVersion::V1_8).is_none());
+    }
+
+    #[test]
+    fn map_event_file_skipped_names_the_file() {
+        let record = map_event(TransferEvent::FileSkipped {
+            name: "photos/2024/a.jpg".to_string(),
+        });
+        assert_eq!(record.kind, "file_skipped");
+        assert_eq!(record.file_name.as_deref(), Some("photos/2024/a.jpg"));
+        assert!(record.to_versioned_json(SchemaVersion::V1_11).is_none());
+    }
+
+    #[test]
+    fn map_event_preview_carries_the_bytes_as_base64() {
+        let record = map_event(TransferEvent::Preview {
+            bytes: b"\x89PNG".to_vec(),
+        });
+        assert_eq!(record.kind, "preview");
+        assert_eq!(record.size_bytes, Some(4));
+        assert_eq!(record.value.as_deref(), Some("iVBORw=="));
+        assert!(record.to_versioned_json(SchemaVersion::V1_12).is_none());
+    }
+
+    #[test]
+    fn map_event_public_address_carries_the_origin() {
+        let record = map_event(TransferEvent::PublicAddress {
+            addr: "203.0.113.7:41641".to_string(),
+            origin: "qad".to_string(),
+        });
+        assert_eq!(record.kind, "public_address");
+        assert_eq!(record.value.as_deref(), Some("203.0.113.7:41641"));
+        assert_eq!(record.message.as_deref(), Some("qad"));
+        assert!(record.to_versioned_json(SchemaVersion::V1_10).is_none());
+    }
+
+    #[test]
+    fn map_event_connection_path_mixed_formats_message() {
+        let record = map_event(TransferEvent::ConnectionPath {
+            kind: ConnectionPathKind::Mixed {
+                udp_addr: "192.168.1.2:4000".to_string(),
+                relay_url: "https://relay.example".to_string(),
+            },
+            latency_ms: Some(21.5),
+        });
+        assert_eq!(record.kind, "connection_path");
+        assert_eq!(record.value.as_deref(), Some("mixed"));
+        assert_eq!(
+            record.message.as_deref(),
+            Some("udp: 192.168.1.2:4000, relay: https://relay.example")
+        );
+        assert_eq!(record.latency_ms, Some(21.5));
+    }
+
+    #[test]
+    fn map_event_completed_includes_saved_path() {
+        let record = map_event(TransferEvent::Completed(TransferCompleted {
+            file_name: "demo.txt".to_string(),
+            size_bytes: 42,
+            saved_path: Some(PathBuf::from("/tmp/demo.txt")),
+            content_kind: TransferContentKind::Bundle,
+            item_count: 3,
+            mime: None,
+            compression_level: Some(3),
+            blake3: Some("ab".repeat(32)),
+        }));
+        assert_eq!(record.kind, "completed");
+        assert_eq!(record.file_name.as_deref(), Some("demo.txt"));
+        assert_eq!(record.size_bytes, Some(42));
+        assert_eq!(record.saved_path.as_deref(), Some("/tmp/demo.txt"));
+        assert_eq!(record.content_kind, Some(TransferContentKind::Bundle));
+        assert_eq!(record.item_count, Some(3));
+        assert_eq!(record.compression_level, Some(3));
+        assert_eq!(record.blake3, Some("ab".repeat(32)));
+    }
+
+    #[test]
+    fn older_schema_versions_omit_newer_fields_and_kinds() {
+        let completed = map_event(TransferEvent::Completed(TransferCompleted {
+            file_name: "demo.txt".to_string(),
+            size_bytes: 42,
+            saved_path: None,
+            content_kind: TransferContentKind::File,
+            item_count: 1,
+            mime: Some("text/plain".to_string()),
+            compression_level: None,
+            blake3: None,
+        }));
+
+        let v1_0 = completed.to_versioned_json(SchemaVersion::V1_0).unwrap();
+        assert_eq!(v1_0["schema_version"], "1.0.0");
+        assert_eq!(v1_0["file_name"], "demo.txt");
+        for field in ["content_kind", "item_count", "file_index", "session_id"] {
+            assert!(v1_0.get(field).is_none(), "{field} leaked into 1.0.0");
+        }
+        let v1_1 = completed.to_versioned_json(SchemaVersion::V1_1).unwrap();
+        assert_eq!(v1_1["content_kind"], "file");
+        assert!(v1_1.get("file_index").is_none());
+        let v1_9 = completed.to_versioned_json(SchemaVersion::V1_9).unwrap();
+        assert!(v1_9.get("mime").is_none());
+        let v1_13 = completed.to_versioned_json(SchemaVersion::V1_13).unwrap();
+        assert!(v1_13.get("compression_level").is_none());
+        let v1_14 = completed.to_versioned_json(SchemaVersion::V1_14).unwrap();
+        assert!(v1_14.get("blake3").is_none());
+
+        let current = completed.to_versioned_json(SchemaVersion::CURRENT).unwrap();
+        assert_eq!(current, serde_json::to_value(&completed).unwrap());
+
+        let file_started = map_event(TransferEvent::FileFinished { index: 0 });
+        assert!(file_started
+            .to_versioned_json(SchemaVersion::V1_1)
+            .is_none());
+    }
+
+    #[test]
+    fn schema_versions_parse_and_round_trip() {
+        for version in [
+            SchemaVersion::V1_0,
+            SchemaVersion::V1_1,
+            SchemaVersion::V1_2,
+            SchemaVersion::V1_3,
+            SchemaVersion::V1_4,
+            SchemaVersion::V1_5,
+            SchemaVersion::V1_6,
+            SchemaVersion::V1_7,
+            SchemaVersion::V1_8,
+            SchemaVersion::V1_9,
+            SchemaVersion::V1_10,
+            SchemaVersion::V1_11,
+            SchemaVersion::V1_12,
+            SchemaVersion::V1_13,
+            SchemaVersion::V1_14,
+            SchemaVersion::V1_15,
+        ] {
+            assert_eq!(version.as_str().parse::<SchemaVersion>().unwrap(), version);
+        }
+        assert_eq!(
+            SchemaVersion::CURRENT.as_str(),
+            TRANSFER_EVENT_SCHEMA_VERSION
+        );
+        assert!("2.0.0".parse::<SchemaVersion>().is_err());
+    }
+}
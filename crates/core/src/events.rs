@@ -1,4 +1,6 @@
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
 use serde::{Deserialize, Serialize};
 
@@ -32,6 +34,18 @@ pub struct TransferCompleted {
     pub saved_path: Option<PathBuf>,
     pub content_kind: TransferContentKind,
     pub item_count: u64,
+    /// MIME type of a received single file, from its first bytes (with the
+    /// `mime-sniff` feature) or its extension. `None` for bundles, sends
+    /// and unrecognized files.
+    pub mime: Option<String>,
+    /// zstd level the data was compressed at on the wire, or `None` when it
+    /// was sent raw, because compression was off or the receiver could not
+    /// decompress.
+    pub compression_level: Option<i32>,
+    /// Hex-encoded blake3 hash of the data, as verified by the receiver.
+    /// `None` on the sending side of a followed file (`send --follow`),
+    /// whose hash is only known once the last chunk is out.
+    pub blake3: Option<String>,
 }
 
 #[derive(Debug, Clone)]
@@ -40,23 +54,131 @@ pub enum TransferEvent {
     Ticket(String),
     QrPayload(String),
     HandshakeCode(String),
+    /// `total` is `None` while the size is unknown, when following a
+    /// growing file (`send --follow`).
     Progress {
         done: u64,
-        total: u64,
+        total: Option<u64>,
     },
     ConnectionPath {
         kind: ConnectionPathKind,
         latency_ms: Option<f64>,
     },
+    /// The node on the other end of an iroh connection, sent once it is
+    /// connected. `short` is the abbreviated form shown to users; plain TCP
+    /// transfers have no node id and never send this.
+    PeerIdentified {
+        node_id: String,
+        short: String,
+    },
+    /// An address of this node as seen from outside the local network,
+    /// discovered through the relay (`qad`) or a port mapping (`portmap`).
+    /// Sent once per connection next to `PeerIdentified`, for debugging NAT
+    /// behaviour; the ticket is not affected.
+    PublicAddress {
+        addr: String,
+        origin: String,
+    },
+    /// Outcome of waiting for the home relay during endpoint setup. When
+    /// `connected` is false only direct/LAN connections can succeed.
+    /// `latency_ms` is set when the relay was picked by probing several.
+    /// A dialing side waits in the background, so a transfer that ends
+    /// first reports none.
+    RelayStatus {
+        connected: bool,
+        url: Option<String>,
+        latency_ms: Option<f64>,
+    },
+    /// A file inside a bundle started transferring. `index` is its position
+    /// in the archive, counting regular files only.
+    FileStarted {
+        index: u64,
+        name: String,
+        size: u64,
+    },
+    /// Byte progress of one bundle file, alongside the aggregate `Progress`.
+    FileProgress {
+        index: u64,
+        done: u64,
+        total: u64,
+    },
+    FileFinished {
+        index: u64,
+    },
+    /// The receiver picked the temporary `.part` path the download is
+    /// written to before being renamed or extracted into place.
+    TempFile {
+        path: PathBuf,
+    },
+    /// A bundle file was skipped in keep-going mode and the batch carried
+    /// on. On the sender `index` counts files in walk order, skipped ones
+    /// included; on the receiver it is the archive position of
+    /// `FileStarted`. `code` is `unreadable` (sender) or `write_failed`
+    /// (receiver).
+    FileError {
+        index: u64,
+        name: String,
+        code: String,
+        message: String,
+    },
+    /// A folder file left out of a sync transfer because the receiver
+    /// already has it unchanged. `name` is its path inside the archive.
+    FileSkipped {
+        name: String,
+    },
+    /// The first bytes of a single file, sent before the receiver decides
+    /// on the rest (`receive --preview`). Receivers only.
+    Preview {
+        bytes: Vec<u8>,
+    },
+    /// Senders waiting for a free slot and transfers in progress in
+    /// keep-listening mode, sent whenever either changes.
+    AcceptQueue {
+        waiting: u64,
+        active: u64,
+    },
+    /// Best-effort guess, made once the connection path is known and before
+    /// streaming, of how long sending `bytes` will take. `est_secs` is
+    /// `None` while no path is known. See [`crate::estimate`].
+    Estimate {
+        bytes: u64,
+        est_secs: Option<f64>,
+    },
     Completed(TransferCompleted),
+    /// `partial_path` is the `.part` file a canceled receive left in place
+    /// because [`crate::receiver::ReceiveOptions::keep_partial`] was set.
     Error {
         code: String,
         message: String,
+        partial_path: Option<PathBuf>,
     },
 }
 
+impl TransferEvent {
+    /// Whether this event ends a transfer: `Completed` or `Error`. Every
+    /// transfer emits exactly one, as its last event.
+    pub fn is_terminal(&self) -> bool {
+        matches!(
+            self,
+            TransferEvent::Completed(_) | TransferEvent::Error { .. }
+        )
+    }
+}
+
 pub trait TransferEventSink: Send + Sync {
     fn on_event(&self, event: TransferEvent);
+
+    /// Whether this sink only records events, such as a transfer log,
+    /// while the terminal keeps the progress bars it shows without a sink.
+    fn passive(&self) -> bool {
+        false
+    }
+}
+
+/// Whether events go to a frontend that shows them instead of the
+/// terminal: any sink that is not [`passive`](TransferEventSink::passive).
+pub(crate) fn presented(sink: Option<&Arc<dyn TransferEventSink>>) -> bool {
+    sink.is_some_and(|sink| !sink.passive())
 }
 
 impl<F> TransferEventSink for F
@@ -67,3 +189,52 @@ where
         self(event)
     }
 }
+
+/// Forwards events to an inner sink and keeps the terminal-event contract
+/// for a whole run: a run that fails without an `Error` event of its own,
+/// such as on bad input, gets one from [`finish`](Self::finish).
+pub struct TerminalSink {
+    inner: Arc<dyn TransferEventSink>,
+    ended: AtomicBool,
+}
+
+impl TerminalSink {
+    pub fn new(inner: Arc<dyn TransferEventSink>) -> Self {
+        Self {
+            inner,
+            ended: AtomicBool::new(false),
+        }
+    }
+
+    /// Whether a terminal event went out.
+    pub fn ended(&self) -> bool {
+        self.ended.load(Ordering::Relaxed)
+    }
+
+    /// End the run with `result`: emit a `transfer_error` for a failure
+    /// when no terminal event went out yet.
+    pub fn finish<T>(&self, result: &anyhow::Result<T>) {
+        if let Err(err) = result {
+            if !self.ended() {
+                self.on_event(TransferEvent::Error {
+                    code: "transfer_error".to_string(),
+                    message: format!("{:#}", err),
+                    partial_path: None,
+                });
+            }
+        }
+    }
+}
+
+impl TransferEventSink for TerminalSink {
+    fn on_event(&self, event: TransferEvent) {
+        if event.is_terminal() {
+            self.ended.store(true, Ordering::Relaxed);
+        }
+        self.inner.on_event(event);
+    }
+
+    fn passive(&self) -> bool {
+        self.inner.passive()
+    }
+}
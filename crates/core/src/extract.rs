@@ -0,0 +1,378 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+
+use crate::events::TransferEvent;
+use crate::sanitize;
+
+/// Archive formats this build can extract, by their usual extension. `.zip`
+/// needs the `extract-zip` feature and `.tar.zst` the `compression` one.
+pub const FORMATS: &[&str] = &[
+    "tar",
+    #[cfg(feature = "compression")]
+    "tar.zst",
+    #[cfg(feature = "extract-zip")]
+    "zip",
+];
+
+/// Bytes needed to recognize any supported format by its magic bytes: the
+/// `ustar` magic sits at offset 257 of a tar header.
+const SNIFF_LEN: usize = 262;
+
+const TAR_MAGIC: &[u8] = b"ustar";
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+const EMPTY_ZIP_MAGIC: &[u8] = b"PK\x05\x06";
+
+/// Format of a received archive for `receive --extract`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveKind {
+    Tar,
+    TarZst,
+    Zip,
+}
+
+impl ArchiveKind {
+    /// The format of the file at `path`, from its extension or else its
+    /// first bytes. `None` for anything that is not an archive. A zstd
+    /// file only counts when it holds a tar archive.
+    pub fn detect(path: &Path) -> io::Result<Option<Self>> {
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_ascii_lowercase())
+            .unwrap_or_default();
+        if name.ends_with(".tar.zst") || name.ends_with(".tzst") {
+            return Ok(Some(ArchiveKind::TarZst));
+        }
+        if name.ends_with(".tar") {
+            return Ok(Some(ArchiveKind::Tar));
+        }
+        if name.ends_with(".zip") {
+            return Ok(Some(ArchiveKind::Zip));
+        }
+
+        let mut head = Vec::with_capacity(SNIFF_LEN);
+        File::open(path)?
+            .take(SNIFF_LEN as u64)
+            .read_to_end(&mut head)?;
+        if head.starts_with(ZIP_MAGIC) || head.starts_with(EMPTY_ZIP_MAGIC) {
+            return Ok(Some(ArchiveKind::Zip));
+        }
+        if is_tar(&head) {
+            return Ok(Some(ArchiveKind::Tar));
+        }
+        if head.starts_with(ZSTD_MAGIC) && zstd_holds_tar(path) {
+            return Ok(Some(ArchiveKind::TarZst));
+        }
+        Ok(None)
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            ArchiveKind::Tar => "tar",
+            ArchiveKind::TarZst => "tar.zst",
+            ArchiveKind::Zip => "zip",
+        }
+    }
+}
+
+fn is_tar(head: &[u8]) -> bool {
+    head.get(257..SNIFF_LEN) == Some(TAR_MAGIC)
+}
+
+#[cfg(feature = "compression")]
+fn zstd_holds_tar(path: &Path) -> bool {
+    let mut head = Vec::with_capacity(SNIFF_LEN);
+    File::open(path)
+        .and_then(zstd::stream::read::Decoder::new)
+        .and_then(|decoder| decoder.take(SNIFF_LEN as u64).read_to_end(&mut head))
+        .is_ok_and(|_| is_tar(&head))
+}
+
+#[cfg(not(feature = "compression"))]
+fn zstd_holds_tar(_path: &Path) -> bool {
+    false
+}
+
+/// Unpack the `kind` archive at `archive` into `output_dir`, reporting
+/// `FileStarted` and `FileFinished` for each file to `on_event`. `place`
+/// turns the path a file would have into the one it gets, such as a
+/// numbered name when that one is taken. Entries that would escape
+/// `output_dir`, links and special files fail the extraction; so does a
+/// format this build cannot read. On failure the files written so far
+/// are removed. Returns the paths written.
+pub fn extract(
+    archive: &Path,
+    kind: ArchiveKind,
+    output_dir: &Path,
+    mut place: impl FnMut(&Path) -> Result<PathBuf>,
+    mut on_event: impl FnMut(TransferEvent),
+) -> Result<Vec<PathBuf>> {
+    if !FORMATS.contains(&kind.extension()) {
+        bail!("this build cannot extract .{} archives", kind.extension());
+    }
+    let file =
+        File::open(archive).with_context(|| format!("failed to open {}", archive.display()))?;
+    let mut writer = EntryWriter {
+        output_dir,
+        place: &mut place,
+        on_event: &mut on_event,
+        written: Vec::new(),
+    };
+    let result = match kind {
+        ArchiveKind::Tar => extract_tar(file, &mut writer),
+        ArchiveKind::TarZst => extract_tar_zst(file, &mut writer),
+        ArchiveKind::Zip => extract_zip(file, &mut writer),
+    };
+    match result {
+        Ok(()) => Ok(writer.written),
+        Err(err) => {
+            for path in writer.written.iter().rev() {
+                let _ = std::fs::remove_file(path);
+            }
+            Err(err)
+        }
+    }
+}
+
+fn extract_tar(reader: impl Read, writer: &mut EntryWriter<'_>) -> Result<()> {
+    let mut archive = tar::Archive::new(reader);
+    for entry in archive
+        .entries()
+        .context("failed to read archive entries")?
+    {
+        let mut entry = entry.context("failed to read archive entry")?;
+        let entry_type = entry.header().entry_type();
+        let path = entry
+            .path()
+            .context("archive entry path is invalid")?
+            .into_owned();
+        if entry_type.is_dir() {
+            writer.dir(&path)?;
+        } else if entry_type.is_file() {
+            let size = entry.size();
+            writer.file(&path, size, &mut entry)?;
+        } else if !entry_type.is_pax_global_extensions() {
+            bail!("archive entry {} is a link or special file", path.display());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(feature = "compression")]
+fn extract_tar_zst(reader: impl Read, writer: &mut EntryWriter<'_>) -> Result<()> {
+    let decoder = zstd::stream::read::Decoder::new(reader).context("failed to read archive")?;
+    extract_tar(decoder, writer)
+}
+
+#[cfg(not(feature = "compression"))]
+fn extract_tar_zst(_reader: impl Read, _writer: &mut EntryWriter<'_>) -> Result<()> {
+    unreachable!("checked against FORMATS")
+}
+
+#[cfg(feature = "extract-zip")]
+fn extract_zip(file: File, writer: &mut EntryWriter<'_>) -> Result<()> {
+    let mut archive = zip::ZipArchive::new(file).context("failed to read archive")?;
+    for i in 0..archive.len() {
+        let mut entry = archive
+            .by_index(i)
+            .context("failed to read archive entry")?;
+        let path = PathBuf::from(entry.name());
+        if entry.is_dir() {
+            writer.dir(&path)?;
+        } else if entry.is_symlink() {
+            bail!("archive entry {} is a link", path.display());
+        } else {
+            let size = entry.size();
+            writer.file(&path, size, &mut entry)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "extract-zip"))]
+fn extract_zip(_file: File, _writer: &mut EntryWriter<'_>) -> Result<()> {
+    unreachable!("checked against FORMATS")
+}
+
+/// Writes the entries of one archive below `output_dir`.
+struct EntryWriter<'a> {
+    output_dir: &'a Path,
+    place: &'a mut dyn FnMut(&Path) -> Result<PathBuf>,
+    on_event: &'a mut dyn FnMut(TransferEvent),
+    written: Vec<PathBuf>,
+}
+
+impl EntryWriter<'_> {
+    fn dir(&mut self, path: &Path) -> Result<()> {
+        let dest = self.output_dir.join(entry_path(path)?);
+        std::fs::create_dir_all(sanitize::extended_length_path(&dest))
+            .with_context(|| format!("failed to create {}", dest.display()))
+    }
+
+    fn file(&mut self, path: &Path, size: u64, data: &mut dyn Read) -> Result<()> {
+        let relative = entry_path(path)?;
+        let wanted = self.output_dir.join(&relative);
+        if let Some(parent) = wanted.parent() {
+            std::fs::create_dir_all(sanitize::extended_length_path(parent))
+                .with_context(|| format!("failed to create {}", parent.display()))?;
+        }
+        let dest = (self.place)(&wanted)?;
+        let index = self.written.len() as u64;
+        (self.on_event)(TransferEvent::FileStarted {
+            index,
+            name: relative.to_string_lossy().to_string(),
+            size,
+        });
+        let mut out = File::create_new(sanitize::extended_length_path(&dest))
+            .with_context(|| format!("failed to create {}", dest.display()))?;
+        self.written.push(dest.clone());
+        io::copy(data, &mut out).with_context(|| format!("failed to write {}", dest.display()))?;
+        (self.on_event)(TransferEvent::FileFinished { index });
+        Ok(())
+    }
+}
+
+/// Relative destination of an archive entry. Every component must be a
+/// plain name, so entries cannot escape the output directory.
+fn entry_path(path: &Path) -> Result<PathBuf> {
+    let mut relative = PathBuf::new();
+    for component in path.components() {
+        let name = match component {
+            Component::Normal(name) => name.to_string_lossy().trim().to_string(),
+            Component::CurDir => continue,
+            _ => bail!("archive contains invalid path {}", path.display()),
+        };
+        if name.is_empty() || name == "." || name == ".." {
+            bail!("archive contains invalid path {}", path.display());
+        }
+        relative.push(sanitize::sanitize_file_name(&name));
+    }
+    if relative.as_os_str().is_empty() {
+        bail!("archive contains an empty path");
+    }
+    Ok(relative)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    use super::{extract, ArchiveKind};
+    use crate::events::TransferEvent;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("p2p-share-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn tar_of(entries: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (name, data) in entries {
+            let mut header = tar::Header::new_ustar();
+            header.set_size(data.len() as u64);
+            header.set_entry_type(tar::EntryType::Regular);
+            builder.append_data(&mut header, name, *data).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    /// `wanted`, or `wanted` with a ` (1)` suffix when it is taken.
+    fn numbered(wanted: &Path) -> anyhow::Result<PathBuf> {
+        if !wanted.exists() {
+            return Ok(wanted.to_path_buf());
+        }
+        Ok(wanted.with_file_name(format!(
+            "{} (1)",
+            wanted.file_name().unwrap().to_string_lossy()
+        )))
+    }
+
+    #[test]
+    fn archives_are_detected_by_extension_or_magic_bytes() {
+        let dir = temp_dir("extract-detect");
+        let tar = tar_of(&[("a.txt", b"alpha")]);
+        for (name, data, kind) in [
+            ("photos.tar", &b"not really"[..], Some(ArchiveKind::Tar)),
+            ("photos.TAR.ZST", b"", Some(ArchiveKind::TarZst)),
+            ("photos.zip", b"", Some(ArchiveKind::Zip)),
+            ("download", &tar[..], Some(ArchiveKind::Tar)),
+            ("download.bin", b"PK\x03\x04rest", Some(ArchiveKind::Zip)),
+            ("notes.txt", b"just text", None),
+        ] {
+            let path = dir.join(name);
+            fs::write(&path, data).unwrap();
+            assert_eq!(ArchiveKind::detect(&path).unwrap(), kind, "{name}");
+        }
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn tar_archives_extract_with_numbered_names_for_collisions() {
+        let dir = temp_dir("extract-tar");
+        let archive = dir.join("bundle.tar");
+        fs::write(
+            &archive,
+            tar_of(&[("docs/a.txt", b"alpha"), ("./b.txt", b"beta")]),
+        )
+        .unwrap();
+        let out = dir.join("out");
+        fs::create_dir_all(&out).unwrap();
+        fs::write(out.join("b.txt"), "mine").unwrap();
+
+        let mut events = Vec::new();
+        let written = extract(&archive, ArchiveKind::Tar, &out, numbered, |event| {
+            events.push(event)
+        })
+        .unwrap();
+        assert_eq!(written, [out.join("docs/a.txt"), out.join("b.txt (1)")]);
+        assert_eq!(fs::read(out.join("docs/a.txt")).unwrap(), b"alpha");
+        assert_eq!(fs::read(out.join("b.txt")).unwrap(), b"mine");
+        assert_eq!(fs::read(out.join("b.txt (1)")).unwrap(), b"beta");
+        let finished = events
+            .iter()
+            .filter(|event| matches!(event, TransferEvent::FileFinished { .. }))
+            .count();
+        assert_eq!(finished, 2);
+        assert!(matches!(
+            &events[0],
+            TransferEvent::FileStarted { index: 0, name, size: 5 } if name == "docs/a.txt"
+        ));
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn escaping_entries_fail_and_remove_what_was_written() {
+        let dir = temp_dir("extract-escape");
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_ustar();
+        header.set_size(2);
+        header.set_entry_type(tar::EntryType::Regular);
+        builder
+            .append_data(&mut header, "ok.txt", &b"ok"[..])
+            .unwrap();
+        let mut header = tar::Header::new_ustar();
+        header.as_old_mut().name[..9].copy_from_slice(b"../escape");
+        header.set_size(4);
+        header.set_entry_type(tar::EntryType::Regular);
+        header.set_cksum();
+        builder.append(&header, &b"evil"[..]).unwrap();
+        let archive = dir.join("evil.tar");
+        fs::write(&archive, builder.into_inner().unwrap()).unwrap();
+
+        let out = dir.join("out");
+        let err = extract(&archive, ArchiveKind::Tar, &out, numbered, |_| {}).unwrap_err();
+        assert!(err.to_string().contains("invalid path"), "{err:#}");
+        assert!(!dir.join("escape").exists());
+        assert!(!out.join("ok.txt").exists());
+        assert!(archive.exists());
+
+        let _ = fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,114 @@
+use crate::crypto::ConnectionLost;
+use crate::endpoint::{self, ConnectFailed, RelayUsed, SelfConnection, UnexpectedRelay};
+use crate::receiver::{ChecksumMismatch, Declined, PeerAbandoned, SequenceError};
+use crate::segments::SegmentMismatch;
+use crate::sender::{AckTimeout, ReceiverBusy, Rejected, UnsupportedFileType};
+use crate::ticket::{InvalidTicket, TicketExpired};
+
+/// Broad class of a failed transfer, for callers that react to each
+/// differently, such as the CLI's exit status.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureKind {
+    /// The input cannot work: an invalid or expired ticket, a ticket for
+    /// this device or a path that cannot be sent.
+    Usage,
+    /// The peer could not be reached, or the connection dropped.
+    Network,
+    /// The data arrived corrupted: a checksum, segment or sequence
+    /// mismatch.
+    Integrity,
+    /// The peer or this side turned the transfer down.
+    Rejected,
+    /// The peer stopped answering in time.
+    Timeout,
+    /// Anything else, such as a local disk error.
+    Other,
+}
+
+/// The [`FailureKind`] of `err`, from the typed errors anywhere in its
+/// chain. A dropped connection counts as [`FailureKind::Network`] even
+/// after it was wrapped in context.
+pub fn classify(err: &anyhow::Error) -> FailureKind {
+    if has::<InvalidTicket>(err)
+        || has::<TicketExpired>(err)
+        || has::<SelfConnection>(err)
+        || has::<UnsupportedFileType>(err)
+    {
+        FailureKind::Usage
+    } else if has::<ChecksumMismatch>(err)
+        || has::<SegmentMismatch>(err)
+        || has::<SequenceError>(err)
+    {
+        FailureKind::Integrity
+    } else if has::<Rejected>(err) || has::<Declined>(err) || has::<ReceiverBusy>(err) {
+        FailureKind::Rejected
+    } else if has::<AckTimeout>(err) {
+        FailureKind::Timeout
+    } else if has::<ConnectFailed>(err)
+        || has::<ConnectionLost>(err)
+        || has::<PeerAbandoned>(err)
+        || has::<UnexpectedRelay>(err)
+        || has::<RelayUsed>(err)
+        || endpoint::is_connection_lost(err)
+    {
+        FailureKind::Network
+    } else {
+        FailureKind::Other
+    }
+}
+
+/// Whether `E` is the error or one of its contexts or causes.
+fn has<E>(err: &anyhow::Error) -> bool
+where
+    E: std::error::Error + Send + Sync + 'static,
+{
+    err.downcast_ref::<E>().is_some() || err.chain().any(|cause| cause.is::<E>())
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Context;
+
+    use super::{classify, FailureKind};
+    use crate::endpoint::ConnectFailed;
+    use crate::receiver::ChecksumMismatch;
+    use crate::sender::{AckTimeout, Rejected};
+    use crate::ticket;
+
+    #[test]
+    fn typed_errors_are_classified_through_their_context() {
+        let bad_ticket = ticket::deserialize("p2psh:!!!").unwrap_err();
+        assert_eq!(classify(&bad_ticket), FailureKind::Usage);
+
+        let refused = Err::<(), _>(std::io::Error::from(std::io::ErrorKind::ConnectionRefused))
+            .context(ConnectFailed::to("192.0.2.1:9000"))
+            .unwrap_err();
+        assert_eq!(classify(&refused), FailureKind::Network);
+
+        let corrupted = anyhow::Error::new(ChecksumMismatch {
+            expected: "aa".to_string(),
+            actual: "bb".to_string(),
+        })
+        .context("receiving notes.txt");
+        assert_eq!(classify(&corrupted), FailureKind::Integrity);
+
+        let rejected = anyhow::Error::new(Rejected {
+            reason: "too large".to_string(),
+        });
+        assert_eq!(classify(&rejected), FailureKind::Rejected);
+
+        let stalled = anyhow::Error::new(AckTimeout {
+            waited: std::time::Duration::from_secs(1),
+        });
+        assert_eq!(classify(&stalled), FailureKind::Timeout);
+
+        let dropped = anyhow::Error::new(std::io::Error::from(std::io::ErrorKind::UnexpectedEof))
+            .context("reading frame");
+        assert_eq!(classify(&dropped), FailureKind::Network);
+
+        assert_eq!(
+            classify(&anyhow::anyhow!("disk on fire")),
+            FailureKind::Other
+        );
+    }
+}
@@ -0,0 +1,126 @@
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, ReadBuf};
+use tokio::sync::watch;
+use tokio::time::Sleep;
+
+/// How often a followed file is checked for new data once its end has been
+/// reached.
+pub(crate) const FOLLOW_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Reads a file that is still being appended to, like `tail -f`. At end of
+/// file it polls for new data every [`FOLLOW_POLL_INTERVAL`] until `stop`
+/// turns true (or its sender is dropped), then drains what is left and
+/// reports end of file. Everything read is hashed for the stream trailer.
+pub(crate) struct FollowReader<S> {
+    inner: S,
+    stop: watch::Receiver<bool>,
+    sleep: Option<Pin<Box<Sleep>>>,
+    stopping: bool,
+    size: u64,
+    hasher: blake3::Hasher,
+}
+
+impl<S> FollowReader<S> {
+    pub fn new(inner: S, stop: watch::Receiver<bool>) -> Self {
+        Self {
+            inner,
+            stop,
+            sleep: None,
+            stopping: false,
+            size: 0,
+            hasher: blake3::Hasher::new(),
+        }
+    }
+
+    /// Bytes read so far and their hex-encoded blake3 hash.
+    pub fn finish(&self) -> (u64, String) {
+        (self.size, self.hasher.finalize().to_hex().to_string())
+    }
+
+    fn stop_requested(&self) -> bool {
+        self.stop.has_changed().is_err() || *self.stop.borrow()
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for FollowReader<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            if let Some(sleep) = &mut this.sleep {
+                ready!(sleep.as_mut().poll(cx));
+                this.sleep = None;
+            }
+            let before = buf.filled().len();
+            ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+            let read = &buf.filled()[before..];
+            if !read.is_empty() {
+                this.size += read.len() as u64;
+                this.hasher.update(read);
+                return Poll::Ready(Ok(()));
+            }
+            if this.stopping {
+                return Poll::Ready(Ok(()));
+            }
+            if this.stop_requested() {
+                // Read once more so bytes appended just before the stop
+                // still make it into the transfer.
+                this.stopping = true;
+                continue;
+            }
+            this.sleep = Some(Box::pin(tokio::time::sleep(FOLLOW_POLL_INTERVAL)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Seek, SeekFrom, Write};
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    use tokio::io::AsyncReadExt;
+    use tokio::sync::watch;
+
+    use super::FollowReader;
+
+    #[tokio::test]
+    async fn appended_bytes_are_read_until_stopped() {
+        let stamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|value| value.as_nanos())
+            .unwrap_or(0);
+        let path = std::env::temp_dir().join(format!("p2p-share-follow-{stamp}.log"));
+        let mut writer = std::fs::File::create(&path).expect("create");
+        writer.write_all(b"first ").expect("write");
+
+        let (stop_tx, stop) = watch::channel(false);
+        let file = tokio::fs::File::open(&path).await.expect("open");
+        let mut reader = FollowReader::new(file, stop);
+        let appender = tokio::spawn(async move {
+            tokio::time::sleep(super::FOLLOW_POLL_INTERVAL).await;
+            writer.seek(SeekFrom::End(0)).expect("seek");
+            writer.write_all(b"second").expect("append");
+            writer.flush().expect("flush");
+            tokio::time::sleep(super::FOLLOW_POLL_INTERVAL * 2).await;
+            stop_tx.send(true).expect("stop");
+        });
+
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data).await.expect("read");
+        appender.await.expect("appender");
+        assert_eq!(data, b"first second");
+        let (size, hash) = reader.finish();
+        assert_eq!(size, 12);
+        assert_eq!(hash, blake3::hash(b"first second").to_hex().to_string());
+
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::paths;
+
+/// File name of the cache inside the p2p-share config directory.
+const CACHE_FILE_NAME: &str = "hash-cache.json";
+
+/// Entries kept before the least recently used ones are dropped.
+const MAX_ENTRIES: usize = 256;
+
+/// On-disk `(path, size, mtime) -> blake3` cache that lets repeated sends of
+/// an unchanged file skip rehashing.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct HashCache {
+    #[serde(default)]
+    entries: HashMap<PathBuf, CacheEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CacheEntry {
+    size: u64,
+    mtime_ns: u128,
+    blake3: String,
+    /// Seconds since the Unix epoch of the last lookup or insert.
+    used_at: u64,
+}
+
+impl HashCache {
+    /// Default cache location in the platform config directory, if one can
+    /// be determined from the environment.
+    pub fn default_path() -> Option<PathBuf> {
+        paths::app_config_dir().map(|dir| dir.join(CACHE_FILE_NAME))
+    }
+
+    /// Load the cache, treating a missing or unreadable file as empty.
+    pub fn load(path: &Path) -> Self {
+        std::fs::read(path)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("cannot create {}", parent.display()))?;
+        }
+        let json = serde_json::to_vec(self)?;
+        let temp = path.with_extension("json.tmp");
+        std::fs::write(&temp, json).with_context(|| format!("cannot write {}", temp.display()))?;
+        std::fs::rename(&temp, path).with_context(|| format!("cannot write {}", path.display()))
+    }
+
+    /// Cached hash for `file` if its size and mtime still match.
+    pub fn lookup(&mut self, file: &Path, size: u64, mtime: SystemTime) -> Option<String> {
+        let mtime_ns = mtime_ns(mtime)?;
+        let entry = self.entries.get_mut(file)?;
+        if entry.size != size || entry.mtime_ns != mtime_ns {
+            self.entries.remove(file);
+            return None;
+        }
+        entry.used_at = now_secs();
+        Some(entry.blake3.clone())
+    }
+
+    pub fn insert(&mut self, file: PathBuf, size: u64, mtime: SystemTime, blake3: String) {
+        let Some(mtime_ns) = mtime_ns(mtime) else {
+            return;
+        };
+        self.entries.insert(
+            file,
+            CacheEntry {
+                size,
+                mtime_ns,
+                blake3,
+                used_at: now_secs(),
+            },
+        );
+        while self.entries.len() > MAX_ENTRIES {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.used_at)
+                .map(|(path, _)| path.clone());
+            match oldest {
+                Some(path) => self.entries.remove(&path),
+                None => break,
+            };
+        }
+    }
+}
+
+fn mtime_ns(mtime: SystemTime) -> Option<u128> {
+    mtime.duration_since(UNIX_EPOCH).ok().map(|d| d.as_nanos())
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::HashCache;
+
+    #[test]
+    fn entries_are_invalidated_by_size_or_mtime() {
+        let file = PathBuf::from("/data/big.iso");
+        let mtime = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let mut cache = HashCache::default();
+        cache.insert(file.clone(), 10, mtime, "abc".to_string());
+
+        assert_eq!(cache.lookup(&file, 10, mtime).as_deref(), Some("abc"));
+        assert_eq!(cache.lookup(&file, 11, mtime), None);
+        // A mismatch evicts the entry.
+        assert_eq!(cache.lookup(&file, 10, mtime), None);
+
+        cache.insert(file.clone(), 10, mtime, "abc".to_string());
+        assert_eq!(
+            cache.lookup(&file, 10, mtime + Duration::from_secs(1)),
+            None
+        );
+    }
+
+    #[test]
+    fn cache_round_trips_through_disk() {
+        let dir = std::env::temp_dir().join(format!("p2p-share-hash-cache-{}", std::process::id()));
+        let path = dir.join("hash-cache.json");
+        let file = PathBuf::from("/data/report.pdf");
+        let mtime = UNIX_EPOCH + Duration::from_secs(42);
+
+        let mut cache = HashCache::default();
+        cache.insert(file.clone(), 5, mtime, "def".to_string());
+        cache.save(&path).expect("save");
+
+        let mut loaded = HashCache::load(&path);
+        assert_eq!(loaded.lookup(&file, 5, mtime).as_deref(), Some("def"));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
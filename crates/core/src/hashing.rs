@@ -0,0 +1,66 @@
+use std::io;
+use std::path::Path;
+
+/// Files smaller than this are streamed through one hasher even with the
+/// `parallel-hash` feature: below it, mapping the file and waking threads
+/// costs about as much as it saves.
+pub const PARALLEL_THRESHOLD: u64 = 16 * 1024 * 1024;
+
+/// Blake3 hash of the file at `path`.
+///
+/// With the `parallel-hash` feature, files of at least
+/// [`PARALLEL_THRESHOLD`] bytes are memory-mapped and hashed on `threads`
+/// threads, or on every core when `None`. Smaller files, `Some(1)` and
+/// builds without the feature stream the file through one hasher.
+pub fn hash_path(path: &Path, threads: Option<usize>) -> io::Result<blake3::Hash> {
+    #[cfg(feature = "parallel-hash")]
+    {
+        if threads != Some(1) && std::fs::metadata(path)?.len() >= PARALLEL_THRESHOLD {
+            return hash_parallel(path, threads);
+        }
+    }
+    #[cfg(not(feature = "parallel-hash"))]
+    let _ = threads;
+    let mut hasher = blake3::Hasher::new();
+    io::copy(&mut std::fs::File::open(path)?, &mut hasher)?;
+    Ok(hasher.finalize())
+}
+
+#[cfg(feature = "parallel-hash")]
+fn hash_parallel(path: &Path, threads: Option<usize>) -> io::Result<blake3::Hash> {
+    let mut hasher = blake3::Hasher::new();
+    match threads {
+        None => {
+            hasher.update_mmap_rayon(path)?;
+        }
+        Some(threads) => {
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .map_err(io::Error::other)?;
+            pool.install(|| hasher.update_mmap_rayon(path).map(|_| ()))?;
+        }
+    }
+    Ok(hasher.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{hash_path, PARALLEL_THRESHOLD};
+
+    #[test]
+    fn every_thread_count_gives_the_streaming_hash() {
+        let path = std::env::temp_dir().join(format!("p2p-share-hashing-{}", std::process::id()));
+        let data: Vec<u8> = (0..PARALLEL_THRESHOLD + 12_345)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        std::fs::write(&path, &data).unwrap();
+
+        let expected = blake3::hash(&data);
+        for threads in [None, Some(1), Some(3)] {
+            assert_eq!(hash_path(&path, threads).unwrap(), expected, "{threads:?}");
+        }
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
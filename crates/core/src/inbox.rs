@@ -0,0 +1,242 @@
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+/// What to do with an incoming transfer.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InboxAction {
+    Accept,
+    #[default]
+    Reject,
+}
+
+/// A single declarative accept/reject rule. Every criterion is optional;
+/// a rule matches when all of the criteria it sets match.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct InboxRule {
+    /// Label reported when the rule matches.
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Allowed peer node ids (full hex form). Absent means any peer.
+    #[serde(default)]
+    pub peers: Option<Vec<String>>,
+    /// Allowed file extensions, without the leading dot. Case-insensitive.
+    #[serde(default)]
+    pub extensions: Option<Vec<String>>,
+    /// Maximum accepted size in bytes (inclusive). A live file from
+    /// `send --follow` announces no size, so it is held to this limit as it
+    /// arrives instead.
+    #[serde(default)]
+    pub max_size: Option<u64>,
+    pub action: InboxAction,
+    /// Subfolder of the output directory to save into when accepted.
+    #[serde(default)]
+    pub subdir: Option<PathBuf>,
+}
+
+/// Ordered list of rules evaluated against each incoming header.
+/// The first matching rule wins; `default` applies when none match.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct InboxPolicy {
+    #[serde(default)]
+    pub rules: Vec<InboxRule>,
+    #[serde(default)]
+    pub default: InboxAction,
+}
+
+/// Result of evaluating an [`InboxPolicy`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InboxDecision {
+    pub action: InboxAction,
+    pub subdir: Option<PathBuf>,
+    /// Index of the matching rule, or `None` when the default applied.
+    pub rule_index: Option<usize>,
+    pub rule_name: Option<String>,
+    /// Size limit of the matching rule, to enforce on the bytes actually
+    /// received when the sender could not announce the size.
+    pub max_size: Option<u64>,
+}
+
+impl InboxDecision {
+    /// Human-readable summary of which rule matched.
+    pub fn describe(&self) -> String {
+        let rule = match (self.rule_index, self.rule_name.as_deref()) {
+            (Some(_), Some(name)) => format!("Inbox rule '{}' matched", name),
+            (Some(index), None) => format!("Inbox rule #{} matched", index + 1),
+            (None, _) => "No inbox rule matched (default)".to_string(),
+        };
+        match (self.action, self.subdir.as_deref()) {
+            (InboxAction::Accept, Some(subdir)) => {
+                format!("{}: accepting into {}", rule, subdir.display())
+            }
+            (InboxAction::Accept, None) => format!("{}: accepting", rule),
+            (InboxAction::Reject, _) => format!("{}: rejecting", rule),
+        }
+    }
+}
+
+impl InboxPolicy {
+    /// Parse and validate a policy from its JSON representation.
+    pub fn from_json(json: &str) -> Result<Self> {
+        let policy: Self = serde_json::from_str(json).context("invalid inbox policy")?;
+        policy.validate()?;
+        Ok(policy)
+    }
+
+    /// Load and validate a policy from a JSON file.
+    pub fn load(path: &Path) -> Result<Self> {
+        let json = std::fs::read_to_string(path)
+            .with_context(|| format!("cannot read inbox policy {}", path.display()))?;
+        Self::from_json(&json)
+    }
+
+    /// Reject subdirectories that would escape the output directory.
+    pub fn validate(&self) -> Result<()> {
+        for (index, rule) in self.rules.iter().enumerate() {
+            let Some(subdir) = &rule.subdir else {
+                continue;
+            };
+            let valid = subdir.components().next().is_some()
+                && subdir
+                    .components()
+                    .all(|component| matches!(component, Component::Normal(_)));
+            if !valid {
+                bail!(
+                    "inbox rule #{}: subdir {} must be a relative path without '..'",
+                    index + 1,
+                    subdir.display()
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Evaluate the rules for an incoming transfer.
+    pub fn evaluate(&self, peer: Option<&str>, file_name: &str, size: u64) -> InboxDecision {
+        let extension = Path::new(file_name)
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase());
+
+        for (index, rule) in self.rules.iter().enumerate() {
+            if rule.matches(peer, extension.as_deref(), size) {
+                return InboxDecision {
+                    action: rule.action,
+                    subdir: rule.subdir.clone(),
+                    rule_index: Some(index),
+                    rule_name: rule.name.clone(),
+                    max_size: rule.max_size,
+                };
+            }
+        }
+
+        InboxDecision {
+            action: self.default,
+            subdir: None,
+            rule_index: None,
+            rule_name: None,
+            max_size: None,
+        }
+    }
+}
+
+impl InboxRule {
+    fn matches(&self, peer: Option<&str>, extension: Option<&str>, size: u64) -> bool {
+        if let Some(peers) = &self.peers {
+            let Some(peer) = peer else {
+                return false;
+            };
+            if !peers
+                .iter()
+                .any(|allowed| allowed.eq_ignore_ascii_case(peer))
+            {
+                return false;
+            }
+        }
+        if let Some(extensions) = &self.extensions {
+            let Some(extension) = extension else {
+                return false;
+            };
+            if !extensions.iter().any(|allowed| {
+                allowed
+                    .trim_start_matches('.')
+                    .eq_ignore_ascii_case(extension)
+            }) {
+                return false;
+            }
+        }
+        if let Some(max_size) = self.max_size {
+            if size > max_size {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::{InboxAction, InboxPolicy};
+
+    const POLICY: &str = r#"{
+        "rules": [
+            { "name": "photos", "peers": ["abcd"], "extensions": ["jpg", ".PNG"],
+              "max_size": 1000, "action": "accept", "subdir": "photos" },
+            { "name": "huge", "action": "reject", "extensions": ["iso"] },
+            { "action": "accept", "max_size": 10 }
+        ],
+        "default": "reject"
+    }"#;
+
+    #[test]
+    fn first_matching_rule_wins() {
+        let policy = InboxPolicy::from_json(POLICY).expect("parse");
+        let decision = policy.evaluate(Some("abcd"), "cat.png", 500);
+        assert_eq!(decision.action, InboxAction::Accept);
+        assert_eq!(decision.subdir, Some(PathBuf::from("photos")));
+        assert_eq!(decision.rule_index, Some(0));
+        assert_eq!(
+            decision.describe(),
+            "Inbox rule 'photos' matched: accepting into photos"
+        );
+    }
+
+    #[test]
+    fn unmatched_criteria_fall_through_to_default() {
+        let policy = InboxPolicy::from_json(POLICY).expect("parse");
+
+        let wrong_peer = policy.evaluate(Some("ffff"), "cat.jpg", 500);
+        assert_eq!(wrong_peer.action, InboxAction::Reject);
+        assert_eq!(wrong_peer.rule_index, None);
+
+        let too_big = policy.evaluate(Some("abcd"), "cat.jpg", 5000);
+        assert_eq!(too_big.action, InboxAction::Reject);
+
+        let no_peer = policy.evaluate(None, "cat.jpg", 500);
+        assert_eq!(no_peer.rule_index, None);
+
+        let small = policy.evaluate(None, "notes", 5);
+        assert_eq!(small.action, InboxAction::Accept);
+        assert_eq!(small.rule_index, Some(2));
+        assert_eq!(small.describe(), "Inbox rule #3 matched: accepting");
+    }
+
+    #[test]
+    fn escaping_subdirs_are_rejected() {
+        let json = r#"{ "rules": [{ "action": "accept", "subdir": "../etc" }] }"#;
+        assert!(InboxPolicy::from_json(json).is_err());
+        let json = r#"{ "rules": [{ "action": "accept", "subdir": "/tmp" }] }"#;
+        assert!(InboxPolicy::from_json(json).is_err());
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        let json = r#"{ "rules": [{ "action": "accept", "max_bytes": 10 }] }"#;
+        assert!(InboxPolicy::from_json(json).is_err());
+    }
+}
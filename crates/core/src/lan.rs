@@ -0,0 +1,139 @@
+use std::collections::BTreeSet;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::time::Duration;
+
+use iroh::NodeId;
+use tokio::net::UdpSocket;
+
+use crate::endpoint::AbortOnDrop;
+
+/// UDP port listen-mode receivers answer LAN probes on.
+pub const LAN_PROBE_PORT: u16 = 47_820;
+/// How long a sender waits for a LAN reply before connecting as usual.
+pub(crate) const PROBE_TIMEOUT: Duration = Duration::from_millis(600);
+
+const PROBE_PREFIX: &str = "p2p-share-probe 1 ";
+const REPLY_PREFIX: &str = "p2p-share-here 1 ";
+
+/// Answer probes for `node_id` on [`LAN_PROBE_PORT`] with the iroh `ports`
+/// to dial, until the returned guard is dropped.
+pub(crate) async fn spawn_responder(node_id: NodeId, ports: Vec<u16>) -> io::Result<AbortOnDrop> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, LAN_PROBE_PORT)).await?;
+    Ok(AbortOnDrop::new(tokio::spawn(respond(
+        socket, node_id, ports,
+    ))))
+}
+
+async fn respond(socket: UdpSocket, node_id: NodeId, ports: Vec<u16>) {
+    let ports = ports
+        .iter()
+        .map(u16::to_string)
+        .collect::<Vec<_>>()
+        .join(",");
+    let reply = format!("{}{} {}", REPLY_PREFIX, node_id, ports);
+    let mut buf = [0u8; 256];
+    loop {
+        let Ok((len, from)) = socket.recv_from(&mut buf).await else {
+            continue;
+        };
+        let asked = std::str::from_utf8(&buf[..len])
+            .ok()
+            .and_then(|probe| probe.strip_prefix(PROBE_PREFIX))
+            .and_then(|id| id.trim().parse::<NodeId>().ok());
+        if asked == Some(node_id) {
+            let _ = socket.send_to(reply.as_bytes(), from).await;
+        }
+    }
+}
+
+/// Broadcast a probe for `node_id` on the local IPv4 network and return the
+/// direct addresses from the first reply. Empty when nobody answers within
+/// [`PROBE_TIMEOUT`].
+pub(crate) async fn discover(node_id: NodeId) -> Vec<SocketAddr> {
+    let broadcast = SocketAddr::from((Ipv4Addr::BROADCAST, LAN_PROBE_PORT));
+    probe(node_id, broadcast, PROBE_TIMEOUT)
+        .await
+        .unwrap_or_default()
+}
+
+async fn probe(
+    node_id: NodeId,
+    target: SocketAddr,
+    timeout: Duration,
+) -> io::Result<Vec<SocketAddr>> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+    socket.set_broadcast(true)?;
+    socket
+        .send_to(format!("{}{}", PROBE_PREFIX, node_id).as_bytes(), target)
+        .await?;
+
+    let wait = async {
+        let mut buf = [0u8; 256];
+        loop {
+            let (len, from) = socket.recv_from(&mut buf).await?;
+            if let Some(addrs) = parse_reply(&buf[..len], node_id, from) {
+                return io::Result::Ok(addrs);
+            }
+        }
+    };
+    match tokio::time::timeout(timeout, wait).await {
+        Ok(addrs) => addrs,
+        Err(_) => Ok(Vec::new()),
+    }
+}
+
+/// The addresses in a reply from `from`: its source IP with each port it
+/// lists. `None` for anything but a reply about `node_id`.
+fn parse_reply(reply: &[u8], node_id: NodeId, from: SocketAddr) -> Option<Vec<SocketAddr>> {
+    let reply = std::str::from_utf8(reply)
+        .ok()?
+        .strip_prefix(REPLY_PREFIX)?;
+    let (id, ports) = reply.trim().split_once(' ')?;
+    if id.parse::<NodeId>().ok()? != node_id {
+        return None;
+    }
+    let addrs: BTreeSet<SocketAddr> = ports
+        .split(',')
+        .filter_map(|port| port.parse::<u16>().ok())
+        .filter(|&port| port != 0)
+        .map(|port| SocketAddr::new(from.ip(), port))
+        .collect();
+    (!addrs.is_empty()).then(|| addrs.into_iter().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, SocketAddr};
+    use std::time::Duration;
+
+    use iroh::SecretKey;
+    use tokio::net::UdpSocket;
+
+    use super::{probe, respond};
+
+    #[tokio::test]
+    async fn probes_are_answered_only_for_the_right_node() {
+        let node_id = SecretKey::from_bytes(&[5u8; 32]).public();
+        let other = SecretKey::from_bytes(&[6u8; 32]).public();
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let target = socket.local_addr().unwrap();
+        let responder = tokio::spawn(respond(socket, node_id, vec![40_001, 40_002]));
+
+        let found = probe(node_id, target, Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert_eq!(
+            found,
+            [
+                SocketAddr::from((Ipv4Addr::LOCALHOST, 40_001)),
+                SocketAddr::from((Ipv4Addr::LOCALHOST, 40_002)),
+            ]
+        );
+        let found = probe(other, target, Duration::from_millis(200))
+            .await
+            .unwrap();
+        assert!(found.is_empty());
+        responder.abort();
+    }
+}
@@ -1,11 +1,40 @@
+pub mod alpn;
+pub mod at_rest;
 pub mod bundle;
+pub mod capabilities;
+pub mod code;
+pub mod compression;
 pub mod crypto;
+pub mod diagnostics;
+pub mod endpoint;
+pub mod estimate;
+pub mod event_record;
 pub mod events;
+pub mod extract;
+pub mod failure;
+mod follow;
+pub mod hash_cache;
+pub mod hashing;
+pub mod inbox;
+#[cfg(feature = "lan-probe")]
+pub mod lan;
+pub mod manifest;
+pub mod options;
+pub mod output_template;
+pub mod paths;
 pub mod progress;
 pub mod protocol;
+mod qr;
+mod receive_fs;
 pub mod receiver;
+pub mod route;
+pub mod sanitize;
+pub mod segments;
 pub mod sender;
+pub mod stats;
+pub mod tcp;
 pub mod ticket;
+mod transport;
 
 pub use events::{
     ConnectionPathKind, TransferCompleted, TransferContentKind, TransferEvent, TransferEventSink,
@@ -0,0 +1,172 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::path::{Component, Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::protocol::CHUNK_SIZE;
+use crate::sanitize;
+
+/// Most frames a sender reads for one manifest, about 60 MiB of JSON.
+pub const MAX_FRAMES: u64 = 1024;
+
+/// Size and blake3 hash of a file a sync receiver already has.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub size: u64,
+    /// Hex-encoded blake3 hash of the file contents.
+    pub blake3: String,
+}
+
+/// Files a `receive --sync` receiver already has, keyed by their path
+/// inside the sender's archive with `/` separators (`photos/2024/a.jpg`).
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Manifest {
+    files: BTreeMap<String, ManifestEntry>,
+}
+
+impl Manifest {
+    /// List the regular files under `dir` that an archive with the
+    /// top-level names `roots` would extract over. Roots the receiver would
+    /// rename while extracting, unreadable files and anything that is not a
+    /// regular file or directory are left out, so the sender sends them.
+    pub fn build(dir: &Path, roots: &[String]) -> Self {
+        let mut manifest = Self::default();
+        for root in roots {
+            let single = matches!(
+                Path::new(root).components().collect::<Vec<_>>()[..],
+                [Component::Normal(_)]
+            );
+            if single && sanitize::sanitize_file_name(root) == *root {
+                manifest.add_tree(&dir.join(root), root);
+            }
+        }
+        manifest
+    }
+
+    fn add_tree(&mut self, path: &Path, name: &str) {
+        let Ok(metadata) = std::fs::symlink_metadata(path) else {
+            return;
+        };
+        if metadata.is_file() {
+            let hashed = File::open(path).and_then(|mut file| {
+                let mut hasher = blake3::Hasher::new();
+                std::io::copy(&mut file, &mut hasher)?;
+                Ok(hasher.finalize())
+            });
+            if let Ok(hash) = hashed {
+                self.insert(
+                    name.to_string(),
+                    ManifestEntry {
+                        size: metadata.len(),
+                        blake3: hash.to_hex().to_string(),
+                    },
+                );
+            }
+            return;
+        }
+        if !metadata.is_dir() {
+            return;
+        }
+        let Ok(entries) = std::fs::read_dir(path) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            if let Some(child) = entry.file_name().to_str() {
+                self.add_tree(&entry.path(), &format!("{}/{}", name, child));
+            }
+        }
+    }
+
+    pub fn insert(&mut self, name: String, entry: ManifestEntry) {
+        self.files.insert(name, entry);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&ManifestEntry> {
+        self.files.get(name)
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// The manifest as JSON split into frames of at most [`CHUNK_SIZE`]
+    /// bytes; always at least one.
+    pub fn to_frames(&self) -> anyhow::Result<Vec<Vec<u8>>> {
+        let json = serde_json::to_vec(self)?;
+        Ok(json.chunks(CHUNK_SIZE).map(<[u8]>::to_vec).collect())
+    }
+
+    /// Parse the concatenated frames written by [`Manifest::to_frames`].
+    pub fn from_json(json: &[u8]) -> anyhow::Result<Self> {
+        Ok(serde_json::from_slice(json)?)
+    }
+}
+
+/// `path` with `/` between its components, the key [`Manifest`] uses.
+pub fn entry_name(path: &Path) -> String {
+    path.components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{entry_name, Manifest};
+
+    #[test]
+    fn manifests_list_files_under_the_archive_roots_only() {
+        let dir = std::env::temp_dir().join(format!("p2p-share-manifest-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("photos/2024")).unwrap();
+        std::fs::write(dir.join("photos/2024/a.jpg"), b"jpeg").unwrap();
+        std::fs::write(dir.join("notes.txt"), b"notes").unwrap();
+        std::fs::write(dir.join("unrelated.txt"), b"other").unwrap();
+
+        let roots = [
+            "photos".to_string(),
+            "notes.txt".to_string(),
+            "missing".to_string(),
+            "../escape".to_string(),
+        ];
+        let manifest = Manifest::build(&dir, &roots);
+        assert_eq!(manifest.len(), 2);
+        let photo = manifest.get("photos/2024/a.jpg").expect("photo listed");
+        assert_eq!(photo.size, 4);
+        assert_eq!(photo.blake3, blake3::hash(b"jpeg").to_hex().to_string());
+        assert!(manifest.get("notes.txt").is_some());
+        assert!(manifest.get("unrelated.txt").is_none());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn manifests_survive_framing() {
+        let mut manifest = Manifest::default();
+        for i in 0..2000 {
+            manifest.insert(
+                format!("folder/a-rather-long-file-name-{i}.txt"),
+                super::ManifestEntry {
+                    size: i,
+                    blake3: blake3::hash(&i.to_le_bytes()).to_hex().to_string(),
+                },
+            );
+        }
+        let frames = manifest.to_frames().unwrap();
+        assert!(frames.len() > 1);
+        assert_eq!(Manifest::from_json(&frames.concat()).unwrap(), manifest);
+        assert_eq!(Manifest::default().to_frames().unwrap().len(), 1);
+        assert_eq!(
+            entry_name(Path::new("photos/2024/a.jpg")),
+            "photos/2024/a.jpg"
+        );
+    }
+}
@@ -0,0 +1,200 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::code::CodeFormat;
+use crate::endpoint::{self, RelayPolicy, RelaySelection};
+use crate::stats::TransferStats;
+use crate::tcp::TcpTuning;
+use crate::ticket;
+
+/// Connection and reporting settings shared by both directions, embedded
+/// as `transfer` in [`SendOptions`] and [`ReceiveOptions`] so a frontend
+/// maps its common flags once. Start from [`TransferOptions::builder`] or
+/// [`Default`].
+///
+/// [`SendOptions`]: crate::sender::SendOptions
+/// [`ReceiveOptions`]: crate::receiver::ReceiveOptions
+#[derive(Debug, Clone)]
+pub struct TransferOptions {
+    /// Relay servers the endpoint may use as its home relay.
+    pub relay: RelaySelection,
+    /// Pin [`Self::relay`]: never fall back to other relays, and abort with
+    /// an `unexpected_relay` error if the home relay or the relay on the
+    /// path to the peer is not one of them.
+    pub strict_host: bool,
+    /// Whether to warn about, or refuse, a path to the peer through a
+    /// relay.
+    pub relay_policy: RelayPolicy,
+    /// How the handshake verification code is shown.
+    pub code_format: CodeFormat,
+    /// Also show the verification code as a QR code, to compare the two
+    /// screens at a glance or scan one device's code with the other.
+    pub code_qr: bool,
+    /// Socket options for plain TCP transfers.
+    pub tcp: TcpTuning,
+    /// Minimum time between progress updates, for both the terminal bar
+    /// and [`crate::TransferEvent::Progress`] events. `None` reports every
+    /// chunk.
+    pub progress_interval: Option<Duration>,
+    /// Skip the background watcher that reports connection path changes
+    /// during the transfer; only the final path summary is reported.
+    pub no_path_watch: bool,
+    /// Concurrent streams a peer may open on one connection to this
+    /// endpoint; further opens are refused by the QUIC transport.
+    pub max_streams: u32,
+    /// Talk to the peer over the LAN probe first. A sender dialing a
+    /// listening receiver broadcasts a probe and adds the address that
+    /// answers to the ones dialed, skipping the relay-first attempt; a
+    /// listening receiver answers those probes. Needs the `lan-probe`
+    /// feature on both ends.
+    pub lan: bool,
+    /// After a transfer drops mid-stream, try again for up to this long so
+    /// the receiver continues from its `.part` file: the listening side
+    /// waits for the peer to come back, the connecting side dials again.
+    /// `None` gives up straight away.
+    pub reconnect_window: Option<Duration>,
+    /// Counters updated after every chunk, for embedders that sample
+    /// progress from another thread.
+    pub stats: Option<Arc<TransferStats>>,
+    /// How far the clock of the device that issued an expiring ticket may
+    /// be off from ours when checking the ticket's expiry before
+    /// connecting. See [`ticket::check_expiry`].
+    pub clock_skew: Duration,
+}
+
+impl Default for TransferOptions {
+    fn default() -> Self {
+        Self {
+            relay: RelaySelection::default(),
+            strict_host: false,
+            relay_policy: RelayPolicy::Allow,
+            code_format: CodeFormat::default(),
+            code_qr: false,
+            tcp: TcpTuning::default(),
+            progress_interval: None,
+            no_path_watch: false,
+            max_streams: endpoint::DEFAULT_MAX_STREAMS,
+            lan: false,
+            reconnect_window: None,
+            stats: None,
+            clock_skew: ticket::DEFAULT_CLOCK_SKEW,
+        }
+    }
+}
+
+impl TransferOptions {
+    pub fn builder() -> TransferOptionsBuilder {
+        TransferOptionsBuilder::default()
+    }
+}
+
+/// Builds [`TransferOptions`] one setting at a time; anything left unset
+/// keeps its default.
+#[derive(Debug, Clone, Default)]
+pub struct TransferOptionsBuilder {
+    options: TransferOptions,
+}
+
+impl TransferOptionsBuilder {
+    pub fn relay(mut self, relay: RelaySelection) -> Self {
+        self.options.relay = relay;
+        self
+    }
+
+    pub fn strict_host(mut self, strict_host: bool) -> Self {
+        self.options.strict_host = strict_host;
+        self
+    }
+
+    pub fn relay_policy(mut self, relay_policy: RelayPolicy) -> Self {
+        self.options.relay_policy = relay_policy;
+        self
+    }
+
+    pub fn code_format(mut self, code_format: CodeFormat) -> Self {
+        self.options.code_format = code_format;
+        self
+    }
+
+    pub fn code_qr(mut self, code_qr: bool) -> Self {
+        self.options.code_qr = code_qr;
+        self
+    }
+
+    pub fn tcp(mut self, tcp: TcpTuning) -> Self {
+        self.options.tcp = tcp;
+        self
+    }
+
+    pub fn progress_interval(mut self, interval: Option<Duration>) -> Self {
+        self.options.progress_interval = interval;
+        self
+    }
+
+    pub fn no_path_watch(mut self, no_path_watch: bool) -> Self {
+        self.options.no_path_watch = no_path_watch;
+        self
+    }
+
+    pub fn max_streams(mut self, max_streams: u32) -> Self {
+        self.options.max_streams = max_streams;
+        self
+    }
+
+    pub fn lan(mut self, lan: bool) -> Self {
+        self.options.lan = lan;
+        self
+    }
+
+    pub fn reconnect_window(mut self, window: Option<Duration>) -> Self {
+        self.options.reconnect_window = window;
+        self
+    }
+
+    pub fn stats(mut self, stats: Arc<TransferStats>) -> Self {
+        self.options.stats = Some(stats);
+        self
+    }
+
+    pub fn clock_skew(mut self, skew: Duration) -> Self {
+        self.options.clock_skew = skew;
+        self
+    }
+
+    pub fn build(self) -> TransferOptions {
+        self.options
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::TransferOptions;
+    use crate::endpoint::{RelayRegion, RelaySelection, DEFAULT_MAX_STREAMS};
+    use crate::receiver::ReceiveOptions;
+    use crate::sender::SendOptions;
+
+    #[test]
+    fn the_builder_sets_only_what_it_is_given() {
+        let transfer = TransferOptions::builder()
+            .relay(RelaySelection::Region(RelayRegion::Europe))
+            .reconnect_window(Some(Duration::from_secs(30)))
+            .build();
+        assert_eq!(transfer.relay, RelaySelection::Region(RelayRegion::Europe));
+        assert_eq!(transfer.reconnect_window, Some(Duration::from_secs(30)));
+        assert_eq!(transfer.max_streams, DEFAULT_MAX_STREAMS);
+        assert!(!transfer.strict_host);
+        assert!(!transfer.code_qr);
+
+        let send = SendOptions::from(transfer.clone());
+        assert_eq!(send.transfer.relay, transfer.relay);
+        assert_eq!(send.pipeline_depth, SendOptions::default().pipeline_depth);
+        let receive = ReceiveOptions::from(transfer);
+        assert_eq!(
+            receive.transfer.reconnect_window,
+            Some(Duration::from_secs(30))
+        );
+        assert!(receive.inbox.is_none());
+    }
+}
@@ -0,0 +1,207 @@
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{bail, Result};
+use time::macros::format_description;
+use time::OffsetDateTime;
+
+/// Placeholders accepted by [`OutputTemplate`], for help and error text.
+pub const PLACEHOLDERS: &str = "{name}, {stem}, {ext}, {date}, {time}, {sender}, {size}";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Name,
+    Stem,
+    Ext,
+    Date,
+    Time,
+    Sender,
+    Size,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    Field(Field),
+}
+
+/// Pattern for naming received files, such as `{date}_{sender}_{name}`.
+///
+/// - `{name}`: the sender's file name (the bundle name for multi-file transfers)
+/// - `{stem}` / `{ext}`: the name without / only its last extension, no dot
+/// - `{date}` / `{time}`: UTC arrival time as `YYYY-MM-DD` / `HHMMSS`
+/// - `{sender}`: short node id of the sender, or `unknown` over plain TCP
+/// - `{size}`: size in bytes
+///
+/// `{{` and `}}` produce literal braces. The expansion is sanitized like a
+/// sender-supplied name, so it cannot introduce path separators.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputTemplate {
+    source: String,
+    segments: Vec<Segment>,
+}
+
+/// Values a template is expanded with.
+#[derive(Debug, Clone)]
+pub struct TemplateInput<'a> {
+    pub name: &'a str,
+    pub sender: Option<String>,
+    pub size: u64,
+    pub now: OffsetDateTime,
+}
+
+impl OutputTemplate {
+    pub fn expand(&self, input: &TemplateInput<'_>) -> String {
+        let path = Path::new(input.name);
+        let mut out = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(text) => out.push_str(text),
+                Segment::Field(Field::Name) => out.push_str(input.name),
+                Segment::Field(Field::Stem) => out.push_str(
+                    &path
+                        .file_stem()
+                        .map(|stem| stem.to_string_lossy())
+                        .unwrap_or_default(),
+                ),
+                Segment::Field(Field::Ext) => out.push_str(
+                    &path
+                        .extension()
+                        .map(|ext| ext.to_string_lossy())
+                        .unwrap_or_default(),
+                ),
+                Segment::Field(Field::Date) => {
+                    let format = format_description!("[year]-[month]-[day]");
+                    out.push_str(&input.now.format(&format).unwrap_or_default());
+                }
+                Segment::Field(Field::Time) => {
+                    let format = format_description!("[hour][minute][second]");
+                    out.push_str(&input.now.format(&format).unwrap_or_default());
+                }
+                Segment::Field(Field::Sender) => {
+                    out.push_str(input.sender.as_deref().unwrap_or("unknown"))
+                }
+                Segment::Field(Field::Size) => out.push_str(&input.size.to_string()),
+            }
+        }
+        out
+    }
+}
+
+impl FromStr for OutputTemplate {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+        let mut chars = s.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => bail!("unclosed '{{' in output template"),
+                        }
+                    }
+                    let field = match name.as_str() {
+                        "name" => Field::Name,
+                        "stem" => Field::Stem,
+                        "ext" => Field::Ext,
+                        "date" => Field::Date,
+                        "time" => Field::Time,
+                        "sender" => Field::Sender,
+                        "size" => Field::Size,
+                        other => bail!(
+                            "unknown placeholder '{{{}}}' (expected {})",
+                            other,
+                            PLACEHOLDERS
+                        ),
+                    };
+                    if !literal.is_empty() {
+                        segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                    }
+                    segments.push(Segment::Field(field));
+                }
+                '}' => {
+                    bail!("unmatched '}}' in output template (write '}}}}' for a literal brace)")
+                }
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+        if segments.is_empty() {
+            bail!("output template is empty");
+        }
+        Ok(Self {
+            source: s.to_string(),
+            segments,
+        })
+    }
+}
+
+impl fmt::Display for OutputTemplate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use time::macros::datetime;
+
+    use super::{OutputTemplate, TemplateInput};
+
+    fn input(name: &str) -> TemplateInput<'_> {
+        TemplateInput {
+            name,
+            sender: Some("ab12cd34ef".to_string()),
+            size: 2048,
+            now: datetime!(2026-03-31 19:30:05 UTC),
+        }
+    }
+
+    #[test]
+    fn placeholders_expand() {
+        let template: OutputTemplate = "{date}_{time}_{sender}_{name}".parse().unwrap();
+        assert_eq!(
+            template.expand(&input("notes.txt")),
+            "2026-03-31_193005_ab12cd34ef_notes.txt"
+        );
+        let template: OutputTemplate = "{stem} ({size}).{ext}".parse().unwrap();
+        assert_eq!(template.expand(&input("a.tar.gz")), "a.tar (2048).gz");
+    }
+
+    #[test]
+    fn doubled_braces_are_literal() {
+        let template: OutputTemplate = "{{x}}-{name}".parse().unwrap();
+        assert_eq!(template.expand(&input("f")), "{x}-f");
+        let unknown = TemplateInput {
+            sender: None,
+            ..input("f")
+        };
+        let template: OutputTemplate = "{sender}".parse().unwrap();
+        assert_eq!(template.expand(&unknown), "unknown");
+    }
+
+    #[test]
+    fn invalid_templates_are_rejected() {
+        assert!("{nmae}".parse::<OutputTemplate>().is_err());
+        assert!("{name".parse::<OutputTemplate>().is_err());
+        assert!("name}".parse::<OutputTemplate>().is_err());
+        assert!("".parse::<OutputTemplate>().is_err());
+    }
+}
@@ -0,0 +1,39 @@
+use std::path::PathBuf;
+
+/// The `p2p-share` directory inside the platform config directory, where
+/// the hash cache and the CLI config file live. `None` when the
+/// environment does not say where that is.
+pub fn app_config_dir() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("p2p-share"))
+}
+
+/// Expand a leading `~` in a user-supplied directory to the home directory.
+/// Anything else, including `~user`, is returned as is.
+pub fn expand_home(dir: &str) -> PathBuf {
+    let rest = match dir.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with(['/', '\\']) => rest,
+        _ => return PathBuf::from(dir),
+    };
+    match std::env::var_os("HOME").or_else(|| std::env::var_os("USERPROFILE")) {
+        Some(home) => PathBuf::from(home).join(rest.trim_start_matches(['/', '\\'])),
+        None => PathBuf::from(dir),
+    }
+}
+
+#[cfg(windows)]
+fn config_dir() -> Option<PathBuf> {
+    std::env::var_os("APPDATA").map(PathBuf::from)
+}
+
+#[cfg(target_os = "macos")]
+fn config_dir() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join("Library/Application Support"))
+}
+
+#[cfg(not(any(windows, target_os = "macos")))]
+fn config_dir() -> Option<PathBuf> {
+    std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+}
@@ -1,16 +1,110 @@
-use indicatif::{ProgressBar, ProgressStyle};
-
-/// Create a progress bar configured for file transfer display.
-pub fn transfer_progress_bar(total_bytes: u64) -> ProgressBar {
-    let pb = ProgressBar::new(total_bytes);
-    pb.set_style(
-        ProgressStyle::default_bar()
-            .template(
-                "{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] \
-                 {bytes}/{total_bytes} ({bytes_per_sec}, ETA {eta})",
-            )
-            .expect("invalid progress bar template")
-            .progress_chars("=>-"),
-    );
+use std::time::{Duration, Instant};
+
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+
+/// Create a progress bar configured for file transfer display. Without
+/// `total_bytes`, as when following a growing file, it is a spinner with
+/// the byte count and rate instead. With an `interval`, the bar redraws at
+/// most that often.
+pub fn transfer_progress_bar(total_bytes: Option<u64>, interval: Option<Duration>) -> ProgressBar {
+    let pb = match total_bytes {
+        Some(total_bytes) => {
+            let pb = ProgressBar::new(total_bytes);
+            pb.set_style(
+                ProgressStyle::default_bar()
+                    .template(
+                        "{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] \
+                         {bytes}/{total_bytes} ({bytes_per_sec}, ETA {eta})",
+                    )
+                    .expect("invalid progress bar template")
+                    .progress_chars("=>-"),
+            );
+            pb
+        }
+        None => {
+            let pb = ProgressBar::new_spinner();
+            pb.set_style(
+                ProgressStyle::default_spinner()
+                    .template("{spinner:.green} [{elapsed_precise}] {bytes} ({bytes_per_sec})")
+                    .expect("invalid progress spinner template"),
+            );
+            pb
+        }
+    };
+    if let Some(interval) = interval {
+        let hz = (1.0 / interval.as_secs_f64()).clamp(1.0, 255.0) as u8;
+        pb.set_draw_target(ProgressDrawTarget::stderr_with_hz(hz));
+    }
     pb
 }
+
+/// Coalesces per-chunk progress into updates at most `interval` apart. The
+/// first update and the one that completes a sized transfer always pass;
+/// without an interval every update does. A streaming transfer has no
+/// known end, so [`ProgressThrottle::skipped`] tells whether its last
+/// count still has to be reported.
+pub(crate) struct ProgressThrottle {
+    interval: Option<Duration>,
+    last: Option<Instant>,
+    reported: u64,
+}
+
+impl ProgressThrottle {
+    pub fn new(interval: Option<Duration>) -> Self {
+        Self {
+            interval,
+            last: None,
+            reported: 0,
+        }
+    }
+
+    /// Whether progress at `done` of `total` bytes should be reported now.
+    pub fn ready(&mut self, done: u64, total: u64) -> bool {
+        let Some(interval) = self.interval else {
+            return true;
+        };
+        let now = Instant::now();
+        let due = match self.last {
+            None => true,
+            Some(last) => now.duration_since(last) >= interval,
+        };
+        if due || (total > 0 && done >= total) {
+            self.last = Some(now);
+            self.reported = done;
+            return true;
+        }
+        false
+    }
+
+    /// Whether `done` was held back by the last call to
+    /// [`ProgressThrottle::ready`].
+    pub fn skipped(&self, done: u64) -> bool {
+        self.interval.is_some() && self.reported != done
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::ProgressThrottle;
+
+    #[test]
+    fn throttle_passes_the_first_and_final_update() {
+        let mut throttle = ProgressThrottle::new(Some(Duration::from_secs(60)));
+        assert!(throttle.ready(1, 10));
+        assert!(!throttle.ready(5, 10));
+        assert!(throttle.ready(10, 10));
+
+        let mut streaming = ProgressThrottle::new(Some(Duration::from_millis(20)));
+        assert!(streaming.ready(1, 0));
+        assert!(!streaming.ready(2, 0));
+        assert!(streaming.skipped(2));
+        std::thread::sleep(Duration::from_millis(25));
+        assert!(streaming.ready(3, 0));
+        assert!(!streaming.skipped(3));
+
+        let mut unthrottled = ProgressThrottle::new(None);
+        assert!((0..5).all(|done| unthrottled.ready(done, 10)));
+    }
+}
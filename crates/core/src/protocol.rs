@@ -7,6 +7,118 @@ use crate::events::TransferContentKind;
 /// AEAD tag that Noise appends.
 pub const CHUNK_SIZE: usize = 60 * 1024;
 
+/// Bytes of the little-endian frame index that starts each data frame of a
+/// [`FileHeader::sequenced`] transfer.
+pub const SEQUENCE_LEN: usize = 8;
+
+/// Header ack from a receiver that already holds a file with the same
+/// content (`receive --dedup`). `DONE` follows straight away and no data is
+/// sent. Older senders show it as the reason the transfer was rejected.
+pub const ALREADY_HAVE_ACK: &str = "already have this file";
+
+/// Starts the line a sender sends in place of the [`FileHeader`] when the
+/// file it prepared can no longer be opened, followed by the reason. The
+/// receiver fails at once instead of waiting for a header; older receivers
+/// reject it as an invalid header.
+pub const ABORT_PREFIX: &str = "ABORT ";
+
+/// Starts the request a `send --sync` sender makes before its
+/// [`FileHeader`], followed by the JSON list of its archive's top-level
+/// names. Only sent to receivers announcing [`crate::crypto::FEATURE_SYNC`],
+/// which answer with a [`SyncReply`].
+pub const SYNC_PREFIX: &str = "SYNC ";
+
+/// Largest preview a receiver may ask for with [`HeaderAck::Preview`].
+pub const MAX_PREVIEW_LEN: u64 = 1024 * 1024;
+
+/// The receiver's one-line answer to a [`SYNC_PREFIX`] request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyncReply {
+    /// `MANIFEST <frames>`: that many frames of [`crate::manifest::Manifest`]
+    /// JSON follow.
+    Manifest(u64),
+    /// `NOSYNC`: the receiver is not in sync mode, so the sender sends
+    /// every file.
+    Declined,
+}
+
+impl SyncReply {
+    pub fn to_wire(&self) -> Vec<u8> {
+        match self {
+            Self::Manifest(frames) => format!("MANIFEST {}\n", frames).into_bytes(),
+            Self::Declined => b"NOSYNC\n".to_vec(),
+        }
+    }
+
+    pub fn parse(line: &str) -> anyhow::Result<Self> {
+        let line = line.trim();
+        if line == "NOSYNC" {
+            return Ok(Self::Declined);
+        }
+        line.strip_prefix("MANIFEST ")
+            .and_then(|frames| frames.parse().ok())
+            .map(Self::Manifest)
+            .ok_or_else(|| anyhow::anyhow!("unexpected answer to sync request: {}", line))
+    }
+}
+
+/// The receiver's one-line answer to a [`FileHeader`]. Both sides go
+/// through this type so they agree on the control messages.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HeaderAck {
+    /// `OK`: send the data from the start.
+    Accept,
+    /// `RESUME <offset>`: the receiver kept the first `offset` bytes of an
+    /// interrupted transfer of the same content, so only the rest is sent.
+    /// Only offered to senders announcing [`crate::crypto::FEATURE_RESUME`].
+    Resume(u64),
+    /// [`ALREADY_HAVE_ACK`]: nothing is sent.
+    AlreadyHave,
+    /// `PREVIEW <len>`: send the first `len` bytes (at most
+    /// [`MAX_PREVIEW_LEN`] and the file size) in data frames, then wait for
+    /// a second answer deciding on the whole file. Only sent to senders
+    /// announcing [`crate::crypto::FEATURE_PREVIEW`].
+    Preview(u64),
+    /// Anything else is the reason the transfer was refused.
+    Reject(String),
+}
+
+impl HeaderAck {
+    pub fn to_wire(&self) -> Vec<u8> {
+        let line = match self {
+            Self::Accept => "OK".to_string(),
+            Self::Resume(offset) => format!("RESUME {}", offset),
+            Self::AlreadyHave => ALREADY_HAVE_ACK.to_string(),
+            Self::Preview(len) => format!("PREVIEW {}", len),
+            Self::Reject(reason) => reason.clone(),
+        };
+        format!("{}\n", line).into_bytes()
+    }
+
+    pub fn parse(line: &str) -> Self {
+        let line = line.trim();
+        if line == "OK" {
+            return Self::Accept;
+        }
+        if line == ALREADY_HAVE_ACK {
+            return Self::AlreadyHave;
+        }
+        if let Some(offset) = line
+            .strip_prefix("RESUME ")
+            .and_then(|offset| offset.parse().ok())
+        {
+            return Self::Resume(offset);
+        }
+        if let Some(len) = line
+            .strip_prefix("PREVIEW ")
+            .and_then(|len| len.parse().ok())
+        {
+            return Self::Preview(len);
+        }
+        Self::Reject(line.to_string())
+    }
+}
+
 /// Header sent by the sender before the file data.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct FileHeader {
@@ -25,6 +137,45 @@ pub struct FileHeader {
     /// Display name for extracted bundle contents.
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub logical_name: Option<String>,
+    /// Base64 of the original file name bytes, set only when the name is not
+    /// valid UTF-8 and `name` is therefore a lossy rendering.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name_raw: Option<String>,
+    /// The file is still growing: `size` and `blake3` are unknown (zero and
+    /// empty), data continues until an empty frame, and a [`StreamTrailer`]
+    /// follows with the real values.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub streaming: bool,
+    /// Every data frame starts with its index, counting from 0, as
+    /// [`SEQUENCE_LEN`] little-endian bytes, so the receiver notices a
+    /// missing or misordered chunk at once. Only set for receivers that
+    /// announce [`crate::crypto::FEATURE_SEQUENCE`].
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub sequenced: bool,
+    /// Bytes covered by each hash in the segment hash frames that follow
+    /// the receiver's ack (see [`crate::segments`]), so the receiver stops
+    /// at the first segment that does not match. Only set for receivers
+    /// that announce [`crate::crypto::FEATURE_VERIFY_CHUNKS`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub segment_size: Option<u64>,
+    /// The bundle leaves out the files the receiver listed in its sync
+    /// manifest, so it is extracted into the receiver's output directory,
+    /// replacing older copies, instead of a new folder. Only set after the
+    /// receiver answered a [`SYNC_PREFIX`] request with a manifest.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub sync: bool,
+    /// Every data frame carries a flag byte, after any sequence number,
+    /// saying whether the rest is zstd-compressed (see
+    /// [`crate::compression`]); the sender compressed at this level. Only
+    /// set for receivers that announce [`crate::crypto::FEATURE_COMPRESSION`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compression_level: Option<i32>,
+    /// The bundle is a tar archive compressed as one zstd stream (see
+    /// [`crate::bundle::compress_bundle`]); `size` and `blake3` cover the
+    /// compressed data. Only set for receivers that announce
+    /// [`crate::crypto::FEATURE_ZSTD_ARCHIVE`].
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub zstd_archive: bool,
 }
 
 /// Single-line JSON terminated by `\n`, so the receiver can read it with
@@ -36,8 +187,60 @@ impl FileHeader {
         Ok(buf)
     }
 
+    /// Tolerates a leading UTF-8 byte order mark and a CRLF line ending,
+    /// which some senders add.
     pub fn from_wire(line: &str) -> anyhow::Result<Self> {
-        Ok(serde_json::from_str(line.trim())?)
+        Ok(serde_json::from_str(wire_line(line))?)
+    }
+
+    /// Decoded `name_raw` bytes, if present and well-formed.
+    pub fn raw_name_bytes(&self) -> Option<Vec<u8>> {
+        let raw = self.name_raw.as_deref()?;
+        data_encoding::BASE64.decode(raw.as_bytes()).ok()
+    }
+}
+
+/// Size and hash of a streaming transfer, sent after its end-of-data marker.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StreamTrailer {
+    pub size: u64,
+    /// Hex-encoded blake3 hash of everything sent.
+    pub blake3: String,
+}
+
+impl StreamTrailer {
+    pub fn to_wire(&self) -> anyhow::Result<Vec<u8>> {
+        let mut buf = serde_json::to_vec(self)?;
+        buf.push(b'\n');
+        Ok(buf)
+    }
+
+    pub fn from_wire(line: &str) -> anyhow::Result<Self> {
+        Ok(serde_json::from_str(wire_line(line))?)
+    }
+}
+
+/// A received JSON line without its byte order mark and surrounding
+/// whitespace, including a trailing `\r`.
+fn wire_line(line: &str) -> &str {
+    let line = line.trim();
+    line.strip_prefix('\u{feff}').unwrap_or(line).trim()
+}
+
+/// Encode a file name for [`FileHeader::name_raw`]. Returns `None` for valid
+/// UTF-8 names and on platforms whose names are always Unicode.
+pub fn encode_raw_name(name: &std::ffi::OsStr) -> Option<String> {
+    if name.to_str().is_some() {
+        return None;
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        Some(data_encoding::BASE64.encode(name.as_bytes()))
+    }
+    #[cfg(not(unix))]
+    {
+        None
     }
 }
 
@@ -61,7 +264,7 @@ pub fn human_bytes(bytes: u64) -> String {
 
 #[cfg(test)]
 mod tests {
-    use super::{human_bytes, FileHeader};
+    use super::{encode_raw_name, human_bytes, FileHeader, HeaderAck, SyncReply};
     use crate::events::TransferContentKind;
 
     #[test]
@@ -73,6 +276,13 @@ mod tests {
             content_kind: Some(TransferContentKind::File),
             item_count: Some(1),
             logical_name: None,
+            name_raw: None,
+            streaming: false,
+            sequenced: false,
+            segment_size: None,
+            sync: false,
+            compression_level: None,
+            zstd_archive: false,
         };
         let wire = header.to_wire().expect("serialize");
         let wire_str = String::from_utf8(wire).expect("utf8");
@@ -82,6 +292,61 @@ mod tests {
         assert_eq!(parsed.blake3, "abc123");
         assert_eq!(parsed.content_kind, Some(TransferContentKind::File));
         assert_eq!(parsed.item_count, Some(1));
+        let wire = String::from_utf8(header.to_wire().unwrap()).unwrap();
+        assert!(!wire.contains("name_raw"));
+        assert!(!wire.contains("streaming"));
+        assert!(!wire.contains("sequenced"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_names_round_trip_through_name_raw() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let name = OsStr::from_bytes(b"caf\xe9.txt");
+        let header = FileHeader {
+            name: name.to_string_lossy().to_string(),
+            size: 1,
+            blake3: "abc123".to_string(),
+            content_kind: None,
+            item_count: None,
+            logical_name: None,
+            name_raw: encode_raw_name(name),
+            streaming: false,
+            sequenced: false,
+            segment_size: None,
+            sync: false,
+            compression_level: None,
+            zstd_archive: false,
+        };
+        assert_eq!(
+            header.raw_name_bytes().as_deref(),
+            Some(&b"caf\xe9.txt"[..])
+        );
+        assert_eq!(encode_raw_name(OsStr::new("plain.txt")), None);
+    }
+
+    #[test]
+    fn headers_with_a_bom_or_crlf_still_parse() {
+        let json = r#"{"name":"demo.txt","size":42,"blake3":"abc123"}"#;
+        let prefixes: [&[u8]; 4] = [b"", b"\xef\xbb\xbf", b" ", b"\xef\xbb\xbf\t"];
+        let suffixes: [&[u8]; 5] = [b"", b"\n", b"\r\n", b"\r", b" \r\n\n"];
+        for prefix in prefixes {
+            for suffix in suffixes {
+                let bytes = [prefix, json.as_bytes(), suffix].concat();
+                // As the receiver decodes it off the wire.
+                let line = String::from_utf8(bytes).unwrap();
+                let header =
+                    FileHeader::from_wire(&line).unwrap_or_else(|err| panic!("{line:?}: {err}"));
+                assert_eq!(header.name, "demo.txt");
+                assert_eq!(header.size, 42);
+            }
+        }
+
+        let truncated = &json[..json.len() - 1];
+        assert!(FileHeader::from_wire(&format!("\u{feff}{truncated}\r\n")).is_err());
+        assert!(FileHeader::from_wire(&format!("\u{feff}\u{feff}{json}")).is_err());
     }
 
     #[test]
@@ -90,4 +355,32 @@ mod tests {
         assert_eq!(human_bytes(2048), "2.00 KiB");
         assert_eq!(human_bytes(5 * 1024 * 1024), "5.00 MiB");
     }
+
+    #[test]
+    fn header_acks_round_trip() {
+        for ack in [
+            HeaderAck::Accept,
+            HeaderAck::Resume(61440),
+            HeaderAck::AlreadyHave,
+            HeaderAck::Preview(4096),
+            HeaderAck::Reject("too large".to_string()),
+        ] {
+            let wire = String::from_utf8(ack.to_wire()).unwrap();
+            assert!(wire.ends_with('\n'));
+            assert_eq!(HeaderAck::parse(&wire), ack);
+        }
+        assert_eq!(
+            HeaderAck::parse("RESUME soon"),
+            HeaderAck::Reject("RESUME soon".to_string())
+        );
+    }
+
+    #[test]
+    fn sync_replies_round_trip() {
+        for reply in [SyncReply::Manifest(3), SyncReply::Declined] {
+            let wire = String::from_utf8(reply.to_wire()).unwrap();
+            assert_eq!(SyncReply::parse(&wire).unwrap(), reply);
+        }
+        assert!(SyncReply::parse("OK").is_err());
+    }
 }
@@ -0,0 +1,196 @@
+use iroh::NodeAddr;
+use qr2term::matrix::Matrix;
+use qr2term::render::{QrLight, Renderer};
+use qrcode::{EcLevel, QrCode};
+
+use crate::ticket;
+
+/// Quiet zone around terminal QR codes, matching `qr2term`.
+const QUIET_ZONE_WIDTH: usize = 2;
+
+/// A QR code ready to print, and the text it encodes.
+#[derive(Debug, Clone)]
+pub(crate) struct TicketQr {
+    pub payload: String,
+    pub rendered: String,
+}
+
+/// Characters of ticket text in each QR code of a split ticket: small
+/// enough for a phone camera to read each code at medium error correction
+/// from a terminal.
+const PART_LEN: usize = 600;
+
+/// Encode `ticket` as terminal QR codes, degrading when it does not fit in
+/// one: first to low error correction, then, with `multi`, to one QR code
+/// per part from [`ticket::split`], then to `fallback` (a shorter ticket,
+/// such as a relay-only one). Each degradation is explained to `report`.
+/// Returns no QR codes when nothing fits.
+pub(crate) fn ticket_qr(
+    ticket: &str,
+    fallback: Option<&str>,
+    multi: bool,
+    mut report: impl FnMut(String),
+) -> Vec<TicketQr> {
+    let err = match render(ticket, EcLevel::M) {
+        Ok(rendered) => return vec![qr(ticket, rendered)],
+        Err(err) => err,
+    };
+    if let Ok(rendered) = render(ticket, EcLevel::L) {
+        report("QR code uses low error correction; hold the camera steady.".to_string());
+        return vec![qr(ticket, rendered)];
+    }
+    if multi {
+        let parts: Option<Vec<TicketQr>> = ticket::split(ticket, PART_LEN)
+            .into_iter()
+            .map(|part| {
+                render(&part, EcLevel::M)
+                    .ok()
+                    .map(|rendered| qr(&part, rendered))
+            })
+            .collect();
+        if let Some(parts) = parts {
+            report(format!(
+                "Ticket split across {} QR codes; scan them all, in any order.",
+                parts.len()
+            ));
+            return parts;
+        }
+    }
+    if let Some(fallback) = fallback {
+        let rendered = render(fallback, EcLevel::M).or_else(|_| render(fallback, EcLevel::L));
+        if let Ok(rendered) = rendered {
+            report(format!(
+                "Warning: ticket too long for a QR code ({}); the QR code holds a relay-only ticket.",
+                err
+            ));
+            return vec![qr(fallback, rendered)];
+        }
+    }
+    report(format!(
+        "Warning: ticket too long for a QR code ({}); use the printed ticket instead.",
+        err
+    ));
+    Vec::new()
+}
+
+/// Encode a handshake verification code as a terminal QR code. Codes are
+/// clamped to [`MAX_CODE_LENGTH`](crate::code::MAX_CODE_LENGTH) parts and
+/// always fit; `None` only if encoding fails anyway.
+pub(crate) fn code_qr(code: &str) -> Option<TicketQr> {
+    render(code, EcLevel::M)
+        .ok()
+        .map(|rendered| qr(code, rendered))
+}
+
+/// The relay-only form of the ticket for `addr`, as a QR fallback. `None`
+/// when dropping the direct addresses would not make it any shorter.
+pub(crate) fn relay_only_ticket(addr: &NodeAddr, uri: bool) -> Option<String> {
+    if addr.relay_url.is_none() || addr.direct_addresses.is_empty() {
+        return None;
+    }
+    let relay_only = NodeAddr::from_parts(addr.node_id, addr.relay_url.clone(), Vec::new());
+    let ticket_str = ticket::serialize(&relay_only).ok()?;
+    Some(if uri {
+        ticket::to_uri(&ticket_str)
+    } else {
+        ticket_str
+    })
+}
+
+/// Print QR codes to stderr, indented for readability. Several codes,
+/// the parts of a split ticket, are numbered.
+pub(crate) fn print(qrs: &[TicketQr]) {
+    for (i, qr) in qrs.iter().enumerate() {
+        if qrs.len() > 1 {
+            if i > 0 {
+                eprintln!();
+            }
+            eprintln!("    QR code {}/{}:", i + 1, qrs.len());
+        }
+        for line in qr.rendered.lines() {
+            eprintln!("    {}", line);
+        }
+    }
+}
+
+fn qr(payload: &str, rendered: String) -> TicketQr {
+    TicketQr {
+        payload: payload.to_string(),
+        rendered,
+    }
+}
+
+fn render(data: &str, level: EcLevel) -> Result<String, qrcode::types::QrError> {
+    let code = QrCode::with_error_correction_level(data, level)?;
+    let mut matrix = Matrix::new(code.to_colors());
+    matrix.surround(QUIET_ZONE_WIDTH, QrLight);
+    let mut buf = Vec::new();
+    Renderer::default()
+        .render(&matrix, &mut buf)
+        .expect("rendering into a Vec cannot fail");
+    Ok(String::from_utf8_lossy(&buf).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{code_qr, ticket_qr};
+    use crate::code::{CodeFormat, VerificationCode, MAX_CODE_LENGTH};
+    use crate::ticket;
+
+    #[test]
+    fn short_tickets_are_encoded_as_is() {
+        let mut reports = Vec::new();
+        let qrs = ticket_qr("p2psh:short", None, false, |msg| reports.push(msg));
+        assert_eq!(qrs.len(), 1);
+        assert_eq!(qrs[0].payload, "p2psh:short");
+        assert!(!qrs[0].rendered.is_empty());
+        assert!(reports.is_empty());
+    }
+
+    #[test]
+    fn long_tickets_degrade_before_giving_up() {
+        // Fits byte mode at level L (2953 bytes) but not at M (2331).
+        let long = "a".repeat(2500);
+        let mut reports = Vec::new();
+        let qrs = ticket_qr(&long, None, false, |msg| reports.push(msg));
+        assert_eq!(qrs[0].payload, long);
+        assert_eq!(reports.len(), 1);
+
+        let huge = "a".repeat(4000);
+        let mut reports = Vec::new();
+        let qrs = ticket_qr(&huge, Some("p2psh:relay"), false, |msg| reports.push(msg));
+        assert_eq!(qrs[0].payload, "p2psh:relay");
+        assert!(reports[0].contains("relay-only"), "{reports:?}");
+
+        let mut reports = Vec::new();
+        assert!(ticket_qr(&huge, None, false, |msg| reports.push(msg)).is_empty());
+        assert!(reports[0].contains("use the printed ticket"), "{reports:?}");
+    }
+
+    #[test]
+    fn oversized_tickets_split_across_qr_codes() {
+        let huge = format!("p2psh:{}", "a".repeat(4000));
+        let mut reports = Vec::new();
+        let qrs = ticket_qr(&huge, Some("p2psh:relay"), true, |msg| reports.push(msg));
+        assert_eq!(qrs.len(), 7);
+        assert!(reports[0].contains("7 QR codes"), "{reports:?}");
+        let payloads: Vec<&str> = qrs.iter().map(|qr| qr.payload.as_str()).collect();
+        assert_eq!(ticket::join(&payloads).unwrap(), huge);
+    }
+
+    #[test]
+    fn verification_codes_fit_in_a_qr_code() {
+        let code = VerificationCode::new(&[0x5a; 32]);
+        for format in [
+            CodeFormat::default(),
+            CodeFormat::Words {
+                count: MAX_CODE_LENGTH,
+            },
+        ] {
+            let rendered = code.render(format);
+            let qr = code_qr(&rendered).expect("code fits");
+            assert_eq!(qr.payload, rendered);
+            assert!(!qr.rendered.is_empty());
+        }
+    }
+}
@@ -0,0 +1,464 @@
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// Delay before the first retry of a transient disk error; doubles with
+/// each further attempt.
+const DISK_RETRY_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Bytes already on disk in a `.part` file that is being resumed.
+pub(crate) struct Partial {
+    pub len: u64,
+    /// blake3 state after hashing those bytes, to continue the checksum.
+    pub hasher: blake3::Hasher,
+    /// The first [`crate::route::MIME_SNIFF_LEN`] bytes, for MIME detection.
+    pub head: Vec<u8>,
+}
+
+impl Partial {
+    fn new(len: u64, hasher: blake3::Hasher, head: &[u8]) -> Self {
+        let head = head[..head.len().min(crate::route::MIME_SNIFF_LEN)].to_vec();
+        Self { len, hasher, head }
+    }
+}
+
+/// The subset of filesystem calls `receive_file` needs. Bundle extraction
+/// still works on real paths and is only supported with [`RealFs`].
+pub(crate) trait ReceiveFs {
+    type File: AsyncWrite + Unpin + Send;
+    type Reader: AsyncRead + Unpin + Send;
+
+    fn exists(&self, path: &Path) -> bool;
+    async fn create_dir_all(&self, path: &Path) -> io::Result<()>;
+    /// Create a single directory, failing with `AlreadyExists` when
+    /// anything is at `path` already.
+    async fn create_dir(&self, path: &Path) -> io::Result<()>;
+    async fn create(&self, path: &Path) -> io::Result<Self::File>;
+    /// Create a file, failing with `AlreadyExists` instead of truncating
+    /// one that is there already.
+    async fn create_new(&self, path: &Path) -> io::Result<Self::File>;
+    /// Open an existing file for writing at its end.
+    async fn append(&self, path: &Path) -> io::Result<Self::File>;
+    async fn open(&self, path: &Path) -> io::Result<Self::Reader>;
+    /// The whole contents of a small file, such as a sidecar.
+    async fn read_file(&self, path: &Path) -> io::Result<Vec<u8>>;
+    /// Length, hash state and head of an existing `.part` file.
+    async fn partial(&self, path: &Path) -> io::Result<Partial>;
+    async fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+    async fn remove_file(&self, path: &Path) -> io::Result<()>;
+    async fn remove_dir_all(&self, path: &Path) -> io::Result<()>;
+    /// Regular files directly inside `dir` with their sizes; empty when
+    /// `dir` does not exist.
+    async fn files(&self, dir: &Path) -> io::Result<Vec<(PathBuf, u64)>>;
+    /// Hex-encoded blake3 hash of the file at `path`.
+    async fn blake3(&self, path: &Path) -> io::Result<String>;
+}
+
+/// The local filesystem, through `tokio::fs`.
+pub(crate) struct RealFs;
+
+impl ReceiveFs for RealFs {
+    type File = tokio::fs::File;
+    type Reader = tokio::fs::File;
+
+    fn exists(&self, path: &Path) -> bool {
+        path.exists()
+    }
+
+    async fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        tokio::fs::create_dir_all(path).await
+    }
+
+    async fn create_dir(&self, path: &Path) -> io::Result<()> {
+        tokio::fs::create_dir(path).await
+    }
+
+    async fn create(&self, path: &Path) -> io::Result<Self::File> {
+        tokio::fs::File::create(path).await
+    }
+
+    async fn create_new(&self, path: &Path) -> io::Result<Self::File> {
+        tokio::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(path)
+            .await
+    }
+
+    async fn append(&self, path: &Path) -> io::Result<Self::File> {
+        tokio::fs::OpenOptions::new().append(true).open(path).await
+    }
+
+    async fn open(&self, path: &Path) -> io::Result<Self::Reader> {
+        tokio::fs::File::open(path).await
+    }
+
+    async fn read_file(&self, path: &Path) -> io::Result<Vec<u8>> {
+        tokio::fs::read(path).await
+    }
+
+    async fn partial(&self, path: &Path) -> io::Result<Partial> {
+        let path = path.to_path_buf();
+        tokio::task::spawn_blocking(move || {
+            let mut hasher = blake3::Hasher::new();
+            let mut file = std::fs::File::open(path)?;
+            let mut head = Vec::new();
+            (&mut file)
+                .take(crate::route::MIME_SNIFF_LEN as u64)
+                .read_to_end(&mut head)?;
+            hasher.update(&head);
+            let len = head.len() as u64 + io::copy(&mut file, &mut hasher)?;
+            Ok(Partial::new(len, hasher, &head))
+        })
+        .await?
+    }
+
+    async fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        tokio::fs::rename(from, to).await
+    }
+
+    async fn remove_file(&self, path: &Path) -> io::Result<()> {
+        tokio::fs::remove_file(path).await
+    }
+
+    async fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        tokio::fs::remove_dir_all(path).await
+    }
+
+    async fn files(&self, dir: &Path) -> io::Result<Vec<(PathBuf, u64)>> {
+        let mut entries = match tokio::fs::read_dir(dir).await {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err),
+        };
+        let mut files = Vec::new();
+        while let Some(entry) = entries.next_entry().await? {
+            let metadata = entry.metadata().await?;
+            if metadata.is_file() {
+                files.push((entry.path(), metadata.len()));
+            }
+        }
+        Ok(files)
+    }
+
+    async fn blake3(&self, path: &Path) -> io::Result<String> {
+        let path = path.to_path_buf();
+        tokio::task::spawn_blocking(move || {
+            let mut hasher = blake3::Hasher::new();
+            let mut file = std::fs::File::open(path)?;
+            io::copy(&mut file, &mut hasher)?;
+            Ok(hasher.finalize().to_hex().to_string())
+        })
+        .await?
+    }
+}
+
+/// Bounded exponential backoff for transient errors opening the output
+/// file, such as NFS/SMB blips. Anything else, like `NotFound` or a
+/// permission error, is fatal.
+pub(crate) struct DiskRetry {
+    retries: u32,
+    attempt: u32,
+}
+
+impl DiskRetry {
+    pub fn new(retries: u32) -> Self {
+        Self {
+            retries,
+            attempt: 0,
+        }
+    }
+
+    /// Whether the operation that failed with `err` should run again. Waits
+    /// out the backoff before returning `true`.
+    pub async fn retry(&mut self, err: &io::Error) -> bool {
+        if !is_transient(err) || self.attempt >= self.retries {
+            return false;
+        }
+        tokio::time::sleep(DISK_RETRY_BACKOFF * 2u32.pow(self.attempt)).await;
+        self.attempt += 1;
+        true
+    }
+}
+
+/// Whether `err` is a transient disk error, such as a network filesystem
+/// that briefly stopped answering.
+pub(crate) fn is_transient(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+/// A write to the output file that failed, telling disk errors apart from
+/// network ones of the same kind.
+#[derive(Debug)]
+pub(crate) struct WriteFailed(pub io::Error);
+
+impl std::fmt::Display for WriteFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "cannot write the output file: {}", self.0)
+    }
+}
+
+impl std::error::Error for WriteFailed {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.0)
+    }
+}
+
+/// Whether `err` means the disk (or quota) is full, so the write can only
+/// succeed after space is freed.
+pub(crate) fn is_disk_full(err: &io::Error) -> bool {
+    if err.kind() == io::ErrorKind::StorageFull || err.kind() == io::ErrorKind::QuotaExceeded {
+        return true;
+    }
+    #[cfg(unix)]
+    {
+        err.raw_os_error() == Some(libc::ENOSPC)
+    }
+    #[cfg(not(unix))]
+    {
+        false
+    }
+}
+
+#[cfg(test)]
+pub(crate) use memory::MemoryFs;
+
+#[cfg(test)]
+mod memory {
+    use std::collections::{BTreeMap, BTreeSet};
+    use std::io;
+    use std::path::{Path, PathBuf};
+    use std::pin::Pin;
+    use std::sync::{Arc, Mutex};
+    use std::task::{Context, Poll};
+    use std::time::Duration;
+
+    use tokio::io::AsyncWrite;
+
+    use super::{Partial, ReceiveFs};
+
+    #[derive(Default)]
+    struct State {
+        files: BTreeMap<PathBuf, Vec<u8>>,
+        dirs: BTreeSet<PathBuf>,
+    }
+
+    /// In-memory [`ReceiveFs`] for tests. Renames can be made to fail to
+    /// exercise the cleanup path, files capped to simulate a full disk and
+    /// directory creation slowed down like a stalled network mount.
+    #[derive(Clone, Default)]
+    pub(crate) struct MemoryFs {
+        state: Arc<Mutex<State>>,
+        pub fail_rename: bool,
+        /// Writes that would grow a file past this many bytes fail with
+        /// `StorageFull`, after storing what still fits.
+        pub max_file_len: Option<usize>,
+        /// Fail those writes with this error instead of `StorageFull`.
+        pub write_error: Option<io::ErrorKind>,
+        /// Creating a directory takes this long.
+        pub create_dir_delay: Option<Duration>,
+    }
+
+    impl MemoryFs {
+        pub fn insert(&self, path: impl Into<PathBuf>, data: &[u8]) {
+            let mut state = self.state.lock().unwrap();
+            state.files.insert(path.into(), data.to_vec());
+        }
+
+        pub fn read(&self, path: &Path) -> Option<Vec<u8>> {
+            self.state.lock().unwrap().files.get(path).cloned()
+        }
+
+        pub fn paths(&self) -> Vec<PathBuf> {
+            self.state.lock().unwrap().files.keys().cloned().collect()
+        }
+    }
+
+    /// A file created on a [`MemoryFs`]; writes land in the shared map.
+    pub(crate) struct MemoryFile {
+        state: Arc<Mutex<State>>,
+        path: PathBuf,
+        max_len: Option<usize>,
+        error: io::ErrorKind,
+    }
+
+    impl AsyncWrite for MemoryFile {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            let max_len = self.max_len.unwrap_or(usize::MAX);
+            let mut state = self.state.lock().unwrap();
+            match state.files.get_mut(&self.path) {
+                Some(data) if data.len() >= max_len => Poll::Ready(Err(self.error.into())),
+                Some(data) => {
+                    let n = buf.len().min(max_len - data.len());
+                    data.extend_from_slice(&buf[..n]);
+                    Poll::Ready(Ok(n))
+                }
+                None => Poll::Ready(Err(io::ErrorKind::NotFound.into())),
+            }
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    impl ReceiveFs for MemoryFs {
+        type File = MemoryFile;
+        type Reader = io::Cursor<Vec<u8>>;
+
+        fn exists(&self, path: &Path) -> bool {
+            let state = self.state.lock().unwrap();
+            state.files.contains_key(path) || state.dirs.contains(path)
+        }
+
+        async fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+            if let Some(delay) = self.create_dir_delay {
+                tokio::time::sleep(delay).await;
+            }
+            let mut state = self.state.lock().unwrap();
+            for dir in path.ancestors() {
+                state.dirs.insert(dir.to_path_buf());
+            }
+            Ok(())
+        }
+
+        async fn create_dir(&self, path: &Path) -> io::Result<()> {
+            if self.exists(path) {
+                return Err(io::ErrorKind::AlreadyExists.into());
+            }
+            self.state.lock().unwrap().dirs.insert(path.to_path_buf());
+            Ok(())
+        }
+
+        async fn create(&self, path: &Path) -> io::Result<Self::File> {
+            self.state
+                .lock()
+                .unwrap()
+                .files
+                .insert(path.to_path_buf(), Vec::new());
+            Ok(MemoryFile {
+                state: self.state.clone(),
+                path: path.to_path_buf(),
+                max_len: self.max_file_len,
+                error: self.write_error.unwrap_or(io::ErrorKind::StorageFull),
+            })
+        }
+
+        async fn create_new(&self, path: &Path) -> io::Result<Self::File> {
+            if self.exists(path) {
+                return Err(io::ErrorKind::AlreadyExists.into());
+            }
+            self.create(path).await
+        }
+
+        async fn append(&self, path: &Path) -> io::Result<Self::File> {
+            if !self.state.lock().unwrap().files.contains_key(path) {
+                return Err(io::ErrorKind::NotFound.into());
+            }
+            Ok(MemoryFile {
+                state: self.state.clone(),
+                path: path.to_path_buf(),
+                max_len: self.max_file_len,
+                error: self.write_error.unwrap_or(io::ErrorKind::StorageFull),
+            })
+        }
+
+        async fn open(&self, path: &Path) -> io::Result<Self::Reader> {
+            self.read(path)
+                .map(io::Cursor::new)
+                .ok_or_else(|| io::ErrorKind::NotFound.into())
+        }
+
+        async fn read_file(&self, path: &Path) -> io::Result<Vec<u8>> {
+            self.read(path)
+                .ok_or_else(|| io::ErrorKind::NotFound.into())
+        }
+
+        async fn partial(&self, path: &Path) -> io::Result<Partial> {
+            let data = self.read(path).ok_or(io::ErrorKind::NotFound)?;
+            let mut hasher = blake3::Hasher::new();
+            hasher.update(&data);
+            Ok(Partial::new(data.len() as u64, hasher, &data))
+        }
+
+        async fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+            if self.fail_rename {
+                return Err(io::Error::other("rename failed"));
+            }
+            let mut state = self.state.lock().unwrap();
+            let data = state.files.remove(from).ok_or(io::ErrorKind::NotFound)?;
+            state.files.insert(to.to_path_buf(), data);
+            Ok(())
+        }
+
+        async fn remove_file(&self, path: &Path) -> io::Result<()> {
+            let mut state = self.state.lock().unwrap();
+            state
+                .files
+                .remove(path)
+                .map(|_| ())
+                .ok_or_else(|| io::ErrorKind::NotFound.into())
+        }
+
+        async fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+            let mut state = self.state.lock().unwrap();
+            state.files.retain(|file, _| !file.starts_with(path));
+            state.dirs.retain(|dir| !dir.starts_with(path));
+            Ok(())
+        }
+
+        async fn files(&self, dir: &Path) -> io::Result<Vec<(PathBuf, u64)>> {
+            let state = self.state.lock().unwrap();
+            Ok(state
+                .files
+                .iter()
+                .filter(|(path, _)| path.parent() == Some(dir))
+                .map(|(path, data)| (path.clone(), data.len() as u64))
+                .collect())
+        }
+
+        async fn blake3(&self, path: &Path) -> io::Result<String> {
+            let state = self.state.lock().unwrap();
+            let data = state.files.get(path).ok_or(io::ErrorKind::NotFound)?;
+            Ok(blake3::hash(data).to_hex().to_string())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::{is_disk_full, DiskRetry};
+
+    #[test]
+    fn a_full_disk_is_recognized() {
+        assert!(is_disk_full(&io::ErrorKind::StorageFull.into()));
+        #[cfg(unix)]
+        assert!(is_disk_full(&io::Error::from_raw_os_error(libc::ENOSPC)));
+        assert!(!is_disk_full(&io::ErrorKind::PermissionDenied.into()));
+    }
+
+    #[tokio::test]
+    async fn only_transient_errors_are_retried_and_only_so_often() {
+        let mut retry = DiskRetry::new(2);
+        let interrupted = io::Error::from(io::ErrorKind::Interrupted);
+        assert!(!retry.retry(&io::ErrorKind::PermissionDenied.into()).await);
+        assert!(retry.retry(&interrupted).await);
+        assert!(retry.retry(&io::ErrorKind::TimedOut.into()).await);
+        assert!(!retry.retry(&interrupted).await);
+    }
+}
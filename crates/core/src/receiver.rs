@@ -1,30 +1,470 @@
-use std::path::{Path, PathBuf};
+use std::ffi::{OsStr, OsString};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 
 use anyhow::{bail, Context, Result};
-use iroh::endpoint::ConnectionType;
-use iroh::{Endpoint, NodeId, Watcher as _};
-use n0_future::StreamExt;
-use tokio::fs::File;
-use tokio::io::AsyncWriteExt;
-
-use crate::bundle;
-use crate::crypto;
+use bytes::Bytes;
+use iroh::endpoint::{Connecting, Incoming, RecvStream, SendStream};
+use iroh::{Endpoint, NodeId};
+use time::OffsetDateTime;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::sync::watch;
+
+use crate::alpn::{self, AlpnRole, NegotiatedAlpn, RoleMismatch};
+use crate::at_rest::{self, AtRestKey, AtRestWriter};
+use crate::bundle::{self, BundleTracker};
+use crate::compression;
+use crate::crypto::{self, HandshakePayload, FEATURE_DEDUP, FEATURE_PREVIEW, FEATURE_RESUME};
+use crate::endpoint::{self, AbortOnDrop, HomeRelay, ReconnectWindow};
 use crate::events::{
-    ConnectionPathKind, TransferCompleted, TransferContentKind, TransferEvent, TransferEventSink,
+    self, TransferCompleted, TransferContentKind, TransferEvent, TransferEventSink,
+};
+use crate::extract::{self, ArchiveKind};
+use crate::inbox::{InboxAction, InboxPolicy};
+use crate::manifest::Manifest;
+use crate::options::TransferOptions;
+use crate::output_template::{OutputTemplate, TemplateInput};
+use crate::progress::{transfer_progress_bar, ProgressThrottle};
+use crate::protocol::{
+    human_bytes, FileHeader, HeaderAck, StreamTrailer, SyncReply, ABORT_PREFIX, MAX_PREVIEW_LEN,
+    SEQUENCE_LEN, SYNC_PREFIX,
 };
-use crate::progress::transfer_progress_bar;
-use crate::protocol::{human_bytes, FileHeader};
+use crate::qr;
+use crate::receive_fs::{self, DiskRetry, Partial, RealFs, ReceiveFs, WriteFailed};
+use crate::route::{self, Route};
+use crate::sanitize;
+use crate::segments::{self, SegmentMismatch, SegmentVerifier};
+use crate::stats::TransferStats;
+use crate::tcp;
 use crate::ticket;
+use crate::transport::{
+    self, HandshakeRole, IrohTransport, Link, PathSource, StreamNotOpened, TcpTransport, Transport,
+};
+
+type SharedSink = Arc<dyn TransferEventSink>;
 
-/// ALPN protocol identifier — must match the sender.
-const ALPN: &[u8] = b"p2p-share/1";
+/// How long the connection stays open after DONE waiting for the sender to
+/// close it.
+const DONE_LINGER: Duration = Duration::from_secs(3);
 
-/// ALPN for reverse mode — the connector is the file sender.
-const ALPN_REVERSE: &[u8] = b"p2p-share/1-reverse";
+/// How long a connected sender has to open its stream, finish the
+/// handshake and send the file header before it counts as gone.
+const PEER_START_TIMEOUT: Duration = Duration::from_secs(30);
 
-type SharedSink = Arc<dyn TransferEventSink>;
+/// Default cap on renamed candidates tried for one incoming file name.
+pub const DEFAULT_MAX_NAME_COLLISIONS: u32 = 10_000;
+
+/// Default number of retries for a transient disk error, kept small so a
+/// dead disk still fails within a second or two.
+pub const DEFAULT_DISK_WRITE_RETRIES: u32 = 3;
+
+/// Optional receive-side behavior shared by the receive entry points.
+#[derive(Debug, Clone, Default)]
+pub struct ReceiveOptions {
+    /// Settings shared with the other direction.
+    pub transfer: TransferOptions,
+    /// Accept/reject rules evaluated against each incoming header.
+    pub inbox: Option<InboxPolicy>,
+    /// Asked before accepting each transfer that the inbox policy (if any)
+    /// let through. Absent means accept automatically.
+    pub confirm: Option<AcceptHandler>,
+    /// Publish the listen-mode ticket as a `p2pshare://` deep link, in both
+    /// the printed text and the QR code.
+    pub ticket_uri: bool,
+    /// Split a listen-mode ticket too long for one QR code across several,
+    /// instead of falling back to its relay-only form.
+    pub multi_qr: bool,
+    /// How many ` (n)` suffixes to try when the file name is taken before
+    /// giving up. Defaults to [`DEFAULT_MAX_NAME_COLLISIONS`].
+    pub max_name_collisions: Option<u32>,
+    /// How many times a transient error creating or opening the output
+    /// file is retried. Defaults to [`DEFAULT_DISK_WRITE_RETRIES`]. A write
+    /// that fails partway is not retried; like a full disk, it keeps the
+    /// `.part` file for resume.
+    pub disk_write_retries: Option<u32>,
+    /// Name the saved file (or bundle folder) from a pattern instead of the
+    /// sender's name. The result is sanitized and deduplicated as usual.
+    pub output_template: Option<OutputTemplate>,
+    /// Skip bundle files that cannot be written, reporting each as
+    /// [`TransferEvent::FileError`], instead of failing the whole batch.
+    pub keep_going: bool,
+    /// Write the verified hash to a `<name>.blake3` sidecar in `b3sum`
+    /// format next to each received single file.
+    pub write_checksum: bool,
+    /// Directories for single files by extension or MIME type, checked in
+    /// order before the output directory (and before any inbox subfolder,
+    /// which applies inside the routed directory).
+    pub routes: Vec<Route>,
+    /// Cancel the transfer in progress once this turns true. The receive
+    /// fails with [`Canceled`] and reports an `error` event with code
+    /// `canceled`.
+    pub cancel: Option<watch::Receiver<bool>>,
+    /// Keep the `.part` file of a canceled transfer, or of a single file
+    /// that filled the disk or hit a transient write error, so it can be
+    /// picked up again instead of removing it. Other failures always remove
+    /// it (disk errors and a lost connection also keep it with a reconnect
+    /// window). A
+    /// kept single file gets a `.part.resume` sidecar, and a later transfer
+    /// of the same content from a sender that supports resuming continues
+    /// from where it stopped.
+    pub keep_partial: bool,
+    /// Skip a single file when the destination directory already holds a
+    /// file with the same size and blake3 hash, keeping the existing copy
+    /// instead of saving `name (1).ext`. Checked only once the inbox policy
+    /// and [`confirm`](Self::confirm) have accepted the file.
+    pub dedup: bool,
+    /// Stop listen mode when no sender connects for this long. With a
+    /// listen queue the timer restarts after every transfer and never runs
+    /// out while one is in progress.
+    pub idle_exit: Option<Duration>,
+    /// Keep the `.part` file encrypted with this key while the transfer is
+    /// in progress, and decrypt it into place once the checksum matched.
+    /// Decrypting briefly needs room for a second copy. Encrypted partial
+    /// downloads are never resumed.
+    pub encrypt_at_rest: Option<AtRestKey>,
+    /// Answer a `send --sync` sender with the manifest of the files under
+    /// the output directory it would replace, and extract its archive of
+    /// new and changed files into the output directory itself, overwriting
+    /// older copies, instead of a new folder. Files the sender no longer
+    /// has are kept.
+    pub sync: bool,
+    /// Ask the sender of a single file for up to this many of its first
+    /// bytes (capped at [`MAX_PREVIEW_LEN`]) before accepting it. They are
+    /// reported as [`TransferEvent::Preview`] and passed to the
+    /// [`confirm`](Self::confirm) handler. Senders without preview support
+    /// are asked as usual.
+    pub preview: Option<u64>,
+    /// Choose where each transfer is saved instead of the sender's name
+    /// deduplicated with ` (n)` suffixes, for example to file it into a
+    /// content-addressed store. Not used in sync mode.
+    pub rename: Option<RenameHandler>,
+    /// Unpack a received `.tar`, `.tar.zst` or `.zip` file (see
+    /// [`extract::FORMATS`]) into the folder it was saved to once its
+    /// checksum is verified, then remove it. An archive that cannot be
+    /// extracted is kept as received. Single files only; folders arrive
+    /// extracted already.
+    pub extract: bool,
+    /// Accept live files from `send --follow`, which announce no size or
+    /// hash up front and run until the sender stops. They are declined
+    /// otherwise. An inbox rule's `max_size` is enforced on the bytes
+    /// received so far.
+    pub accept_streaming: bool,
+}
+
+impl From<TransferOptions> for ReceiveOptions {
+    fn from(transfer: TransferOptions) -> Self {
+        Self {
+            transfer,
+            ..Self::default()
+        }
+    }
+}
+
+/// Error listen mode ends with when [`ReceiveOptions::idle_exit`] passed
+/// without a sender. Not a failure: callers usually exit quietly.
+#[derive(Debug)]
+pub struct IdleExit;
+
+impl std::fmt::Display for IdleExit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("no sender connected before the idle timeout")
+    }
+}
+
+impl std::error::Error for IdleExit {}
+
+const IDLE_EXIT_MESSAGE: &str = "No sender connected, exiting";
+
+/// Error a receive fails with when [`ReceiveOptions::cancel`] fired.
+#[derive(Debug)]
+pub struct Canceled;
+
+impl std::fmt::Display for Canceled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("transfer canceled")
+    }
+}
+
+impl std::error::Error for Canceled {}
+
+/// Error a receive fails with when the sender connected but went away, or
+/// went quiet for [`PEER_START_TIMEOUT`], before sending the file header.
+/// Reported as an `Error` event with code `peer_abandoned`.
+#[derive(Debug)]
+pub struct PeerAbandoned;
+
+impl std::fmt::Display for PeerAbandoned {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("the sender disconnected before sending the file header")
+    }
+}
+
+impl std::error::Error for PeerAbandoned {}
+
+/// Error a receive fails with when the sender could no longer read the file
+/// it offered and sent an [`ABORT_PREFIX`] line instead of the header.
+/// Reported as an `Error` event with code `source_unavailable`.
+#[derive(Debug)]
+pub struct SourceUnavailable {
+    pub reason: String,
+}
+
+impl std::fmt::Display for SourceUnavailable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "the sender can no longer read the file it offered: {}",
+            self.reason
+        )
+    }
+}
+
+impl std::error::Error for SourceUnavailable {}
+
+/// Error a receive fails with when this side turned the transfer down: the
+/// inbox policy, the [`ReceiveOptions::confirm`] handler or the limits of
+/// an in-memory receive.
+#[derive(Debug)]
+pub struct Declined {
+    pub reason: String,
+}
+
+impl std::fmt::Display for Declined {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Transfer {}", self.reason)
+    }
+}
+
+impl std::error::Error for Declined {}
+
+/// Error a receive fails with when the received data does not hash to
+/// what the sender announced.
+#[derive(Debug)]
+pub struct ChecksumMismatch {
+    pub expected: String,
+    pub actual: String,
+}
+
+impl std::fmt::Display for ChecksumMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Checksum mismatch!\n  expected: {}\n  got:      {}",
+            self.expected, self.actual
+        )
+    }
+}
+
+impl std::error::Error for ChecksumMismatch {}
+
+/// Error a receive fails with when a data frame of a
+/// [`FileHeader::sequenced`] transfer carries the wrong index, or none.
+/// Reported as an `Error` event with code `sequence_error`.
+#[derive(Debug)]
+pub struct SequenceError {
+    pub expected: u64,
+    /// The index the frame carried; `None` when it was too short for one.
+    pub got: Option<u64>,
+}
+
+impl std::fmt::Display for SequenceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.got {
+            Some(got) => write!(
+                f,
+                "chunk {} arrived out of order (expected chunk {})",
+                got, self.expected
+            ),
+            None => write!(f, "chunk {} has no sequence number", self.expected),
+        }
+    }
+}
+
+impl std::error::Error for SequenceError {}
+
+/// Strip the index from the front of a sequenced data frame, checking it
+/// is `expected`.
+fn strip_sequence(mut frame: Vec<u8>, expected: u64) -> Result<Vec<u8>, SequenceError> {
+    if frame.len() <= SEQUENCE_LEN {
+        return Err(SequenceError {
+            expected,
+            got: None,
+        });
+    }
+    let got = u64::from_le_bytes(frame[..SEQUENCE_LEN].try_into().expect("sequence length"));
+    if got != expected {
+        return Err(SequenceError {
+            expected,
+            got: Some(got),
+        });
+    }
+    frame.drain(..SEQUENCE_LEN);
+    Ok(frame)
+}
+
+/// Emit the `peer_abandoned` error event and return [`PeerAbandoned`].
+fn peer_abandoned(sink: Option<&SharedSink>) -> anyhow::Error {
+    emit(
+        sink,
+        TransferEvent::Error {
+            code: "peer_abandoned".to_string(),
+            message: PeerAbandoned.to_string(),
+            partial_path: None,
+        },
+    );
+    PeerAbandoned.into()
+}
+
+/// Resolves once `cancel` turns true; never when there is no signal or its
+/// sender is gone.
+async fn canceled(cancel: Option<watch::Receiver<bool>>) {
+    if let Some(mut cancel) = cancel {
+        if cancel.wait_for(|canceled| *canceled).await.is_ok() {
+            return;
+        }
+    }
+    std::future::pending::<()>().await
+}
+
+/// Details of an incoming transfer presented to an [`AcceptHandler`].
+#[derive(Debug, Clone)]
+pub struct IncomingOffer {
+    /// Sanitized display name (bundle name for multi-file transfers).
+    pub file_name: String,
+    pub size_bytes: u64,
+    /// Hex-encoded blake3 hash announced by the sender.
+    pub blake3: String,
+    pub content_kind: TransferContentKind,
+    pub item_count: u64,
+    /// Sender node id, when connected over iroh.
+    pub peer: Option<String>,
+    /// Human-readable connection path at the time of the offer.
+    pub connection_path: Option<String>,
+    /// The first bytes of the file, when [`ReceiveOptions::preview`] asked
+    /// for them.
+    pub preview: Option<Vec<u8>>,
+    /// A live file from `send --follow`: `size_bytes` is 0 and `blake3` is
+    /// empty, since neither is known until the sender stops.
+    pub streaming: bool,
+}
+
+/// Accept/reject callback for incoming transfers. Runs on a blocking thread,
+/// so it may prompt interactively; returning `false` declines the transfer.
+#[derive(Clone)]
+pub struct AcceptHandler(Arc<dyn Fn(&IncomingOffer) -> bool + Send + Sync>);
+
+impl AcceptHandler {
+    pub fn new(handler: impl Fn(&IncomingOffer) -> bool + Send + Sync + 'static) -> Self {
+        Self(Arc::new(handler))
+    }
+
+    async fn decide(&self, offer: IncomingOffer) -> Result<bool> {
+        let handler = self.0.clone();
+        Ok(tokio::task::spawn_blocking(move || handler(&offer)).await?)
+    }
+}
+
+impl std::fmt::Debug for AcceptHandler {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("AcceptHandler")
+    }
+}
+
+/// Callback choosing the final path of an accepted transfer: the file, or
+/// the folder a bundle is extracted into. A relative path is taken inside
+/// the output directory; an absolute one must lie inside it. Missing parent
+/// directories are created and an existing file at the path is replaced.
+/// Returning `None` keeps the default name.
+#[derive(Clone)]
+pub struct RenameHandler(Arc<RenameFn>);
+
+type RenameFn = dyn Fn(&FileHeader) -> Option<PathBuf> + Send + Sync;
+
+impl RenameHandler {
+    pub fn new(handler: impl Fn(&FileHeader) -> Option<PathBuf> + Send + Sync + 'static) -> Self {
+        Self(Arc::new(handler))
+    }
+
+    /// The path the handler chose for `header`, checked to lie inside
+    /// `output_dir`.
+    fn choose(
+        &self,
+        header: &FileHeader,
+        output_dir: &Path,
+    ) -> Result<Option<PathBuf>, OutsideOutputDir> {
+        let Some(chosen) = (self.0)(header) else {
+            return Ok(None);
+        };
+        let inside = if chosen.is_absolute() {
+            chosen.strip_prefix(output_dir).ok()
+        } else {
+            Some(chosen.as_path())
+        };
+        match inside {
+            Some(relative)
+                if relative.components().next().is_some()
+                    && relative
+                        .components()
+                        .all(|part| matches!(part, Component::Normal(_) | Component::CurDir)) =>
+            {
+                Ok(Some(output_dir.join(relative)))
+            }
+            _ => Err(OutsideOutputDir { path: chosen }),
+        }
+    }
+}
+
+impl std::fmt::Debug for RenameHandler {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("RenameHandler")
+    }
+}
+
+/// Error a receive fails with when the [`RenameHandler`] chose a path
+/// outside the output directory. Reported as an `Error` event with code
+/// `outside_output_dir`.
+#[derive(Debug)]
+pub struct OutsideOutputDir {
+    pub path: PathBuf,
+}
+
+impl std::fmt::Display for OutsideOutputDir {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "refusing to save to {}: outside the output directory",
+            self.path.display()
+        )
+    }
+}
+
+impl std::error::Error for OutsideOutputDir {}
+
+/// What the receive loop knows about the connected sender.
+struct PeerInfo {
+    node_id: Option<NodeId>,
+    connection_path: Option<String>,
+    /// What the sender announced in the handshake; `None` for older senders.
+    hello: Option<HandshakePayload>,
+}
+
+impl PeerInfo {
+    fn new(path: &PathSource, hello: Option<HandshakePayload>) -> Self {
+        Self {
+            node_id: path.node_id(),
+            connection_path: path.describe(),
+            hello,
+        }
+    }
+
+    fn supports(&self, feature: u64) -> bool {
+        self.hello
+            .as_ref()
+            .is_some_and(|hello| hello.supports(feature))
+    }
+}
 
 fn emit(sink: Option<&SharedSink>, event: TransferEvent) {
     if let Some(sink) = sink {
@@ -38,129 +478,264 @@ fn status(sink: Option<&SharedSink>, msg: impl Into<String>) {
     emit(sink, TransferEvent::Status(msg));
 }
 
-/// Pick a destination path that doesn't collide with existing files.
-fn unique_path(dir: &Path, name: &str) -> PathBuf {
-    let candidate = dir.join(name);
-    if !candidate.exists() {
-        return candidate;
+/// Emit the `connection_lost` error event if `result` failed because the
+/// connection to the sender dropped.
+fn report_connection_lost<T>(sink: Option<&SharedSink>, result: &Result<T>) {
+    if let Some(event) = result
+        .as_ref()
+        .err()
+        .and_then(endpoint::connection_lost_event)
+    {
+        emit(sink, event);
+    }
+}
+
+/// Unpack a received archive into the folder it was saved to, for
+/// [`ReceiveOptions::extract`], and remove it. Returns the files written,
+/// or `None` when the file is not an archive or could not be extracted, in
+/// which case it is kept as received.
+async fn extract_received(
+    archive: &Path,
+    max_attempts: u32,
+    sink: Option<&SharedSink>,
+) -> Option<Vec<PathBuf>> {
+    let kind = match ArchiveKind::detect(archive) {
+        Ok(kind) => kind?,
+        Err(err) => {
+            status(
+                sink,
+                format!(
+                    "Warning: cannot check {} for extraction: {}",
+                    archive.display(),
+                    err
+                ),
+            );
+            return None;
+        }
+    };
+    let dir = archive.parent()?.to_path_buf();
+    status(sink, format!("Extracting {}...", archive.display()));
+    let archive_path = archive.to_path_buf();
+    let events = sink.cloned();
+    let extracted: Result<Vec<PathBuf>> = match tokio::task::spawn_blocking(move || {
+        extract::extract(
+            &archive_path,
+            kind,
+            &dir,
+            |wanted| {
+                let parent = wanted.parent().context("archive entry has no folder")?;
+                let name = wanted.file_name().context("archive entry has no name")?;
+                unique_path(&RealFs, parent, name, max_attempts)
+            },
+            |event| emit(events.as_ref(), event),
+        )
+    })
+    .await
+    {
+        Ok(extracted) => extracted,
+        Err(err) => Err(err.into()),
+    };
+    match extracted {
+        Ok(files) => {
+            if let Err(err) = tokio::fs::remove_file(archive).await {
+                status(
+                    sink,
+                    format!("Warning: could not remove {}: {}", archive.display(), err),
+                );
+            }
+            Some(files)
+        }
+        Err(err) => {
+            status(
+                sink,
+                format!(
+                    "Warning: could not extract {}: {:#}; the archive was kept",
+                    archive.display(),
+                    err
+                ),
+            );
+            None
+        }
+    }
+}
+
+/// Pick a destination path that doesn't collide with existing files, trying
+/// at most `max_attempts` numbered names. Candidates are checked in their
+/// [`sanitize::extended_length_path`] form, the one that is later created,
+/// so a long name's collision is not missed on Windows.
+fn unique_path(
+    fs: &impl ReceiveFs,
+    dir: &Path,
+    name: impl AsRef<OsStr>,
+    max_attempts: u32,
+) -> Result<PathBuf> {
+    let name = Path::new(name.as_ref());
+    let candidate = sanitize::extended_length_path(&dir.join(name));
+    if !fs.exists(&candidate) {
+        return Ok(candidate);
     }
 
-    let stem = Path::new(name)
-        .file_stem()
-        .unwrap_or_default()
-        .to_string_lossy();
-    let ext = Path::new(name)
-        .extension()
-        .map(|e| format!(".{}", e.to_string_lossy()))
-        .unwrap_or_default();
+    let (stem, ext) = split_collision_name(name);
 
-    for i in 1u32.. {
-        let new_name = format!("{} ({}){}", stem, i, ext);
-        let p = dir.join(&new_name);
-        if !p.exists() {
-            return p;
+    for i in 1..=max_attempts {
+        let mut new_name = stem.to_os_string();
+        new_name.push(format!(" ({})", i));
+        if let Some(ext) = ext {
+            new_name.push(".");
+            new_name.push(ext);
+        }
+        let p = sanitize::extended_length_path(&dir.join(&new_name));
+        if !fs.exists(&p) {
+            return Ok(p);
         }
     }
 
-    unreachable!()
+    bail!(
+        "too many files named like '{}' in {} ({} numbered names taken)",
+        name.display(),
+        dir.display(),
+        max_attempts
+    )
 }
 
-/// Spawn a background task that watches connection type changes and prints/emits them.
-fn spawn_conn_type_watcher(
-    ep: &Endpoint,
-    node_id: NodeId,
-    sink: Option<SharedSink>,
-) -> Option<tokio::task::JoinHandle<()>> {
-    let watcher = ep.conn_type(node_id)?;
-    let mut stream = watcher.stream_updates_only();
-    let handle = tokio::task::spawn(async move {
-        while let Some(conn_type) = stream.next().await {
-            match &conn_type {
-                ConnectionType::Direct(addr) => {
-                    eprintln!("Connection upgraded: direct ({})", addr);
-                    emit(
-                        sink.as_ref(),
-                        TransferEvent::ConnectionPath {
-                            kind: ConnectionPathKind::Direct(addr.to_string()),
-                            latency_ms: None,
-                        },
-                    );
-                }
-                ConnectionType::Relay(url) => {
-                    eprintln!("Connection changed: relay ({})", url);
-                    emit(
-                        sink.as_ref(),
-                        TransferEvent::ConnectionPath {
-                            kind: ConnectionPathKind::Relay(url.to_string()),
-                            latency_ms: None,
-                        },
-                    );
-                }
-                ConnectionType::Mixed(addr, url) => {
-                    eprintln!("Connection changed: mixed (udp: {}, relay: {})", addr, url);
-                    emit(
-                        sink.as_ref(),
-                        TransferEvent::ConnectionPath {
-                            kind: ConnectionPathKind::Mixed {
-                                udp_addr: addr.to_string(),
-                                relay_url: url.to_string(),
-                            },
-                            latency_ms: None,
-                        },
-                    );
-                }
-                ConnectionType::None => {
-                    eprintln!("Connection path: none (searching...)");
-                    emit(
-                        sink.as_ref(),
-                        TransferEvent::ConnectionPath {
-                            kind: ConnectionPathKind::None,
-                            latency_ms: None,
-                        },
-                    );
+/// Create `path`, picked by [`unique_path`] for `name` in `dir`,
+/// exclusively: an empty file, or the folder of a bundle. While another
+/// transfer into `dir` got there first, move on to the next free name, so
+/// concurrent transfers of the same name never write over each other. A
+/// claimed file holds the name until the finished `.part` is renamed over
+/// it.
+async fn claim_path<F: ReceiveFs>(
+    fs: &F,
+    dir: &Path,
+    name: &OsStr,
+    kind: TransferContentKind,
+    path: &mut PathBuf,
+    max_attempts: u32,
+    retry: &mut DiskRetry,
+) -> std::io::Result<()> {
+    loop {
+        let created = match kind {
+            TransferContentKind::File => fs.create_new(path).await.map(drop),
+            TransferContentKind::Bundle => fs.create_dir(path).await,
+        };
+        match created {
+            Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {
+                match unique_path(fs, dir, name, max_attempts) {
+                    Ok(next) if next != *path => *path = next,
+                    _ => return Err(err),
                 }
             }
+            Err(err) if retry.retry(&err).await => {}
+            created => return created,
         }
-    });
-    Some(handle)
-}
-
-/// Print a summary of the final connection state.
-fn print_conn_summary(ep: &Endpoint, node_id: NodeId, sink: Option<&SharedSink>) {
-    if let Some(info) = ep.remote_info(node_id) {
-        let latency = info.latency.map(|d| d.as_secs_f64() * 1000.0);
-        let latency_str = latency
-            .map(|d| format!("{:.1}ms", d))
-            .unwrap_or_else(|| "unknown".to_string());
-        eprintln!(
-            "Transfer path: {}, latency: {}",
-            info.conn_type, latency_str
-        );
-
-        let kind = match info.conn_type {
-            ConnectionType::Direct(addr) => ConnectionPathKind::Direct(addr.to_string()),
-            ConnectionType::Relay(url) => ConnectionPathKind::Relay(url.to_string()),
-            ConnectionType::Mixed(addr, url) => ConnectionPathKind::Mixed {
-                udp_addr: addr.to_string(),
-                relay_url: url.to_string(),
-            },
-            ConnectionType::None => ConnectionPathKind::None,
-        };
-        emit(
-            sink,
-            TransferEvent::ConnectionPath {
-                kind,
-                latency_ms: latency,
-            },
-        );
     }
 }
 
-/// Print a QR code to stderr, indented for readability.
-fn print_qr(data: &str) {
-    if let Ok(qr_string) = qr2term::generate_qr_string(data) {
-        for line in qr_string.lines() {
-            eprintln!("    {}", line);
+/// Give up a name taken by [`claim_path`] that nothing was saved under.
+async fn release_claim<F: ReceiveFs>(fs: &F, path: &Path, kind: TransferContentKind) {
+    let _ = match kind {
+        TransferContentKind::File => fs.remove_file(path).await,
+        TransferContentKind::Bundle => fs.remove_dir_all(path).await,
+    };
+}
+
+/// Split `name` into the part a collision number goes after and the
+/// extension kept behind it. A leading dot belongs to the stem, so
+/// `.gitignore` becomes `.gitignore (1)` and `.env.local` becomes
+/// `.env (1).local`; a name with no extension, or a trailing dot, gets the
+/// number appended to the whole name.
+fn split_collision_name(name: &Path) -> (&OsStr, Option<&OsStr>) {
+    match (name.file_stem(), name.extension()) {
+        (Some(stem), Some(ext)) if !stem.is_empty() && !ext.is_empty() => (stem, Some(ext)),
+        _ => (name.as_os_str(), None),
+    }
+}
+
+/// Write `hash` for `file` to a `<name>.blake3` sidecar beside it, in the
+/// `HASH  name` format `b3sum --check` reads. An existing sidecar is not
+/// overwritten; the name is numbered like a colliding download.
+async fn write_checksum_sidecar(
+    fs: &impl ReceiveFs,
+    file: &Path,
+    hash: &str,
+    max_attempts: u32,
+) -> Result<PathBuf> {
+    let name = file.file_name().context("saved file has no name")?;
+    let dir = file.parent().unwrap_or(Path::new(""));
+    let mut sidecar_name = name.to_os_string();
+    sidecar_name.push(".blake3");
+    let sidecar = unique_path(fs, dir, &sidecar_name, max_attempts)?;
+    let mut out = fs.create(&sidecar).await?;
+    let line = format!("{}  {}\n", hash, name.to_string_lossy());
+    out.write_all(line.as_bytes()).await?;
+    out.flush().await?;
+    Ok(sidecar)
+}
+
+/// The `<name>.part.resume` sidecar recording what a kept `.part` file is a
+/// prefix of.
+fn resume_sidecar(part: &Path) -> PathBuf {
+    let mut name = part.as_os_str().to_os_string();
+    name.push(".resume");
+    PathBuf::from(name)
+}
+
+/// Record the size and hash `header` announced next to the kept `part`.
+async fn write_resume_sidecar(fs: &impl ReceiveFs, part: &Path, header: &FileHeader) -> Result<()> {
+    let mut out = fs.create(&resume_sidecar(part)).await?;
+    let line = format!("{} {}\n", header.blake3, header.size);
+    out.write_all(line.as_bytes()).await?;
+    out.flush().await?;
+    Ok(())
+}
+
+/// The kept `part` file, if its sidecar says it belongs to the transfer
+/// `header` announces and it is no longer than that transfer.
+async fn find_partial(fs: &impl ReceiveFs, part: &Path, header: &FileHeader) -> Option<Partial> {
+    let sidecar = fs.read_file(&resume_sidecar(part)).await.ok()?;
+    let sidecar = String::from_utf8(sidecar).ok()?;
+    let (hash, size) = sidecar.trim().split_once(' ')?;
+    if !hash.eq_ignore_ascii_case(&header.blake3) || size.parse::<u64>().ok()? != header.size {
+        return None;
+    }
+    fs.partial(part)
+        .await
+        .ok()
+        .filter(|partial| partial.len <= header.size)
+}
+
+/// `<name>.part.decrypted`, the plaintext copy of an encrypted `part`.
+fn decrypted_part(part: &Path) -> PathBuf {
+    let mut name = part.as_os_str().to_os_string();
+    name.push(".decrypted");
+    PathBuf::from(name)
+}
+
+/// Decrypt the at-rest encrypted `part` next to itself and remove it,
+/// returning the plaintext copy. Fails unless it holds exactly `size` bytes.
+async fn decrypt_part(
+    fs: &impl ReceiveFs,
+    key: &AtRestKey,
+    part: &Path,
+    size: u64,
+) -> Result<PathBuf> {
+    let plain = decrypted_part(part);
+    let mut reader = fs.open(part).await?;
+    let mut out = fs.create(&plain).await?;
+    let decrypted = at_rest::decrypt(key, &mut reader, &mut out).await;
+    drop(out);
+    match decrypted {
+        Ok(len) if len == size => {
+            fs.remove_file(part).await?;
+            Ok(plain)
+        }
+        Ok(len) => {
+            let _ = fs.remove_file(&plain).await;
+            bail!("Encrypted partial download holds {} of {} bytes", len, size)
+        }
+        Err(err) => {
+            let _ = fs.remove_file(&plain).await;
+            Err(err)
         }
     }
 }
@@ -186,73 +761,138 @@ pub async fn run_with_sink(
     output_dir: &Path,
     sink: Option<SharedSink>,
 ) -> Result<()> {
+    run_with_options(target, output_dir, sink, &ReceiveOptions::default()).await?;
+    Ok(())
+}
+
+/// Receive from a target with extra receive options. Returns the completion
+/// record so callers can act on the saved path.
+pub async fn run_with_options(
+    target: &str,
+    output_dir: &Path,
+    sink: Option<SharedSink>,
+    options: &ReceiveOptions,
+) -> Result<TransferCompleted> {
     let target = target.trim();
-    if ticket::is_ticket(target) {
-        run_iroh(target, output_dir, sink).await
-    } else {
-        run_direct_tcp(target, output_dir, sink).await
+    prepare_output_dir(output_dir, sink.as_ref()).await?;
+    let mut reconnect = ReconnectWindow::new(options.transfer.reconnect_window);
+    loop {
+        let received = if ticket::is_ticket(target) {
+            run_iroh(target, output_dir, sink.clone(), options).await
+        } else {
+            run_direct_tcp(target, output_dir, sink.clone(), options).await
+        };
+        match received {
+            Err(err) if reconnect.retry(&err, |message| status(sink.as_ref(), message)) => {
+                tokio::time::sleep(endpoint::RECONNECT_RETRY_DELAY).await;
+            }
+            received => {
+                report_connection_lost(sink.as_ref(), &received);
+                return received;
+            }
+        }
     }
 }
 
+/// Make sure `output_dir` is a directory before any peer is contacted,
+/// creating it when missing. Otherwise reports an `output_not_dir` error.
+async fn prepare_output_dir(output_dir: &Path, sink: Option<&SharedSink>) -> Result<()> {
+    let problem = match tokio::fs::metadata(output_dir).await {
+        Ok(metadata) if metadata.is_dir() => return Ok(()),
+        Ok(_) => "it is a file, not a directory".to_string(),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            match tokio::fs::create_dir_all(output_dir).await {
+                Ok(()) => return Ok(()),
+                Err(err) => format!("it cannot be created as a directory ({})", err),
+            }
+        }
+        Err(err) => format!("it cannot be read ({})", err),
+    };
+    let message = format!("Cannot save to {}: {}", output_dir.display(), problem);
+    emit(
+        sink,
+        TransferEvent::Error {
+            code: "output_not_dir".to_string(),
+            message: message.clone(),
+            partial_path: None,
+        },
+    );
+    bail!(message)
+}
+
 /// Connect to the sender via an iroh ticket (NAT-traversal, hole-punching, relay).
-async fn run_iroh(target: &str, output_dir: &Path, sink: Option<SharedSink>) -> Result<()> {
+async fn run_iroh(
+    target: &str,
+    output_dir: &Path,
+    sink: Option<SharedSink>,
+    options: &ReceiveOptions,
+) -> Result<TransferCompleted> {
     let addr = ticket::deserialize(target)?;
-
-    status(sink.as_ref(), "Connecting to sender via iroh...");
-
-    let ep = Endpoint::builder()
-        .bind()
-        .await
-        .context("failed to create iroh endpoint")?;
-
-    let conn = ep
-        .connect(addr, ALPN)
-        .await
-        .context("failed to connect to sender")?;
-
-    let remote_node_id = conn.remote_node_id()?;
-    status(sink.as_ref(), "Connected to sender.");
-
-    if let Some(info) = ep.remote_info(remote_node_id) {
-        eprintln!("Connection path: {}", info.conn_type);
+    match ticket::check_expiry(target, SystemTime::now(), options.transfer.clock_skew) {
+        Ok(warning) => {
+            if let Some(warning) = warning {
+                status(sink.as_ref(), warning);
+            }
+        }
+        Err(err) => {
+            emit(
+                sink.as_ref(),
+                TransferEvent::Error {
+                    code: "ticket_expired".to_string(),
+                    message: err.to_string(),
+                    partial_path: None,
+                },
+            );
+            return Err(err);
+        }
     }
-    let watcher_handle = spawn_conn_type_watcher(&ep, remote_node_id, sink.clone());
-
-    let (mut send_stream, mut recv_stream) = conn
-        .open_bi()
-        .await
-        .map_err(|e| anyhow::anyhow!("failed to open bi stream: {}", e))?;
 
-    let (mut transport, code) =
-        crypto::handshake_initiator(&mut recv_stream, &mut send_stream).await?;
-    status(
-        sink.as_ref(),
-        format!("Encryption established. Verification code: {}", code),
-    );
-    emit(sink.as_ref(), TransferEvent::HandshakeCode(code));
+    status(sink.as_ref(), "Connecting to sender via iroh...");
 
-    receive_file(
-        &mut recv_stream,
-        &mut send_stream,
-        &mut transport,
-        output_dir,
-        sink.as_ref(),
+    let ep = endpoint::bind(
+        Vec::new(),
+        &options.transfer.relay,
+        options.transfer.max_streams,
     )
     .await?;
+    let events = sink.clone();
+    let _relay = endpoint::report_home_relay(&ep, move |event| emit(events.as_ref(), event));
+    let result = endpoint::close_after(&ep, async {
+        let (conn, negotiated) = endpoint::connect(&ep, addr, AlpnRole::Forward)
+            .await
+            .context(endpoint::ConnectFailed::to("sender"))?;
 
-    if let Some(handle) = watcher_handle {
-        handle.abort();
+        status(sink.as_ref(), "Connected to sender.");
+        let link = IrohTransport {
+            ep: &ep,
+            conn,
+            negotiated,
+            role: HandshakeRole::Initiator,
+            transfer: &options.transfer,
+            stream_timeout: None,
+        }
+        .establish(sink.as_ref())
+        .await?;
+        receive_over(link, output_dir, options, sink.as_ref()).await
+    })
+    .await;
+    if let Some(event) = result
+        .as_ref()
+        .err()
+        .and_then(endpoint::self_connection_event)
+    {
+        emit(sink.as_ref(), event);
     }
-    print_conn_summary(&ep, remote_node_id, sink.as_ref());
-
-    conn.close(0u8.into(), b"done");
-    ep.close().await;
-
-    Ok(())
+    result
 }
 
 /// Connect to the sender via direct TCP (for LAN use when the ip:port is reachable).
-async fn run_direct_tcp(addr: &str, output_dir: &Path, sink: Option<SharedSink>) -> Result<()> {
+async fn run_direct_tcp(
+    addr: &str,
+    output_dir: &Path,
+    sink: Option<SharedSink>,
+    options: &ReceiveOptions,
+) -> Result<TransferCompleted> {
     if addr.contains("p2psh") {
         bail!(
             "This looks like an iroh ticket, not an ip:port address.\n\
@@ -260,33 +900,52 @@ async fn run_direct_tcp(addr: &str, output_dir: &Path, sink: Option<SharedSink>)
         );
     }
 
+    let addr = tcp::resolve_direct_addr(addr).await?;
     status(sink.as_ref(), format!("Connecting to {}...", addr));
 
-    let stream = tokio::net::TcpStream::connect(addr)
-        .await
-        .with_context(|| format!("failed to connect to {}", addr))?;
-
+    let link = TcpTransport::Dial(addr, &options.transfer.tcp)
+        .establish(sink.as_ref())
+        .await?;
     status(sink.as_ref(), "Connected to sender.");
 
-    let (mut reader, mut writer) = stream.into_split();
+    receive_over(link, output_dir, options, sink.as_ref()).await
+}
 
-    let (mut transport, code) = crypto::handshake_initiator(&mut reader, &mut writer).await?;
-    status(
-        sink.as_ref(),
-        format!("Encryption established. Verification code: {}", code),
+/// Listen for one sender on a plain TCP socket (`send --tcp`), for LAN
+/// transfers that should not touch relays or STUN.
+pub async fn run_tcp_listen_with_options(
+    listen_addr: &str,
+    output_dir: &Path,
+    sink: Option<SharedSink>,
+    options: &ReceiveOptions,
+) -> Result<TransferCompleted> {
+    let listen_addr = tcp::parse_listen_addr(listen_addr)?;
+    prepare_output_dir(output_dir, sink.as_ref()).await?;
+    let listener = tcp::listen(listen_addr, &options.transfer.tcp)?;
+    let local_addr = listener.local_addr()?;
+
+    status(sink.as_ref(), format!("Listening on {} (TCP).", local_addr));
+    eprintln!();
+    eprintln!(
+        "  On the sending device run:\n\n    p2p-share send --tcp <THIS-IP>:{} <FILE>...",
+        local_addr.port()
     );
-    emit(sink.as_ref(), TransferEvent::HandshakeCode(code));
+    eprintln!();
+    status(sink.as_ref(), "Waiting for sender to connect...");
 
-    receive_file(
-        &mut reader,
-        &mut writer,
-        &mut transport,
-        output_dir,
-        sink.as_ref(),
-    )
-    .await?;
+    let link = TcpTransport::Accept(listener, &options.transfer.tcp)
+        .establish(sink.as_ref())
+        .await?;
+    if let Some(peer_addr) = link.path.peer_addr() {
+        status(
+            sink.as_ref(),
+            format!("Sender connected from {}.", peer_addr),
+        );
+    }
 
-    Ok(())
+    let completed = receive_over(link, output_dir, options, sink.as_ref()).await;
+    report_connection_lost(sink.as_ref(), &completed);
+    completed
 }
 
 /// Run the receive side in listen mode: create an iroh endpoint, display a
@@ -296,268 +955,3253 @@ pub async fn run_listen(output_dir: &Path) -> Result<()> {
 }
 
 pub async fn run_listen_with_sink(output_dir: &Path, sink: Option<SharedSink>) -> Result<()> {
-    status(sink.as_ref(), "Setting up secure connection...");
+    run_listen_with_options(output_dir, sink, &ReceiveOptions::default()).await?;
+    Ok(())
+}
 
-    let ep = Endpoint::builder()
-        .alpns(vec![ALPN.to_vec(), ALPN_REVERSE.to_vec()])
-        .bind()
-        .await
-        .context("failed to create iroh endpoint")?;
+/// Listen mode with extra receive options, such as an inbox policy for an
+/// always-on receiver.
+pub async fn run_listen_with_options(
+    output_dir: &Path,
+    sink: Option<SharedSink>,
+    options: &ReceiveOptions,
+) -> Result<TransferCompleted> {
+    prepare_output_dir(output_dir, sink.as_ref()).await?;
+    let (ep, home_relay) = bind_listener(options, sink.as_ref()).await?;
+    let received = endpoint::close_after(&ep, async {
+        announce_listener(&ep, home_relay.as_ref(), options, sink.as_ref()).await?;
+        let _lan = lan_responder(&ep, options, sink.as_ref()).await;
+
+        status(sink.as_ref(), "Waiting for sender to connect...");
+        let mut reconnect = ReconnectWindow::new(options.transfer.reconnect_window);
+        let mut lost = None;
+        loop {
+            let accept = ep.accept();
+            let incoming = match (reconnect.remaining(), options.idle_exit) {
+                (Some(left), _) => match tokio::time::timeout(left, accept).await {
+                    Ok(incoming) => incoming,
+                    Err(_) => {
+                        let err: anyhow::Error = lost.take().expect("a transfer was lost");
+                        return Err(err.context("the sender did not reconnect in time"));
+                    }
+                },
+                (None, Some(limit)) => match tokio::time::timeout(limit, accept).await {
+                    Ok(incoming) => incoming,
+                    Err(_) => {
+                        status(sink.as_ref(), IDLE_EXIT_MESSAGE);
+                        return Err(IdleExit.into());
+                    }
+                },
+                (None, None) => accept.await,
+            }
+            .context("no incoming connection")?;
+            // Only one transfer at a time; tell other senders why instead
+            // of leaving them waiting on a connection nobody accepts.
+            let busy_sink = sink.clone();
+            let reject_others = endpoint::reject_while_busy(&ep, move |message| {
+                status(busy_sink.as_ref(), message)
+            });
+
+            let received = match accept_connection(incoming) {
+                Ok(connecting) => {
+                    receive_incoming(&ep, connecting, output_dir, options, sink.clone()).await
+                }
+                Err(err) => Err(err),
+            };
+            drop(reject_others);
+            match received {
+                Err(err) if err.is::<RoleMismatch>() => {
+                    status(
+                        sink.as_ref(),
+                        format!("Warning: turned away a peer: {}", err),
+                    );
+                }
+                Err(err) if reconnect.retry(&err, |message| status(sink.as_ref(), message)) => {
+                    // Report the loss itself if the sender never makes it
+                    // back, not a failed attempt to reconnect.
+                    if endpoint::is_connection_lost(&err) {
+                        lost = Some(err);
+                    }
+                }
+                received => return received,
+            }
+        }
+    })
+    .await;
+    report_connection_lost(sink.as_ref(), &received);
+    received
+}
+
+/// Limits for [`run_listen_queue_with_options`]: how many senders are
+/// received from at once, and how many more may wait for a free slot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ListenQueue {
+    pub workers: usize,
+    /// Connections allowed to wait while every worker is busy; further
+    /// senders are turned away as busy. At least 1.
+    pub backlog: usize,
+}
 
-    status(sink.as_ref(), "Connecting to relay...");
-    let relay_timeout =
-        tokio::time::timeout(Duration::from_secs(10), ep.home_relay().initialized()).await;
+/// Default [`ListenQueue::backlog`].
+pub const DEFAULT_LISTEN_BACKLOG: usize = 4;
 
-    match &relay_timeout {
-        Ok(relay_url) => {
-            status(sink.as_ref(), format!("Relay connected: {}", relay_url));
+impl Default for ListenQueue {
+    fn default() -> Self {
+        Self {
+            workers: 1,
+            backlog: DEFAULT_LISTEN_BACKLOG,
         }
-        Err(_) => {
-            status(
-                sink.as_ref(),
-                "Warning: could not connect to relay (timed out).",
-            );
-            status(sink.as_ref(), "Only direct/LAN connections will work.");
+    }
+}
+
+/// Listen mode that keeps accepting senders after the first transfer.
+/// Connections wait in a bounded queue served by `queue.workers` transfers
+/// at a time; a failed transfer is reported and the next sender is served.
+/// Runs until the endpoint stops accepting connections, or fails with
+/// [`IdleExit`] once [`ReceiveOptions::idle_exit`] passes with nothing
+/// queued or in progress.
+pub async fn run_listen_queue_with_options(
+    output_dir: &Path,
+    sink: Option<SharedSink>,
+    options: &ReceiveOptions,
+    queue: ListenQueue,
+) -> Result<()> {
+    prepare_output_dir(output_dir, sink.as_ref()).await?;
+    let (ep, home_relay) = bind_listener(options, sink.as_ref()).await?;
+    endpoint::close_after(&ep, async {
+        announce_listener(&ep, home_relay.as_ref(), options, sink.as_ref()).await?;
+        let _lan = lan_responder(&ep, options, sink.as_ref()).await;
+        status(sink.as_ref(), "Waiting for senders to connect...");
+
+        let (queued, pending) = tokio::sync::mpsc::channel(queue.backlog.max(1));
+        let pending = tokio::sync::Mutex::new(pending);
+        let counts = QueueCounts::default();
+        let activity = tokio::sync::Notify::new();
+        let (ep, pending, counts, sink, activity) = (&ep, &pending, &counts, &sink, &activity);
+
+        let acceptor = async move {
+            while let Some(incoming) = ep.accept().await {
+                activity.notify_one();
+                match queued.try_reserve() {
+                    // Accept the QUIC connection before it queues, so its
+                    // handshake completes now rather than timing out while
+                    // every worker is busy.
+                    Ok(slot) => match accept_connection(incoming) {
+                        Ok(connecting) => {
+                            counts.waiting.fetch_add(1, Ordering::Relaxed);
+                            slot.send(connecting);
+                            counts.report(sink.as_ref());
+                        }
+                        Err(err) => status(sink.as_ref(), format!("Warning: {:#}", err)),
+                    },
+                    Err(_) => {
+                        let sink = sink.clone();
+                        tokio::spawn(async move {
+                            if let Some(peer) = endpoint::reject_busy(incoming).await {
+                                status(
+                                    sink.as_ref(),
+                                    format!(
+                                        "Warning: rejected a sender ({}); the queue is full.",
+                                        peer
+                                    ),
+                                );
+                            }
+                        });
+                    }
+                }
+            }
+        };
+        let workers = (0..queue.workers.max(1)).map(|_| async move {
+            loop {
+                let Some(connecting) = pending.lock().await.recv().await else {
+                    break;
+                };
+                counts.waiting.fetch_sub(1, Ordering::Relaxed);
+                counts.active.fetch_add(1, Ordering::Relaxed);
+                counts.report(sink.as_ref());
+                if let Err(err) =
+                    receive_incoming(ep, connecting, output_dir, options, sink.clone()).await
+                {
+                    // A resumable transfer keeps its part file, so the
+                    // sender can pick up where it stopped on reconnecting.
+                    let message = if options.transfer.reconnect_window.is_some()
+                        && endpoint::is_connection_lost(&err)
+                    {
+                        endpoint::RECONNECT_STATUS.to_string()
+                    } else {
+                        format!("Warning: transfer failed: {:#}", err)
+                    };
+                    status(sink.as_ref(), message);
+                }
+                counts.active.fetch_sub(1, Ordering::Relaxed);
+                counts.report(sink.as_ref());
+                activity.notify_one();
+            }
+        });
+        tokio::select! {
+            _ = async { tokio::join!(acceptor, n0_future::join_all(workers)) } => Ok(()),
+            _ = wait_until_idle(options.idle_exit, counts, activity) => {
+                status(sink.as_ref(), IDLE_EXIT_MESSAGE);
+                Err(IdleExit.into())
+            }
+        }
+    })
+    .await
+}
+
+/// Resolves once `limit` passes with no sender queued or in progress and no
+/// `activity` notification; each notification restarts the timer. Never
+/// resolves without a limit.
+async fn wait_until_idle(
+    limit: Option<Duration>,
+    counts: &QueueCounts,
+    activity: &tokio::sync::Notify,
+) {
+    let Some(limit) = limit else {
+        return std::future::pending().await;
+    };
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep(limit) => {
+                if counts.is_idle() {
+                    return;
+                }
+            }
+            _ = activity.notified() => {}
+        }
+    }
+}
+
+/// Senders waiting in and being served from a listen queue.
+#[derive(Debug, Default)]
+struct QueueCounts {
+    waiting: AtomicU64,
+    active: AtomicU64,
+}
+
+impl QueueCounts {
+    fn is_idle(&self) -> bool {
+        self.waiting.load(Ordering::Relaxed) == 0 && self.active.load(Ordering::Relaxed) == 0
+    }
+
+    fn report(&self, sink: Option<&SharedSink>) {
+        emit(
+            sink,
+            TransferEvent::AcceptQueue {
+                waiting: self.waiting.load(Ordering::Relaxed),
+                active: self.active.load(Ordering::Relaxed),
+            },
+        );
+    }
+}
+
+/// Bind the endpoint for listen mode, accepting both transfer directions.
+async fn bind_listener(
+    options: &ReceiveOptions,
+    sink: Option<&SharedSink>,
+) -> Result<(Endpoint, Option<HomeRelay>)> {
+    status(sink, "Setting up secure connection...");
+
+    endpoint::bind_with_home_relay(
+        [
+            alpn::supported_alpns(AlpnRole::Forward),
+            alpn::supported_alpns(AlpnRole::Reverse),
+        ]
+        .concat(),
+        &options.transfer.relay,
+        options.transfer.max_streams,
+        options.transfer.strict_host,
+        |message| status(sink, message),
+    )
+    .await
+}
+
+/// With [`TransferOptions::lan`], answer LAN probes for `ep` until the
+/// returned guard is dropped.
+async fn lan_responder(
+    ep: &Endpoint,
+    options: &ReceiveOptions,
+    sink: Option<&SharedSink>,
+) -> Option<AbortOnDrop> {
+    if !options.transfer.lan {
+        return None;
+    }
+    #[cfg(feature = "lan-probe")]
+    {
+        let ports = ep
+            .bound_sockets()
+            .iter()
+            .filter(|addr| addr.is_ipv4())
+            .map(|addr| addr.port())
+            .collect();
+        match crate::lan::spawn_responder(ep.node_id(), ports).await {
+            Ok(guard) => Some(guard),
+            Err(err) => {
+                status(
+                    sink,
+                    format!(
+                        "Warning: cannot answer LAN probes on UDP port {} ({})",
+                        crate::lan::LAN_PROBE_PORT,
+                        err
+                    ),
+                );
+                None
+            }
+        }
+    }
+    #[cfg(not(feature = "lan-probe"))]
+    {
+        let _ = ep;
+        status(sink, "Warning: --lan needs the lan-probe feature.");
+        None
+    }
+}
+
+/// Report the relay outcome and publish the listen ticket as text and QR.
+async fn announce_listener(
+    ep: &Endpoint,
+    home_relay: Option<&HomeRelay>,
+    options: &ReceiveOptions,
+    sink: Option<&SharedSink>,
+) -> Result<()> {
+    emit(sink, endpoint::relay_status(home_relay));
+    match home_relay {
+        Some(HomeRelay { url: relay_url, .. }) => {
+            status(sink, format!("Relay connected: {}", relay_url));
+        }
+        None => {
+            status(sink, "Warning: could not connect to relay (timed out).");
+            status(sink, "Only direct/LAN connections will work.");
         }
     }
 
-    let node_addr = ep.node_addr().initialized().await;
-    let ticket_str = ticket::serialize(&node_addr)?;
-    emit(sink.as_ref(), TransferEvent::Ticket(ticket_str.clone()));
-    emit(sink.as_ref(), TransferEvent::QrPayload(ticket_str.clone()));
+    let node_addr = endpoint::node_addr_with_timeout(ep, endpoint::NODE_ADDR_TIMEOUT, |message| {
+        status(sink, message)
+    })
+    .await;
+    let (mut ticket_str, report) = ticket::serialize_with_report(&node_addr)?;
+    if let Some(summary) = report.summary() {
+        status(sink, summary);
+    }
+    if options.ticket_uri {
+        ticket_str = ticket::to_uri(&ticket_str);
+    }
+    emit(sink, TransferEvent::Ticket(ticket_str.clone()));
+    let fallback = qr::relay_only_ticket(&node_addr, options.ticket_uri);
+    let ticket_qr = qr::ticket_qr(
+        &ticket_str,
+        fallback.as_deref(),
+        options.multi_qr,
+        |message| status(sink, message),
+    );
+    for part in &ticket_qr {
+        emit(sink, TransferEvent::QrPayload(part.payload.clone()));
+    }
 
     eprintln!();
     eprintln!("Ready to receive files.");
-    eprintln!();
-    eprintln!("  Scan this QR code on the sending device:");
-    eprintln!();
-    print_qr(&ticket_str);
+    match ticket_qr.len() {
+        0 => {}
+        1 => {
+            eprintln!();
+            eprintln!("  Scan this QR code on the sending device:");
+            eprintln!();
+        }
+        _ => {
+            eprintln!();
+            eprintln!("  Scan all of these QR codes on the sending device:");
+            eprintln!();
+        }
+    }
+    qr::print(&ticket_qr);
     eprintln!();
     eprintln!(
         "  Or run:\n\n    p2p-share send --to {} <FILE>...",
         ticket_str
     );
     eprintln!();
+    Ok(())
+}
+
+/// A sender connection accepted on a listening endpoint, with the Noise
+/// handshake completed.
+struct AcceptedSender {
+    link: Link<RecvStream, SendStream>,
+    transport: snow::TransportState,
+    hello: Option<HandshakePayload>,
+}
+
+impl AcceptedSender {
+    /// Stop watching the path, print the summary and close the connection.
+    async fn finish(mut self, sink: Option<&SharedSink>) {
+        self.link.path.finish(sink);
+        close_after_done(&mut self.link).await;
+    }
+}
+
+/// Finish our stream after DONE and give the sender up to [`DONE_LINGER`]
+/// to read it and close first, so closing here cannot discard the ack.
+async fn close_after_done<R, W>(link: &mut Link<R, W>)
+where
+    W: AsyncWrite + Unpin,
+{
+    let _ = link.writer.shutdown().await;
+    link.path.closed(DONE_LINGER).await;
+    link.path.close();
+}
+
+/// Run the handshake over `link` and receive its transfer into
+/// `output_dir`.
+async fn receive_over<R, W>(
+    mut link: Link<R, W>,
+    output_dir: &Path,
+    options: &ReceiveOptions,
+    sink: Option<&SharedSink>,
+) -> Result<TransferCompleted>
+where
+    R: tokio::io::AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let (mut transport, hello) = transport::handshake(&mut link, &options.transfer, sink).await?;
+
+    let completed = receive_file(
+        &mut link.reader,
+        &mut link.writer,
+        &mut transport,
+        output_dir,
+        &PeerInfo::new(&link.path, hello),
+        options,
+        sink,
+    )
+    .await?;
+
+    link.path.finish(sink);
+    close_after_done(&mut link).await;
+    Ok(completed)
+}
+
+/// Complete the connection from `incoming` and receive its transfer into
+/// `output_dir`.
+async fn receive_incoming(
+    ep: &Endpoint,
+    connecting: Connecting,
+    output_dir: &Path,
+    options: &ReceiveOptions,
+    sink: Option<SharedSink>,
+) -> Result<TransferCompleted> {
+    let mut accepted = handshake_sender(ep, connecting, options, sink.clone()).await?;
+
+    let completed = receive_file(
+        &mut accepted.link.reader,
+        &mut accepted.link.writer,
+        &mut accepted.transport,
+        output_dir,
+        &PeerInfo::new(&accepted.link.path, accepted.hello.clone()),
+        options,
+        sink.as_ref(),
+    )
+    .await?;
+
+    accepted.finish(sink.as_ref()).await;
+    Ok(completed)
+}
+
+/// Wait for a sender to connect to `ep` and run the responder handshake.
+async fn accept_sender(ep: &Endpoint, sink: Option<SharedSink>) -> Result<AcceptedSender> {
     status(sink.as_ref(), "Waiting for sender to connect...");
 
     let incoming = ep.accept().await.context("no incoming connection")?;
+    handshake_sender(
+        ep,
+        accept_connection(incoming)?,
+        &ReceiveOptions::default(),
+        sink,
+    )
+    .await
+}
 
-    let conn = incoming
+/// Accept the QUIC connection from `incoming`. Its handshake then runs in
+/// the background until the returned future is awaited.
+fn accept_connection(incoming: Incoming) -> Result<Connecting> {
+    incoming
         .accept()
-        .map_err(|e| anyhow::anyhow!("failed to accept connection: {}", e))?
+        .map_err(|e| anyhow::anyhow!("failed to accept connection: {}", e))
+}
+
+/// Wait for the connection to be established and run the responder
+/// handshake.
+async fn handshake_sender(
+    ep: &Endpoint,
+    connecting: Connecting,
+    options: &ReceiveOptions,
+    sink: Option<SharedSink>,
+) -> Result<AcceptedSender> {
+    let conn = connecting
         .await
         .map_err(|e| anyhow::anyhow!("connection failed: {}", e))?;
 
-    let remote_node_id = conn.remote_node_id()?;
     status(sink.as_ref(), "Sender connected.");
-
-    if let Some(info) = ep.remote_info(remote_node_id) {
-        eprintln!("Connection path: {}", info.conn_type);
+    // The listener accepts forward-mode ALPNs too, so a receiver that
+    // dialed this receiver's ticket is turned away here.
+    let negotiated = match NegotiatedAlpn::from_connection(&conn)?.expect_role(AlpnRole::Reverse) {
+        Ok(negotiated) => negotiated,
+        Err(err) => {
+            conn.close(0u8.into(), b"wrong direction");
+            return Err(err.into());
+        }
+    };
+    // A sender that connects and vanishes would otherwise leave us waiting
+    // for its stream until the connection idles out.
+    let mut link = IrohTransport {
+        ep,
+        conn,
+        negotiated,
+        role: HandshakeRole::Responder,
+        transfer: &options.transfer,
+        stream_timeout: Some(PEER_START_TIMEOUT),
     }
-    let watcher_handle = spawn_conn_type_watcher(&ep, remote_node_id, sink.clone());
+    .establish(sink.as_ref())
+    .await
+    .map_err(|err| {
+        if err.is::<StreamNotOpened>() {
+            peer_abandoned(sink.as_ref())
+        } else {
+            err
+        }
+    })?;
 
-    let (mut send_stream, mut recv_stream) = conn
-        .accept_bi()
+    let handshake = transport::handshake(&mut link, &options.transfer, sink.as_ref());
+    let (transport, hello) = tokio::time::timeout(PEER_START_TIMEOUT, handshake)
         .await
-        .map_err(|e| anyhow::anyhow!("failed to accept bi stream: {}", e))?;
+        .map_err(|_| peer_abandoned(sink.as_ref()))??;
 
-    let (mut transport, code) =
-        crypto::handshake_responder(&mut recv_stream, &mut send_stream).await?;
-    status(
-        sink.as_ref(),
-        format!("Encryption established. Verification code: {}", code),
-    );
-    emit(sink.as_ref(), TransferEvent::HandshakeCode(code));
+    Ok(AcceptedSender {
+        link,
+        transport,
+        hello,
+    })
+}
 
-    receive_file(
-        &mut recv_stream,
-        &mut send_stream,
-        &mut transport,
-        output_dir,
+/// Accept one sender on `ep` (a peer running `send --to` against this
+/// endpoint's ticket) and receive its file into memory without touching the
+/// filesystem. Transfers larger than `max_size` bytes and bundles are
+/// rejected before any data is sent. `ep` must accept the
+/// [`AlpnRole::Reverse`] entries of [`alpn::supported_alpns`]; the
+/// caller is responsible for sharing its ticket and closing it afterwards.
+/// The sender's connection is closed here, whether the transfer succeeded
+/// or not.
+pub async fn receive_bytes_on_endpoint(
+    ep: &Endpoint,
+    max_size: u64,
+    sink: Option<SharedSink>,
+) -> Result<(FileHeader, Bytes)> {
+    let mut accepted = accept_sender(ep, sink.clone()).await?;
+    let received = receive_bytes(
+        &mut accepted.link.reader,
+        &mut accepted.link.writer,
+        &mut accepted.transport,
+        max_size,
         sink.as_ref(),
     )
-    .await?;
-
-    if let Some(handle) = watcher_handle {
-        handle.abort();
+    .await;
+    match received {
+        Ok(received) => {
+            accepted.finish(sink.as_ref()).await;
+            Ok(received)
+        }
+        Err(err) => {
+            accepted.link.path.abort();
+            Err(err)
+        }
     }
-    print_conn_summary(&ep, remote_node_id, sink.as_ref());
-
-    conn.close(0u8.into(), b"done");
-    ep.close().await;
-
-    Ok(())
 }
 
-/// Shared file-reception logic used by all receive paths (iroh, TCP, listen).
-async fn receive_file<R, W>(
+/// In-memory counterpart of [`receive_file`].
+async fn receive_bytes<R, W>(
     reader: &mut R,
     writer: &mut W,
     transport: &mut snow::TransportState,
-    output_dir: &Path,
+    max_size: u64,
     sink: Option<&SharedSink>,
-) -> Result<()>
+) -> Result<(FileHeader, Bytes)>
 where
     R: tokio::io::AsyncRead + Unpin,
     W: tokio::io::AsyncWrite + Unpin,
 {
-    let header_bytes = crypto::encrypted_read(reader, transport).await?;
-    let header_str = String::from_utf8(header_bytes).context("invalid UTF-8 in file header")?;
-
-    if header_str.is_empty() {
-        bail!("Connection closed before receiving file header");
+    let header = read_header(reader, writer, transport, None, sink).await?;
+    if header.content_kind == Some(TransferContentKind::Bundle) {
+        crypto::encrypted_write(writer, transport, b"bundles not accepted\n").await?;
+        return Err(Declined {
+            reason: "rejected: bundles cannot be received into memory".to_string(),
+        }
+        .into());
     }
-
-    let header = FileHeader::from_wire(&header_str)?;
-    let content_kind = header.content_kind.unwrap_or(TransferContentKind::File);
-    let item_count = header.item_count.unwrap_or(1).max(1);
-    let completed_name = if content_kind == TransferContentKind::Bundle {
-        bundle_logical_name(&header)
-    } else {
-        header.name.clone()
-    };
-
-    eprintln!();
-    let incoming_status = if content_kind == TransferContentKind::Bundle {
-        format!(
-            "Incoming files: {} ({} files, {})",
-            completed_name,
-            item_count,
-            human_bytes(header.size)
-        )
-    } else {
+    if header.streaming {
+        crypto::encrypted_write(writer, transport, b"streams not accepted\n").await?;
+        return Err(Declined {
+            reason: "rejected: live streams cannot be received into memory".to_string(),
+        }
+        .into());
+    }
+    if header.size > max_size {
+        crypto::encrypted_write(writer, transport, b"too large\n").await?;
+        return Err(Declined {
+            reason: format!(
+                "rejected: {} exceeds the {} in-memory limit",
+                human_bytes(header.size),
+                human_bytes(max_size)
+            ),
+        }
+        .into());
+    }
+    status(
+        sink,
         format!(
             "Incoming file: {} ({})",
-            header.name,
+            sanitize::sanitize_file_name(&header.name),
             human_bytes(header.size)
-        )
-    };
-    status(sink, incoming_status);
+        ),
+    );
+    crypto::encrypted_write(writer, transport, &HeaderAck::Accept.to_wire()).await?;
 
-    crypto::encrypted_write(writer, transport, b"OK\n").await?;
+    let mut buffer = Vec::with_capacity(header.size as usize);
+    receive_body(
+        reader,
+        transport,
+        &mut buffer,
+        &header,
+        None,
+        sink,
+        None,
+        None,
+        None,
+        None,
+    )
+    .await?;
+    crypto::encrypted_write(writer, transport, b"DONE\n").await?;
 
-    tokio::fs::create_dir_all(output_dir).await?;
-    let temp_dest = unique_path(output_dir, &format!("{}.part", header.name));
-    let final_dest = if content_kind == TransferContentKind::Bundle {
-        unique_path(output_dir, &completed_name)
-    } else {
-        unique_path(output_dir, &header.name)
-    };
+    status(sink, "Checksum verified (blake3).");
+    Ok((header, Bytes::from(buffer)))
+}
 
-    if content_kind == TransferContentKind::Bundle {
-        status(
+/// Read and parse the encrypted file header, first answering a sync
+/// sender's [`SYNC_PREFIX`] request with the manifest of `sync_dir`, or a
+/// refusal without one. A sender that closes the stream first, or sends
+/// nothing for [`PEER_START_TIMEOUT`], fails with [`PeerAbandoned`].
+async fn read_header<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    transport: &mut snow::TransportState,
+    sync_dir: Option<&Path>,
+    sink: Option<&SharedSink>,
+) -> Result<FileHeader>
+where
+    R: tokio::io::AsyncRead + Unpin,
+    W: tokio::io::AsyncWrite + Unpin,
+{
+    let mut header_str =
+        read_header_line(reader, transport, Some(PEER_START_TIMEOUT), sink).await?;
+    if let Some(roots) = header_str.strip_prefix(SYNC_PREFIX) {
+        answer_sync(writer, transport, roots, sync_dir, sink).await?;
+        // Rebuilding the archive without our files can take the sender a
+        // while, so the start timeout no longer applies.
+        header_str = read_header_line(reader, transport, None, sink).await?;
+    }
+    if let Some(reason) = header_str.strip_prefix(ABORT_PREFIX) {
+        let err = SourceUnavailable {
+            reason: reason.trim().to_string(),
+        };
+        emit(
             sink,
-            format!("Saving extracted files to: {}", final_dest.display()),
+            TransferEvent::Error {
+                code: "source_unavailable".to_string(),
+                message: err.to_string(),
+                partial_path: None,
+            },
         );
-    } else {
-        status(sink, format!("Saving to: {}", final_dest.display()));
+        return Err(err.into());
     }
-    eprintln!();
+    FileHeader::from_wire(&header_str)
+}
 
-    let mut file = File::create(&temp_dest).await?;
-    let pb = if sink.is_none() {
-        Some(transfer_progress_bar(header.size))
-    } else {
-        None
+/// Read one control line before the data, waiting at most `wait`.
+async fn read_header_line<R>(
+    reader: &mut R,
+    transport: &mut snow::TransportState,
+    wait: Option<Duration>,
+    sink: Option<&SharedSink>,
+) -> Result<String>
+where
+    R: tokio::io::AsyncRead + Unpin,
+{
+    let read = crypto::encrypted_read(reader, transport);
+    let read = match wait {
+        Some(wait) => tokio::time::timeout(wait, read).await,
+        None => Ok(read.await),
     };
-    let mut received: u64 = 0;
-    let mut hasher = blake3::Hasher::new();
-    let receive_result: Result<(PathBuf, u64)> = async {
-        while received < header.size {
-            let plaintext = crypto::encrypted_read(reader, transport).await?;
-            if plaintext.is_empty() {
-                break;
-            }
+    let header_bytes = match read {
+        Ok(Ok(bytes)) if !bytes.is_empty() => bytes,
+        // Transport errors mean the stream ended; anything else, such as a
+        // frame that fails to decrypt, is a genuine protocol error.
+        Ok(Err(err)) if !err.is::<std::io::Error>() => return Err(err),
+        Ok(_) | Err(_) => return Err(peer_abandoned(sink)),
+    };
+    String::from_utf8(header_bytes).context("invalid UTF-8 in file header")
+}
 
-            file.write_all(&plaintext).await?;
-            hasher.update(&plaintext);
-            received += plaintext.len() as u64;
-            if let Some(pb) = &pb {
-                pb.set_position(received);
-            }
-            emit(
-                sink,
-                TransferEvent::Progress {
-                    done: received,
-                    total: header.size,
-                },
-            );
-        }
+/// Answer a [`SYNC_PREFIX`] request listing the archive's top-level
+/// `roots` with the manifest of what `sync_dir` already holds, or decline
+/// when this receiver is not in sync mode.
+async fn answer_sync<W>(
+    writer: &mut W,
+    transport: &mut snow::TransportState,
+    roots: &str,
+    sync_dir: Option<&Path>,
+    sink: Option<&SharedSink>,
+) -> Result<()>
+where
+    W: tokio::io::AsyncWrite + Unpin,
+{
+    let Some(dir) = sync_dir else {
+        return crypto::encrypted_write(writer, transport, &SyncReply::Declined.to_wire()).await;
+    };
+    let roots: Vec<String> = serde_json::from_str(roots.trim()).context("invalid sync request")?;
+    status(sink, "Sender is syncing; listing the files already here...");
+    let dir = dir.to_path_buf();
+    let have = tokio::task::spawn_blocking(move || Manifest::build(&dir, &roots)).await?;
+    status(
+        sink,
+        format!("Already have {} of the sender's files.", have.len()),
+    );
+    let frames = have.to_frames()?;
+    let reply = SyncReply::Manifest(frames.len() as u64);
+    crypto::encrypted_write(writer, transport, &reply.to_wire()).await?;
+    for frame in frames {
+        crypto::encrypted_write(writer, transport, &frame).await?;
+    }
+    Ok(())
+}
 
-        file.flush().await?;
-        if let Some(pb) = &pb {
-            pb.finish_with_message("done");
+/// Read the segment hashes a [`FileHeader::segment_size`] transfer sends
+/// after the ack, returning a verifier for the data from `offset` on.
+async fn read_segment_hashes<R>(
+    reader: &mut R,
+    transport: &mut snow::TransportState,
+    header: &FileHeader,
+    offset: u64,
+) -> Result<Option<SegmentVerifier>>
+where
+    R: tokio::io::AsyncRead + Unpin,
+{
+    let Some(segment_size) = header.segment_size else {
+        return Ok(None);
+    };
+    if header.streaming || segment_size < segments::MIN_SEGMENT_SIZE {
+        bail!(
+            "Sender announced an invalid segment size ({})",
+            segment_size
+        );
+    }
+    // The count comes from the sender, so the hashes are only held as they
+    // arrive, never allocated up front.
+    let count = segments::segment_count(header.size, segment_size);
+    if count > segments::MAX_SEGMENTS {
+        bail!(
+            "Sender announced {} segments, more than the {} accepted",
+            count,
+            segments::MAX_SEGMENTS
+        );
+    }
+    let mut hashes = Vec::new();
+    for _ in 0..segments::frame_count(count) {
+        let frame = crypto::encrypted_read(reader, transport).await?;
+        hashes.extend(segments::decode(&frame)?);
+    }
+    if hashes.len() as u64 != count {
+        bail!(
+            "Sender sent {} segment hashes for {} segments",
+            hashes.len(),
+            count
+        );
+    }
+    Ok(Some(SegmentVerifier::new(segment_size, hashes, offset)))
+}
+
+/// Emit the `chunk_mismatch` error event for `err` and return it.
+fn chunk_mismatch(sink: Option<&SharedSink>, err: SegmentMismatch) -> anyhow::Error {
+    emit(
+        sink,
+        TransferEvent::Error {
+            code: "chunk_mismatch".to_string(),
+            message: err.to_string(),
+            partial_path: None,
+        },
+    );
+    err.into()
+}
+
+/// Move the progress bar to `received` and report it to `sink`, along with
+/// any bundle file boundaries it crossed.
+fn report_progress(
+    received: u64,
+    header: &FileHeader,
+    pb: Option<&indicatif::ProgressBar>,
+    sink: Option<&SharedSink>,
+    tracker: Option<&mut BundleTracker>,
+) {
+    if let Some(pb) = pb {
+        pb.set_position(received);
+    }
+    emit(
+        sink,
+        TransferEvent::Progress {
+            done: received,
+            total: (!header.streaming).then_some(header.size),
+        },
+    );
+    if let Some(tracker) = tracker {
+        for event in tracker.advance(received) {
+            emit(sink, event);
         }
-        drop(file);
+    }
+}
+
+/// Decrypted frames queued between the network reader and the disk writer,
+/// bounding buffered data to about 1 MiB.
+const WRITE_QUEUE_DEPTH: usize = 16;
+
+/// Stream `header.size` bytes of file data into `dest`, reporting progress,
+/// then verify the byte count and blake3 hash. Returns the bytes received,
+/// the verified hash and the first [`route::MIME_SNIFF_LEN`] bytes of data.
+/// With `resumed`, `dest` already holds that prefix and only the rest
+/// arrives; counts, progress and the hash cover the whole file.
+///
+/// A streaming header has no size or hash up front: data runs until an
+/// empty frame, and the [`StreamTrailer`] after it is verified instead.
+/// Progress is then reported with a `total` of 0, and the transfer is
+/// declined once more than `size_limit` bytes arrived.
+///
+/// Network reads and writes to `dest` run concurrently, connected by a
+/// channel of up to [`WRITE_QUEUE_DEPTH`] frames, so a slow disk does not
+/// stall decryption until the queue is full.
+#[allow(clippy::too_many_arguments)]
+async fn receive_body<R, D>(
+    reader: &mut R,
+    transport: &mut snow::TransportState,
+    dest: &mut D,
+    header: &FileHeader,
+    stats: Option<&TransferStats>,
+    sink: Option<&SharedSink>,
+    pb: Option<&indicatif::ProgressBar>,
+    progress_interval: Option<Duration>,
+    resumed: Option<Partial>,
+    size_limit: Option<u64>,
+) -> Result<(u64, String, Vec<u8>)>
+where
+    R: tokio::io::AsyncRead + Unpin,
+    D: tokio::io::AsyncWrite + Unpin,
+{
+    let (tx, mut rx) = tokio::sync::mpsc::channel::<Vec<u8>>(WRITE_QUEUE_DEPTH);
+
+    let read = async move {
+        let offset = resumed.as_ref().map_or(0, |partial| partial.len);
+        let mut verifier = read_segment_hashes(reader, transport, header, offset).await?;
+        let Partial {
+            len: mut received,
+            mut hasher,
+            mut head,
+        } = resumed.unwrap_or_else(|| Partial {
+            len: 0,
+            hasher: blake3::Hasher::new(),
+            head: Vec::new(),
+        });
+        let mut tracker = (sink.is_some()
+            && header.content_kind == Some(TransferContentKind::Bundle)
+            && !header.zstd_archive)
+            .then(BundleTracker::streaming);
+        let mut throttle = ProgressThrottle::new(progress_interval);
+        if let Some(stats) = stats {
+            stats.begin(header.size);
+        }
+        let mut index = 0u64;
+        while header.streaming || received < header.size {
+            let plaintext = crypto::encrypted_read(reader, transport).await?;
+            if plaintext.is_empty() {
+                break;
+            }
+            let plaintext = if header.sequenced {
+                let data = strip_sequence(plaintext, index).inspect_err(|err| {
+                    emit(
+                        sink,
+                        TransferEvent::Error {
+                            code: "sequence_error".to_string(),
+                            message: err.to_string(),
+                            partial_path: None,
+                        },
+                    )
+                })?;
+                index += 1;
+                data
+            } else {
+                plaintext
+            };
+            let plaintext = if header.compression_level.is_some() {
+                compression::unpack(plaintext)?
+            } else {
+                plaintext
+            };
+
+            received += plaintext.len() as u64;
+            if !header.streaming && received > header.size {
+                bail!(
+                    "Sender sent more data than announced ({} bytes)",
+                    header.size
+                );
+            }
+            if let Some(limit) = size_limit.filter(|limit| received > *limit) {
+                return Err(Declined {
+                    reason: format!(
+                        "rejected: the live file grew past the {} inbox limit",
+                        human_bytes(limit)
+                    ),
+                }
+                .into());
+            }
+            hasher.update(&plaintext);
+            if let Some(verifier) = &mut verifier {
+                verifier
+                    .update(&plaintext)
+                    .map_err(|err| chunk_mismatch(sink, err))?;
+            }
+            if head.len() < route::MIME_SNIFF_LEN {
+                let take = (route::MIME_SNIFF_LEN - head.len()).min(plaintext.len());
+                head.extend_from_slice(&plaintext[..take]);
+            }
+            if let Some(tracker) = &mut tracker {
+                tracker.observe(&plaintext)?;
+            }
+            if tx.send(plaintext).await.is_err() {
+                // The writer failed; its error is reported below.
+                break;
+            }
+            if let Some(stats) = stats {
+                stats.record(received);
+            }
+            if throttle.ready(received, header.size) {
+                report_progress(received, header, pb, sink, tracker.as_mut());
+            }
+        }
+        if throttle.skipped(received) {
+            report_progress(received, header, pb, sink, tracker.as_mut());
+        }
+        if let Some(verifier) = verifier {
+            if received == header.size {
+                verifier.finish().map_err(|err| chunk_mismatch(sink, err))?;
+            }
+        }
+        let trailer = if header.streaming {
+            let trailer = crypto::encrypted_read(reader, transport).await?;
+            let trailer = String::from_utf8(trailer).context("invalid UTF-8 in stream trailer")?;
+            Some(StreamTrailer::from_wire(&trailer).context("invalid stream trailer")?)
+        } else {
+            None
+        };
+        Ok((received, hasher, trailer, head))
+    };
+
+    // Failed writes are not retried: a file may report the error of an
+    // earlier write that already returned, and writing on would leave a
+    // hole. The `.part` file holds an intact prefix to resume from instead.
+    let dest = &mut *dest;
+    let write = async move {
+        while let Some(chunk) = rx.recv().await {
+            dest.write_all(&chunk).await.map_err(WriteFailed)?;
+        }
+        dest.flush().await.map_err(WriteFailed)?;
+        anyhow::Ok(())
+    };
+
+    let (read, write) = tokio::join!(read, write);
+    write?;
+    let (received, hasher, trailer, head) = read?;
+
+    if let Some(pb) = pb {
+        pb.finish_with_message("done");
+    }
+
+    let (expected_size, expected_hash) = match &trailer {
+        Some(trailer) => (trailer.size, trailer.blake3.as_str()),
+        None => (header.size, header.blake3.as_str()),
+    };
+    if received != expected_size {
+        bail!(
+            "Incomplete transfer: got {} of {} bytes",
+            received,
+            expected_size
+        );
+    }
+
+    let computed_hash = hasher.finalize().to_hex().to_string();
+    if computed_hash != expected_hash {
+        return Err(ChecksumMismatch {
+            expected: expected_hash.to_string(),
+            actual: computed_hash,
+        }
+        .into());
+    }
+    Ok((received, computed_hash, head))
+}
+
+/// A file directly in `dir` with `size` bytes and the hex blake3 `hash`.
+/// Only same-sized files are hashed, so most directories cost one listing.
+async fn find_duplicate<F: ReceiveFs>(
+    fs: &F,
+    dir: &Path,
+    size: u64,
+    hash: &str,
+) -> Option<PathBuf> {
+    let candidates = fs.files(dir).await.ok()?;
+    for (path, len) in candidates {
+        if len != size || path.extension().is_some_and(|ext| ext == "part") {
+            continue;
+        }
+        if fs
+            .blake3(&path)
+            .await
+            .is_ok_and(|candidate| candidate.eq_ignore_ascii_case(hash))
+        {
+            return Some(path);
+        }
+    }
+    None
+}
+
+/// Ask for the first `len` bytes of the file with [`HeaderAck::Preview`] and
+/// read them. The decision on the whole file is still owed to the sender.
+async fn request_preview<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    transport: &mut snow::TransportState,
+    header: &FileHeader,
+    len: u64,
+    sink: Option<&SharedSink>,
+) -> Result<Vec<u8>>
+where
+    R: tokio::io::AsyncRead + Unpin,
+    W: tokio::io::AsyncWrite + Unpin,
+{
+    let len = len.min(MAX_PREVIEW_LEN).min(header.size);
+    crypto::encrypted_write(writer, transport, &HeaderAck::Preview(len).to_wire()).await?;
+    let mut preview = Vec::with_capacity(len as usize);
+    while (preview.len() as u64) < len {
+        let frame = crypto::encrypted_read(reader, transport).await?;
+        if frame.is_empty() || (preview.len() + frame.len()) as u64 > len {
+            bail!("Sender sent a malformed preview");
+        }
+        preview.extend_from_slice(&frame);
+    }
+    status(sink, format!("Received a {} preview.", human_bytes(len)));
+    emit(
+        sink,
+        TransferEvent::Preview {
+            bytes: preview.clone(),
+        },
+    );
+    Ok(preview)
+}
+
+/// Shared file-reception logic used by all receive paths (iroh, TCP, listen).
+#[allow(clippy::too_many_arguments)]
+async fn receive_file<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    transport: &mut snow::TransportState,
+    output_dir: &Path,
+    peer: &PeerInfo,
+    options: &ReceiveOptions,
+    sink: Option<&SharedSink>,
+) -> Result<TransferCompleted>
+where
+    R: tokio::io::AsyncRead + Unpin,
+    W: tokio::io::AsyncWrite + Unpin,
+{
+    receive_file_with_fs(
+        reader, writer, transport, &RealFs, output_dir, peer, options, sink,
+    )
+    .await
+}
+
+/// [`receive_file`] against an explicit filesystem.
+#[allow(clippy::too_many_arguments)]
+async fn receive_file_with_fs<R, W, F>(
+    reader: &mut R,
+    writer: &mut W,
+    transport: &mut snow::TransportState,
+    fs: &F,
+    output_dir: &Path,
+    peer: &PeerInfo,
+    options: &ReceiveOptions,
+    sink: Option<&SharedSink>,
+) -> Result<TransferCompleted>
+where
+    R: tokio::io::AsyncRead + Unpin,
+    W: tokio::io::AsyncWrite + Unpin,
+    F: ReceiveFs,
+{
+    let sync_dir = options.sync.then_some(output_dir);
+    let header = read_header(reader, writer, transport, sync_dir, sink).await?;
+    let content_kind = header.content_kind.unwrap_or(TransferContentKind::File);
+    // Only merge archives built against the manifest this receiver sent.
+    let sync_dir = sync_dir.filter(|_| header.sync && content_kind == TransferContentKind::Bundle);
+    let item_count = header.item_count.unwrap_or(1).max(1);
+    // Sender-supplied names are untrusted: keep them to a single component
+    // that the local filesystem can actually create.
+    let safe_name = sanitize::sanitize_file_name(&header.name);
+    let completed_name = if content_kind == TransferContentKind::Bundle {
+        sanitize::sanitize_file_name(&bundle_logical_name(&header))
+    } else {
+        safe_name.clone()
+    };
+    let templated_name = options.output_template.as_ref().map(|template| {
+        sanitize::sanitize_file_name(&template.expand(&TemplateInput {
+            name: &completed_name,
+            sender: peer.node_id.map(|id| id.fmt_short()),
+            size: header.size,
+            now: OffsetDateTime::now_utc(),
+        }))
+    });
+    // Prefer the exact original bytes of a non-UTF-8 name where the local
+    // filesystem can represent them.
+    let local_name = match (&templated_name, content_kind) {
+        (Some(name), _) => OsString::from(name),
+        (None, TransferContentKind::File) => header
+            .raw_name_bytes()
+            .and_then(|raw| sanitize::raw_file_name(&raw))
+            .unwrap_or_else(|| OsString::from(&completed_name)),
+        (None, TransferContentKind::Bundle) => OsString::from(&completed_name),
+    };
+
+    if header.streaming && !options.accept_streaming {
+        crypto::encrypted_write(writer, transport, b"live files not accepted\n").await?;
+        return Err(Declined {
+            reason: format!("rejected: {} is a live file", safe_name),
+        }
+        .into());
+    }
+
+    eprintln!();
+    let incoming_status = if header.streaming {
+        format!("Incoming live file: {} (size unknown)", safe_name)
+    } else if content_kind == TransferContentKind::Bundle {
+        format!(
+            "Incoming files: {} ({} files, {})",
+            completed_name,
+            item_count,
+            human_bytes(header.size)
+        )
+    } else {
+        format!(
+            "Incoming file: {} ({})",
+            safe_name,
+            human_bytes(header.size)
+        )
+    };
+    status(sink, incoming_status);
 
-        if received != header.size {
-            bail!(
-                "Incomplete transfer: got {} of {} bytes",
-                received,
-                header.size
+    let mut output_dir = output_dir.to_path_buf();
+    if content_kind == TransferContentKind::File {
+        if let Some(route) = route::select(&options.routes, &completed_name) {
+            status(
+                sink,
+                format!(
+                    "Route '{}' matched: saving to {}",
+                    route.pattern(),
+                    route.dir().display()
+                ),
             );
+            output_dir = route.dir().to_path_buf();
+        }
+    }
+    let mut size_limit = None;
+    if let Some(policy) = &options.inbox {
+        let peer = peer.node_id.map(|id| id.to_string());
+        let decision = policy.evaluate(peer.as_deref(), &completed_name, header.size);
+        status(sink, decision.describe());
+        if decision.action == InboxAction::Reject {
+            crypto::encrypted_write(writer, transport, b"blocked by inbox policy\n").await?;
+            return Err(Declined {
+                reason: format!("rejected by inbox policy: {}", completed_name),
+            }
+            .into());
+        }
+        if let Some(subdir) = decision.subdir {
+            output_dir = output_dir.join(subdir);
+        }
+        size_limit = decision.max_size;
+    }
+    let output_dir = output_dir.as_path();
+
+    let preview = match options.preview {
+        Some(len)
+            if content_kind == TransferContentKind::File
+                && !header.streaming
+                && peer.supports(FEATURE_PREVIEW) =>
+        {
+            Some(request_preview(reader, writer, transport, &header, len, sink).await?)
         }
+        _ => None,
+    };
+
+    if let Some(confirm) = &options.confirm {
+        let offer = IncomingOffer {
+            file_name: completed_name.clone(),
+            size_bytes: header.size,
+            blake3: header.blake3.clone(),
+            content_kind,
+            item_count,
+            peer: peer.node_id.map(|id| id.to_string()),
+            connection_path: peer.connection_path.clone(),
+            preview,
+            streaming: header.streaming,
+        };
+        if !confirm.decide(offer).await? {
+            crypto::encrypted_write(writer, transport, b"declined by receiver\n").await?;
+            return Err(Declined {
+                reason: format!("declined: {}", completed_name),
+            }
+            .into());
+        }
+    }
+
+    // Only after the inbox and the user have accepted the file: answering
+    // before would tell any sender which content is already here. Older
+    // senders would report the skip as a rejection, so they get the whole
+    // file instead.
+    if options.dedup
+        && content_kind == TransferContentKind::File
+        && !header.streaming
+        && peer.supports(FEATURE_DEDUP)
+    {
+        if let Some(existing) = find_duplicate(fs, output_dir, header.size, &header.blake3).await {
+            status(sink, "Already have this file, skipping");
+            eprintln!("Existing copy: {}", existing.display());
+            crypto::encrypted_write(writer, transport, &HeaderAck::AlreadyHave.to_wire()).await?;
+            crypto::encrypted_write(writer, transport, b"DONE\n").await?;
+            let mime = route::detect_mime(&completed_name, &[]);
+            let completed = TransferCompleted {
+                file_name: existing
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or(completed_name),
+                size_bytes: header.size,
+                saved_path: Some(existing),
+                content_kind,
+                item_count,
+                mime,
+                compression_level: None,
+                blake3: Some(header.blake3.clone()),
+            };
+            emit(sink, TransferEvent::Completed(completed.clone()));
+            return Ok(completed);
+        }
+    }
 
-        let computed_hash = hasher.finalize().to_hex().to_string();
-        if computed_hash != header.blake3 {
-            bail!(
-                "Checksum mismatch!\n  expected: {}\n  got:      {}",
-                header.blake3,
-                computed_hash
+    let mut part_name = match content_kind {
+        TransferContentKind::File => local_name.clone(),
+        TransferContentKind::Bundle => OsString::from(&safe_name),
+    };
+    part_name.push(".part");
+    let max_attempts = options
+        .max_name_collisions
+        .unwrap_or(DEFAULT_MAX_NAME_COLLISIONS);
+    // Only whole single files can pick up where they stopped: a bundle's
+    // file boundaries are tracked from its first byte.
+    let resumable = content_kind == TransferContentKind::File
+        && !header.streaming
+        && options.encrypt_at_rest.is_none();
+    let resumed = if resumable && peer.supports(FEATURE_RESUME) {
+        find_partial(fs, &output_dir.join(&part_name), &header).await
+    } else {
+        None
+    };
+    let temp = match &resumed {
+        Some(_) => Ok(output_dir.join(&part_name)),
+        None => unique_path(fs, output_dir, &part_name, max_attempts),
+    };
+    let renamed = match (&options.rename, sync_dir) {
+        (Some(rename), None) => match rename.choose(&header, output_dir) {
+            Ok(renamed) => renamed,
+            Err(err) => {
+                emit(
+                    sink,
+                    TransferEvent::Error {
+                        code: "outside_output_dir".to_string(),
+                        message: err.to_string(),
+                        partial_path: None,
+                    },
+                );
+                crypto::encrypted_write(writer, transport, b"cannot write the file\n").await?;
+                return Err(err.into());
+            }
+        },
+        _ => None,
+    };
+    if let Some(parent) = renamed.as_deref().and_then(Path::parent) {
+        if let Err(err) = fs.create_dir_all(parent).await {
+            crypto::encrypted_write(writer, transport, b"cannot write the file\n").await?;
+            return Err(
+                anyhow::Error::new(err).context(format!("failed to create {}", parent.display()))
+            );
+        }
+    }
+    // Only names picked from the sender's are claimed below; sync and the
+    // rename handler choose theirs on purpose.
+    let claim_final = sync_dir.is_none() && renamed.is_none();
+    let destinations = temp.and_then(|temp| {
+        let dest = match (sync_dir, renamed) {
+            (Some(dir), _) => dir.to_path_buf(),
+            (None, Some(renamed)) => renamed,
+            (None, None) => unique_path(fs, output_dir, &local_name, max_attempts)?,
+        };
+        Ok((temp, dest))
+    });
+    let (mut temp_dest, mut final_dest) = match destinations {
+        Ok((temp, dest)) => (
+            sanitize::extended_length_path(&temp),
+            sanitize::extended_length_path(&dest),
+        ),
+        Err(err) => {
+            emit(
+                sink,
+                TransferEvent::Error {
+                    code: "too_many_collisions".to_string(),
+                    message: format!("{:#}", err),
+                    partial_path: None,
+                },
             );
+            crypto::encrypted_write(writer, transport, b"too many name collisions\n").await?;
+            return Err(err);
+        }
+    };
+
+    let ack = match &resumed {
+        Some(partial) => HeaderAck::Resume(partial.len),
+        None => HeaderAck::Accept,
+    };
+
+    let disk_retries = options
+        .disk_write_retries
+        .unwrap_or(DEFAULT_DISK_WRITE_RETRIES);
+    let mut retry = DiskRetry::new(disk_retries);
+    let mut claimed = false;
+    let opened = async {
+        fs.create_dir_all(output_dir).await?;
+        if claim_final {
+            claim_path(
+                fs,
+                output_dir,
+                &local_name,
+                content_kind,
+                &mut final_dest,
+                max_attempts,
+                &mut retry,
+            )
+            .await?;
+            claimed = true;
+        }
+        if resumed.is_none() {
+            claim_path(
+                fs,
+                output_dir,
+                &part_name,
+                TransferContentKind::File,
+                &mut temp_dest,
+                max_attempts,
+                &mut retry,
+            )
+            .await?;
+        }
+        loop {
+            match fs.append(&temp_dest).await {
+                Ok(file) => return Ok(file),
+                Err(err) if retry.retry(&err).await => status(sink, "Retrying disk write..."),
+                Err(err) => return Err(err),
+            }
+        }
+    }
+    .await;
+    let mut file = match opened {
+        Ok(file) => file,
+        Err(err) => {
+            if claimed {
+                release_claim(fs, &final_dest, content_kind).await;
+            }
+            crypto::encrypted_write(writer, transport, b"cannot write the file\n").await?;
+            return Err(err.into());
+        }
+    };
+
+    emit(
+        sink,
+        TransferEvent::TempFile {
+            path: temp_dest.clone(),
+        },
+    );
+    if content_kind == TransferContentKind::Bundle {
+        status(
+            sink,
+            format!("Saving extracted files to: {}", final_dest.display()),
+        );
+    } else {
+        status(sink, format!("Saving to: {}", final_dest.display()));
+    }
+    if let Some(partial) = &resumed {
+        status(
+            sink,
+            format!(
+                "Resuming after {} already received.",
+                human_bytes(partial.len)
+            ),
+        );
+    }
+    eprintln!();
+
+    // Acknowledge only once the `.part` file is open, so the sender's data
+    // never waits on a slow disk and the ack means the receiver is ready.
+    if let Err(err) = crypto::encrypted_write(writer, transport, &ack.to_wire()).await {
+        drop(file);
+        if resumed.is_none() {
+            let _ = fs.remove_file(&temp_dest).await;
+        }
+        if claimed {
+            release_claim(fs, &final_dest, content_kind).await;
         }
+        return Err(err);
+    }
+    let pb = if !events::presented(sink) {
+        Some(transfer_progress_bar(
+            (!header.streaming).then_some(header.size),
+            options.transfer.progress_interval,
+        ))
+    } else {
+        None
+    };
+    let mut size = header.size;
+    let mut verified_hash = String::new();
+    let mut head = Vec::new();
+    let receive_result: Result<(PathBuf, u64, u64)> = async {
+        let mut sealed;
+        let mut dest: &mut (dyn AsyncWrite + Unpin + Send) = match &options.encrypt_at_rest {
+            Some(key) => {
+                sealed = AtRestWriter::new(&mut file, key)?;
+                &mut sealed
+            }
+            None => &mut file,
+        };
+        let body = receive_body(
+            reader,
+            transport,
+            &mut dest,
+            &header,
+            options.transfer.stats.as_deref(),
+            sink,
+            pb.as_ref(),
+            options.transfer.progress_interval,
+            resumed,
+            size_limit,
+        );
+        (size, verified_hash, head) = tokio::select! {
+            biased;
+            _ = canceled(options.cancel.clone()) => return Err(Canceled.into()),
+            received = body => received?,
+        };
+        drop(file);
+        let received = match &options.encrypt_at_rest {
+            Some(key) => decrypt_part(fs, key, &temp_dest, size).await?,
+            None => temp_dest.clone(),
+        };
 
-        let final_count = if content_kind == TransferContentKind::Bundle {
-            let extracted = bundle::extract_bundle(&temp_dest, &final_dest).await?;
-            tokio::fs::remove_file(&temp_dest).await?;
-            extracted
+        let (final_count, failed_count) = if content_kind == TransferContentKind::Bundle {
+            let extracted = if header.zstd_archive {
+                bundle::extract_zstd_bundle(&received, &final_dest, options.keep_going).await?
+            } else {
+                bundle::extract_bundle(&received, &final_dest, options.keep_going).await?
+            };
+            fs.remove_file(&received).await?;
+            for failure in &extracted.failures {
+                status(
+                    sink,
+                    format!("Warning: skipping {}: {}", failure.name, failure.message),
+                );
+                emit(
+                    sink,
+                    TransferEvent::FileError {
+                        index: failure.index,
+                        name: failure.name.clone(),
+                        code: "write_failed".to_string(),
+                        message: failure.message.clone(),
+                    },
+                );
+            }
+            (extracted.item_count, extracted.failures.len() as u64)
         } else {
-            tokio::fs::rename(&temp_dest, &final_dest).await?;
-            1
+            fs.rename(&received, &final_dest).await?;
+            claimed = false;
+            let _ = fs.remove_file(&resume_sidecar(&temp_dest)).await;
+            (1, 0)
         };
         crypto::encrypted_write(writer, transport, b"DONE\n").await?;
-        Ok((final_dest.clone(), final_count))
+        Ok((final_dest.clone(), final_count, failed_count))
     }
     .await;
 
-    let (saved_path, completed_count) = match receive_result {
+    let (saved_path, completed_count, failed_count) = match receive_result {
         Ok(result) => result,
         Err(err) => {
-            let _ = tokio::fs::remove_file(&temp_dest).await;
-            if content_kind == TransferContentKind::Bundle {
-                let _ = tokio::fs::remove_dir_all(&final_dest).await;
+            let canceled = err.is::<Canceled>();
+            let lost = resumable
+                && options.transfer.reconnect_window.is_some()
+                && endpoint::is_connection_lost(&err);
+            let disk_full = err
+                .chain()
+                .filter_map(|cause| cause.downcast_ref::<std::io::Error>())
+                .any(receive_fs::is_disk_full);
+            let disk_blip = err
+                .chain()
+                .filter_map(|cause| cause.downcast_ref::<WriteFailed>())
+                .any(|failed| receive_fs::is_transient(&failed.0));
+            // A full disk is worth resuming from once space is freed, and a
+            // write that failed transiently once the disk answers again,
+            // when the receiver resumes at all.
+            let resume_disk = (disk_full || disk_blip)
+                && resumable
+                && (options.keep_partial || options.transfer.reconnect_window.is_some());
+            let kept = (canceled && options.keep_partial) || lost || resume_disk;
+            if kept {
+                let reason = if canceled {
+                    "Transfer canceled"
+                } else if disk_full {
+                    "Disk full; free some space and resume"
+                } else if disk_blip {
+                    "Disk write failed; resume once the disk is back"
+                } else {
+                    "Connection lost"
+                };
+                status(
+                    sink,
+                    format!(
+                        "{}; partial download kept at {}",
+                        reason,
+                        temp_dest.display()
+                    ),
+                );
+                if resumable {
+                    if let Err(err) = write_resume_sidecar(fs, &temp_dest, &header).await {
+                        status(
+                            sink,
+                            format!("Warning: the download cannot be resumed: {:#}", err),
+                        );
+                    }
+                }
+            } else {
+                let _ = fs.remove_file(&temp_dest).await;
+                let _ = fs.remove_file(&resume_sidecar(&temp_dest)).await;
+                if options.encrypt_at_rest.is_some() {
+                    let _ = fs.remove_file(&decrypted_part(&temp_dest)).await;
+                }
+                if content_kind == TransferContentKind::Bundle {
+                    let _ = fs.remove_dir_all(&final_dest).await;
+                }
+            }
+            if claimed && content_kind == TransferContentKind::File {
+                release_claim(fs, &final_dest, content_kind).await;
+            }
+            if canceled || disk_full {
+                emit(
+                    sink,
+                    TransferEvent::Error {
+                        code: if canceled { "canceled" } else { "disk_full" }.to_string(),
+                        message: err.to_string(),
+                        partial_path: kept.then(|| temp_dest.clone()),
+                    },
+                );
             }
             return Err(err);
         }
     };
 
+    // An extracted archive is reported like a received folder: the files
+    // now sit in the folder it was saved to.
+    let extracted = if options.extract && content_kind == TransferContentKind::File {
+        extract_received(&saved_path, max_attempts, sink).await
+    } else {
+        None
+    };
+    let (saved_path, content_kind, completed_count) = match extracted {
+        Some(files) => (
+            saved_path
+                .parent()
+                .map(Path::to_path_buf)
+                .unwrap_or(saved_path),
+            TransferContentKind::Bundle,
+            files.len() as u64,
+        ),
+        None => (saved_path, content_kind, completed_count),
+    };
+
+    if options.write_checksum && content_kind == TransferContentKind::File {
+        match write_checksum_sidecar(fs, &saved_path, &verified_hash, max_attempts).await {
+            Ok(sidecar) => status(sink, format!("Checksum written to: {}", sidecar.display())),
+            Err(err) => status(
+                sink,
+                format!("Warning: could not write checksum file: {:#}", err),
+            ),
+        }
+    }
+
     eprintln!();
-    let success_status = if content_kind == TransferContentKind::Bundle {
+    let success_status = if failed_count > 0 {
+        format!(
+            "Files received: {} ({} of {} files, {} failed, {})",
+            saved_path.display(),
+            completed_count,
+            completed_count + failed_count,
+            failed_count,
+            human_bytes(size)
+        )
+    } else if content_kind == TransferContentKind::Bundle {
         format!(
             "Files received successfully: {} ({} files, {})",
             saved_path.display(),
             completed_count,
-            human_bytes(header.size)
+            human_bytes(size)
         )
     } else {
         format!(
             "File received successfully: {} ({})",
             saved_path.display(),
-            human_bytes(header.size)
+            human_bytes(size)
         )
     };
     status(sink, success_status);
     status(sink, "Checksum verified (blake3).");
-    emit(
-        sink,
-        TransferEvent::Completed(TransferCompleted {
-            file_name: completed_name,
-            size_bytes: header.size,
-            saved_path: Some(saved_path),
-            content_kind,
-            item_count: completed_count,
-        }),
+    let mime = (content_kind == TransferContentKind::File)
+        .then(|| route::detect_mime(&completed_name, &head))
+        .flatten();
+    let completed = TransferCompleted {
+        file_name: completed_name,
+        size_bytes: size,
+        saved_path: Some(saved_path),
+        content_kind,
+        item_count: completed_count,
+        mime,
+        compression_level: header.compression_level,
+        blake3: Some(verified_hash),
+    };
+    emit(sink, TransferEvent::Completed(completed.clone()));
+
+    Ok(completed)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+    use std::sync::atomic::Ordering;
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    use tokio::sync::{watch, Notify};
+
+    use super::{
+        receive_bytes, receive_file_with_fs, unique_path, wait_until_idle, AcceptHandler, Canceled,
+        Declined, OutsideOutputDir, PeerAbandoned, PeerInfo, QueueCounts, ReceiveOptions,
+        RenameHandler, SequenceError, SharedSink, SourceUnavailable, TransferCompleted,
+    };
+    use crate::at_rest;
+    use crate::crypto::{self, HandshakePayload};
+    use crate::events::{TerminalSink, TransferEvent};
+    use crate::inbox::InboxPolicy;
+    use crate::options::TransferOptions;
+    use crate::protocol::{FileHeader, StreamTrailer, ALREADY_HAVE_ACK, CHUNK_SIZE};
+    use crate::receive_fs::{MemoryFs, RealFs, WriteFailed};
+    use crate::segments;
+    use crate::sender::{self, SendOptions};
+    use crate::stats::TransferStats;
+
+    type Duplex = tokio::io::DuplexStream;
+    type Halves = (
+        tokio::io::ReadHalf<Duplex>,
+        tokio::io::WriteHalf<Duplex>,
+        snow::TransportState,
     );
 
-    Ok(())
+    /// Connect both ends of an in-memory duplex through the Noise handshake,
+    /// returning the receiver (responder) and sender (initiator) halves.
+    async fn handshaken_pair() -> (Halves, Halves) {
+        let (left, right) = tokio::io::duplex(256 * 1024);
+        let (mut recv_read, mut recv_write) = tokio::io::split(left);
+        let (mut send_read, mut send_write) = tokio::io::split(right);
+
+        let payload = HandshakePayload::default();
+        let (receiver, sender) = tokio::join!(
+            crypto::handshake_responder(&mut recv_read, &mut recv_write, &[], &payload),
+            crypto::handshake_initiator(&mut send_read, &mut send_write, &[], &payload),
+        );
+        let (recv_transport, _, _) = receiver.expect("responder handshake");
+        let (send_transport, _, _) = sender.expect("initiator handshake");
+        (
+            (recv_read, recv_write, recv_transport),
+            (send_read, send_write, send_transport),
+        )
+    }
+
+    fn header_for(data: &[u8]) -> FileHeader {
+        FileHeader {
+            name: "notes.txt".to_string(),
+            size: data.len() as u64,
+            blake3: blake3::hash(data).to_hex().to_string(),
+            content_kind: None,
+            item_count: None,
+            logical_name: None,
+            name_raw: None,
+            streaming: false,
+            sequenced: false,
+            segment_size: None,
+            sync: false,
+            compression_level: None,
+            zstd_archive: false,
+        }
+    }
+
+    /// Play the sender side of one transfer of `data` and return the acks
+    /// the receiver sent back.
+    async fn play_sender(sender: &mut Halves, data: &[u8]) -> anyhow::Result<Vec<String>> {
+        let (send_read, send_write, send_transport) = sender;
+        let mut acks = Vec::new();
+        let wire = header_for(data).to_wire().expect("header");
+        crypto::encrypted_write(send_write, send_transport, &wire).await?;
+        let ack = crypto::encrypted_read(send_read, send_transport).await?;
+        acks.push(String::from_utf8_lossy(&ack).trim().to_string());
+        if acks[0] != "OK" {
+            return Ok(acks);
+        }
+        for chunk in data.chunks(CHUNK_SIZE) {
+            crypto::encrypted_write(send_write, send_transport, chunk).await?;
+        }
+        let done = crypto::encrypted_read(send_read, send_transport).await?;
+        acks.push(String::from_utf8_lossy(&done).trim().to_string());
+        Ok(acks)
+    }
+
+    /// Receive one transfer of `data` into memory without touching the
+    /// filesystem, together with the acks sent back.
+    async fn receive_into_memory(
+        data: &[u8],
+        max_size: u64,
+    ) -> (anyhow::Result<(FileHeader, bytes::Bytes)>, Vec<String>) {
+        let ((mut recv_read, mut recv_write, mut recv_transport), mut sender) =
+            handshaken_pair().await;
+        let receive = receive_bytes(
+            &mut recv_read,
+            &mut recv_write,
+            &mut recv_transport,
+            max_size,
+            None,
+        );
+
+        let (received, acks) = tokio::join!(receive, play_sender(&mut sender, data));
+        (received, acks.expect("sender side"))
+    }
+
+    /// Receive one transfer of `data` into `dir` on `fs`.
+    async fn receive_into_fs(
+        fs: &MemoryFs,
+        dir: &Path,
+        data: &[u8],
+        options: ReceiveOptions,
+    ) -> (anyhow::Result<TransferCompleted>, Vec<String>) {
+        receive_into_fs_with_sink(fs, dir, data, options, None).await
+    }
+
+    async fn receive_into_fs_with_sink(
+        fs: &MemoryFs,
+        dir: &Path,
+        data: &[u8],
+        options: ReceiveOptions,
+        sink: Option<&SharedSink>,
+    ) -> (anyhow::Result<TransferCompleted>, Vec<String>) {
+        let ((mut recv_read, mut recv_write, mut recv_transport), mut sender) =
+            handshaken_pair().await;
+        let peer = PeerInfo {
+            node_id: None,
+            connection_path: None,
+            hello: Some(HandshakePayload::local()),
+        };
+        // Owns the receiver halves so they drop, and the sender sees EOF,
+        // as soon as the receive fails.
+        let receive = async move {
+            receive_file_with_fs(
+                &mut recv_read,
+                &mut recv_write,
+                &mut recv_transport,
+                fs,
+                dir,
+                &peer,
+                &options,
+                sink,
+            )
+            .await
+        };
+
+        let (received, acks) = tokio::join!(receive, play_sender(&mut sender, data));
+        (received, acks.unwrap_or_default())
+    }
+
+    #[tokio::test]
+    async fn small_files_are_received_into_memory() {
+        let (received, acks) = receive_into_memory(b"preview me", 1024).await;
+        let (header, bytes) = received.expect("receive");
+        assert_eq!(header.name, "notes.txt");
+        assert_eq!(&bytes[..], b"preview me");
+        assert_eq!(acks, ["OK", "DONE"]);
+    }
+
+    #[tokio::test]
+    async fn multi_frame_bodies_keep_order_through_the_write_queue() {
+        let data: Vec<u8> = (0..CHUNK_SIZE * 40 + 123)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        let (received, acks) = receive_into_memory(&data, u64::MAX).await;
+        let (_, bytes) = received.expect("receive");
+        assert_eq!(bytes.len(), data.len());
+        assert!(bytes[..] == data[..]);
+        assert_eq!(acks, ["OK", "DONE"]);
+    }
+
+    #[tokio::test]
+    async fn oversized_files_are_rejected_before_streaming() {
+        let (received, acks) = receive_into_memory(&[7u8; 64], 16).await;
+        assert!(received.is_err());
+        assert_eq!(acks, ["too large"]);
+    }
+
+    #[tokio::test]
+    async fn a_corrupted_middle_segment_stops_the_transfer_early() {
+        let segment = segments::MIN_SEGMENT_SIZE as usize;
+        let data: Vec<u8> = (0..segment * 4).map(|i| (i % 251) as u8).collect();
+        let (_, hashes) = segments::hash_segments(&data[..], segment as u64).unwrap();
+        let mut corrupted = data.clone();
+        corrupted[segment + 100] ^= 0xff;
+
+        let ((mut recv_read, mut recv_write, mut recv_transport), sender) = handshaken_pair().await;
+        let (mut send_read, mut send_write, mut send_transport) = sender;
+        let codes = Arc::new(Mutex::new(Vec::new()));
+        let captured = codes.clone();
+        let sink: SharedSink = Arc::new(move |event| {
+            if let TransferEvent::Error { code, .. } = event {
+                captured.lock().unwrap().push(code);
+            }
+        });
+        let receive = receive_bytes(
+            &mut recv_read,
+            &mut recv_write,
+            &mut recv_transport,
+            u64::MAX,
+            Some(&sink),
+        );
+        let send = async {
+            let header = FileHeader {
+                segment_size: Some(segment as u64),
+                ..header_for(&data)
+            };
+            let wire = header.to_wire().expect("header");
+            crypto::encrypted_write(&mut send_write, &mut send_transport, &wire).await?;
+            crypto::encrypted_read(&mut send_read, &mut send_transport).await?;
+            for frame in segments::encode(&hashes) {
+                crypto::encrypted_write(&mut send_write, &mut send_transport, &frame).await?;
+            }
+            for chunk in corrupted.chunks(CHUNK_SIZE) {
+                crypto::encrypted_write(&mut send_write, &mut send_transport, chunk).await?;
+            }
+            anyhow::Ok(())
+        };
+
+        let (received, sent) = tokio::join!(receive, send);
+        sent.expect("sender side");
+        let err = received.unwrap_err();
+        let mismatch = err
+            .downcast_ref::<segments::SegmentMismatch>()
+            .expect("SegmentMismatch");
+        assert_eq!((mismatch.index, mismatch.offset), (1, segment as u64));
+        assert_eq!(*codes.lock().unwrap(), ["chunk_mismatch"]);
+    }
+
+    #[tokio::test]
+    async fn huge_segment_counts_are_rejected_without_allocating() {
+        let ((mut recv_read, mut recv_write, mut recv_transport), sender) = handshaken_pair().await;
+        let (mut send_read, mut send_write, mut send_transport) = sender;
+        let fs = MemoryFs::default();
+        let peer = PeerInfo {
+            node_id: None,
+            connection_path: None,
+            hello: Some(HandshakePayload::local()),
+        };
+        let options = ReceiveOptions::default();
+        let receive = receive_file_with_fs(
+            &mut recv_read,
+            &mut recv_write,
+            &mut recv_transport,
+            &fs,
+            Path::new("inbox"),
+            &peer,
+            &options,
+            None,
+        );
+        let send = async {
+            let header = FileHeader {
+                size: 1 << 62,
+                segment_size: Some(segments::MIN_SEGMENT_SIZE),
+                ..header_for(b"")
+            };
+            let wire = header.to_wire().expect("header");
+            crypto::encrypted_write(&mut send_write, &mut send_transport, &wire).await?;
+            crypto::encrypted_read(&mut send_read, &mut send_transport).await?;
+            anyhow::Ok(())
+        };
+
+        let (received, sent) = tokio::join!(receive, send);
+        sent.expect("sender side");
+        let err = received.unwrap_err();
+        assert!(err.to_string().contains("more than the"), "{err:#}");
+        assert!(fs.paths().is_empty(), "left behind: {:?}", fs.paths());
+    }
+
+    #[test]
+    fn unique_path_gives_up_after_max_attempts() {
+        let fs = MemoryFs::default();
+        let dir = PathBuf::from("inbox");
+        fs.insert(dir.join("a.txt"), b"");
+        for i in 1..=5 {
+            fs.insert(dir.join(format!("a ({i}).txt")), b"");
+        }
+
+        assert_eq!(
+            unique_path(&fs, &dir, "a.txt", 6).unwrap(),
+            dir.join("a (6).txt")
+        );
+        let err = unique_path(&fs, &dir, "a.txt", 5).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("too many files named like 'a.txt'"));
+    }
+
+    #[test]
+    fn unique_path_numbers_dotfiles_and_extensionless_names() {
+        let fs = MemoryFs::default();
+        let dir = PathBuf::from("inbox");
+        let cases = [
+            ("Makefile", "Makefile (1)"),
+            (".gitignore", ".gitignore (1)"),
+            (".env.local", ".env (1).local"),
+            ("archive.tar.gz", "archive.tar (1).gz"),
+            ("notes.", "notes. (1)"),
+        ];
+        for (name, _) in cases {
+            fs.insert(dir.join(name), b"");
+        }
+
+        for (name, expected) in cases {
+            assert_eq!(
+                unique_path(&fs, &dir, name, 3).unwrap(),
+                dir.join(expected),
+                "collision name for {name}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn colliding_names_get_a_numbered_copy() {
+        let fs = MemoryFs::default();
+        let dir = PathBuf::from("inbox");
+        fs.insert(dir.join("notes.txt"), b"existing");
+
+        let (received, acks) =
+            receive_into_fs(&fs, &dir, b"incoming", ReceiveOptions::default()).await;
+        let completed = received.expect("receive");
+        assert_eq!(acks, ["OK", "DONE"]);
+        assert_eq!(completed.saved_path, Some(dir.join("notes (1).txt")));
+        assert_eq!(fs.read(&dir.join("notes.txt")).unwrap(), b"existing");
+        assert_eq!(fs.read(&dir.join("notes (1).txt")).unwrap(), b"incoming");
+        assert_eq!(fs.paths().len(), 2, "no .part file left behind");
+    }
+
+    #[tokio::test]
+    async fn concurrent_transfers_of_one_name_do_not_overwrite_each_other() {
+        // Both pick their names before either creates the folder, so both
+        // pick `notes.txt`; only one may keep it.
+        let mut fs = MemoryFs::default();
+        fs.create_dir_delay = Some(Duration::from_millis(20));
+        let dir = PathBuf::from("inbox");
+
+        let ((first, _), (second, _)) = tokio::join!(
+            receive_into_fs(&fs, &dir, b"first", ReceiveOptions::default()),
+            receive_into_fs(&fs, &dir, b"second", ReceiveOptions::default()),
+        );
+        let first = first.expect("first").saved_path.expect("saved");
+        let second = second.expect("second").saved_path.expect("saved");
+        assert_ne!(first, second);
+        assert_eq!(fs.read(&first).unwrap(), b"first");
+        assert_eq!(fs.read(&second).unwrap(), b"second");
+        assert_eq!(fs.paths().len(), 2, "left behind: {:?}", fs.paths());
+    }
+
+    #[tokio::test]
+    async fn completed_files_carry_their_mime_type() {
+        let fs = MemoryFs::default();
+        let dir = PathBuf::from("inbox");
+        let (received, _) =
+            receive_into_fs(&fs, &dir, b"%PDF-1.7\n", ReceiveOptions::default()).await;
+        let completed = received.expect("receive");
+        // The header names the file notes.txt, so only sniffing sees a PDF.
+        let expected = if cfg!(feature = "mime-sniff") {
+            "application/pdf"
+        } else {
+            "text/plain"
+        };
+        assert_eq!(completed.mime.as_deref(), Some(expected));
+    }
+
+    #[tokio::test]
+    async fn output_template_names_the_file_and_still_dedupes() {
+        let fs = MemoryFs::default();
+        let dir = PathBuf::from("inbox");
+        fs.insert(dir.join("unknown_notes-8.txt"), b"existing");
+        let options = ReceiveOptions {
+            output_template: Some("{sender}/{stem}-{size}.{ext}".parse().unwrap()),
+            ..ReceiveOptions::default()
+        };
+
+        let (received, _) = receive_into_fs(&fs, &dir, b"incoming", options).await;
+        let completed = received.expect("receive");
+        assert_eq!(
+            completed.saved_path,
+            Some(dir.join("unknown_notes-8 (1).txt"))
+        );
+    }
+
+    #[tokio::test]
+    async fn stats_count_the_received_bytes() {
+        let fs = MemoryFs::default();
+        let stats = Arc::new(TransferStats::new());
+        let options = ReceiveOptions {
+            transfer: TransferOptions::builder().stats(stats.clone()).build(),
+            ..ReceiveOptions::default()
+        };
+        let data = vec![7u8; CHUNK_SIZE * 2 + 10];
+
+        let (received, _) = receive_into_fs(&fs, Path::new("inbox"), &data, options).await;
+        received.expect("receive");
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.bytes_transferred, data.len() as u64);
+        assert_eq!(snapshot.bytes_total, data.len() as u64);
+        assert!(snapshot.updated_at_ms >= snapshot.started_at_ms);
+    }
+
+    /// Send `data` as a live file announcing no size, then a trailer
+    /// claiming the size and hash of `claimed`.
+    async fn receive_stream(
+        fs: &MemoryFs,
+        data: &[u8],
+        claimed: &[u8],
+        options: &ReceiveOptions,
+    ) -> anyhow::Result<TransferCompleted> {
+        let ((mut recv_read, mut recv_write, mut recv_transport), sender) = handshaken_pair().await;
+        let (mut send_read, mut send_write, mut send_transport) = sender;
+        let peer = PeerInfo {
+            node_id: None,
+            connection_path: None,
+            hello: None,
+        };
+        let receive = receive_file_with_fs(
+            &mut recv_read,
+            &mut recv_write,
+            &mut recv_transport,
+            fs,
+            Path::new("inbox"),
+            &peer,
+            options,
+            None,
+        );
+        let send = async {
+            let header = FileHeader {
+                size: 0,
+                blake3: String::new(),
+                streaming: true,
+                ..header_for(b"")
+            };
+            let wire = header.to_wire()?;
+            crypto::encrypted_write(&mut send_write, &mut send_transport, &wire).await?;
+            crypto::encrypted_read(&mut send_read, &mut send_transport).await?;
+            for chunk in data.chunks(CHUNK_SIZE) {
+                crypto::encrypted_write(&mut send_write, &mut send_transport, chunk).await?;
+            }
+            crypto::encrypted_write(&mut send_write, &mut send_transport, &[]).await?;
+            let trailer = StreamTrailer {
+                size: claimed.len() as u64,
+                blake3: blake3::hash(claimed).to_hex().to_string(),
+            }
+            .to_wire()?;
+            crypto::encrypted_write(&mut send_write, &mut send_transport, &trailer).await?;
+            anyhow::Ok(())
+        };
+        let (received, _) = tokio::join!(receive, send);
+        received
+    }
+
+    #[tokio::test]
+    async fn streamed_files_are_checked_against_the_trailer() {
+        let data = vec![3u8; CHUNK_SIZE + 77];
+        let options = ReceiveOptions {
+            accept_streaming: true,
+            ..ReceiveOptions::default()
+        };
+        for (claimed, accepted) in [(&data[..], true), (&b"other"[..], false)] {
+            let fs = MemoryFs::default();
+            let received = receive_stream(&fs, &data, claimed, &options).await;
+            if accepted {
+                let completed = received.expect("receive");
+                assert_eq!(completed.size_bytes, data.len() as u64);
+                assert_eq!(
+                    fs.read(Path::new("inbox/notes.txt")).as_deref(),
+                    Some(&data[..])
+                );
+            } else {
+                assert!(received.is_err());
+                assert!(fs.paths().is_empty(), "left behind: {:?}", fs.paths());
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn streams_need_consent_and_keep_to_the_inbox_size_limit() {
+        let data = vec![3u8; CHUNK_SIZE + 77];
+
+        let fs = MemoryFs::default();
+        let err = receive_stream(&fs, &data, &data, &ReceiveOptions::default())
+            .await
+            .expect_err("live files are declined by default");
+        assert!(err.is::<Declined>(), "{err:#}");
+        assert!(fs.paths().is_empty(), "left behind: {:?}", fs.paths());
+
+        let offers = Arc::new(Mutex::new(Vec::new()));
+        let seen = offers.clone();
+        let policy = r#"{ "rules": [{ "action": "accept", "max_size": 1000 }] }"#;
+        let options = ReceiveOptions {
+            accept_streaming: true,
+            inbox: Some(InboxPolicy::from_json(policy).expect("policy")),
+            confirm: Some(AcceptHandler::new(move |offer| {
+                seen.lock().unwrap().push(offer.streaming);
+                true
+            })),
+            ..ReceiveOptions::default()
+        };
+        let fs = MemoryFs::default();
+        let err = receive_stream(&fs, &data, &data, &options)
+            .await
+            .expect_err("the stream outgrew the rule");
+        assert!(err.is::<Declined>(), "{err:#}");
+        assert!(err.to_string().contains("inbox limit"), "{err}");
+        assert_eq!(*offers.lock().unwrap(), [true]);
+        assert!(fs.paths().is_empty(), "left behind: {:?}", fs.paths());
+    }
+
+    #[tokio::test]
+    async fn listen_queues_only_go_idle_between_transfers() {
+        let counts = QueueCounts::default();
+        let activity = Notify::new();
+        let limit = Some(Duration::from_millis(40));
+
+        counts.active.store(1, Ordering::Relaxed);
+        let busy = tokio::time::timeout(
+            Duration::from_millis(150),
+            wait_until_idle(limit, &counts, &activity),
+        );
+        assert!(busy.await.is_err(), "went idle during a transfer");
+
+        counts.active.store(0, Ordering::Relaxed);
+        activity.notify_one();
+        let started = Instant::now();
+        tokio::time::timeout(
+            Duration::from_secs(2),
+            wait_until_idle(limit, &counts, &activity),
+        )
+        .await
+        .expect("idle after the transfer");
+        assert!(started.elapsed() >= Duration::from_millis(40));
+
+        let never = tokio::time::timeout(
+            Duration::from_millis(100),
+            wait_until_idle(None, &counts, &activity),
+        );
+        assert!(never.await.is_err());
+    }
+
+    #[tokio::test]
+    async fn dedup_skips_content_the_inbox_already_has() {
+        let fs = MemoryFs::default();
+        let dir = PathBuf::from("inbox");
+        fs.insert(dir.join("renamed.txt"), b"same bytes");
+        fs.insert(dir.join("other.txt"), b"diff bytes");
+        let options = ReceiveOptions {
+            dedup: true,
+            ..ReceiveOptions::default()
+        };
+
+        let (received, acks) = receive_into_fs(&fs, &dir, b"same bytes", options.clone()).await;
+        let completed = received.expect("skipped");
+        assert_eq!(completed.saved_path, Some(dir.join("renamed.txt")));
+        assert_eq!(acks, [ALREADY_HAVE_ACK]);
+        assert_eq!(fs.paths().len(), 2, "{:?}", fs.paths());
+
+        let (received, acks) = receive_into_fs(&fs, &dir, b"new  bytes", options.clone()).await;
+        received.expect("receive");
+        assert_eq!(acks, ["OK", "DONE"]);
+        assert_eq!(fs.read(&dir.join("notes.txt")).unwrap(), b"new  bytes");
+
+        // A declined offer must not learn that the content is here.
+        let options = ReceiveOptions {
+            confirm: Some(AcceptHandler::new(|_| false)),
+            ..options
+        };
+        let (received, _) = receive_into_fs(&fs, &dir, b"same bytes", options).await;
+        assert!(received.expect_err("declined").is::<Declined>());
+    }
+
+    #[tokio::test]
+    async fn checksum_sidecars_sit_next_to_the_file_without_overwriting() {
+        let fs = MemoryFs::default();
+        let dir = PathBuf::from("inbox");
+        fs.insert(dir.join("notes.txt.blake3"), b"stale");
+        let options = ReceiveOptions {
+            write_checksum: true,
+            ..ReceiveOptions::default()
+        };
+
+        let (received, _) = receive_into_fs(&fs, &dir, b"hash me", options).await;
+        received.expect("receive");
+        let sidecar = fs
+            .read(&dir.join("notes.txt (1).blake3"))
+            .expect("numbered sidecar");
+        let expected = format!("{}  notes.txt\n", blake3::hash(b"hash me").to_hex());
+        assert_eq!(String::from_utf8(sidecar).unwrap(), expected);
+        assert_eq!(fs.read(&dir.join("notes.txt.blake3")).unwrap(), b"stale");
+    }
+
+    #[tokio::test]
+    async fn failed_rename_removes_the_part_file() {
+        let mut fs = MemoryFs::default();
+        fs.fail_rename = true;
+        let dir = PathBuf::from("inbox");
+
+        let (received, _) =
+            receive_into_fs(&fs, &dir, b"incoming", ReceiveOptions::default()).await;
+        assert!(received.is_err());
+        assert!(fs.paths().is_empty(), "left behind: {:?}", fs.paths());
+    }
+
+    /// Receive `data` with the cancel signal already raised, returning the
+    /// result and the `partial_path` of the `canceled` error event.
+    async fn receive_canceled(
+        fs: &MemoryFs,
+        keep_partial: bool,
+    ) -> (anyhow::Result<TransferCompleted>, Option<PathBuf>) {
+        let (_cancel_tx, cancel) = watch::channel(true);
+        let options = ReceiveOptions {
+            cancel: Some(cancel),
+            keep_partial,
+            ..ReceiveOptions::default()
+        };
+        let errors = Arc::new(Mutex::new(Vec::new()));
+        let captured = errors.clone();
+        let sink: SharedSink = Arc::new(move |event| {
+            if let TransferEvent::Error {
+                code, partial_path, ..
+            } = event
+            {
+                captured.lock().unwrap().push((code, partial_path));
+            }
+        });
+
+        let (received, _) =
+            receive_into_fs_with_sink(fs, Path::new("inbox"), b"incoming", options, Some(&sink))
+                .await;
+        let errors = errors.lock().unwrap().clone();
+        assert_eq!(errors.len(), 1, "{errors:?}");
+        assert_eq!(errors[0].0, "canceled");
+        (received, errors[0].1.clone())
+    }
+
+    /// Every event of a receive into `fs`, ended by a [`TerminalSink`] the
+    /// way the CLI and Android wrappers end a run.
+    async fn receive_to_the_end(fs: &MemoryFs, options: ReceiveOptions) -> Vec<TransferEvent> {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let captured = events.clone();
+        let terminal = Arc::new(TerminalSink::new(Arc::new(move |event: TransferEvent| {
+            captured.lock().unwrap().push(event)
+        })));
+        let sink: SharedSink = terminal.clone();
+        let (received, _) =
+            receive_into_fs_with_sink(fs, Path::new("inbox"), b"incoming", options, Some(&sink))
+                .await;
+        terminal.finish(&received);
+        let events = events.lock().unwrap().clone();
+        assert_eq!(
+            events.iter().filter(|event| event.is_terminal()).count(),
+            1,
+            "{events:?}"
+        );
+        assert!(events.last().is_some_and(TransferEvent::is_terminal));
+        events
+    }
+
+    #[tokio::test]
+    async fn every_receive_ends_with_exactly_one_terminal_event() {
+        let events = receive_to_the_end(&MemoryFs::default(), ReceiveOptions::default()).await;
+        assert!(matches!(events.last(), Some(TransferEvent::Completed(_))));
+
+        let mut failing = MemoryFs::default();
+        failing.fail_rename = true;
+        let events = receive_to_the_end(&failing, ReceiveOptions::default()).await;
+        assert!(
+            matches!(events.last(), Some(TransferEvent::Error { code, .. }) if code == "transfer_error"),
+            "{events:?}"
+        );
+
+        let (_cancel_tx, cancel) = watch::channel(true);
+        let options = ReceiveOptions {
+            cancel: Some(cancel),
+            ..ReceiveOptions::default()
+        };
+        let events = receive_to_the_end(&MemoryFs::default(), options).await;
+        assert!(
+            matches!(events.last(), Some(TransferEvent::Error { code, .. }) if code == "canceled"),
+            "{events:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn senders_that_leave_before_the_header_are_reported() {
+        let fs = MemoryFs::default();
+        let ((mut recv_read, mut recv_write, mut recv_transport), sender) = handshaken_pair().await;
+        drop(sender);
+        let codes = Arc::new(Mutex::new(Vec::new()));
+        let captured = codes.clone();
+        let sink: SharedSink = Arc::new(move |event| {
+            if let TransferEvent::Error { code, .. } = event {
+                captured.lock().unwrap().push(code);
+            }
+        });
+        let peer = PeerInfo {
+            node_id: None,
+            connection_path: None,
+            hello: None,
+        };
+
+        let received = tokio::time::timeout(
+            Duration::from_secs(5),
+            receive_file_with_fs(
+                &mut recv_read,
+                &mut recv_write,
+                &mut recv_transport,
+                &fs,
+                Path::new("inbox"),
+                &peer,
+                &ReceiveOptions::default(),
+                Some(&sink),
+            ),
+        )
+        .await
+        .expect("detected promptly");
+        assert!(received.unwrap_err().is::<PeerAbandoned>());
+        assert_eq!(*codes.lock().unwrap(), ["peer_abandoned"]);
+        assert!(fs.paths().is_empty());
+    }
+
+    #[tokio::test]
+    async fn senders_that_lost_the_file_abort_instead_of_a_header() {
+        let (
+            (mut recv_read, mut recv_write, mut recv_transport),
+            (_, mut send_write, mut send_transport),
+        ) = handshaken_pair().await;
+        crypto::encrypted_write(
+            &mut send_write,
+            &mut send_transport,
+            b"ABORT No such file or directory (os error 2)\n",
+        )
+        .await
+        .expect("abort line");
+
+        let fs = MemoryFs::default();
+        let codes = Arc::new(Mutex::new(Vec::new()));
+        let captured = codes.clone();
+        let sink: SharedSink = Arc::new(move |event| {
+            if let TransferEvent::Error { code, .. } = event {
+                captured.lock().unwrap().push(code);
+            }
+        });
+        let peer = PeerInfo {
+            node_id: None,
+            connection_path: None,
+            hello: None,
+        };
+        let received = receive_file_with_fs(
+            &mut recv_read,
+            &mut recv_write,
+            &mut recv_transport,
+            &fs,
+            Path::new("inbox"),
+            &peer,
+            &ReceiveOptions::default(),
+            Some(&sink),
+        )
+        .await;
+        let err = received.unwrap_err();
+        let err = err
+            .downcast_ref::<SourceUnavailable>()
+            .expect("SourceUnavailable");
+        assert_eq!(err.reason, "No such file or directory (os error 2)");
+        assert_eq!(*codes.lock().unwrap(), ["source_unavailable"]);
+        assert!(fs.paths().is_empty());
+    }
+
+    #[tokio::test]
+    async fn canceled_receives_remove_the_part_file_without_resume() {
+        let fs = MemoryFs::default();
+        let (received, partial) = receive_canceled(&fs, false).await;
+        assert!(received.unwrap_err().is::<Canceled>());
+        assert_eq!(partial, None);
+        assert!(fs.paths().is_empty(), "left behind: {:?}", fs.paths());
+    }
+
+    /// Receive into `fs` with writes past 8 bytes failing with `write_error`
+    /// (a full disk by default), returning the result and the error events.
+    async fn receive_onto_failing_disk(
+        keep_partial: bool,
+        write_error: Option<std::io::ErrorKind>,
+    ) -> (
+        MemoryFs,
+        anyhow::Result<TransferCompleted>,
+        Vec<(String, Option<PathBuf>)>,
+    ) {
+        let mut fs = MemoryFs::default();
+        fs.max_file_len = Some(8);
+        fs.write_error = write_error;
+        let options = ReceiveOptions {
+            keep_partial,
+            ..ReceiveOptions::default()
+        };
+        let errors = Arc::new(Mutex::new(Vec::new()));
+        let captured = errors.clone();
+        let sink: SharedSink = Arc::new(move |event| {
+            if let TransferEvent::Error {
+                code, partial_path, ..
+            } = event
+            {
+                captured.lock().unwrap().push((code, partial_path));
+            }
+        });
+        let (received, _) = receive_into_fs_with_sink(
+            &fs,
+            Path::new("inbox"),
+            b"more than eight bytes",
+            options,
+            Some(&sink),
+        )
+        .await;
+        let errors = errors.lock().unwrap().clone();
+        (fs, received, errors)
+    }
+
+    #[tokio::test]
+    async fn a_full_disk_is_reported_and_cleaned_up_without_resume() {
+        let (fs, received, errors) = receive_onto_failing_disk(false, None).await;
+        assert!(received.is_err());
+        assert_eq!(errors, [("disk_full".to_string(), None)]);
+        assert!(fs.paths().is_empty(), "left behind: {:?}", fs.paths());
+    }
+
+    #[tokio::test]
+    async fn a_full_disk_keeps_the_part_file_for_resume() {
+        let (fs, received, errors) = receive_onto_failing_disk(true, None).await;
+        assert!(received.is_err());
+        assert_eq!(errors.len(), 1, "{errors:?}");
+        assert_eq!(errors[0].0, "disk_full");
+        let partial = errors[0].1.clone().expect("partial path");
+        assert_eq!(fs.read(&partial).unwrap(), b"more tha");
+    }
+
+    #[tokio::test]
+    async fn a_transient_write_error_keeps_the_part_file_for_resume() {
+        let (fs, received, _) =
+            receive_onto_failing_disk(true, Some(std::io::ErrorKind::TimedOut)).await;
+        assert!(received.unwrap_err().is::<WriteFailed>());
+        let parts: Vec<_> = fs
+            .paths()
+            .into_iter()
+            .filter(|path| path.to_string_lossy().ends_with(".part"))
+            .collect();
+        assert_eq!(parts.len(), 1, "{:?}", fs.paths());
+        assert_eq!(fs.read(&parts[0]).unwrap(), b"more tha");
+
+        let (fs, received, _) =
+            receive_onto_failing_disk(false, Some(std::io::ErrorKind::TimedOut)).await;
+        assert!(received.is_err());
+        assert!(fs.paths().is_empty(), "left behind: {:?}", fs.paths());
+    }
+
+    #[tokio::test]
+    async fn canceled_receives_keep_the_part_file_for_resume() {
+        let fs = MemoryFs::default();
+        let (received, partial) = receive_canceled(&fs, true).await;
+        assert!(received.unwrap_err().is::<Canceled>());
+        let partial = partial.expect("partial path");
+        assert!(partial.to_string_lossy().ends_with(".part"), "{partial:?}");
+        assert_eq!(
+            fs.paths(),
+            [partial.clone(), super::resume_sidecar(&partial)]
+        );
+    }
+
+    /// Send `data` with the real sender into `dir` on `fs`, returning the
+    /// receive result and the sender's progress events.
+    async fn send_to_fs(
+        fs: &MemoryFs,
+        dir: &Path,
+        data: &bytes::Bytes,
+        options: ReceiveOptions,
+        sink: Option<&SharedSink>,
+    ) -> (anyhow::Result<TransferCompleted>, Vec<u64>) {
+        let ((mut recv_read, mut recv_write, mut recv_transport), sender_halves) =
+            handshaken_pair().await;
+        let (mut send_read, mut send_write, mut send_transport) = sender_halves;
+        let peer = PeerInfo {
+            node_id: None,
+            connection_path: None,
+            hello: Some(HandshakePayload::local()),
+        };
+        let receive = async move {
+            receive_file_with_fs(
+                &mut recv_read,
+                &mut recv_write,
+                &mut recv_transport,
+                fs,
+                dir,
+                &peer,
+                &options,
+                sink,
+            )
+            .await
+        };
+
+        let progress = Arc::new(Mutex::new(Vec::new()));
+        let captured = progress.clone();
+        let sender_sink: SharedSink = Arc::new(move |event| {
+            if let TransferEvent::Progress { done, .. } = event {
+                captured.lock().unwrap().push(done);
+            }
+        });
+        let prepared = sender::prepare_send_bytes("notes.txt", data.clone())
+            .await
+            .expect("prepare");
+        let send = async move {
+            sender::send_prepared(
+                &mut send_read,
+                &mut send_write,
+                &mut send_transport,
+                &prepared,
+                Some(&HandshakePayload::local()),
+                &SendOptions::default(),
+                Some(&sender_sink),
+            )
+            .await?;
+            crypto::encrypted_read(&mut send_read, &mut send_transport).await
+        };
+
+        let (received, _) = tokio::join!(receive, send);
+        let progress = progress.lock().unwrap().clone();
+        (received, progress)
+    }
+
+    #[tokio::test]
+    async fn rename_handlers_pick_destinations_inside_the_output_dir() {
+        let data = bytes::Bytes::from_static(b"content addressed");
+        let hash = blake3::hash(&data).to_hex().to_string();
+        let fs = MemoryFs::default();
+        let options = ReceiveOptions {
+            rename: Some(RenameHandler::new(|header| {
+                Some(
+                    Path::new("store")
+                        .join(&header.blake3[..2])
+                        .join(&header.blake3),
+                )
+            })),
+            ..ReceiveOptions::default()
+        };
+        let (received, _) = send_to_fs(&fs, Path::new("inbox"), &data, options, None).await;
+        let expected = Path::new("inbox/store").join(&hash[..2]).join(&hash);
+        assert_eq!(
+            received.expect("renamed receive").saved_path.as_deref(),
+            Some(expected.as_path())
+        );
+        assert_eq!(fs.read(&expected).unwrap(), data);
+
+        let options = ReceiveOptions {
+            rename: Some(RenameHandler::new(|_| None)),
+            ..ReceiveOptions::default()
+        };
+        let (received, _) = send_to_fs(&fs, Path::new("inbox"), &data, options, None).await;
+        received.expect("default name");
+        assert_eq!(fs.read(Path::new("inbox/notes.txt")).unwrap(), data);
+
+        for outside in ["/etc/notes.txt", "../notes.txt", ""] {
+            let fs = MemoryFs::default();
+            let options = ReceiveOptions {
+                rename: Some(RenameHandler::new(move |_| Some(PathBuf::from(outside)))),
+                ..ReceiveOptions::default()
+            };
+            let (received, _) = send_to_fs(&fs, Path::new("inbox"), &data, options, None).await;
+            let err = received.unwrap_err();
+            assert!(err.is::<OutsideOutputDir>(), "{outside}: {err:#}");
+            assert!(fs.paths().is_empty(), "{outside}");
+        }
+    }
+
+    #[tokio::test]
+    async fn senders_wait_for_the_part_file_and_give_up_on_a_stalled_disk() {
+        let data = bytes::Bytes::from_static(b"written to a slow mount");
+        let slow = Duration::from_millis(300);
+
+        // The ack only goes out once the directory exists and the `.part`
+        // file is open.
+        let mut fs = MemoryFs::default();
+        fs.create_dir_delay = Some(slow);
+        let started = Instant::now();
+        let (received, _) = send_to_fs(
+            &fs,
+            Path::new("inbox"),
+            &data,
+            ReceiveOptions::default(),
+            None,
+        )
+        .await;
+        received.expect("patient receive");
+        assert!(started.elapsed() >= slow);
+        assert_eq!(fs.read(Path::new("inbox/notes.txt")).unwrap(), data);
+
+        // A sender with a shorter acknowledgement timeout stops waiting.
+        let mut fs = MemoryFs::default();
+        fs.create_dir_delay = Some(slow);
+        let ((mut recv_read, mut recv_write, mut recv_transport), sender) = handshaken_pair().await;
+        let peer = PeerInfo {
+            node_id: None,
+            connection_path: None,
+            hello: Some(HandshakePayload::local()),
+        };
+        let options = ReceiveOptions::default();
+        let receive = receive_file_with_fs(
+            &mut recv_read,
+            &mut recv_write,
+            &mut recv_transport,
+            &fs,
+            Path::new("inbox"),
+            &peer,
+            &options,
+            None,
+        );
+        let codes = Arc::new(Mutex::new(Vec::new()));
+        let captured = codes.clone();
+        let sink: SharedSink = Arc::new(move |event| {
+            if let TransferEvent::Error { code, .. } = event {
+                captured.lock().unwrap().push(code);
+            }
+        });
+        let prepared = sender::prepare_send_bytes("notes.txt", data.clone())
+            .await
+            .expect("prepare");
+        let send_options = SendOptions {
+            ack_timeout: Some(Duration::from_millis(50)),
+            ..SendOptions::default()
+        };
+        let send = async move {
+            let (mut send_read, mut send_write, mut send_transport) = sender;
+            sender::send_prepared(
+                &mut send_read,
+                &mut send_write,
+                &mut send_transport,
+                &prepared,
+                Some(&HandshakePayload::local()),
+                &send_options,
+                Some(&sink),
+            )
+            .await
+        };
+        let (received, sent) = tokio::join!(receive, send);
+        let err = sent.unwrap_err();
+        assert!(err.is::<sender::AckTimeout>(), "{err:#}");
+        assert_eq!(*codes.lock().unwrap(), ["ack_timeout"]);
+        assert!(received.is_err());
+    }
+
+    #[tokio::test]
+    async fn previews_arrive_before_the_file_is_accepted() {
+        let fs = MemoryFs::default();
+        let data: bytes::Bytes = (0..20 * 1024)
+            .map(|i| (i % 251) as u8)
+            .collect::<Vec<_>>()
+            .into();
+        let offered = Arc::new(Mutex::new(None));
+        let seen = offered.clone();
+        let options = ReceiveOptions {
+            preview: Some(4096),
+            confirm: Some(AcceptHandler::new(move |offer| {
+                *seen.lock().unwrap() = offer.preview.clone();
+                true
+            })),
+            ..ReceiveOptions::default()
+        };
+        let previews = Arc::new(Mutex::new(Vec::new()));
+        let captured = previews.clone();
+        let sink: SharedSink = Arc::new(move |event| {
+            if let TransferEvent::Preview { bytes } = event {
+                captured.lock().unwrap().push(bytes);
+            }
+        });
+
+        let (received, _) = send_to_fs(&fs, Path::new("inbox"), &data, options, Some(&sink)).await;
+        received.expect("receive after preview");
+        assert_eq!(*previews.lock().unwrap(), [data[..4096].to_vec()]);
+        assert_eq!(offered.lock().unwrap().as_deref(), Some(&data[..4096]));
+        assert_eq!(fs.read(Path::new("inbox/notes.txt")).unwrap(), data);
+    }
+
+    #[tokio::test]
+    async fn interrupted_transfers_resume_from_the_kept_part_file() {
+        let fs = MemoryFs::default();
+        let dir = Path::new("inbox");
+        let data: bytes::Bytes = (0..16 * CHUNK_SIZE)
+            .map(|i| (i.wrapping_mul(31) % 251) as u8)
+            .collect::<Vec<_>>()
+            .into();
+        let total = data.len() as u64;
+
+        // Cancel once half of the file has arrived, keeping the part file.
+        let (cancel_tx, cancel) = watch::channel(false);
+        let halfway: SharedSink = Arc::new(move |event| {
+            if let TransferEvent::Progress { done, .. } = event {
+                if done * 2 >= total {
+                    let _ = cancel_tx.send(true);
+                }
+            }
+        });
+        let options = ReceiveOptions {
+            cancel: Some(cancel),
+            keep_partial: true,
+            ..ReceiveOptions::default()
+        };
+        let (received, _) = send_to_fs(&fs, dir, &data, options, Some(&halfway)).await;
+        assert!(received.unwrap_err().is::<Canceled>());
+        let part = dir.join("notes.txt.part");
+        let kept = fs.read(&part).expect("part file").len() as u64;
+        assert!(kept > 0 && kept < total, "kept {kept} of {total}");
+        assert_eq!(data[..kept as usize], fs.read(&part).unwrap()[..]);
+
+        let (received, progress) =
+            send_to_fs(&fs, dir, &data, ReceiveOptions::default(), None).await;
+        let completed = received.expect("resumed receive");
+        let saved = dir.join("notes.txt");
+        assert_eq!(completed.saved_path.as_deref(), Some(saved.as_path()));
+        assert_eq!(completed.size_bytes, total);
+        assert!(progress[0] > kept, "{progress:?}");
+        assert_eq!(progress.last(), Some(&total));
+        let saved_data = fs.read(&saved).expect("saved file");
+        assert_eq!(blake3::hash(&saved_data), blake3::hash(&data));
+        assert_eq!(fs.paths(), [saved]);
+    }
+
+    #[tokio::test]
+    async fn encrypted_part_files_never_hold_the_plaintext() {
+        let fs = MemoryFs::default();
+        let dir = Path::new("inbox");
+        let data: bytes::Bytes = (0..16 * CHUNK_SIZE)
+            .map(|i| (i.wrapping_mul(31) % 251) as u8)
+            .collect::<Vec<_>>()
+            .into();
+        let total = data.len() as u64;
+        let key = at_rest::test_key("hunter2");
+
+        let options = ReceiveOptions {
+            encrypt_at_rest: Some(key.clone()),
+            ..ReceiveOptions::default()
+        };
+        let (received, _) = send_to_fs(&fs, dir, &data, options, None).await;
+        let completed = received.expect("encrypted receive");
+        let saved = dir.join("notes.txt");
+        assert_eq!(completed.saved_path.as_deref(), Some(saved.as_path()));
+        assert_eq!(fs.read(&saved).expect("saved file"), data);
+        assert_eq!(fs.paths(), std::slice::from_ref(&saved));
+
+        // A canceled receive keeps only ciphertext, and no resume sidecar.
+        let (cancel_tx, cancel) = watch::channel(false);
+        let halfway: SharedSink = Arc::new(move |event| {
+            if let TransferEvent::Progress { done, .. } = event {
+                if done * 2 >= total {
+                    let _ = cancel_tx.send(true);
+                }
+            }
+        });
+        let options = ReceiveOptions {
+            cancel: Some(cancel),
+            keep_partial: true,
+            encrypt_at_rest: Some(key),
+            ..ReceiveOptions::default()
+        };
+        let (received, _) = send_to_fs(&fs, dir, &data, options, Some(&halfway)).await;
+        assert!(received.unwrap_err().is::<Canceled>());
+        let part = dir.join("notes.txt.part");
+        let kept = fs.read(&part).expect("part file");
+        assert!(kept.len() > CHUNK_SIZE, "kept {} bytes", kept.len());
+        assert!(kept.starts_with(b"P2PSREST"));
+        assert!(!kept.windows(64).any(|window| window == &data[..64]));
+        assert_eq!(fs.paths(), [saved, part]);
+    }
+
+    #[tokio::test]
+    async fn a_file_as_the_output_dir_fails_before_connecting() {
+        let dir = std::env::temp_dir().join(format!("p2p-share-output-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("foo.txt");
+        std::fs::write(&file, b"keep me").unwrap();
+
+        let codes = Arc::new(Mutex::new(Vec::new()));
+        let captured = codes.clone();
+        let sink: SharedSink = Arc::new(move |event| {
+            if let TransferEvent::Error { code, .. } = event {
+                captured.lock().unwrap().push(code);
+            }
+        });
+        // Nothing listens on the target: the check must fail first.
+        let err = super::run_with_options(
+            "127.0.0.1:9",
+            &file,
+            Some(sink.clone()),
+            &ReceiveOptions::default(),
+        )
+        .await
+        .unwrap_err();
+        assert!(err.to_string().contains("not a directory"), "{err:#}");
+        assert_eq!(*codes.lock().unwrap(), ["output_not_dir"]);
+        assert_eq!(std::fs::read(&file).unwrap(), b"keep me");
+
+        // A missing directory is created, then the bad ticket is reported.
+        let missing = dir.join("new").join("inbox");
+        let err = super::run_with_options(
+            "p2psh:not-a-ticket",
+            &missing,
+            Some(sink),
+            &ReceiveOptions::default(),
+        )
+        .await
+        .unwrap_err();
+        assert!(!err.to_string().contains("Cannot save"), "{err:#}");
+        assert!(missing.is_dir());
+        assert_eq!(codes.lock().unwrap().len(), 1);
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn a_lost_connection_keeps_the_part_file_inside_the_reconnect_window() {
+        let fs = MemoryFs::default();
+        let dir = Path::new("inbox");
+        let data: bytes::Bytes = (0..8 * CHUNK_SIZE)
+            .map(|i| (i.wrapping_mul(7) % 251) as u8)
+            .collect::<Vec<_>>()
+            .into();
+        let options = ReceiveOptions {
+            transfer: TransferOptions::builder()
+                .reconnect_window(Some(Duration::from_secs(60)))
+                .build(),
+            ..ReceiveOptions::default()
+        };
+
+        // The sender vanishes after three chunks.
+        let ((mut recv_read, mut recv_write, mut recv_transport), sender) = handshaken_pair().await;
+        let peer = PeerInfo {
+            node_id: None,
+            connection_path: None,
+            hello: Some(HandshakePayload::local()),
+        };
+        let receive = receive_file_with_fs(
+            &mut recv_read,
+            &mut recv_write,
+            &mut recv_transport,
+            &fs,
+            dir,
+            &peer,
+            &options,
+            None,
+        );
+        let sent = data.clone();
+        let vanish = async move {
+            let data = sent;
+            let (mut send_read, mut send_write, mut send_transport) = sender;
+            let wire = header_for(&data[..]).to_wire().unwrap();
+            crypto::encrypted_write(&mut send_write, &mut send_transport, &wire)
+                .await
+                .unwrap();
+            crypto::encrypted_read(&mut send_read, &mut send_transport)
+                .await
+                .unwrap();
+            for chunk in data.chunks(CHUNK_SIZE).take(3) {
+                crypto::encrypted_write(&mut send_write, &mut send_transport, chunk)
+                    .await
+                    .unwrap();
+            }
+        };
+        let (received, ()) = tokio::join!(receive, vanish);
+        let err = received.unwrap_err();
+        assert!(crate::endpoint::is_connection_lost(&err), "{err:#}");
+        let part = dir.join("notes.txt.part");
+        assert_eq!(fs.read(&part).expect("kept part").len(), 3 * CHUNK_SIZE);
+        assert!(fs.read(&part.with_extension("part.resume")).is_some());
+
+        let (received, progress) = send_to_fs(&fs, dir, &data, options, None).await;
+        received.expect("resumed receive");
+        assert!(progress[0] > 3 * CHUNK_SIZE as u64, "{progress:?}");
+        let saved = dir.join("notes.txt");
+        assert_eq!(fs.read(&saved).expect("saved file"), data);
+        assert_eq!(fs.paths(), [saved]);
+    }
+
+    #[tokio::test]
+    async fn sync_sends_only_new_and_changed_files() {
+        let root = std::env::temp_dir().join(format!("p2p-share-sync-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let source = root.join("src/photos");
+        let inbox = root.join("inbox");
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::create_dir_all(inbox.join("photos")).unwrap();
+        std::fs::write(source.join("same.jpg"), b"unchanged").unwrap();
+        std::fs::write(source.join("edited.jpg"), b"new edit").unwrap();
+        std::fs::write(source.join("added.jpg"), b"added").unwrap();
+        std::fs::write(inbox.join("photos/same.jpg"), b"unchanged").unwrap();
+        std::fs::write(inbox.join("photos/edited.jpg"), b"old edit").unwrap();
+
+        let send_options = SendOptions {
+            sync: true,
+            ..SendOptions::default()
+        };
+        let prepared =
+            sender::prepare_send_paths(std::slice::from_ref(&source), &send_options, None)
+                .await
+                .expect("prepare");
+        let skipped = Arc::new(Mutex::new(Vec::new()));
+        let captured = skipped.clone();
+        let sink: SharedSink = Arc::new(move |event| {
+            if let TransferEvent::FileSkipped { name } = event {
+                captured.lock().unwrap().push(name);
+            }
+        });
+        let peer = PeerInfo {
+            node_id: None,
+            connection_path: None,
+            hello: Some(HandshakePayload::local()),
+        };
+        let options = ReceiveOptions {
+            sync: true,
+            ..ReceiveOptions::default()
+        };
+        let ((mut recv_read, mut recv_write, mut recv_transport), sender) = handshaken_pair().await;
+        let (mut send_read, mut send_write, mut send_transport) = sender;
+        let receive = receive_file_with_fs(
+            &mut recv_read,
+            &mut recv_write,
+            &mut recv_transport,
+            &RealFs,
+            &inbox,
+            &peer,
+            &options,
+            None,
+        );
+        let send = async {
+            sender::send_prepared(
+                &mut send_read,
+                &mut send_write,
+                &mut send_transport,
+                &prepared,
+                Some(&HandshakePayload::local()),
+                &send_options,
+                Some(&sink),
+            )
+            .await?;
+            crypto::encrypted_read(&mut send_read, &mut send_transport).await
+        };
+        let (received, sent) = tokio::join!(receive, send);
+        prepared.cleanup().await;
+        let completed = received.expect("sync receive");
+        sent.expect("DONE");
+
+        assert_eq!(completed.saved_path.as_deref(), Some(inbox.as_path()));
+        assert_eq!(completed.item_count, 2);
+        assert_eq!(*skipped.lock().unwrap(), ["photos/same.jpg"]);
+        assert_eq!(
+            std::fs::read(inbox.join("photos/edited.jpg")).unwrap(),
+            b"new edit"
+        );
+        assert_eq!(
+            std::fs::read(inbox.join("photos/added.jpg")).unwrap(),
+            b"added"
+        );
+        let entries: Vec<_> = std::fs::read_dir(&inbox)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(entries, ["photos"]);
+
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[tokio::test]
+    async fn sequenced_chunks_are_checked_as_they_arrive() {
+        let data: bytes::Bytes = (0..3 * CHUNK_SIZE + 17)
+            .map(|i| (i % 251) as u8)
+            .collect::<Vec<_>>()
+            .into();
+        let peer = PeerInfo {
+            node_id: None,
+            connection_path: None,
+            hello: Some(HandshakePayload::local()),
+        };
+        let options = ReceiveOptions::default();
+
+        // A sequencing sender and a receiver that announces support.
+        let fs = MemoryFs::default();
+        let ((mut recv_read, mut recv_write, mut recv_transport), sender) = handshaken_pair().await;
+        let (mut send_read, mut send_write, mut send_transport) = sender;
+        let prepared = sender::prepare_send_bytes("notes.txt", data.clone())
+            .await
+            .expect("prepare");
+        let send_options = SendOptions {
+            sequence_chunks: true,
+            ..SendOptions::default()
+        };
+        let receive = receive_file_with_fs(
+            &mut recv_read,
+            &mut recv_write,
+            &mut recv_transport,
+            &fs,
+            Path::new("inbox"),
+            &peer,
+            &options,
+            None,
+        );
+        let send = async {
+            sender::send_prepared(
+                &mut send_read,
+                &mut send_write,
+                &mut send_transport,
+                &prepared,
+                Some(&HandshakePayload::local()),
+                &send_options,
+                None,
+            )
+            .await?;
+            crypto::encrypted_read(&mut send_read, &mut send_transport).await
+        };
+        let (received, sent) = tokio::join!(receive, send);
+        received.expect("sequenced receive");
+        sent.expect("DONE");
+        assert_eq!(fs.read(Path::new("inbox/notes.txt")).unwrap(), data);
+
+        // A frame that skips ahead fails the transfer straight away.
+        let fs = MemoryFs::default();
+        let ((mut recv_read, mut recv_write, mut recv_transport), sender) = handshaken_pair().await;
+        let codes = Arc::new(Mutex::new(Vec::new()));
+        let captured = codes.clone();
+        let sink: SharedSink = Arc::new(move |event| {
+            if let TransferEvent::Error { code, .. } = event {
+                captured.lock().unwrap().push(code);
+            }
+        });
+        let receive = receive_file_with_fs(
+            &mut recv_read,
+            &mut recv_write,
+            &mut recv_transport,
+            &fs,
+            Path::new("inbox"),
+            &peer,
+            &options,
+            Some(&sink),
+        );
+        let misorder = async move {
+            let (mut send_read, mut send_write, mut send_transport) = sender;
+            let header = FileHeader {
+                sequenced: true,
+                segment_size: None,
+                ..header_for(&data[..])
+            };
+            let wire = header.to_wire().unwrap();
+            crypto::encrypted_write(&mut send_write, &mut send_transport, &wire).await?;
+            crypto::encrypted_read(&mut send_read, &mut send_transport).await?;
+            for (index, chunk) in [0u64, 2].into_iter().zip(data.chunks(CHUNK_SIZE)) {
+                let frame = [&index.to_le_bytes()[..], chunk].concat();
+                crypto::encrypted_write(&mut send_write, &mut send_transport, &frame).await?;
+            }
+            anyhow::Ok((send_read, send_write))
+        };
+        let (received, _halves) = tokio::join!(receive, misorder);
+        let err = received.unwrap_err();
+        let sequence = err.downcast_ref::<SequenceError>().expect("sequence error");
+        assert_eq!((sequence.expected, sequence.got), (1, Some(2)));
+        assert_eq!(*codes.lock().unwrap(), ["sequence_error"]);
+        assert!(fs.paths().is_empty());
+    }
+
+    #[cfg(feature = "compression")]
+    #[tokio::test]
+    async fn compression_is_used_only_when_the_receiver_announces_it() {
+        let data: bytes::Bytes = b"the quick brown fox jumps over the lazy dog\n"
+            .iter()
+            .copied()
+            .cycle()
+            .take(3 * CHUNK_SIZE + 17)
+            .collect::<Vec<_>>()
+            .into();
+        let peer = PeerInfo {
+            node_id: None,
+            connection_path: None,
+            hello: Some(HandshakePayload::local()),
+        };
+        let options = ReceiveOptions::default();
+        let send_options = SendOptions {
+            compress_level: Some(3),
+            sequence_chunks: true,
+            ..SendOptions::default()
+        };
+
+        // The receiver's hello as the sender saw it: with the compression
+        // bit, and as from a build without it.
+        let without = HandshakePayload {
+            features: HandshakePayload::local().features & !crypto::FEATURE_COMPRESSION,
+            ..HandshakePayload::local()
+        };
+        for (hello, expected) in [(HandshakePayload::local(), Some(3)), (without, None)] {
+            let fs = MemoryFs::default();
+            let ((mut recv_read, mut recv_write, mut recv_transport), sender) =
+                handshaken_pair().await;
+            let (mut send_read, mut send_write, mut send_transport) = sender;
+            let prepared = sender::prepare_send_bytes("notes.txt", data.clone())
+                .await
+                .expect("prepare");
+            let receive = receive_file_with_fs(
+                &mut recv_read,
+                &mut recv_write,
+                &mut recv_transport,
+                &fs,
+                Path::new("inbox"),
+                &peer,
+                &options,
+                None,
+            );
+            let send = async {
+                sender::send_prepared(
+                    &mut send_read,
+                    &mut send_write,
+                    &mut send_transport,
+                    &prepared,
+                    Some(&hello),
+                    &send_options,
+                    None,
+                )
+                .await?;
+                crypto::encrypted_read(&mut send_read, &mut send_transport).await
+            };
+            let (received, sent) = tokio::join!(receive, send);
+            let completed = received.expect("receive");
+            sent.expect("DONE");
+            assert_eq!(completed.compression_level, expected);
+            assert_eq!(fs.read(Path::new("inbox/notes.txt")).unwrap(), data);
+        }
+    }
+
+    #[cfg(feature = "compression")]
+    #[tokio::test]
+    async fn compressed_archives_are_extracted() {
+        let root = std::env::temp_dir().join(format!("p2p-share-zstd-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let source = root.join("src/logs");
+        std::fs::create_dir_all(source.join("old")).unwrap();
+        let line = b"GET /index.html 200\n".repeat(500);
+        for name in ["a.log", "b.log", "old/c.log"] {
+            std::fs::write(source.join(name), &line).unwrap();
+        }
+
+        let send_options = SendOptions {
+            archive_level: Some(3),
+            ..SendOptions::default()
+        };
+        let prepared =
+            sender::prepare_send_paths(std::slice::from_ref(&source), &send_options, None)
+                .await
+                .expect("prepare");
+        let peer = PeerInfo {
+            node_id: None,
+            connection_path: None,
+            hello: Some(HandshakePayload::local()),
+        };
+        let options = ReceiveOptions::default();
+        let inbox = root.join("inbox");
+        let ((mut recv_read, mut recv_write, mut recv_transport), sender) = handshaken_pair().await;
+        let (mut send_read, mut send_write, mut send_transport) = sender;
+        let receive = receive_file_with_fs(
+            &mut recv_read,
+            &mut recv_write,
+            &mut recv_transport,
+            &RealFs,
+            &inbox,
+            &peer,
+            &options,
+            None,
+        );
+        let send = async {
+            sender::send_prepared(
+                &mut send_read,
+                &mut send_write,
+                &mut send_transport,
+                &prepared,
+                Some(&HandshakePayload::local()),
+                &send_options,
+                None,
+            )
+            .await?;
+            crypto::encrypted_read(&mut send_read, &mut send_transport).await
+        };
+        let (received, sent) = tokio::join!(receive, send);
+        prepared.cleanup().await;
+        let completed = received.expect("receive");
+        sent.expect("DONE");
+
+        assert_eq!(completed.item_count, 3);
+        assert!(
+            completed.size_bytes < line.len() as u64,
+            "{} bytes on the wire",
+            completed.size_bytes
+        );
+        let saved = completed.saved_path.expect("saved path");
+        for name in ["a.log", "b.log", "old/c.log"] {
+            assert_eq!(std::fs::read(saved.join("logs").join(name)).unwrap(), line);
+        }
+
+        let _ = std::fs::remove_dir_all(&root);
+    }
 }
@@ -0,0 +1,274 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::{bail, Result};
+
+use crate::paths;
+
+/// Send received files to a directory picked by type, from a `PATTERN=DIR`
+/// entry such as `pdf=/srv/docs` or `image/*=~/Pictures`.
+///
+/// `PATTERN` is a file extension, a MIME type, or a MIME type with a `*`
+/// subtype. MIME types are looked up from the extension: routes are picked
+/// from the header, before any data has arrived to sniff. A leading `~` in
+/// `DIR` is the home directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Route {
+    pattern: String,
+    dir: PathBuf,
+}
+
+impl Route {
+    pub fn pattern(&self) -> &str {
+        &self.pattern
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn matches(&self, extension: &str, mime: Option<&str>) -> bool {
+        match self.pattern.split_once('/') {
+            None => self.pattern == extension,
+            Some((kind, "*")) => mime.is_some_and(|mime| {
+                mime.split_once('/')
+                    .is_some_and(|(mime_kind, _)| mime_kind == kind)
+            }),
+            Some(_) => mime == Some(self.pattern.as_str()),
+        }
+    }
+}
+
+/// The first route matching `file_name`, if any.
+pub fn select<'a>(routes: &'a [Route], file_name: &str) -> Option<&'a Route> {
+    let extension = Path::new(file_name)
+        .extension()?
+        .to_string_lossy()
+        .to_ascii_lowercase();
+    let mime = mime_type(&extension);
+    routes.iter().find(|route| route.matches(&extension, mime))
+}
+
+/// MIME type for a lowercase file extension, for the common types people
+/// route. Unknown extensions only match extension patterns.
+pub fn mime_type(extension: &str) -> Option<&'static str> {
+    Some(match extension {
+        "jpg" | "jpeg" => "image/jpeg",
+        "png" => "image/png",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "heic" => "image/heic",
+        "bmp" => "image/bmp",
+        "tif" | "tiff" => "image/tiff",
+        "svg" => "image/svg+xml",
+        "mp4" | "m4v" => "video/mp4",
+        "mov" => "video/quicktime",
+        "mkv" => "video/x-matroska",
+        "webm" => "video/webm",
+        "avi" => "video/x-msvideo",
+        "mp3" => "audio/mpeg",
+        "m4a" => "audio/mp4",
+        "aac" => "audio/aac",
+        "flac" => "audio/flac",
+        "ogg" | "opus" => "audio/ogg",
+        "wav" => "audio/wav",
+        "txt" => "text/plain",
+        "md" => "text/markdown",
+        "csv" => "text/csv",
+        "html" | "htm" => "text/html",
+        "pdf" => "application/pdf",
+        "doc" => "application/msword",
+        "docx" => "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+        "xlsx" => "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+        "pptx" => "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+        "odt" => "application/vnd.oasis.opendocument.text",
+        "rtf" => "application/rtf",
+        "epub" => "application/epub+zip",
+        "zip" => "application/zip",
+        "tar" => "application/x-tar",
+        "gz" => "application/gzip",
+        "7z" => "application/x-7z-compressed",
+        "apk" => "application/vnd.android.package-archive",
+        "json" => "application/json",
+        _ => return None,
+    })
+}
+
+/// Leading bytes of a received file kept for [`detect_mime`]; enough to
+/// reach the `ustar` magic of a tar header.
+pub const MIME_SNIFF_LEN: usize = 512;
+
+/// MIME type of a received file. With the `mime-sniff` feature the first
+/// bytes of the content are checked against known signatures; otherwise,
+/// or when none matches, the type comes from the extension of `file_name`.
+pub fn detect_mime(file_name: &str, head: &[u8]) -> Option<String> {
+    let by_extension = Path::new(file_name)
+        .extension()
+        .and_then(|ext| mime_type(&ext.to_string_lossy().to_ascii_lowercase()));
+    #[cfg(feature = "mime-sniff")]
+    if let Some(sniffed) = sniff_mime(head) {
+        // Office documents, EPUBs and APKs are zip archives underneath;
+        // their extension is more specific than the signature.
+        if sniffed != "application/zip" || by_extension.is_none() {
+            return Some(sniffed.to_string());
+        }
+    }
+    #[cfg(not(feature = "mime-sniff"))]
+    let _ = head;
+    by_extension.map(str::to_string)
+}
+
+/// MIME type from the magic number at the start of `head`.
+#[cfg(feature = "mime-sniff")]
+fn sniff_mime(head: &[u8]) -> Option<&'static str> {
+    let at = |offset: usize, magic: &[u8]| head.get(offset..offset + magic.len()) == Some(magic);
+    Some(if at(0, b"\xFF\xD8\xFF") {
+        "image/jpeg"
+    } else if at(0, b"\x89PNG\r\n\x1a\n") {
+        "image/png"
+    } else if at(0, b"GIF87a") || at(0, b"GIF89a") {
+        "image/gif"
+    } else if at(0, b"RIFF") && at(8, b"WEBP") {
+        "image/webp"
+    } else if at(0, b"RIFF") && at(8, b"WAVE") {
+        "audio/wav"
+    } else if at(0, b"RIFF") && at(8, b"AVI ") {
+        "video/x-msvideo"
+    } else if at(0, b"II*\0") || at(0, b"MM\0*") {
+        "image/tiff"
+    } else if at(4, b"ftypheic") || at(4, b"ftypheix") || at(4, b"ftypmif1") {
+        "image/heic"
+    } else if at(4, b"ftypqt  ") {
+        "video/quicktime"
+    } else if at(4, b"ftypM4A ") {
+        "audio/mp4"
+    } else if at(4, b"ftyp") {
+        "video/mp4"
+    } else if at(0, b"\x1A\x45\xDF\xA3") {
+        "video/x-matroska"
+    } else if at(0, b"ID3") || at(0, b"\xFF\xFB") {
+        "audio/mpeg"
+    } else if at(0, b"fLaC") {
+        "audio/flac"
+    } else if at(0, b"OggS") {
+        "audio/ogg"
+    } else if at(0, b"%PDF-") {
+        "application/pdf"
+    } else if at(0, b"PK\x03\x04") {
+        "application/zip"
+    } else if at(0, b"\x1F\x8B") {
+        "application/gzip"
+    } else if at(0, b"7z\xBC\xAF\x27\x1C") {
+        "application/x-7z-compressed"
+    } else if at(257, b"ustar") {
+        "application/x-tar"
+    } else {
+        return None;
+    })
+}
+
+impl FromStr for Route {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let Some((pattern, dir)) = s.split_once('=') else {
+            bail!(
+                "route '{}' must look like PATTERN=DIR, e.g. jpg=~/Pictures",
+                s
+            );
+        };
+        let pattern = pattern.trim().trim_start_matches('.').to_ascii_lowercase();
+        if pattern.is_empty() || pattern.starts_with('/') || pattern.ends_with('/') {
+            bail!("route '{}' has no extension or MIME type before '='", s);
+        }
+        if dir.trim().is_empty() {
+            bail!("route '{}' has no directory after '='", s);
+        }
+        Ok(Self {
+            pattern,
+            dir: paths::expand_home(dir.trim()),
+        })
+    }
+}
+
+impl fmt::Display for Route {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.pattern, self.dir.display())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{detect_mime, select, Route};
+
+    fn routes(entries: &[&str]) -> Vec<Route> {
+        entries.iter().map(|entry| entry.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn first_matching_route_wins() {
+        let routes = routes(&[
+            ".PDF=/srv/docs",
+            "image/*=/srv/pictures",
+            "image/png=/srv/png",
+        ]);
+        assert_eq!(
+            select(&routes, "report.pdf").map(Route::dir),
+            Some(Path::new("/srv/docs"))
+        );
+        assert_eq!(
+            select(&routes, "cat.PNG").map(Route::pattern),
+            Some("image/*")
+        );
+        assert!(select(&routes, "notes.txt").is_none());
+        assert!(select(&routes, "README").is_none());
+    }
+
+    #[test]
+    fn exact_mime_types_match_their_extensions() {
+        let routes = routes(&["audio/mpeg=/music"]);
+        assert!(select(&routes, "song.mp3").is_some());
+        assert!(select(&routes, "song.flac").is_none());
+    }
+
+    #[test]
+    fn mime_falls_back_to_the_extension() {
+        assert_eq!(detect_mime("Photo.JPG", b"").as_deref(), Some("image/jpeg"));
+        assert_eq!(detect_mime("README", b""), None);
+    }
+
+    #[cfg(feature = "mime-sniff")]
+    #[test]
+    fn mime_is_sniffed_from_the_content() {
+        assert_eq!(
+            detect_mime("upload.bin", b"\x89PNG\r\n\x1a\n....").as_deref(),
+            Some("image/png")
+        );
+        assert_eq!(
+            detect_mime("scan", b"%PDF-1.7\n").as_deref(),
+            Some("application/pdf")
+        );
+        // A zip signature defers to a more specific extension.
+        assert_eq!(
+            detect_mime("book.epub", b"PK\x03\x04").as_deref(),
+            Some("application/epub+zip")
+        );
+        assert_eq!(
+            detect_mime("archive", b"PK\x03\x04").as_deref(),
+            Some("application/zip")
+        );
+    }
+
+    #[test]
+    fn malformed_routes_are_rejected() {
+        assert!("jpg".parse::<Route>().is_err());
+        assert!("=/srv".parse::<Route>().is_err());
+        assert!("jpg=".parse::<Route>().is_err());
+        assert!("/=/srv".parse::<Route>().is_err());
+        let route: Route = "jpg=/srv/photos=old".parse().unwrap();
+        assert_eq!(route.dir(), Path::new("/srv/photos=old"));
+    }
+}
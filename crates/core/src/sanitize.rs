@@ -0,0 +1,205 @@
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+/// Name used when a sender-supplied name sanitizes down to nothing.
+const FALLBACK_NAME: &str = "unnamed";
+
+/// Device names that Windows reserves in every directory, with or without
+/// an extension (`CON`, `con.txt`, ...).
+const WINDOWS_RESERVED: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM0", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7",
+    "COM8", "COM9", "LPT0", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Paths at or above this length need the `\\?\` prefix on Windows.
+#[cfg(windows)]
+const WINDOWS_MAX_PATH: usize = 260;
+
+/// Turn a sender-supplied file name into a single safe path component for
+/// the local platform. Path separators are always replaced; on Windows the
+/// reserved device names, illegal characters and trailing dots/spaces are
+/// rewritten as well.
+pub fn sanitize_file_name(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| {
+            if matches!(c, '/' | '\\' | '\0') {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect();
+
+    #[cfg(windows)]
+    let name = sanitize_windows_name(&name);
+
+    let trimmed = name.trim();
+    if trimmed.is_empty() || trimmed == "." || trimmed == ".." {
+        return FALLBACK_NAME.to_string();
+    }
+    name
+}
+
+/// Rebuild a file name from the raw bytes a sender supplied for a non-UTF-8
+/// name. Only Unix can create arbitrary byte names; elsewhere, and when the
+/// bytes sanitize down to nothing, `None` tells the caller to fall back to
+/// the display name.
+pub fn raw_file_name(raw: &[u8]) -> Option<OsString> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStringExt;
+
+        let bytes: Vec<u8> = raw
+            .iter()
+            .map(|&b| {
+                if matches!(b, b'/' | b'\\' | 0) {
+                    b'_'
+                } else {
+                    b
+                }
+            })
+            .collect();
+        if bytes.is_empty() || bytes == b"." || bytes == b".." {
+            return None;
+        }
+        Some(OsString::from_vec(bytes))
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = raw;
+        None
+    }
+}
+
+/// Windows-specific rewriting, kept platform-independent so it can be tested
+/// everywhere.
+pub fn sanitize_windows_name(name: &str) -> String {
+    let mut out: String = name
+        .chars()
+        .map(|c| match c {
+            '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*' => '_',
+            c if (c as u32) < 0x20 => '_',
+            c => c,
+        })
+        .collect();
+
+    // Windows silently strips trailing dots and spaces, which would make the
+    // created file differ from the name we checked for collisions.
+    let kept = out.trim_end_matches(['.', ' ']).len();
+    out.truncate(kept);
+    if out.is_empty() {
+        return FALLBACK_NAME.to_string();
+    }
+
+    let stem_len = out.find('.').unwrap_or(out.len());
+    let stem = &out[..stem_len];
+    if WINDOWS_RESERVED
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(stem.trim_end()))
+    {
+        out.insert(stem_len, '_');
+    }
+    out
+}
+
+/// Return a path that can exceed `MAX_PATH` on Windows by converting it to
+/// the extended-length `\\?\` form. A no-op elsewhere, and for paths that
+/// are short enough or already prefixed.
+pub fn extended_length_path(path: &Path) -> PathBuf {
+    #[cfg(windows)]
+    {
+        let raw = path.as_os_str().to_string_lossy();
+        if raw.len() < WINDOWS_MAX_PATH || raw.starts_with(r"\\?\") {
+            return path.to_path_buf();
+        }
+        let Ok(absolute) = std::path::absolute(path) else {
+            return path.to_path_buf();
+        };
+        let absolute = absolute.to_string_lossy();
+        if let Some(unc) = absolute.strip_prefix(r"\\") {
+            PathBuf::from(format!(r"\\?\UNC\{}", unc))
+        } else {
+            PathBuf::from(format!(r"\\?\{}", absolute))
+        }
+    }
+    #[cfg(not(windows))]
+    {
+        path.to_path_buf()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{extended_length_path, raw_file_name, sanitize_file_name, sanitize_windows_name};
+    use std::path::Path;
+
+    #[test]
+    fn separators_and_dot_names_are_neutralized() {
+        assert_eq!(sanitize_file_name("../../etc/passwd"), ".._.._etc_passwd");
+        assert_eq!(sanitize_file_name(".."), "unnamed");
+        assert_eq!(sanitize_file_name("  "), "unnamed");
+        assert_eq!(sanitize_file_name("report.pdf"), "report.pdf");
+    }
+
+    #[test]
+    fn windows_reserved_names_get_suffix() {
+        assert_eq!(sanitize_windows_name("CON"), "CON_");
+        assert_eq!(sanitize_windows_name("con.txt"), "con_.txt");
+        assert_eq!(sanitize_windows_name("Lpt9.tar.gz"), "Lpt9_.tar.gz");
+        assert_eq!(sanitize_windows_name("com0.txt"), "com0_.txt");
+        assert_eq!(sanitize_windows_name("LPT0"), "LPT0_");
+        assert_eq!(sanitize_windows_name("CONSOLE.txt"), "CONSOLE.txt");
+    }
+
+    #[test]
+    fn windows_illegal_characters_and_trailing_dots_are_rewritten() {
+        assert_eq!(
+            sanitize_windows_name("a<b>:c\"d|e?f*.txt"),
+            "a_b__c_d_e_f_.txt"
+        );
+        assert_eq!(sanitize_windows_name("tab\there"), "tab_here");
+        assert_eq!(sanitize_windows_name("notes. . "), "notes");
+        assert_eq!(sanitize_windows_name("..."), "unnamed");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn raw_names_keep_bytes_but_not_separators() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let name = raw_file_name(b"caf\xe9/x.txt").expect("name");
+        assert_eq!(name.as_bytes(), b"caf\xe9_x.txt");
+        assert!(raw_file_name(b"..").is_none());
+        assert!(raw_file_name(b"").is_none());
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn unix_keeps_windows_only_characters() {
+        assert_eq!(sanitize_file_name("a:b?.txt"), "a:b?.txt");
+        assert_eq!(sanitize_file_name("CON"), "CON");
+        let long = Path::new("/tmp").join("x".repeat(300));
+        assert_eq!(extended_length_path(&long), long);
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn windows_rewrites_reserved_names_and_long_paths() {
+        assert_eq!(sanitize_file_name("aux.log"), "aux_.log");
+        assert_eq!(sanitize_file_name("what?.txt"), "what_.txt");
+
+        let short = Path::new(r"C:\Users\demo\file.txt");
+        assert_eq!(extended_length_path(short), short);
+
+        let long = Path::new(r"C:\data").join("x".repeat(300));
+        let extended = extended_length_path(&long);
+        assert!(extended.to_string_lossy().starts_with(r"\\?\C:\data\"));
+
+        let unc = Path::new(r"\\server\share").join("y".repeat(300));
+        let extended = extended_length_path(&unc);
+        assert!(extended
+            .to_string_lossy()
+            .starts_with(r"\\?\UNC\server\share\"));
+    }
+}
@@ -0,0 +1,251 @@
+use std::io::Read;
+
+use crate::protocol::CHUNK_SIZE;
+
+/// Bytes covered by each hash of a `--verify-chunks` transfer.
+pub const SEGMENT_SIZE: u64 = 1024 * 1024;
+
+/// Smallest segment size a receiver accepts, which bounds how many hashes a
+/// sender can make it hold.
+pub const MIN_SEGMENT_SIZE: u64 = 64 * 1024;
+
+/// Most segments a receiver takes hashes for: 4 TiB at [`SEGMENT_SIZE`].
+pub const MAX_SEGMENTS: u64 = 1 << 22;
+
+/// Length of one segment hash on the wire.
+pub const HASH_LEN: usize = blake3::OUT_LEN;
+
+/// Segment hashes carried by one frame.
+const HASHES_PER_FRAME: usize = CHUNK_SIZE / HASH_LEN;
+
+/// Number of segments of `segment_size` bytes in a file of `size` bytes.
+pub fn segment_count(size: u64, segment_size: u64) -> u64 {
+    size.div_ceil(segment_size)
+}
+
+/// Number of frames [`encode`] splits `count` hashes into.
+pub fn frame_count(count: u64) -> u64 {
+    count.div_ceil(HASHES_PER_FRAME as u64)
+}
+
+/// The blake3 hash of every `segment_size` bytes of `reader`, together with
+/// the hash of the whole stream, in one pass.
+pub fn hash_segments(
+    mut reader: impl Read,
+    segment_size: u64,
+) -> std::io::Result<(String, Vec<blake3::Hash>)> {
+    let mut whole = blake3::Hasher::new();
+    let mut segments = Vec::new();
+    let mut segment = blake3::Hasher::new();
+    let mut filled = 0u64;
+    let mut buf = vec![0u8; CHUNK_SIZE];
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        whole.update(&buf[..n]);
+        let mut data = &buf[..n];
+        while !data.is_empty() {
+            let take = ((segment_size - filled) as usize).min(data.len());
+            segment.update(&data[..take]);
+            filled += take as u64;
+            data = &data[take..];
+            if filled == segment_size {
+                segments.push(segment.finalize());
+                segment.reset();
+                filled = 0;
+            }
+        }
+    }
+    if filled > 0 {
+        segments.push(segment.finalize());
+    }
+    Ok((whole.finalize().to_hex().to_string(), segments))
+}
+
+/// Frames carrying `hashes` back to back, as many per frame as fit.
+pub fn encode(hashes: &[blake3::Hash]) -> Vec<Vec<u8>> {
+    hashes
+        .chunks(HASHES_PER_FRAME)
+        .map(|frame| frame.iter().flat_map(|hash| *hash.as_bytes()).collect())
+        .collect()
+}
+
+/// Hashes from one frame written by [`encode`].
+pub fn decode(frame: &[u8]) -> anyhow::Result<Vec<blake3::Hash>> {
+    if frame.is_empty() || !frame.len().is_multiple_of(HASH_LEN) {
+        anyhow::bail!("malformed segment hash frame ({} bytes)", frame.len());
+    }
+    Ok(frame
+        .chunks_exact(HASH_LEN)
+        .map(|hash| blake3::Hash::from_bytes(hash.try_into().expect("hash length")))
+        .collect())
+}
+
+/// Error for a segment whose data does not match the hash the sender
+/// announced. Reported as an `Error` event with code `chunk_mismatch`.
+#[derive(Debug)]
+pub struct SegmentMismatch {
+    pub index: u64,
+    /// Offset of the segment's first byte in the file.
+    pub offset: u64,
+}
+
+impl std::fmt::Display for SegmentMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "segment {} (starting at byte {}) does not match the sender's hash",
+            self.index, self.offset
+        )
+    }
+}
+
+impl std::error::Error for SegmentMismatch {}
+
+/// Checks received data against the sender's segment hashes as it arrives.
+#[derive(Debug)]
+pub struct SegmentVerifier {
+    segment_size: u64,
+    expected: Vec<blake3::Hash>,
+    index: u64,
+    hasher: blake3::Hasher,
+    filled: u64,
+    /// The current segment began before a resumed transfer's offset, so
+    /// only the final whole-file checksum covers it.
+    skip_current: bool,
+}
+
+impl SegmentVerifier {
+    /// Verify data from `offset` on. A segment the offset falls inside is
+    /// left to the whole-file checksum.
+    pub fn new(segment_size: u64, expected: Vec<blake3::Hash>, offset: u64) -> Self {
+        let filled = offset % segment_size;
+        Self {
+            segment_size,
+            expected,
+            index: offset / segment_size,
+            hasher: blake3::Hasher::new(),
+            filled,
+            skip_current: filled != 0,
+        }
+    }
+
+    /// Feed the next received bytes, failing at the first segment that
+    /// does not match.
+    pub fn update(&mut self, mut data: &[u8]) -> Result<(), SegmentMismatch> {
+        while !data.is_empty() {
+            let take = ((self.segment_size - self.filled) as usize).min(data.len());
+            if !self.skip_current {
+                self.hasher.update(&data[..take]);
+            }
+            self.filled += take as u64;
+            data = &data[take..];
+            if self.filled == self.segment_size {
+                self.check()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Check the last, shorter segment once all data has arrived.
+    pub fn finish(mut self) -> Result<(), SegmentMismatch> {
+        if self.filled > 0 {
+            self.check()?;
+        }
+        Ok(())
+    }
+
+    fn check(&mut self) -> Result<(), SegmentMismatch> {
+        if !self.skip_current {
+            match self.expected.get(self.index as usize) {
+                Some(expected) if self.hasher.finalize() == *expected => {}
+                _ => {
+                    return Err(SegmentMismatch {
+                        index: self.index,
+                        offset: self.index * self.segment_size,
+                    })
+                }
+            }
+        }
+        self.index += 1;
+        self.hasher.reset();
+        self.filled = 0;
+        self.skip_current = false;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, encode, frame_count, hash_segments, SegmentVerifier};
+
+    const SEGMENT: u64 = 1024;
+
+    fn data() -> Vec<u8> {
+        (0..SEGMENT as usize * 5 + 100)
+            .map(|i| (i % 251) as u8)
+            .collect()
+    }
+
+    #[test]
+    fn segment_hashes_cover_the_file_and_survive_framing() {
+        let data = data();
+        let (whole, segments) = hash_segments(&data[..], SEGMENT).unwrap();
+        assert_eq!(whole, blake3::hash(&data).to_hex().to_string());
+        assert_eq!(segments.len(), 6);
+        assert_eq!(segments[5], blake3::hash(&data[5 * SEGMENT as usize..]));
+
+        let frames = encode(&segments);
+        assert_eq!(frames.len() as u64, frame_count(segments.len() as u64));
+        let decoded: Vec<_> = frames.iter().flat_map(|f| decode(f).unwrap()).collect();
+        assert_eq!(decoded, segments);
+    }
+
+    #[test]
+    fn a_corrupted_middle_segment_is_caught_at_its_boundary() {
+        let data = data();
+        let (_, segments) = hash_segments(&data[..], SEGMENT).unwrap();
+        let mut corrupted = data.clone();
+        corrupted[2 * SEGMENT as usize + 17] ^= 0xff;
+
+        let mut verifier = SegmentVerifier::new(SEGMENT, segments, 0);
+        let mut fed = 0;
+        let err = corrupted
+            .chunks(300)
+            .find_map(|chunk| {
+                fed += chunk.len();
+                verifier.update(chunk).err()
+            })
+            .expect("mismatch");
+        assert_eq!((err.index, err.offset), (2, 2 * SEGMENT));
+        assert!(fed < 3 * SEGMENT as usize + 300, "caught late at {fed}");
+    }
+
+    #[test]
+    fn intact_data_verifies_including_a_resumed_start() {
+        let data = data();
+        let (_, segments) = hash_segments(&data[..], SEGMENT).unwrap();
+
+        let mut verifier = SegmentVerifier::new(SEGMENT, segments.clone(), 0);
+        verifier.update(&data).unwrap();
+        verifier.finish().unwrap();
+
+        let offset = SEGMENT as usize + 10;
+        let mut resumed = SegmentVerifier::new(SEGMENT, segments, offset as u64);
+        resumed.update(&data[offset..]).unwrap();
+        resumed.finish().unwrap();
+    }
+
+    #[test]
+    fn truncated_hash_lists_fail_instead_of_passing() {
+        let data = data();
+        let (_, mut segments) = hash_segments(&data[..], SEGMENT).unwrap();
+        segments.truncate(2);
+        let mut verifier = SegmentVerifier::new(SEGMENT, segments, 0);
+        let err = verifier.update(&data).unwrap_err();
+        assert_eq!(err.index, 2);
+        assert!(decode(&[0u8; 31]).is_err());
+    }
+}
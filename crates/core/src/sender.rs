@@ -1,40 +1,286 @@
+use std::io::{self, SeekFrom};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 
 use anyhow::{bail, Context, Result};
-use iroh::endpoint::ConnectionType;
-use iroh::{Endpoint, NodeAddr, NodeId, Watcher as _};
-use n0_future::StreamExt;
+use bytes::Bytes;
+use iroh::endpoint::{ConnectionError, Incoming, ReadError, RecvStream, SendStream, VarInt};
+use iroh::{Endpoint, NodeAddr};
+use serde::Serialize;
 use tokio::fs::File;
-use tokio::io::AsyncReadExt;
-
-use crate::bundle;
-use crate::crypto;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinSet;
+
+use crate::alpn::{self, AlpnRole, NegotiatedAlpn};
+use crate::bundle::{self, BundleEntry, BundleTracker};
+use crate::compression;
+use crate::crypto::{self, HandshakePayload};
+use crate::endpoint::{self, HomeRelay, ReconnectWindow};
+use crate::estimate;
 use crate::events::{
-    ConnectionPathKind, TransferCompleted, TransferContentKind, TransferEvent, TransferEventSink,
+    self, ConnectionPathKind, TransferCompleted, TransferContentKind, TransferEvent,
+    TransferEventSink,
+};
+use crate::follow::FollowReader;
+use crate::hash_cache::HashCache;
+use crate::hashing;
+use crate::manifest::{self, Manifest};
+use crate::options::TransferOptions;
+use crate::progress::{transfer_progress_bar, ProgressThrottle};
+use crate::protocol::{
+    self, human_bytes, FileHeader, HeaderAck, StreamTrailer, SyncReply, CHUNK_SIZE,
+    MAX_PREVIEW_LEN, SEQUENCE_LEN, SYNC_PREFIX,
 };
-use crate::progress::transfer_progress_bar;
-use crate::protocol::{human_bytes, FileHeader, CHUNK_SIZE};
+use crate::qr::{self, TicketQr};
+use crate::segments;
+use crate::tcp;
 use crate::ticket;
+use crate::transport::{self, HandshakeRole, IrohTransport, Link, TcpTransport, Transport};
 
-/// ALPN protocol identifier for p2p-share connections.
-const ALPN: &[u8] = b"p2p-share/1";
+type SharedSink = Arc<dyn TransferEventSink>;
 
-/// ALPN for reverse mode: the connector is the file sender.
-const ALPN_REVERSE: &[u8] = b"p2p-share/1-reverse";
+/// Default number of chunks buffered between the read, encrypt and write
+/// stages of a send.
+pub const DEFAULT_PIPELINE_DEPTH: usize = 4;
+
+/// Default for [`SendOptions::ack_timeout`]: long enough for a receiver
+/// that asks its user first, which the CLI gives up on after a minute.
+pub const DEFAULT_ACK_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Default for [`SendOptions::wait_for_timeout`].
+pub const DEFAULT_WAIT_FOR_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// How long one receiver of a [`SendOptions::wait_for`] group has from
+/// connecting to opening its stream before it is given up on.
+const RECEIVER_SETUP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Which addresses the sender puts into the ticket it advertises in wait mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdvertiseMode {
+    /// Relay URL plus every useful direct address.
+    Full,
+    /// Relay URL only. Avoids unstable direct-path upgrades on some mobile
+    /// LAN/IPv6 combinations at the cost of routing through the relay.
+    RelayOnly,
+    /// Direct addresses only; the receiver must be reachable without a relay.
+    DirectOnly,
+}
 
-type SharedSink = Arc<dyn TransferEventSink>;
+impl Default for AdvertiseMode {
+    /// Relay-only on Android builds, where the heuristic originated, and
+    /// full everywhere else.
+    fn default() -> Self {
+        if cfg!(target_os = "android") {
+            AdvertiseMode::RelayOnly
+        } else {
+            AdvertiseMode::Full
+        }
+    }
+}
+
+impl std::str::FromStr for AdvertiseMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "full" => Ok(AdvertiseMode::Full),
+            "relay-only" => Ok(AdvertiseMode::RelayOnly),
+            "direct-only" => Ok(AdvertiseMode::DirectOnly),
+            other => bail!(
+                "unknown advertise mode '{}' (expected full, relay-only or direct-only)",
+                other
+            ),
+        }
+    }
+}
+
+/// Optional send-side behavior shared by the send entry points.
+#[derive(Debug, Clone)]
+pub struct SendOptions {
+    /// Settings shared with the other direction.
+    pub transfer: TransferOptions,
+    /// Chunks buffered between pipeline stages. `1` disables pipelining.
+    pub pipeline_depth: usize,
+    /// Addresses included in the wait-mode ticket.
+    pub advertise: AdvertiseMode,
+    /// Cache file for single-file hashes, so retries of an unchanged file
+    /// skip rehashing. `None` disables the cache.
+    pub hash_cache: Option<PathBuf>,
+    /// Publish the wait-mode ticket as a `p2pshare://` deep link, in both
+    /// the printed text and the QR code.
+    pub ticket_uri: bool,
+    /// Split a wait-mode ticket too long for one QR code across several,
+    /// instead of falling back to its relay-only form.
+    pub multi_qr: bool,
+    /// Send the paths as one tar archive even when there is only one file.
+    /// Directories are always archived recursively.
+    pub archive: bool,
+    /// Send the target of a symbolic link, both for the paths given and
+    /// inside archived directories. Off, symbolic links are rejected like
+    /// FIFOs, sockets and devices.
+    pub follow_symlinks: bool,
+    /// Skip bundle files that cannot be read, reporting each as
+    /// [`TransferEvent::FileError`], instead of failing the whole batch.
+    pub keep_going: bool,
+    /// Follow a single growing file like `tail -f`, sending bytes as they
+    /// are appended until the value turns true. The size is unknown to the
+    /// receiver, so the upfront checksum is replaced by a size and blake3
+    /// trailer sent at stop time. Receivers decline it unless they set
+    /// [`ReceiveOptions::accept_streaming`](crate::receiver::ReceiveOptions::accept_streaming).
+    pub follow: Option<watch::Receiver<bool>>,
+    /// Number every data chunk so the receiver catches a missing or
+    /// misordered one as it arrives, with a `sequence_error`. Only used
+    /// with receivers that announce [`crypto::FEATURE_SEQUENCE`].
+    pub sequence_chunks: bool,
+    /// Hash every [`segments::SEGMENT_SIZE`] bytes while preparing and send
+    /// the hashes ahead of the data, so the receiver stops at the first
+    /// segment that does not match, with a `chunk_mismatch`. Only used
+    /// with receivers that announce [`crypto::FEATURE_VERIFY_CHUNKS`].
+    pub verify_chunks: bool,
+    /// Ask the receiver which files of a folder transfer it already has
+    /// and send only the new or changed ones, reporting the rest as
+    /// [`TransferEvent::FileSkipped`]. Receivers without
+    /// [`crypto::FEATURE_SYNC`] or not in sync mode get every file. Single
+    /// files are sent as usual.
+    pub sync: bool,
+    /// How long to wait for the receiver to accept the header and open its
+    /// file before failing with [`AckTimeout`]. `None` waits indefinitely.
+    /// Defaults to [`DEFAULT_ACK_TIMEOUT`].
+    pub ack_timeout: Option<Duration>,
+    /// Threads a single file of at least [`hashing::PARALLEL_THRESHOLD`]
+    /// bytes is hashed on with the `parallel-hash` feature. `None` uses
+    /// every core and `Some(1)` keeps to one streaming thread. Bundles are
+    /// hashed while they are written and do not use it.
+    pub hash_threads: Option<usize>,
+    /// Wait-mode only: wait until this many receivers have connected with
+    /// the ticket, then send to all of them at once so they finish
+    /// together. Their events share the sink. `None` or `Some(1)` serves
+    /// the first receiver as usual.
+    pub wait_for: Option<usize>,
+    /// With [`wait_for`](Self::wait_for), how long the other receivers have
+    /// to join once the first one connected before the transfer starts
+    /// with those present. `None` waits for all of them. Defaults to
+    /// [`DEFAULT_WAIT_FOR_TIMEOUT`].
+    pub wait_for_timeout: Option<Duration>,
+    /// Compress data frames with zstd at this level, from
+    /// [`compression::MIN_LEVEL`] to [`compression::MAX_LEVEL`]. Only used
+    /// with receivers that announce [`crypto::FEATURE_COMPRESSION`]; others
+    /// get the data raw. `None` never compresses.
+    pub compress_level: Option<i32>,
+    /// Compress a bundle as one zstd stream at this level, from
+    /// [`compression::MIN_LEVEL`] to [`compression::MAX_LEVEL`], before
+    /// sending it. Folders of many small files shrink much further than
+    /// with [`compress_level`](Self::compress_level). Only used with
+    /// receivers that announce [`crypto::FEATURE_ZSTD_ARCHIVE`]; others get
+    /// the plain archive. Needs the `compression` feature.
+    pub archive_level: Option<i32>,
+    /// Wait-mode only: mark the ticket as expiring this long after it was
+    /// issued, and stop waiting for a receiver once that passed on this
+    /// machine's clock, failing with [`ticket::TicketExpired`]. Receivers
+    /// also check the expiry against their own clock, allowing for
+    /// [`TransferOptions::clock_skew`]. `None` issues tickets that do not
+    /// expire.
+    pub ticket_ttl: Option<Duration>,
+}
+
+impl Default for SendOptions {
+    fn default() -> Self {
+        Self::from(TransferOptions::default())
+    }
+}
+
+impl From<TransferOptions> for SendOptions {
+    fn from(transfer: TransferOptions) -> Self {
+        Self {
+            transfer,
+            pipeline_depth: DEFAULT_PIPELINE_DEPTH,
+            advertise: AdvertiseMode::default(),
+            hash_cache: None,
+            ticket_uri: false,
+            multi_qr: false,
+            archive: false,
+            follow_symlinks: false,
+            keep_going: false,
+            follow: None,
+            sequence_chunks: false,
+            verify_chunks: false,
+            sync: false,
+            ack_timeout: Some(DEFAULT_ACK_TIMEOUT),
+            hash_threads: None,
+            wait_for: None,
+            wait_for_timeout: Some(DEFAULT_WAIT_FOR_TIMEOUT),
+            compress_level: None,
+            archive_level: None,
+            ticket_ttl: None,
+        }
+    }
+}
+
+/// Restrict `node_addr` to the addresses selected by `mode`. Falls back to
+/// the full address when the requested subset would leave nothing to dial.
+fn advertised_node_addr(
+    node_addr: NodeAddr,
+    mode: AdvertiseMode,
+    sink: Option<&SharedSink>,
+) -> NodeAddr {
+    match mode {
+        AdvertiseMode::Full => node_addr,
+        AdvertiseMode::RelayOnly if node_addr.relay_url.is_some() => {
+            status(sink, "Advertising relay-only ticket.");
+            NodeAddr::from_parts(node_addr.node_id, node_addr.relay_url, Vec::new())
+        }
+        AdvertiseMode::DirectOnly if !node_addr.direct_addresses.is_empty() => {
+            status(sink, "Advertising direct-only ticket.");
+            NodeAddr::from_parts(node_addr.node_id, None, node_addr.direct_addresses)
+        }
+        AdvertiseMode::RelayOnly | AdvertiseMode::DirectOnly => {
+            status(
+                sink,
+                "Warning: requested ticket addresses unavailable; advertising all addresses.",
+            );
+            node_addr
+        }
+    }
+}
+
+/// Where the bytes of a prepared transfer come from.
+#[derive(Debug, Clone)]
+enum TransferSource {
+    Path(PathBuf),
+    Memory(Bytes),
+}
 
 #[derive(Debug, Clone)]
-struct PreparedTransfer {
-    transfer_path: PathBuf,
+pub(crate) struct PreparedTransfer {
+    source: TransferSource,
     wire_name: String,
+    /// Raw bytes of a non-UTF-8 `wire_name`, see [`FileHeader::name_raw`].
+    name_raw: Option<String>,
     logical_name: String,
     file_size: u64,
     hash: String,
     content_kind: TransferContentKind,
     item_count: u64,
+    /// Bundle files skipped in keep-going mode.
+    failed_count: u64,
+    /// Follow the file as it grows; `file_size` and `hash` are unknown.
+    streaming: bool,
+    /// Where each file's data sits in a bundle, for per-file events.
+    bundle_entries: Vec<BundleEntry>,
+    /// Hash of every [`segments::SEGMENT_SIZE`] bytes, for
+    /// [`SendOptions::verify_chunks`]; empty otherwise.
+    segments: Vec<blake3::Hash>,
+    /// Paths a bundle was built from, to rebuild it for a
+    /// [`SendOptions::sync`] receiver; empty for single files.
+    sync_paths: Vec<PathBuf>,
+    /// Built without the files a sync receiver listed, see
+    /// [`FileHeader::sync`].
+    synced: bool,
+    /// The bundle is compressed as one zstd stream, see
+    /// [`FileHeader::zstd_archive`].
+    zstd_archive: bool,
     cleanup_path: Option<PathBuf>,
 }
 
@@ -50,127 +296,374 @@ fn status(sink: Option<&SharedSink>, msg: impl Into<String>) {
     emit(sink, TransferEvent::Status(msg));
 }
 
-/// Spawn a background task that watches connection type changes and prints/emits them.
-fn spawn_conn_type_watcher(
-    ep: &Endpoint,
-    node_id: NodeId,
-    sink: Option<SharedSink>,
-) -> Option<tokio::task::JoinHandle<()>> {
-    let watcher = ep.conn_type(node_id)?;
-    let mut stream = watcher.stream_updates_only();
-    let handle = tokio::task::spawn(async move {
-        while let Some(conn_type) = stream.next().await {
-            match &conn_type {
-                ConnectionType::Direct(addr) => {
-                    eprintln!("Connection upgraded: direct ({})", addr);
-                    emit(
-                        sink.as_ref(),
-                        TransferEvent::ConnectionPath {
-                            kind: ConnectionPathKind::Direct(addr.to_string()),
-                            latency_ms: None,
-                        },
-                    );
-                }
-                ConnectionType::Relay(url) => {
-                    eprintln!("Connection changed: relay ({})", url);
-                    emit(
-                        sink.as_ref(),
-                        TransferEvent::ConnectionPath {
-                            kind: ConnectionPathKind::Relay(url.to_string()),
-                            latency_ms: None,
-                        },
-                    );
-                }
-                ConnectionType::Mixed(addr, url) => {
-                    eprintln!("Connection changed: mixed (udp: {}, relay: {})", addr, url);
-                    emit(
-                        sink.as_ref(),
-                        TransferEvent::ConnectionPath {
-                            kind: ConnectionPathKind::Mixed {
-                                udp_addr: addr.to_string(),
-                                relay_url: url.to_string(),
-                            },
-                            latency_ms: None,
-                        },
-                    );
-                }
-                ConnectionType::None => {
-                    eprintln!("Connection path: none (searching...)");
-                    emit(
-                        sink.as_ref(),
-                        TransferEvent::ConnectionPath {
-                            kind: ConnectionPathKind::None,
-                            latency_ms: None,
-                        },
-                    );
-                }
-            }
-        }
-    });
-    Some(handle)
+/// Emit the `connection_lost` error event if `result` failed because the
+/// connection to the receiver dropped.
+fn report_connection_lost<T>(sink: Option<&SharedSink>, result: &Result<T>) {
+    if let Some(event) = result
+        .as_ref()
+        .err()
+        .and_then(endpoint::connection_lost_event)
+    {
+        emit(sink, event);
+    }
 }
 
-/// Print/emit a summary of the final connection state.
-fn print_conn_summary(ep: &Endpoint, node_id: NodeId, sink: Option<&SharedSink>) {
-    if let Some(info) = ep.remote_info(node_id) {
-        let latency = info.latency.map(|d| d.as_secs_f64() * 1000.0);
-        let latency_str = latency
-            .map(|d| format!("{:.1}ms", d))
-            .unwrap_or_else(|| "unknown".to_string());
+/// Print/emit an [`estimate`] of how long sending `prepared` over `path`
+/// will take. Followed files have no size to estimate from.
+fn emit_estimate(
+    prepared: &PreparedTransfer,
+    path: &ConnectionPathKind,
+    latency_ms: Option<f64>,
+    sink: Option<&SharedSink>,
+) {
+    if prepared.streaming {
+        return;
+    }
+    let est_secs = estimate::estimate_secs(prepared.file_size, path, latency_ms);
+    if let Some(secs) = est_secs {
         eprintln!(
-            "Transfer path: {}, latency: {}",
-            info.conn_type, latency_str
+            "Estimated transfer time: {} (a rough guess until data flows)",
+            estimate::human_estimate(secs)
         );
+    }
+    emit(
+        sink,
+        TransferEvent::Estimate {
+            bytes: prepared.file_size,
+            est_secs,
+        },
+    );
+}
 
-        let kind = match info.conn_type {
-            ConnectionType::Direct(addr) => ConnectionPathKind::Direct(addr.to_string()),
-            ConnectionType::Relay(url) => ConnectionPathKind::Relay(url.to_string()),
-            ConnectionType::Mixed(addr, url) => ConnectionPathKind::Mixed {
-                udp_addr: addr.to_string(),
-                relay_url: url.to_string(),
-            },
-            ConnectionType::None => ConnectionPathKind::None,
-        };
-        emit(
+async fn hash_file(
+    file_path: &Path,
+    threads: Option<usize>,
+    sink: Option<&SharedSink>,
+    label: &str,
+) -> Result<String> {
+    status(sink, label);
+    let path = file_path.to_path_buf();
+    tokio::task::spawn_blocking(move || -> Result<String> {
+        Ok(hashing::hash_path(&path, threads)?.to_hex().to_string())
+    })
+    .await?
+}
+
+/// Like [`hash_file`], also hashing every [`segments::SEGMENT_SIZE`] bytes
+/// for [`SendOptions::verify_chunks`] in the same pass.
+async fn hash_file_segments(
+    file_path: &Path,
+    sink: Option<&SharedSink>,
+) -> Result<(String, Vec<blake3::Hash>)> {
+    status(sink, "Hashing file segments...");
+    let path = file_path.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        let file = std::fs::File::open(&path)?;
+        anyhow::Ok(segments::hash_segments(file, segments::SEGMENT_SIZE)?)
+    })
+    .await?
+}
+
+/// Outcome of re-checking a file against an expected blake3 hash.
+#[derive(Debug, Clone, Serialize)]
+pub struct VerifyReport {
+    pub path: String,
+    pub size_bytes: u64,
+    pub expected: String,
+    pub actual: String,
+    pub matches: bool,
+}
+
+/// Hash `file_path` with the same code used to prepare a send and compare
+/// it with `expected`, a hex blake3 digest, on `threads` threads as in
+/// [`SendOptions::hash_threads`]. No networking is involved.
+pub async fn verify_file(
+    file_path: &Path,
+    expected: &str,
+    threads: Option<usize>,
+) -> Result<VerifyReport> {
+    let expected = expected.trim().to_ascii_lowercase();
+    if expected.len() != 64 || !expected.chars().all(|c| c.is_ascii_hexdigit()) {
+        bail!("expected a 64-character hex blake3 hash");
+    }
+    let metadata = tokio::fs::metadata(file_path)
+        .await
+        .with_context(|| format!("cannot access {:?}", file_path))?;
+    if !metadata.is_file() {
+        bail!("{:?} is not a regular file", file_path);
+    }
+
+    let actual = hash_file(file_path, threads, None, "Hashing file...").await?;
+    Ok(VerifyReport {
+        path: file_path.display().to_string(),
+        size_bytes: metadata.len(),
+        matches: actual == expected,
+        expected,
+        actual,
+    })
+}
+
+/// Like [`hash_file`], but reuse a hash from `cache_path` while the file's
+/// size and mtime are unchanged. Cache problems never fail the send.
+async fn hash_file_cached(
+    file_path: &Path,
+    metadata: &std::fs::Metadata,
+    cache_path: &Path,
+    threads: Option<usize>,
+    sink: Option<&SharedSink>,
+) -> Result<String> {
+    let key = tokio::fs::canonicalize(file_path)
+        .await
+        .unwrap_or_else(|_| file_path.to_path_buf());
+    let Ok(mtime) = metadata.modified() else {
+        return hash_file(file_path, threads, sink, "Hashing file...").await;
+    };
+
+    let path = cache_path.to_path_buf();
+    let mut cache = tokio::task::spawn_blocking(move || HashCache::load(&path)).await?;
+    if let Some(hash) = cache.lookup(&key, metadata.len(), mtime) {
+        status(sink, "File unchanged since last send; using cached hash.");
+        return Ok(hash);
+    }
+
+    let hash = hash_file(file_path, threads, sink, "Hashing file...").await?;
+    cache.insert(key, metadata.len(), mtime, hash.clone());
+    let path = cache_path.to_path_buf();
+    if let Err(err) = tokio::task::spawn_blocking(move || cache.save(&path)).await? {
+        status(
             sink,
-            TransferEvent::ConnectionPath {
-                kind,
-                latency_ms: latency,
-            },
+            format!("Warning: could not update hash cache: {:#}", err),
         );
     }
+    Ok(hash)
 }
 
-/// Print a QR code to stderr, indented for readability.
-fn print_qr(data: &str) {
-    if let Ok(qr_string) = qr2term::generate_qr_string(data) {
-        for line in qr_string.lines() {
-            eprintln!("    {}", line);
+/// Error a send fails with for a path that is neither a regular file nor a
+/// directory: a FIFO, socket or device, or a symbolic link without
+/// [`SendOptions::follow_symlinks`]. Reported as an `Error` event with code
+/// `unsupported_file_type`.
+#[derive(Debug)]
+pub struct UnsupportedFileType {
+    pub path: PathBuf,
+    /// Such as `FIFO`, `socket` or `symbolic link`.
+    pub kind: &'static str,
+}
+
+impl std::fmt::Display for UnsupportedFileType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.kind == "symbolic link" {
+            write!(
+                f,
+                "{:?} is a symbolic link; enable following symlinks to send its target",
+                self.path
+            )
+        } else {
+            write!(
+                f,
+                "{:?} is a {}; only regular files and directories can be sent",
+                self.path, self.kind
+            )
         }
     }
 }
 
-async fn hash_file(file_path: &Path, sink: Option<&SharedSink>, label: &str) -> Result<String> {
-    status(sink, label);
-    let path = file_path.to_path_buf();
-    tokio::task::spawn_blocking(move || -> Result<String> {
-        let mut hasher = blake3::Hasher::new();
-        let mut file = std::fs::File::open(&path)?;
-        std::io::copy(&mut file, &mut hasher)?;
-        Ok(hasher.finalize().to_hex().to_string())
-    })
-    .await?
+impl std::error::Error for UnsupportedFileType {}
+
+/// Error a send fails with when the prepared file can no longer be opened or
+/// read, such as when it was deleted after hashing. Reported as an `Error`
+/// event with code `source_unavailable`.
+#[derive(Debug)]
+pub struct SourceUnavailable {
+    pub path: PathBuf,
+    pub reason: String,
+}
+
+impl std::fmt::Display for SourceUnavailable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:?} is no longer readable ({}); was it moved or deleted?",
+            self.path, self.reason
+        )
+    }
+}
+
+impl std::error::Error for SourceUnavailable {}
+
+/// Error a send fails with when the receiver does not answer the header
+/// within [`SendOptions::ack_timeout`], for example because opening its
+/// output file hangs. Reported as an `Error` event with code `ack_timeout`.
+#[derive(Debug)]
+pub struct AckTimeout {
+    pub waited: Duration,
+}
+
+impl std::fmt::Display for AckTimeout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "receiver did not get ready within {:?}; its disk may be stalled",
+            self.waited
+        )
+    }
+}
+
+impl std::error::Error for AckTimeout {}
+
+/// Error for a receiver that refused the header, with the reason it gave.
+#[derive(Debug)]
+pub struct Rejected {
+    pub reason: String,
+}
+
+impl std::fmt::Display for Rejected {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Receiver rejected the transfer: {}", self.reason)
+    }
+}
+
+impl std::error::Error for Rejected {}
+
+/// Error for a listening receiver that turned a reverse send away because
+/// it is busy with another transfer. Reported as an `Error` event with
+/// code `receiver_busy`.
+#[derive(Debug)]
+pub struct ReceiverBusy;
+
+impl std::fmt::Display for ReceiverBusy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("the receiver is busy with another transfer; try again once it finishes")
+    }
+}
+
+impl std::error::Error for ReceiverBusy {}
+
+/// A read from the source that failed inside [`stream_chunks`], kept apart
+/// from write errors so it can be reported as [`SourceUnavailable`].
+#[derive(Debug)]
+struct SourceRead(io::Error);
+
+impl std::fmt::Display for SourceRead {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "failed to read the file being sent: {}", self.0)
+    }
 }
 
-async fn prepare_send_paths(
+impl std::error::Error for SourceRead {}
+
+/// Emit the `source_unavailable` error event for `path` and return the
+/// error.
+fn source_unavailable(
+    path: &Path,
+    reason: impl std::fmt::Display,
+    sink: Option<&SharedSink>,
+) -> anyhow::Error {
+    let err = SourceUnavailable {
+        path: path.to_path_buf(),
+        reason: reason.to_string(),
+    };
+    emit(
+        sink,
+        TransferEvent::Error {
+            code: "source_unavailable".to_string(),
+            message: err.to_string(),
+            partial_path: None,
+        },
+    );
+    err.into()
+}
+
+/// Open the prepared file at `path`. When it has gone away since it was
+/// hashed, tell the receiver with an [`protocol::ABORT_PREFIX`] line in
+/// place of the header so it does not wait for one.
+async fn open_source<W>(
+    writer: &mut W,
+    transport: &mut snow::TransportState,
+    path: &Path,
+    sink: Option<&SharedSink>,
+) -> Result<File>
+where
+    W: tokio::io::AsyncWrite + Unpin,
+{
+    match File::open(path).await {
+        Ok(file) => Ok(file),
+        Err(err) => {
+            let line = format!("{}{}\n", protocol::ABORT_PREFIX, err);
+            let _ = crypto::encrypted_write(writer, transport, line.as_bytes()).await;
+            Err(source_unavailable(path, err, sink))
+        }
+    }
+}
+
+/// Report a [`SourceRead`] failure of the file at `path` as
+/// [`SourceUnavailable`]. The receiver learns of it when the connection
+/// drops; mid-stream there is no room for a control message.
+fn map_source_read(err: anyhow::Error, path: &Path, sink: Option<&SharedSink>) -> anyhow::Error {
+    match err.downcast_ref::<SourceRead>() {
+        Some(SourceRead(read_err)) => source_unavailable(path, read_err, sink),
+        None => err,
+    }
+}
+
+/// Whether `path` is a directory, failing with [`UnsupportedFileType`] when
+/// it is something that cannot be sent. Paths that cannot be accessed are
+/// left for the caller to report.
+async fn check_source_path(
+    path: &Path,
+    follow_symlinks: bool,
+    sink: Option<&SharedSink>,
+) -> Result<bool> {
+    let metadata = if follow_symlinks {
+        tokio::fs::metadata(path).await
+    } else {
+        tokio::fs::symlink_metadata(path).await
+    };
+    let Ok(metadata) = metadata else {
+        return Ok(false);
+    };
+    if metadata.is_file() || metadata.is_dir() {
+        return Ok(metadata.is_dir());
+    }
+    let err = UnsupportedFileType {
+        path: path.to_path_buf(),
+        kind: bundle::file_type_name(&metadata.file_type()),
+    };
+    emit(
+        sink,
+        TransferEvent::Error {
+            code: "unsupported_file_type".to_string(),
+            message: err.to_string(),
+            partial_path: None,
+        },
+    );
+    Err(err.into())
+}
+
+pub(crate) async fn prepare_send_paths(
     file_paths: &[PathBuf],
+    options: &SendOptions,
     sink: Option<&SharedSink>,
 ) -> Result<PreparedTransfer> {
     if file_paths.is_empty() {
         bail!("at least one file is required");
     }
 
-    if file_paths.len() == 1 {
+    let mut archive = options.archive;
+    for path in file_paths {
+        if check_source_path(path, options.follow_symlinks, sink).await? && !archive {
+            status(
+                sink,
+                format!("{:?} is a directory; sending it as an archive.", path),
+            );
+            archive = true;
+        }
+    }
+
+    if options.follow.is_some() && (file_paths.len() != 1 || archive) {
+        bail!("follow mode sends exactly one file and cannot be combined with archive mode");
+    }
+
+    if file_paths.len() == 1 && !archive {
         let transfer_path = file_paths[0].clone();
         let metadata = tokio::fs::metadata(&transfer_path)
             .await
@@ -180,31 +673,116 @@ async fn prepare_send_paths(
             bail!("{:?} is not a regular file", transfer_path);
         }
 
-        let wire_name = transfer_path
-            .file_name()
-            .context("path has no file name")?
-            .to_string_lossy()
-            .to_string();
-        let hash = hash_file(&transfer_path, sink, "Hashing file...").await?;
+        let os_name = transfer_path.file_name().context("path has no file name")?;
+        let wire_name = os_name.to_string_lossy().to_string();
+        let name_raw = protocol::encode_raw_name(os_name);
+        if options.follow.is_some() {
+            return Ok(PreparedTransfer {
+                logical_name: wire_name.clone(),
+                source: TransferSource::Path(transfer_path),
+                wire_name,
+                name_raw,
+                file_size: metadata.len(),
+                hash: String::new(),
+                content_kind: TransferContentKind::File,
+                item_count: 1,
+                failed_count: 0,
+                streaming: true,
+                bundle_entries: Vec::new(),
+                segments: Vec::new(),
+                sync_paths: Vec::new(),
+                synced: false,
+                zstd_archive: false,
+                cleanup_path: None,
+            });
+        }
+        // The cache only holds whole-file hashes, so segments need a pass.
+        let (hash, segments) = if options.verify_chunks {
+            hash_file_segments(&transfer_path, sink).await?
+        } else {
+            let threads = options.hash_threads;
+            let hash = match options.hash_cache.as_deref() {
+                Some(cache_path) => {
+                    hash_file_cached(&transfer_path, &metadata, cache_path, threads, sink).await?
+                }
+                None => hash_file(&transfer_path, threads, sink, "Hashing file...").await?,
+            };
+            (hash, Vec::new())
+        };
 
         return Ok(PreparedTransfer {
             logical_name: wire_name.clone(),
-            transfer_path,
+            source: TransferSource::Path(transfer_path),
             wire_name,
+            name_raw,
             file_size: metadata.len(),
             hash,
             content_kind: TransferContentKind::File,
             item_count: 1,
+            failed_count: 0,
+            streaming: false,
+            bundle_entries: Vec::new(),
+            segments,
+            sync_paths: Vec::new(),
+            synced: false,
+            zstd_archive: false,
             cleanup_path: None,
         });
     }
 
     status(
         sink,
-        format!("Preparing bundle for {} files...", file_paths.len()),
+        format!("Preparing bundle for {} paths...", file_paths.len()),
     );
-    let bundle_build = bundle::create_bundle(file_paths).await?;
+    prepare_bundle(file_paths, options, None, sink).await
+}
+
+/// Archive `file_paths` for sending. With `have`, the manifest of a sync
+/// receiver, the files it already has are left out and reported as
+/// [`TransferEvent::FileSkipped`].
+async fn prepare_bundle(
+    file_paths: &[PathBuf],
+    options: &SendOptions,
+    have: Option<Manifest>,
+    sink: Option<&SharedSink>,
+) -> Result<PreparedTransfer> {
+    let synced = have.is_some();
+    let bundle_build = bundle::create_sync_bundle(
+        file_paths,
+        options.keep_going,
+        options.follow_symlinks,
+        have.unwrap_or_default(),
+    )
+    .await?;
     let cleanup_path = bundle_build.bundle_path.clone();
+    if synced {
+        status(
+            sink,
+            format!(
+                "Receiver already has {} unchanged files; sending {}.",
+                bundle_build.unchanged.len(),
+                bundle_build.item_count
+            ),
+        );
+    }
+    for name in &bundle_build.unchanged {
+        emit(sink, TransferEvent::FileSkipped { name: name.clone() });
+    }
+    for failure in &bundle_build.failures {
+        status(
+            sink,
+            format!("Warning: skipping {}: {}", failure.name, failure.message),
+        );
+        emit(
+            sink,
+            TransferEvent::FileError {
+                index: failure.index,
+                name: failure.name.clone(),
+                code: "unreadable".to_string(),
+                message: failure.message.clone(),
+            },
+        );
+    }
 
     let result: Result<PreparedTransfer> = async {
         let metadata = tokio::fs::metadata(&bundle_build.bundle_path)
@@ -221,21 +799,32 @@ async fn prepare_send_paths(
             .context("bundle path has no file name")?
             .to_string_lossy()
             .to_string();
-        let hash = hash_file(
-            &bundle_build.bundle_path,
-            sink,
-            "Hashing transfer bundle...",
-        )
-        .await?;
+        let hash = bundle_build.blake3;
+        let layout_path = bundle_build.bundle_path.clone();
+        let bundle_entries =
+            tokio::task::spawn_blocking(move || bundle::bundle_layout(&layout_path)).await??;
+        let segments = if options.verify_chunks {
+            hash_file_segments(&bundle_build.bundle_path, sink).await?.1
+        } else {
+            Vec::new()
+        };
 
         Ok(PreparedTransfer {
-            transfer_path: bundle_build.bundle_path,
+            source: TransferSource::Path(bundle_build.bundle_path),
             wire_name,
+            name_raw: None,
             logical_name: bundle_build.logical_name,
             file_size: metadata.len(),
             hash,
             content_kind: TransferContentKind::Bundle,
             item_count: bundle_build.item_count,
+            failed_count: bundle_build.failures.len() as u64,
+            streaming: false,
+            bundle_entries,
+            segments,
+            sync_paths: file_paths.to_vec(),
+            synced,
+            zstd_archive: false,
             cleanup_path: Some(cleanup_path.clone()),
         })
     }
@@ -248,13 +837,57 @@ async fn prepare_send_paths(
     result
 }
 
+/// Prepare an in-memory buffer for sending as a single regular file.
+pub(crate) async fn prepare_send_bytes(name: &str, data: Bytes) -> Result<PreparedTransfer> {
+    let wire_name = name.trim();
+    if wire_name.is_empty() {
+        bail!("a file name is required");
+    }
+    let hashed = data.clone();
+    let hash =
+        tokio::task::spawn_blocking(move || blake3::hash(&hashed).to_hex().to_string()).await?;
+
+    Ok(PreparedTransfer {
+        wire_name: wire_name.to_string(),
+        name_raw: None,
+        logical_name: wire_name.to_string(),
+        file_size: data.len() as u64,
+        source: TransferSource::Memory(data),
+        hash,
+        content_kind: TransferContentKind::File,
+        item_count: 1,
+        failed_count: 0,
+        streaming: false,
+        bundle_entries: Vec::new(),
+        segments: Vec::new(),
+        sync_paths: Vec::new(),
+        synced: false,
+        zstd_archive: false,
+        cleanup_path: None,
+    })
+}
+
 async fn cleanup_temp_file(path: Option<&Path>) {
     if let Some(path) = path {
         let _ = tokio::fs::remove_file(path).await;
     }
 }
 
+impl PreparedTransfer {
+    /// Remove the temporary archive of a bundle.
+    pub(crate) async fn cleanup(&self) {
+        cleanup_temp_file(self.cleanup_path.as_deref()).await;
+    }
+}
+
 fn ready_to_send_message(prepared: &PreparedTransfer) -> String {
+    if prepared.streaming {
+        return format!(
+            "Ready to follow: {} ({} so far)",
+            prepared.logical_name,
+            human_bytes(prepared.file_size)
+        );
+    }
     match prepared.content_kind {
         TransferContentKind::File => format!(
             "Ready to send: {} ({})",
@@ -271,12 +904,26 @@ fn ready_to_send_message(prepared: &PreparedTransfer) -> String {
 }
 
 fn sent_success_message(prepared: &PreparedTransfer) -> String {
+    if prepared.streaming {
+        return format!(
+            "File sent successfully: {} (followed)",
+            prepared.logical_name
+        );
+    }
     match prepared.content_kind {
         TransferContentKind::File => format!(
             "File sent successfully: {} ({})",
             prepared.logical_name,
             human_bytes(prepared.file_size)
         ),
+        TransferContentKind::Bundle if prepared.failed_count > 0 => format!(
+            "Files sent: {} ({} of {} files, {} failed, {})",
+            prepared.logical_name,
+            prepared.item_count,
+            prepared.item_count + prepared.failed_count,
+            prepared.failed_count,
+            human_bytes(prepared.file_size)
+        ),
         TransferContentKind::Bundle => format!(
             "Files sent successfully: {} ({} files, {})",
             prepared.logical_name,
@@ -286,82 +933,450 @@ fn sent_success_message(prepared: &PreparedTransfer) -> String {
     }
 }
 
-/// Send the file over an already-established encrypted channel.
-/// Used by both normal and reverse modes.
-async fn send_file<R, W>(
+/// Open the prepared source and send it with [`send_file`]. `peer` is what
+/// the receiver announced in the handshake. A [`SendOptions::sync`] bundle
+/// is first rebuilt without the files the receiver already has, and a
+/// bundle is compressed for [`SendOptions::archive_level`].
+pub(crate) async fn send_prepared<R, W>(
     reader: &mut R,
     writer: &mut W,
     transport: &mut snow::TransportState,
     prepared: &PreparedTransfer,
+    peer: Option<&HandshakePayload>,
+    options: &SendOptions,
     sink: Option<&SharedSink>,
 ) -> Result<()>
 where
     R: tokio::io::AsyncRead + Unpin,
     W: tokio::io::AsyncWrite + Unpin,
 {
-    let header = FileHeader {
-        name: prepared.wire_name.clone(),
-        size: prepared.file_size,
-        blake3: prepared.hash.clone(),
-        content_kind: Some(prepared.content_kind),
-        item_count: Some(prepared.item_count),
-        logical_name: (prepared.content_kind == TransferContentKind::Bundle)
-            .then(|| prepared.logical_name.clone()),
-    };
-    let header_bytes = header.to_wire()?;
-    crypto::encrypted_write(writer, transport, &header_bytes).await?;
-
-    let ack = crypto::encrypted_read(reader, transport).await?;
-    let ack_str = String::from_utf8_lossy(&ack);
-    let ack_str = ack_str.trim();
-
-    if ack_str != "OK" {
-        bail!("Receiver rejected the transfer: {}", ack_str);
-    }
-
-    let transfer_label = if prepared.content_kind == TransferContentKind::Bundle {
-        format!(
-            "Receiver accepted. Sending {} files...",
-            prepared.item_count
-        )
-    } else {
-        "Receiver accepted. Sending file...".to_string()
-    };
-    status(sink, transfer_label);
-
-    let mut file = File::open(&prepared.transfer_path).await?;
-    let pb = if sink.is_none() {
-        Some(transfer_progress_bar(prepared.file_size))
+    let synced = if options.sync && !prepared.sync_paths.is_empty() {
+        sync_bundle(reader, writer, transport, prepared, peer, options, sink).await?
     } else {
         None
     };
-    let mut buf = vec![0u8; CHUNK_SIZE];
-    let mut sent: u64 = 0;
-
-    loop {
-        let n = file.read(&mut buf).await?;
-        if n == 0 {
-            break;
-        }
-        crypto::encrypted_write(writer, transport, &buf[..n]).await?;
-        sent += n as u64;
-        if let Some(pb) = &pb {
-            pb.set_position(sent);
+    let bundle = synced.as_ref().unwrap_or(prepared);
+    let result = match compressed_bundle(bundle, peer, options, sink).await {
+        Ok(Some(compressed)) => {
+            let result =
+                send_source(reader, writer, transport, &compressed, peer, options, sink).await;
+            compressed.cleanup().await;
+            result
         }
-        emit(
-            sink,
-            TransferEvent::Progress {
-                done: sent,
-                total: prepared.file_size,
-            },
-        );
+        Ok(None) => send_source(reader, writer, transport, bundle, peer, options, sink).await,
+        Err(err) => Err(err),
+    };
+    if let Some(synced) = synced {
+        synced.cleanup().await;
     }
+    result
+}
 
-    if let Some(pb) = pb {
-        pb.finish_with_message("done");
-    }
+/// Compress `prepared` as one zstd stream for [`SendOptions::archive_level`].
+/// `None` sends it as it is: it is not a bundle, the option is off, or this
+/// build or the receiver cannot handle compressed archives.
+async fn compressed_bundle(
+    prepared: &PreparedTransfer,
+    peer: Option<&HandshakePayload>,
+    options: &SendOptions,
+    sink: Option<&SharedSink>,
+) -> Result<Option<PreparedTransfer>> {
+    let Some(level) = options.archive_level else {
+        return Ok(None);
+    };
+    let TransferSource::Path(bundle_path) = &prepared.source else {
+        return Ok(None);
+    };
+    if prepared.content_kind != TransferContentKind::Bundle || !compression::SUPPORTED {
+        return Ok(None);
+    }
+    if !peer.is_some_and(|peer| peer.supports(crypto::FEATURE_ZSTD_ARCHIVE)) {
+        status(
+            sink,
+            "Receiver cannot extract compressed archives; sending the archive uncompressed.",
+        );
+        return Ok(None);
+    }
+
+    status(sink, "Compressing archive...");
+    let (path, hash) = bundle::compress_bundle(bundle_path, level).await?;
+    let result: Result<PreparedTransfer> = async {
+        let file_size = tokio::fs::metadata(&path).await?.len();
+        let segments = if options.verify_chunks {
+            hash_file_segments(&path, sink).await?.1
+        } else {
+            Vec::new()
+        };
+        status(
+            sink,
+            format!(
+                "Archive compressed from {} to {}.",
+                human_bytes(prepared.file_size),
+                human_bytes(file_size)
+            ),
+        );
+        Ok(PreparedTransfer {
+            source: TransferSource::Path(path.clone()),
+            file_size,
+            hash,
+            // Offsets into the tar no longer match the compressed data.
+            bundle_entries: Vec::new(),
+            segments,
+            zstd_archive: true,
+            cleanup_path: Some(path.clone()),
+            ..prepared.clone()
+        })
+    }
+    .await;
+    if result.is_err() {
+        let _ = tokio::fs::remove_file(&path).await;
+    }
+    result.map(Some)
+}
+
+/// Ask the receiver for the manifest of what it already has and rebuild the
+/// bundle without those files. `None` means every file goes out: the
+/// receiver does not support sync mode or is not in it.
+async fn sync_bundle<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    transport: &mut snow::TransportState,
+    prepared: &PreparedTransfer,
+    peer: Option<&HandshakePayload>,
+    options: &SendOptions,
+    sink: Option<&SharedSink>,
+) -> Result<Option<PreparedTransfer>>
+where
+    R: tokio::io::AsyncRead + Unpin,
+    W: tokio::io::AsyncWrite + Unpin,
+{
+    if !peer.is_some_and(|peer| peer.supports(crypto::FEATURE_SYNC)) {
+        status(
+            sink,
+            "Receiver does not support sync mode; sending every file.",
+        );
+        return Ok(None);
+    }
+    let roots = bundle::archive_roots(&prepared.sync_paths)?;
+    let request = format!("{}{}\n", SYNC_PREFIX, serde_json::to_string(&roots)?);
+    crypto::encrypted_write(writer, transport, request.as_bytes()).await?;
+
+    let reply = crypto::encrypted_read(reader, transport).await?;
+    let frames = match SyncReply::parse(&String::from_utf8_lossy(&reply))? {
+        SyncReply::Manifest(frames) if frames <= manifest::MAX_FRAMES => frames,
+        SyncReply::Manifest(frames) => {
+            bail!("Receiver sent an oversized manifest ({} frames)", frames)
+        }
+        SyncReply::Declined => {
+            status(sink, "Receiver is not in sync mode; sending every file.");
+            return Ok(None);
+        }
+    };
+    let mut json = Vec::new();
+    for _ in 0..frames {
+        json.extend(crypto::encrypted_read(reader, transport).await?);
+    }
+    let have = Manifest::from_json(&json).context("invalid sync manifest")?;
+    status(
+        sink,
+        format!("Receiver listed {} files; comparing...", have.len()),
+    );
+    prepare_bundle(&prepared.sync_paths, options, Some(have), sink)
+        .await
+        .map(Some)
+}
+
+/// [`send_prepared`] without the sync step.
+async fn send_source<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    transport: &mut snow::TransportState,
+    prepared: &PreparedTransfer,
+    peer: Option<&HandshakePayload>,
+    options: &SendOptions,
+    sink: Option<&SharedSink>,
+) -> Result<()>
+where
+    R: tokio::io::AsyncRead + Unpin,
+    W: tokio::io::AsyncWrite + Unpin,
+{
+    match &prepared.source {
+        TransferSource::Path(path) if prepared.streaming => {
+            let stop = options
+                .follow
+                .clone()
+                .context("follow mode has no stop signal")?;
+            let file = open_source(writer, transport, path, sink).await?;
+            let mut file = FollowReader::new(file, stop);
+            send_file(
+                reader, writer, transport, &mut file, prepared, peer, options, sink,
+            )
+            .await
+            .map_err(|err| map_source_read(err, path, sink))?;
+            // An empty frame ends the data; the trailer carries what the
+            // header could not.
+            let (size, blake3) = file.finish();
+            crypto::encrypted_write(writer, transport, &[]).await?;
+            let trailer = StreamTrailer { size, blake3 }.to_wire()?;
+            crypto::encrypted_write(writer, transport, &trailer).await?;
+            status(
+                sink,
+                format!("Stopped following after {}.", human_bytes(size)),
+            );
+            Ok(())
+        }
+        TransferSource::Path(path) => {
+            let mut file = open_source(writer, transport, path, sink).await?;
+            send_file(
+                reader, writer, transport, &mut file, prepared, peer, options, sink,
+            )
+            .await
+            .map_err(|err| map_source_read(err, path, sink))
+        }
+        TransferSource::Memory(data) => {
+            let mut cursor = &data[..];
+            send_file(
+                reader,
+                writer,
+                transport,
+                &mut cursor,
+                prepared,
+                peer,
+                options,
+                sink,
+            )
+            .await
+        }
+    }
+}
+
+/// A source [`send_file`] can start part-way through, for a receiver that
+/// answered [`HeaderAck::Resume`], and show the head of for one that
+/// answered [`HeaderAck::Preview`].
+trait SendSource: tokio::io::AsyncRead + Unpin {
+    async fn skip_to(&mut self, offset: u64) -> io::Result<()>;
+
+    /// Up to the first `len` bytes, leaving the source at its start.
+    async fn head(&mut self, len: usize) -> io::Result<Vec<u8>>;
+}
+
+impl SendSource for File {
+    async fn skip_to(&mut self, offset: u64) -> io::Result<()> {
+        self.seek(SeekFrom::Start(offset)).await.map(|_| ())
+    }
+
+    async fn head(&mut self, len: usize) -> io::Result<Vec<u8>> {
+        let mut head = Vec::with_capacity(len);
+        (&mut *self).take(len as u64).read_to_end(&mut head).await?;
+        self.seek(SeekFrom::Start(0)).await?;
+        Ok(head)
+    }
+}
+
+impl SendSource for &[u8] {
+    async fn skip_to(&mut self, offset: u64) -> io::Result<()> {
+        let offset = usize::try_from(offset)
+            .ok()
+            .filter(|offset| *offset <= self.len())
+            .ok_or(io::ErrorKind::UnexpectedEof)?;
+        *self = &self[offset..];
+        Ok(())
+    }
+
+    async fn head(&mut self, len: usize) -> io::Result<Vec<u8>> {
+        Ok(self[..len.min(self.len())].to_vec())
+    }
+}
+
+impl<S: tokio::io::AsyncRead + Unpin> SendSource for FollowReader<S> {
+    async fn skip_to(&mut self, _offset: u64) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "a followed file cannot be resumed",
+        ))
+    }
+
+    async fn head(&mut self, _len: usize) -> io::Result<Vec<u8>> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "a followed file cannot be previewed",
+        ))
+    }
+}
+
+/// Send the header, wait for the receiver's acceptance and stream `source`,
+/// skipping whatever part the receiver already has.
+#[allow(clippy::too_many_arguments)]
+async fn send_file<R, W, S>(
+    reader: &mut R,
+    writer: &mut W,
+    transport: &mut snow::TransportState,
+    source: &mut S,
+    prepared: &PreparedTransfer,
+    peer: Option<&HandshakePayload>,
+    options: &SendOptions,
+    sink: Option<&SharedSink>,
+) -> Result<()>
+where
+    R: tokio::io::AsyncRead + Unpin,
+    W: tokio::io::AsyncWrite + Unpin,
+    S: SendSource,
+{
+    let sequenced =
+        options.sequence_chunks && peer.is_some_and(|peer| peer.supports(crypto::FEATURE_SEQUENCE));
+    if options.sequence_chunks && !sequenced {
+        status(
+            sink,
+            "Receiver does not support chunk sequence numbers; sending without them.",
+        );
+    }
+    let compress_level = compression_level(options, peer);
+    if options.compress_level.is_some() && compress_level.is_none() {
+        status(
+            sink,
+            "Receiver does not support compression; sending uncompressed.",
+        );
+    }
+    let too_many_segments = prepared.segments.len() as u64 > segments::MAX_SEGMENTS;
+    let verify_chunks = !prepared.segments.is_empty()
+        && !too_many_segments
+        && peer.is_some_and(|peer| peer.supports(crypto::FEATURE_VERIFY_CHUNKS));
+    if too_many_segments {
+        status(
+            sink,
+            "File is too large for segment verification; sending without it.",
+        );
+    } else if options.verify_chunks && !prepared.segments.is_empty() && !verify_chunks {
+        status(
+            sink,
+            "Receiver does not support segment verification; sending without it.",
+        );
+    }
+    let header = FileHeader {
+        name: prepared.wire_name.clone(),
+        size: if prepared.streaming {
+            0
+        } else {
+            prepared.file_size
+        },
+        blake3: prepared.hash.clone(),
+        content_kind: Some(prepared.content_kind),
+        item_count: Some(prepared.item_count),
+        logical_name: (prepared.content_kind == TransferContentKind::Bundle)
+            .then(|| prepared.logical_name.clone()),
+        name_raw: prepared.name_raw.clone(),
+        streaming: prepared.streaming,
+        sequenced,
+        segment_size: verify_chunks.then_some(segments::SEGMENT_SIZE),
+        sync: prepared.synced,
+        compression_level: compress_level,
+        zstd_archive: prepared.zstd_archive,
+    };
+    let header_bytes = header.to_wire()?;
+    crypto::encrypted_write(writer, transport, &header_bytes).await?;
+
+    let ack = read_ack(reader, transport, options.ack_timeout, sink).await?;
+    let mut ack = HeaderAck::parse(&String::from_utf8_lossy(&ack));
+    if let HeaderAck::Preview(len) = ack {
+        send_preview(writer, transport, source, prepared, len).await?;
+        status(sink, "Receiver is previewing the file...");
+        let decision = read_ack(reader, transport, options.ack_timeout, sink).await?;
+        ack = HeaderAck::parse(&String::from_utf8_lossy(&decision));
+    }
+    let offset = match ack {
+        HeaderAck::Accept => 0,
+        HeaderAck::Resume(offset) => {
+            if prepared.streaming || offset > prepared.file_size {
+                bail!("Receiver asked to resume at an invalid offset ({})", offset);
+            }
+            source.skip_to(offset).await?;
+            status(
+                sink,
+                format!("Receiver has the first {}; resuming.", human_bytes(offset)),
+            );
+            offset
+        }
+        HeaderAck::AlreadyHave => {
+            status(sink, "Receiver already has this file; nothing to send.");
+            return Ok(());
+        }
+        HeaderAck::Preview(_) => bail!("Receiver asked for a second preview"),
+        HeaderAck::Reject(reason) => return Err(Rejected { reason }.into()),
+    };
+    if verify_chunks {
+        for frame in segments::encode(&prepared.segments) {
+            crypto::encrypted_write(writer, transport, &frame).await?;
+        }
+    }
+
+    let transfer_label = if prepared.content_kind == TransferContentKind::Bundle {
+        format!(
+            "Receiver accepted. Sending {} files...",
+            prepared.item_count
+        )
+    } else if prepared.streaming {
+        "Receiver accepted. Following file until stopped...".to_string()
+    } else {
+        "Receiver accepted. Sending file...".to_string()
+    };
+    status(sink, transfer_label);
+
+    let pb = if !events::presented(sink) {
+        Some(transfer_progress_bar(
+            (!prepared.streaming).then_some(prepared.file_size),
+            options.transfer.progress_interval,
+        ))
+    } else {
+        None
+    };
+    let mut tracker = BundleTracker::from_layout(prepared.bundle_entries.clone());
+    let stats = options.transfer.stats.as_deref();
+    if let Some(stats) = stats {
+        stats.begin(header.size);
+    }
+    let mut report = |sent: u64| {
+        if let Some(pb) = &pb {
+            pb.set_position(sent);
+        }
+        emit(
+            sink,
+            TransferEvent::Progress {
+                done: sent,
+                total: (!header.streaming).then_some(header.size),
+            },
+        );
+        if sink.is_some() {
+            for event in tracker.advance(sent) {
+                emit(sink, event);
+            }
+        }
+    };
+    let mut throttle = ProgressThrottle::new(options.transfer.progress_interval);
+    let depth = options.pipeline_depth;
+    let frames = Framing {
+        sequenced,
+        compress_level,
+    };
+    let streamed = stream_chunks(source, writer, transport, depth, frames, |sent| {
+        let sent = offset + sent;
+        if let Some(stats) = stats {
+            stats.record(sent);
+        }
+        if throttle.ready(sent, header.size) {
+            report(sent);
+        }
+    })
+    .await?;
+    let sent = offset + streamed;
+    if throttle.skipped(sent) {
+        report(sent);
+    }
+
+    if let Some(pb) = pb {
+        pb.finish_with_message("done");
+    }
 
-    if sent != prepared.file_size {
+    if !prepared.streaming && sent != prepared.file_size {
         bail!(
             "local file changed during transfer: read {} of {} bytes",
             sent,
@@ -372,14 +1387,235 @@ where
     Ok(())
 }
 
-/// Wait for the receiver's "DONE" acknowledgement after finishing the stream.
+/// Send the first `len` bytes of `source` for a receiver that answered
+/// [`HeaderAck::Preview`], as many data frames as they fill.
+async fn send_preview<W, S>(
+    writer: &mut W,
+    transport: &mut snow::TransportState,
+    source: &mut S,
+    prepared: &PreparedTransfer,
+    len: u64,
+) -> Result<()>
+where
+    W: tokio::io::AsyncWrite + Unpin,
+    S: SendSource,
+{
+    if prepared.streaming || len > MAX_PREVIEW_LEN || len > prepared.file_size {
+        bail!("Receiver asked for an invalid preview ({} bytes)", len);
+    }
+    let head = source.head(len as usize).await?;
+    if head.len() as u64 != len {
+        bail!(
+            "local file changed during transfer: read {} of {} preview bytes",
+            head.len(),
+            len
+        );
+    }
+    for frame in head.chunks(CHUNK_SIZE) {
+        crypto::encrypted_write(writer, transport, frame).await?;
+    }
+    Ok(())
+}
+
+/// Read the receiver's answer to the header, giving up after `wait`.
+async fn read_ack<R>(
+    reader: &mut R,
+    transport: &mut snow::TransportState,
+    wait: Option<Duration>,
+    sink: Option<&SharedSink>,
+) -> Result<Vec<u8>>
+where
+    R: tokio::io::AsyncRead + Unpin,
+{
+    let read = crypto::encrypted_read(reader, transport);
+    let Some(waited) = wait else {
+        return read.await;
+    };
+    match tokio::time::timeout(waited, read).await {
+        Ok(ack) => ack,
+        Err(_) => {
+            let err = AckTimeout { waited };
+            emit(
+                sink,
+                TransferEvent::Error {
+                    code: "ack_timeout".to_string(),
+                    message: err.to_string(),
+                    partial_path: None,
+                },
+            );
+            Err(err.into())
+        }
+    }
+}
+
+/// Layout of the data frames [`stream_chunks`] writes.
+#[derive(Debug, Clone, Copy)]
+struct Framing {
+    /// Start each frame with its index, see [`FileHeader::sequenced`].
+    sequenced: bool,
+    /// Follow that with a flag byte and compress the data at this level
+    /// when it shrinks, see [`FileHeader::compression_level`].
+    compress_level: Option<i32>,
+}
+
+impl Framing {
+    /// Bytes in front of the file data of every frame.
+    fn prefix(self) -> usize {
+        let sequence = if self.sequenced { SEQUENCE_LEN } else { 0 };
+        let flag = if self.compress_level.is_some() {
+            compression::FLAG_LEN
+        } else {
+            0
+        };
+        sequence + flag
+    }
+
+    /// Fill in the prefix of `frame`, the `index`th frame, whose data
+    /// follows [`Framing::prefix`] bytes, compressing the data if asked to.
+    fn finish(self, frame: &mut Vec<u8>, index: u64) {
+        let mut flag = 0;
+        if self.sequenced {
+            frame[..SEQUENCE_LEN].copy_from_slice(&index.to_le_bytes());
+            flag = SEQUENCE_LEN;
+        }
+        if let Some(level) = self.compress_level {
+            compression::pack(frame, flag, level);
+        }
+    }
+}
+
+/// Read `source` in `CHUNK_SIZE` blocks, encrypt each block and write it as
+/// a frame laid out as `framing` says, calling `on_progress` with the
+/// running byte count after each frame is written. Returns the number of
+/// file bytes sent, before any compression.
+///
+/// With `depth > 1` reading, compression plus encryption, and writing run
+/// as overlapping stages connected by channels holding up to `depth`
+/// chunks each. The middle stage runs on a blocking thread so the CPU work
+/// overlaps the I/O. Frames are still encrypted and written strictly in
+/// read order, as the Noise nonces require.
+///
+/// snow cannot lend a transport state to another thread, so the real one
+/// moves to that thread for the stream and a [`crypto::placeholder_transport`]
+/// stands in for it. It is put back once the stages finish, including on
+/// error; if this future is dropped mid-stream `transport` is left as the
+/// placeholder, which is fine since the connection is unusable by then.
+async fn stream_chunks<S, W>(
+    source: &mut S,
+    writer: &mut W,
+    transport: &mut snow::TransportState,
+    depth: usize,
+    framing: Framing,
+    mut on_progress: impl FnMut(u64),
+) -> Result<u64>
+where
+    S: tokio::io::AsyncRead + Unpin,
+    W: tokio::io::AsyncWrite + Unpin,
+{
+    let mut sent: u64 = 0;
+    let prefix = framing.prefix();
+    let mut index = 0u64;
+
+    if depth <= 1 {
+        let mut buf = Vec::with_capacity(prefix + CHUNK_SIZE);
+        loop {
+            buf.resize(prefix + CHUNK_SIZE, 0);
+            let n = source.read(&mut buf[prefix..]).await.map_err(SourceRead)?;
+            if n == 0 {
+                break;
+            }
+            buf.truncate(prefix + n);
+            framing.finish(&mut buf, index);
+            index += 1;
+            crypto::encrypted_write(writer, transport, &buf).await?;
+            sent += n as u64;
+            on_progress(sent);
+        }
+        return Ok(sent);
+    }
+
+    let (plain_tx, mut plain_rx) = mpsc::channel::<(Vec<u8>, usize)>(depth);
+    let (cipher_tx, cipher_rx) = mpsc::channel::<(Vec<u8>, usize)>(depth);
+
+    let read_stage = async move {
+        loop {
+            let mut buf = vec![0u8; prefix + CHUNK_SIZE];
+            let n = source.read(&mut buf[prefix..]).await.map_err(SourceRead)?;
+            if n == 0 {
+                break;
+            }
+            buf.truncate(prefix + n);
+            if plain_tx.send((buf, n)).await.is_err() {
+                break;
+            }
+        }
+        Ok::<_, anyhow::Error>(())
+    };
+
+    let mut owned = std::mem::replace(transport, crypto::placeholder_transport()?);
+    let encrypt_stage = tokio::task::spawn_blocking(move || {
+        let mut encrypt = || {
+            while let Some((mut frame, len)) = plain_rx.blocking_recv() {
+                framing.finish(&mut frame, index);
+                index += 1;
+                let ciphertext = crypto::encrypt(&mut owned, &frame)?;
+                if cipher_tx.blocking_send((ciphertext, len)).is_err() {
+                    break;
+                }
+            }
+            Ok::<_, anyhow::Error>(())
+        };
+        let result = encrypt();
+        (owned, result)
+    });
+
+    let write_stage = async {
+        // Owned here so a failed write closes the channel and frees the
+        // encryption thread.
+        let mut cipher_rx = cipher_rx;
+        while let Some((ciphertext, len)) = cipher_rx.recv().await {
+            crypto::write_ciphertext(writer, &ciphertext).await?;
+            sent += len as u64;
+            on_progress(sent);
+        }
+        Ok::<_, anyhow::Error>(())
+    };
+
+    // A failed stage drops the other one and with it its channel, which
+    // ends the encryption thread, so its transport state always comes back.
+    let streamed = tokio::try_join!(read_stage, write_stage);
+    let (owned, encrypted) = encrypt_stage.await.context("encryption stage panicked")?;
+    *transport = owned;
+    streamed?;
+    encrypted?;
+    Ok(sent)
+}
+
+/// The zstd level data frames for `peer` are compressed at:
+/// [`SendOptions::compress_level`] when both this build and the peer
+/// support compression, `None` otherwise.
+fn compression_level(options: &SendOptions, peer: Option<&HandshakePayload>) -> Option<i32> {
+    options.compress_level.filter(|_| {
+        compression::SUPPORTED
+            && peer.is_some_and(|peer| peer.supports(crypto::FEATURE_COMPRESSION))
+    })
+}
+
+/// Wait for the receiver's "DONE" acknowledgement after finishing the
+/// stream, then read the receiver's stream to its end.
+///
+/// The receiver finishes its stream after DONE and closes the connection
+/// with the `done` reason only once the file is saved. If that close wins
+/// the race against the DONE frame, the close itself is the confirmation.
 async fn wait_for_done<R>(reader: &mut R, transport: &mut snow::TransportState) -> Result<()>
 where
     R: tokio::io::AsyncRead + Unpin,
 {
-    let done = crypto::encrypted_read(reader, transport)
-        .await
-        .context("connection lost before receiver confirmation")?;
+    let done = match crypto::encrypted_read(reader, transport).await {
+        Ok(done) => done,
+        Err(err) if closed_by_receiver(&err) => return Ok(()),
+        Err(err) => return Err(err.context("connection lost before receiver confirmation")),
+    };
     let done_str = String::from_utf8_lossy(&done);
     if done_str.trim() != "DONE" {
         bail!(
@@ -387,9 +1623,33 @@ where
             done_str.trim()
         );
     }
+
+    // The transfer is confirmed; only trailing data is worth reporting.
+    // Older receivers close without finishing, which ends the read early.
+    let mut rest = [0u8; 1];
+    if let Ok(1..) = reader.read(&mut rest).await {
+        bail!("unexpected data from receiver after DONE");
+    }
     Ok(())
 }
 
+/// Whether `err` is the receiver closing the connection after a successful
+/// transfer, rather than a reset or a close caused by a failure.
+fn closed_by_receiver(err: &anyhow::Error) -> bool {
+    let Some(read_err) = err
+        .downcast_ref::<std::io::Error>()
+        .and_then(|err| err.get_ref())
+        .and_then(|inner| inner.downcast_ref::<ReadError>())
+    else {
+        return false;
+    };
+    matches!(
+        read_err,
+        ReadError::ConnectionLost(ConnectionError::ApplicationClosed(close))
+            if close.error_code == VarInt::from_u32(0) && close.reason.as_ref() == b"done"
+    )
+}
+
 /// Run the send side (normal mode): create an iroh endpoint, wait for a
 /// receiver to connect, perform the Noise handshake, then stream the file.
 pub async fn run(file_path: &Path) -> Result<()> {
@@ -407,133 +1667,468 @@ pub async fn run_with_sink(file_path: &Path, sink: Option<SharedSink>) -> Result
 }
 
 pub async fn run_paths_with_sink(file_paths: &[PathBuf], sink: Option<SharedSink>) -> Result<()> {
-    let prepared = prepare_send_paths(file_paths, sink.as_ref()).await?;
+    run_paths_with_options(file_paths, sink, &SendOptions::default()).await
+}
+
+pub async fn run_paths_with_options(
+    file_paths: &[PathBuf],
+    sink: Option<SharedSink>,
+    options: &SendOptions,
+) -> Result<()> {
+    let prepared = prepare_send_paths(file_paths, options, sink.as_ref()).await?;
     let cleanup_path = prepared.cleanup_path.clone();
 
     let result: Result<()> = async {
-        status(sink.as_ref(), "Setting up secure connection...");
-        let ep = Endpoint::builder()
-            .alpns(vec![ALPN.to_vec()])
-            .bind()
-            .await
-            .context("failed to create iroh endpoint")?;
+        let (ep, ticket_str, ticket_qr) = bind_and_publish_ticket(options, sink.as_ref()).await?;
+
+        eprintln!();
+        eprintln!("{}", ready_to_send_message(&prepared));
+        eprintln!();
+        eprintln!(
+            "  On the receiving device, run:\n\n    p2p-share receive {}",
+            ticket_str
+        );
+        match ticket_qr.len() {
+            0 => {}
+            1 => {
+                eprintln!();
+                eprintln!("  Or scan this QR code:");
+                eprintln!();
+            }
+            _ => {
+                eprintln!();
+                eprintln!("  Or scan all of these QR codes:");
+                eprintln!();
+            }
+        }
+        qr::print(&ticket_qr);
+        eprintln!();
+        match options.wait_for {
+            Some(count) if count > 1 => {
+                endpoint::close_after(
+                    &ep,
+                    serve_group(&ep, &prepared, count, options, sink.clone()),
+                )
+                .await
+            }
+            _ => {
+                endpoint::close_after(&ep, serve_prepared(&ep, &prepared, options, sink.clone()))
+                    .await
+            }
+        }
+    }
+    .await;
+
+    report_connection_lost(sink.as_ref(), &result);
+    cleanup_temp_file(cleanup_path.as_deref()).await;
+    result
+}
+
+/// Bind a forward-mode endpoint, wait for its home relay and publish the
+/// wait-mode ticket as `Ticket` and `QrPayload` events, one `QrPayload`
+/// per QR code. There are no QR codes when no form of the ticket fits.
+async fn bind_and_publish_ticket(
+    options: &SendOptions,
+    sink: Option<&SharedSink>,
+) -> Result<(Endpoint, String, Vec<TicketQr>)> {
+    status(sink, "Setting up secure connection...");
+    let (ep, home_relay) = endpoint::bind_with_home_relay(
+        alpn::supported_alpns(AlpnRole::Forward),
+        &options.transfer.relay,
+        options.transfer.max_streams,
+        options.transfer.strict_host,
+        |message| status(sink, message),
+    )
+    .await?;
+
+    emit(sink, endpoint::relay_status(home_relay.as_ref()));
+    match &home_relay {
+        Some(HomeRelay { url: relay_url, .. }) => {
+            status(sink, format!("Relay connected: {}", relay_url));
+        }
+        None => {
+            status(sink, "Warning: could not connect to relay (timed out).");
+            status(sink, "Only direct/LAN connections will work.");
+        }
+    }
 
-        status(sink.as_ref(), "Connecting to relay...");
-        let relay_timeout =
-            tokio::time::timeout(Duration::from_secs(10), ep.home_relay().initialized()).await;
+    let node_addr = endpoint::node_addr_with_timeout(&ep, endpoint::NODE_ADDR_TIMEOUT, |message| {
+        status(sink, message)
+    })
+    .await;
+    let advertised_addr = advertised_node_addr(node_addr, options.advertise, sink);
+    let serialized = match options.ticket_ttl {
+        Some(ttl) => ticket::serialize_expiring(&advertised_addr, ttl),
+        None => ticket::serialize_with_report(&advertised_addr),
+    };
+    let ticket_str = match serialized {
+        Ok((ticket_str, report)) => {
+            if let Some(summary) = report.summary() {
+                status(sink, summary);
+            }
+            if options.ticket_uri {
+                ticket::to_uri(&ticket_str)
+            } else {
+                ticket_str
+            }
+        }
+        Err(err) => {
+            ep.close().await;
+            return Err(err);
+        }
+    };
+    emit(sink, TransferEvent::Ticket(ticket_str.clone()));
+    let fallback = qr::relay_only_ticket(&advertised_addr, options.ticket_uri);
+    let ticket_qr = qr::ticket_qr(
+        &ticket_str,
+        fallback.as_deref(),
+        options.multi_qr,
+        |message| status(sink, message),
+    );
+    for part in &ticket_qr {
+        emit(sink, TransferEvent::QrPayload(part.payload.clone()));
+    }
+    Ok((ep, ticket_str, ticket_qr))
+}
+
+/// Generate a wait-mode ticket and exit without waiting for a receiver.
+///
+/// The endpoint is closed before returning and its key is not persisted,
+/// so receivers cannot connect with the ticket afterwards. Useful for
+/// previews, documentation and integrations that only need the `Ticket`
+/// and `QrPayload` events.
+pub async fn issue_ticket(sink: Option<SharedSink>, options: &SendOptions) -> Result<String> {
+    let (ep, ticket_str, ticket_qr) = bind_and_publish_ticket(options, sink.as_ref()).await?;
+    ep.close().await;
+
+    eprintln!();
+    eprintln!("  Ticket (not listening; valid only for this preview):");
+    eprintln!();
+    eprintln!("    {}", ticket_str);
+    eprintln!();
+    if !ticket_qr.is_empty() {
+        qr::print(&ticket_qr);
+        eprintln!();
+    }
+    Ok(ticket_str)
+}
 
-        match &relay_timeout {
-            Ok(relay_url) => {
-                status(sink.as_ref(), format!("Relay connected: {}", relay_url));
+/// Wait for one receiver to connect to `ep` and send `prepared` to it.
+/// The endpoint is left open for the caller to close.
+async fn serve_prepared(
+    ep: &Endpoint,
+    prepared: &PreparedTransfer,
+    options: &SendOptions,
+    sink: Option<SharedSink>,
+) -> Result<()> {
+    status(sink.as_ref(), "Waiting for receiver to connect...");
+
+    let expires = ticket_expiry(options);
+    let mut reconnect = ReconnectWindow::new(options.transfer.reconnect_window);
+    let mut lost = None;
+    loop {
+        let incoming = match reconnect.remaining() {
+            Some(left) => match tokio::time::timeout(left, ep.accept()).await {
+                Ok(incoming) => incoming,
+                Err(_) => {
+                    let err: anyhow::Error = lost.take().expect("a transfer was lost");
+                    return Err(err.context("the receiver did not reconnect in time"));
+                }
+            },
+            None => accept_before(ep, expires, options, sink.as_ref()).await?,
+        }
+        .context("no incoming connection")?;
+        match serve_incoming(ep, incoming, prepared, options, sink.clone()).await {
+            Err(err) if reconnect.retry(&err, |message| status(sink.as_ref(), message)) => {
+                // Report the loss itself if the receiver never makes it
+                // back, not a failed attempt to reconnect.
+                if endpoint::is_connection_lost(&err) {
+                    lost = Some(err);
+                }
+            }
+            served => return served,
+        }
+    }
+}
+
+/// When the wait-mode ticket runs out by this machine's clock, counted from
+/// now, just after it was issued. This is what enforces
+/// [`SendOptions::ticket_ttl`]: it does not depend on the receiver's clock.
+fn ticket_expiry(options: &SendOptions) -> Option<tokio::time::Instant> {
+    options
+        .ticket_ttl
+        .map(|ttl| tokio::time::Instant::now() + ttl)
+}
+
+/// Accept the next connection to `ep`, failing with
+/// [`ticket::TicketExpired`] once `expires` passed.
+async fn accept_before(
+    ep: &Endpoint,
+    expires: Option<tokio::time::Instant>,
+    options: &SendOptions,
+    sink: Option<&SharedSink>,
+) -> Result<Option<Incoming>> {
+    let (Some(expires), Some(ttl)) = (expires, options.ticket_ttl) else {
+        return Ok(ep.accept().await);
+    };
+    match tokio::time::timeout_at(expires, ep.accept()).await {
+        Ok(incoming) => Ok(incoming),
+        Err(_) => {
+            let err = ticket::TicketExpired { ttl };
+            emit(
+                sink,
+                TransferEvent::Error {
+                    code: "ticket_expired".to_string(),
+                    message: err.to_string(),
+                    partial_path: None,
+                },
+            );
+            Err(err.into())
+        }
+    }
+}
+
+/// Wait until `count` receivers have connected to `ep` and finished the
+/// handshake, then send `prepared` to all of them at once. Once the first
+/// is ready the others have [`SendOptions::wait_for_timeout`] to join, after
+/// which the transfer starts with those present. Fails when any of them
+/// fails, once the rest are done.
+async fn serve_group(
+    ep: &Endpoint,
+    prepared: &PreparedTransfer,
+    count: usize,
+    options: &SendOptions,
+    sink: Option<SharedSink>,
+) -> Result<()> {
+    let sink = sink.as_ref();
+    status(
+        sink,
+        format!("Waiting for {} receivers to connect...", count),
+    );
+
+    let ready = gather_receivers(ep, count, options, sink).await?;
+    let started = ready.len();
+    status(sink, format!("Sending to {} receivers...", started));
+    let results = n0_future::join_all(
+        ready
+            .into_iter()
+            .map(|receiver| send_over(receiver, prepared, options, sink)),
+    )
+    .await;
+    let mut errors = results.into_iter().filter_map(Result::err);
+    let Some(first) = errors.next() else {
+        status(sink, format!("Sent to all {} receivers.", started));
+        return Ok(());
+    };
+    let failed = 1 + errors.count();
+    Err(first.context(format!(
+        "{} of {} receivers did not get the transfer",
+        failed, started
+    )))
+}
+
+/// Wait until `count` receivers have connected to `ep` and opened their
+/// streams, each within [`RECEIVER_SETUP_TIMEOUT`], setting them up side by
+/// side so one that stalls holds up nobody. Once the first is ready the
+/// others have [`SendOptions::wait_for_timeout`], after which those still
+/// connecting are dropped. A second connection from a receiver that is
+/// already in the group is ignored.
+async fn gather_receivers(
+    ep: &Endpoint,
+    count: usize,
+    options: &SendOptions,
+    sink: Option<&SharedSink>,
+) -> Result<Vec<ReadyReceiver>> {
+    let expires = ticket_expiry(options);
+    let mut ready: Vec<ReadyReceiver> = Vec::with_capacity(count);
+    let mut pending = JoinSet::new();
+    let mut deadline = None;
+    while ready.len() < count {
+        // The ticket only runs out on a group nobody has joined yet.
+        let first = ready.is_empty() && pending.is_empty();
+        let accept = async {
+            if first {
+                accept_before(ep, expires, options, sink).await
+            } else {
+                Ok(ep.accept().await)
+            }
+        };
+        let expired = async {
+            match deadline {
+                Some(deadline) => tokio::time::sleep_until(deadline).await,
+                None => std::future::pending().await,
+            }
+        };
+        tokio::select! {
+            incoming = accept => {
+                let incoming = incoming?.context("no incoming connection")?;
+                let ep = ep.clone();
+                let transfer = options.transfer.clone();
+                let sink = sink.cloned();
+                pending.spawn(async move {
+                    let setup = accept_receiver(&ep, incoming, &transfer, sink.as_ref());
+                    match tokio::time::timeout(RECEIVER_SETUP_TIMEOUT, setup).await {
+                        Ok(receiver) => receiver,
+                        Err(_) => bail!(
+                            "no stream opened within {}s",
+                            RECEIVER_SETUP_TIMEOUT.as_secs()
+                        ),
+                    }
+                });
+            }
+            Some(joined) = pending.join_next() => {
+                let receiver = match joined.map_err(anyhow::Error::from).and_then(|r| r) {
+                    Ok(receiver) => receiver,
+                    Err(err) => {
+                        status(
+                            sink,
+                            format!("Warning: a receiver failed to connect: {:#}", err),
+                        );
+                        continue;
+                    }
+                };
+                let node_id = receiver.path.node_id();
+                if ready.iter().any(|other| other.path.node_id() == node_id) {
+                    status(sink, "Ignored a second connection from a receiver in the group.");
+                    continue;
+                }
+                ready.push(receiver);
+                status(
+                    sink,
+                    format!("{}/{} receivers connected", ready.len(), count),
+                );
+                if let Some(wait) = options.wait_for_timeout {
+                    deadline.get_or_insert_with(|| tokio::time::Instant::now() + wait);
+                }
             }
-            Err(_) => {
+            () = expired => {
                 status(
-                    sink.as_ref(),
-                    "Warning: could not connect to relay (timed out).",
+                    sink,
+                    format!(
+                        "Stopped waiting for stragglers; starting with {}/{} receivers.",
+                        ready.len(),
+                        count
+                    ),
                 );
-                status(sink.as_ref(), "Only direct/LAN connections will work.");
+                break;
             }
         }
+    }
+    Ok(ready)
+}
 
-        let node_addr = ep.node_addr().initialized().await;
-        let advertised_addr = if sink.is_some() && node_addr.relay_url.is_some() {
-            status(
-                sink.as_ref(),
-                "Mobile stability mode: advertising relay-only ticket.",
-            );
-            NodeAddr::from_parts(node_addr.node_id, node_addr.relay_url.clone(), Vec::new())
-        } else {
-            node_addr
-        };
-        let ticket_str = ticket::serialize(&advertised_addr)?;
-        emit(sink.as_ref(), TransferEvent::Ticket(ticket_str.clone()));
-        emit(sink.as_ref(), TransferEvent::QrPayload(ticket_str.clone()));
-
-        eprintln!();
-        eprintln!("{}", ready_to_send_message(&prepared));
-        eprintln!();
-        eprintln!(
-            "  On the receiving device, run:\n\n    p2p-share receive {}",
-            ticket_str
-        );
-        eprintln!();
-        eprintln!("  Or scan this QR code:");
-        eprintln!();
-        print_qr(&ticket_str);
-        eprintln!();
-        status(sink.as_ref(), "Waiting for receiver to connect...");
-
-        let incoming = ep.accept().await.context("no incoming connection")?;
-
-        let conn = incoming
-            .accept()
-            .map_err(|e| anyhow::anyhow!("failed to accept connection: {}", e))?
-            .await
-            .map_err(|e| anyhow::anyhow!("connection failed: {}", e))?;
-
-        let remote_node_id = conn.remote_node_id()?;
-        status(sink.as_ref(), "Receiver connected.");
-
-        if let Some(info) = ep.remote_info(remote_node_id) {
-            eprintln!("Connection path: {}", info.conn_type);
-        }
-        let watcher_handle = spawn_conn_type_watcher(&ep, remote_node_id, sink.clone());
-
-        let (mut send_stream, mut recv_stream) = conn
-            .accept_bi()
-            .await
-            .map_err(|e| anyhow::anyhow!("failed to accept bi stream: {}", e))?;
-
-        let (mut transport, code) =
-            crypto::handshake_responder(&mut recv_stream, &mut send_stream).await?;
-        status(
-            sink.as_ref(),
-            format!("Encryption established. Verification code: {}", code),
-        );
-        emit(sink.as_ref(), TransferEvent::HandshakeCode(code));
+/// Send `prepared` over the connection `incoming` is for.
+async fn serve_incoming(
+    ep: &Endpoint,
+    incoming: Incoming,
+    prepared: &PreparedTransfer,
+    options: &SendOptions,
+    sink: Option<SharedSink>,
+) -> Result<()> {
+    let receiver = accept_receiver(ep, incoming, &options.transfer, sink.as_ref()).await?;
+    send_over(receiver, prepared, options, sink.as_ref()).await
+}
 
-        send_file(
-            &mut recv_stream,
-            &mut send_stream,
-            &mut transport,
-            &prepared,
-            sink.as_ref(),
-        )
-        .await?;
+/// A receiver that connected and opened its stream. Its first handshake
+/// message is left unanswered until the transfer starts: receivers wait
+/// for that answer indefinitely, but only 30 seconds for the header.
+type ReadyReceiver = Link<RecvStream, SendStream>;
 
-        send_stream
-            .finish()
-            .map_err(|e| anyhow::anyhow!("failed to finish stream: {}", e))?;
+/// Accept the connection `incoming` is for and the stream the receiver
+/// opens on it.
+async fn accept_receiver(
+    ep: &Endpoint,
+    incoming: Incoming,
+    transfer: &TransferOptions,
+    sink: Option<&SharedSink>,
+) -> Result<ReadyReceiver> {
+    let conn = incoming
+        .accept()
+        .map_err(|e| anyhow::anyhow!("failed to accept connection: {}", e))?
+        .await
+        .map_err(|e| anyhow::anyhow!("connection failed: {}", e))?;
+
+    status(sink, "Receiver connected.");
+    let negotiated = NegotiatedAlpn::from_connection(&conn)?.expect_role(AlpnRole::Forward)?;
+    IrohTransport {
+        ep,
+        conn,
+        negotiated,
+        role: HandshakeRole::Responder,
+        transfer,
+        stream_timeout: None,
+    }
+    .establish(sink)
+    .await
+}
 
-        wait_for_done(&mut recv_stream, &mut transport).await?;
+/// Run the handshake over `link`, send `prepared` and wait for its `DONE`.
+async fn send_over<R, W>(
+    mut link: Link<R, W>,
+    prepared: &PreparedTransfer,
+    options: &SendOptions,
+    sink: Option<&SharedSink>,
+) -> Result<()>
+where
+    R: tokio::io::AsyncRead + Unpin,
+    W: tokio::io::AsyncWrite + Unpin,
+{
+    let (mut transport, hello) = transport::handshake(&mut link, &options.transfer, sink).await?;
+    let (path, latency_ms) = link.path.current();
+    emit_estimate(prepared, &path, latency_ms, sink);
+
+    send_prepared(
+        &mut link.reader,
+        &mut link.writer,
+        &mut transport,
+        prepared,
+        hello.as_ref(),
+        options,
+        sink,
+    )
+    .await?;
 
-        if let Some(handle) = watcher_handle {
-            handle.abort();
-        }
+    link.writer
+        .shutdown()
+        .await
+        .context("failed to finish stream")?;
 
-        eprintln!();
-        print_conn_summary(&ep, remote_node_id, sink.as_ref());
-        status(sink.as_ref(), sent_success_message(&prepared));
-        emit(
-            sink.as_ref(),
-            TransferEvent::Completed(TransferCompleted {
-                file_name: prepared.logical_name.clone(),
-                size_bytes: prepared.file_size,
-                saved_path: None,
-                content_kind: prepared.content_kind,
-                item_count: prepared.item_count,
-            }),
-        );
+    wait_for_done(&mut link.reader, &mut transport).await?;
 
-        conn.close(0u8.into(), b"done");
-        ep.close().await;
+    eprintln!();
+    link.path.finish(sink);
+    status(sink, sent_success_message(prepared));
+    emit(
+        sink,
+        TransferEvent::Completed(TransferCompleted {
+            file_name: prepared.logical_name.clone(),
+            size_bytes: prepared.file_size,
+            saved_path: None,
+            content_kind: prepared.content_kind,
+            item_count: prepared.item_count,
+            mime: None,
+            compression_level: compression_level(options, hello.as_ref()),
+            blake3: (!prepared.streaming).then(|| prepared.hash.clone()),
+        }),
+    );
 
-        Ok(())
-    }
-    .await;
+    link.path.close();
+    Ok(())
+}
 
-    cleanup_temp_file(cleanup_path.as_deref()).await;
-    result
+/// Send an in-memory buffer as the file `name` to the first receiver that
+/// connects to `ep`, without touching disk. `ep` must accept the
+/// [`AlpnRole::Forward`] entries of [`alpn::supported_alpns`]; the
+/// caller is responsible for sharing its ticket and closing it afterwards.
+pub async fn send_bytes_on_endpoint(
+    ep: &Endpoint,
+    name: &str,
+    data: Bytes,
+    sink: Option<SharedSink>,
+    options: &SendOptions,
+) -> Result<()> {
+    let prepared = prepare_send_bytes(name, data).await?;
+    serve_prepared(ep, &prepared, options, sink).await
 }
 
 /// Run the send side (reverse mode): connect to a receiver that is already
@@ -560,108 +2155,226 @@ pub async fn run_reverse_paths_with_sink(
     file_paths: &[PathBuf],
     target: &str,
     sink: Option<SharedSink>,
+) -> Result<()> {
+    run_reverse_paths_with_options(file_paths, target, sink, &SendOptions::default()).await
+}
+
+pub async fn run_reverse_paths_with_options(
+    file_paths: &[PathBuf],
+    target: &str,
+    sink: Option<SharedSink>,
+    options: &SendOptions,
 ) -> Result<()> {
     let target = target.trim();
-    let prepared = prepare_send_paths(file_paths, sink.as_ref()).await?;
+    let prepared = prepare_send_paths(file_paths, options, sink.as_ref()).await?;
     let cleanup_path = prepared.cleanup_path.clone();
 
     let result: Result<()> = async {
-        let addr = ticket::deserialize(target)?;
-        let addr_fallback = addr.clone();
-
-        status(sink.as_ref(), "Connecting to receiver...");
-
-        let ep = Endpoint::builder()
-            .bind()
-            .await
-            .context("failed to create iroh endpoint")?;
-
-        // On mobile, prefer relay-first to avoid unstable direct-path upgrades on
-        // some LAN/IPv6 combinations. Fall back to the full address list.
-        let conn = if sink.is_some() {
-            connect_reverse_relay_first(&ep, addr, addr_fallback, sink.as_ref()).await?
-        } else {
-            ep.connect(addr_fallback, ALPN_REVERSE)
-                .await
-                .context("failed to connect to receiver")?
-        };
-
-        let remote_node_id = conn.remote_node_id()?;
-        status(sink.as_ref(), "Connected to receiver.");
-
-        if let Some(info) = ep.remote_info(remote_node_id) {
-            eprintln!("Connection path: {}", info.conn_type);
+        let mut addr = ticket::deserialize(target)?;
+        match ticket::check_expiry(target, SystemTime::now(), options.transfer.clock_skew) {
+            Ok(warning) => {
+                if let Some(warning) = warning {
+                    status(sink.as_ref(), warning);
+                }
+            }
+            Err(err) => {
+                emit(
+                    sink.as_ref(),
+                    TransferEvent::Error {
+                        code: "ticket_expired".to_string(),
+                        message: err.to_string(),
+                        partial_path: None,
+                    },
+                );
+                return Err(err);
+            }
         }
-        let watcher_handle = spawn_conn_type_watcher(&ep, remote_node_id, sink.clone());
 
-        let (mut send_stream, mut recv_stream) = conn
-            .open_bi()
-            .await
-            .map_err(|e| anyhow::anyhow!("failed to open bi stream: {}", e))?;
+        let on_lan = options.transfer.lan && probe_lan(&mut addr, sink.as_ref()).await;
 
-        let (mut transport, code) =
-            crypto::handshake_initiator(&mut recv_stream, &mut send_stream).await?;
-        status(
-            sink.as_ref(),
-            format!("Encryption established. Verification code: {}", code),
-        );
-        emit(sink.as_ref(), TransferEvent::HandshakeCode(code));
+        status(sink.as_ref(), "Connecting to receiver...");
 
-        send_file(
-            &mut recv_stream,
-            &mut send_stream,
-            &mut transport,
-            &prepared,
-            sink.as_ref(),
+        let ep = endpoint::bind(
+            Vec::new(),
+            &options.transfer.relay,
+            options.transfer.max_streams,
         )
         .await?;
+        let events = sink.clone();
+        let _relay = endpoint::report_home_relay(&ep, move |event| emit(events.as_ref(), event));
+        let mut peer_conn = None;
+        let sent: Result<()> = endpoint::close_after(&ep, async {
+            let mut reconnect = ReconnectWindow::new(options.transfer.reconnect_window);
+            loop {
+                let sent = send_reverse_once(
+                    &ep,
+                    &addr,
+                    on_lan,
+                    &prepared,
+                    options,
+                    sink.as_ref(),
+                    &mut peer_conn,
+                )
+                .await;
+                match sent {
+                    Err(err) if reconnect.retry(&err, |message| status(sink.as_ref(), message)) => {
+                        tokio::time::sleep(endpoint::RECONNECT_RETRY_DELAY).await;
+                    }
+                    sent => return sent,
+                }
+            }
+        })
+        .await;
 
-        send_stream
-            .finish()
-            .map_err(|e| anyhow::anyhow!("failed to finish stream: {}", e))?;
-
-        wait_for_done(&mut recv_stream, &mut transport).await?;
-
-        if let Some(handle) = watcher_handle {
-            handle.abort();
+        match sent {
+            Err(err) if receiver_busy(&err, peer_conn.as_ref()) => {
+                emit(
+                    sink.as_ref(),
+                    TransferEvent::Error {
+                        code: "receiver_busy".to_string(),
+                        message: ReceiverBusy.to_string(),
+                        partial_path: None,
+                    },
+                );
+                Err(ReceiverBusy.into())
+            }
+            Err(err) => {
+                if let Some(event) = endpoint::self_connection_event(&err)
+                    .or_else(|| endpoint::connection_lost_event(&err))
+                {
+                    emit(sink.as_ref(), event);
+                }
+                Err(err)
+            }
+            sent => sent,
         }
+    }
+    .await;
 
-        eprintln!();
-        print_conn_summary(&ep, remote_node_id, sink.as_ref());
-        status(sink.as_ref(), sent_success_message(&prepared));
-        emit(
-            sink.as_ref(),
-            TransferEvent::Completed(TransferCompleted {
-                file_name: prepared.logical_name.clone(),
-                size_bytes: prepared.file_size,
-                saved_path: None,
-                content_kind: prepared.content_kind,
-                item_count: prepared.item_count,
-            }),
-        );
+    cleanup_temp_file(cleanup_path.as_deref()).await;
+    result
+}
 
-        conn.close(0u8.into(), b"done");
-        ep.close().await;
+/// Connect to the receiver at `addr` and send `prepared` to it once,
+/// recording the connection in `peer_conn` for error reporting.
+async fn send_reverse_once(
+    ep: &Endpoint,
+    addr: &NodeAddr,
+    on_lan: bool,
+    prepared: &PreparedTransfer,
+    options: &SendOptions,
+    sink: Option<&SharedSink>,
+    peer_conn: &mut Option<iroh::endpoint::Connection>,
+) -> Result<()> {
+    // On mobile, prefer relay-first to avoid unstable direct-path upgrades on
+    // some LAN/IPv6 combinations. Fall back to the full address list.
+    let (conn, negotiated) = if events::presented(sink) && !on_lan {
+        connect_reverse_relay_first(ep, addr.clone(), addr.clone(), sink).await?
+    } else {
+        endpoint::connect(ep, addr.clone(), AlpnRole::Reverse)
+            .await
+            .context(endpoint::ConnectFailed::to("receiver"))?
+    };
+    *peer_conn = Some(conn.clone());
+
+    status(sink, "Connected to receiver.");
+    let link = IrohTransport {
+        ep,
+        conn,
+        negotiated,
+        role: HandshakeRole::Initiator,
+        transfer: &options.transfer,
+        stream_timeout: None,
+    }
+    .establish(sink)
+    .await?;
+    send_over(link, prepared, options, sink).await
+}
 
-        Ok(())
+/// Connect to a receiver listening with `receive --tcp-listen` and send over
+/// the plain TCP stream, without an iroh endpoint.
+pub async fn run_tcp_paths_with_options(
+    file_paths: &[PathBuf],
+    target: &str,
+    sink: Option<SharedSink>,
+    options: &SendOptions,
+) -> Result<()> {
+    let addr = tcp::resolve_direct_addr(target).await?;
+    let prepared = prepare_send_paths(file_paths, options, sink.as_ref()).await?;
+    let cleanup_path = prepared.cleanup_path.clone();
+
+    let result: Result<()> = async {
+        status(sink.as_ref(), format!("Connecting to {}...", addr));
+        let link = TcpTransport::Dial(addr, &options.transfer.tcp)
+            .establish(sink.as_ref())
+            .await?;
+        status(sink.as_ref(), "Connected to receiver.");
+        send_over(link, &prepared, options, sink.as_ref()).await
     }
     .await;
 
+    report_connection_lost(sink.as_ref(), &result);
     cleanup_temp_file(cleanup_path.as_deref()).await;
     result
 }
 
+/// Whether a failed reverse send was the receiver turning us away because
+/// it is already receiving from someone else. The close can surface while
+/// connecting or on a later stream operation, so both are checked.
+fn receiver_busy(err: &anyhow::Error, conn: Option<&iroh::endpoint::Connection>) -> bool {
+    conn.and_then(|conn| conn.close_reason())
+        .is_some_and(|reason| endpoint::is_busy_close(&reason))
+        || err.chain().any(|cause| {
+            cause
+                .downcast_ref::<ConnectionError>()
+                .is_some_and(endpoint::is_busy_close)
+        })
+}
+
+/// Ask the receiver `addr` names for its LAN address by broadcast and add
+/// what it answers to `addr`. Returns whether it answered.
+async fn probe_lan(addr: &mut NodeAddr, sink: Option<&SharedSink>) -> bool {
+    #[cfg(feature = "lan-probe")]
+    {
+        status(sink, "Looking for the receiver on the local network...");
+        let found = crate::lan::discover(addr.node_id).await;
+        if found.is_empty() {
+            status(sink, "No reply on the local network; connecting as usual.");
+            return false;
+        }
+        let listed = found
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(", ");
+        status(
+            sink,
+            format!("Receiver found on the local network: {}", listed),
+        );
+        addr.direct_addresses.extend(found);
+        true
+    }
+    #[cfg(not(feature = "lan-probe"))]
+    {
+        let _ = addr;
+        status(
+            sink,
+            "Warning: --lan needs the lan-probe feature; connecting as usual.",
+        );
+        false
+    }
+}
+
 async fn connect_reverse_relay_first(
     ep: &Endpoint,
     relay_candidate: NodeAddr,
     fallback: NodeAddr,
     sink: Option<&SharedSink>,
-) -> Result<iroh::endpoint::Connection> {
+) -> Result<(iroh::endpoint::Connection, NegotiatedAlpn)> {
     if relay_candidate.relay_url.is_none() {
-        return ep
-            .connect(fallback, ALPN_REVERSE)
+        return endpoint::connect(ep, fallback, AlpnRole::Reverse)
             .await
-            .context("failed to connect to receiver");
+            .context(endpoint::ConnectFailed::to("receiver"));
     }
 
     let relay_only = NodeAddr::from_parts(
@@ -674,8 +2387,9 @@ async fn connect_reverse_relay_first(
         sink,
         "Trying relay-preferred connect (mobile stability mode)...",
     );
-    match ep.connect(relay_only, ALPN_REVERSE).await {
-        Ok(conn) => Ok(conn),
+    match endpoint::connect(ep, relay_only, AlpnRole::Reverse).await {
+        Ok(connected) => Ok(connected),
+        Err(err) if err.is::<endpoint::SelfConnection>() => Err(err),
         Err(err) => {
             status(
                 sink,
@@ -684,9 +2398,534 @@ async fn connect_reverse_relay_first(
                     err
                 ),
             );
-            ep.connect(fallback, ALPN_REVERSE)
+            endpoint::connect(ep, fallback, AlpnRole::Reverse)
+                .await
+                .context(endpoint::ConnectFailed::to("receiver"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use super::{
+        advertised_node_addr, gather_receivers, prepare_send_bytes, prepare_send_paths,
+        send_prepared, stream_chunks, verify_file, wait_for_done, AdvertiseMode, Framing,
+        PreparedTransfer, SendOptions, SharedSink, SourceUnavailable, TransferSource,
+        UnsupportedFileType,
+    };
+    use crate::alpn::{self, AlpnRole};
+    use crate::crypto::{self, HandshakePayload};
+    use crate::endpoint;
+    use crate::events::{TransferContentKind, TransferEvent};
+    use crate::protocol::{ABORT_PREFIX, CHUNK_SIZE, SEQUENCE_LEN};
+    use iroh::endpoint::{ApplicationClose, ConnectionError, ReadError, VarInt};
+    use iroh::{NodeAddr, RelayUrl, SecretKey, Watcher as _};
+    use tokio::io::AsyncWriteExt;
+
+    /// Send `data` through `stream_chunks` over an in-memory duplex and
+    /// return what the peer decrypted.
+    async fn round_trip(data: &[u8], depth: usize) -> Vec<u8> {
+        let (left, right) = tokio::io::duplex(256 * 1024);
+        let (mut left_read, mut left_write) = tokio::io::split(left);
+        let (mut right_read, mut right_write) = tokio::io::split(right);
+
+        let payload = HandshakePayload::default();
+        let (sender, receiver) = tokio::join!(
+            crypto::handshake_responder(&mut left_read, &mut left_write, &[], &payload),
+            crypto::handshake_initiator(&mut right_read, &mut right_write, &[], &payload),
+        );
+        let (mut send_transport, _, _) = sender.expect("responder handshake");
+        let (mut recv_transport, _, _) = receiver.expect("initiator handshake");
+
+        let mut source = data;
+        let mut progress = Vec::new();
+        let send = stream_chunks(
+            &mut source,
+            &mut left_write,
+            &mut send_transport,
+            depth,
+            Framing {
+                sequenced: false,
+                compress_level: None,
+            },
+            |sent| progress.push(sent),
+        );
+        let receive = async {
+            let mut received = Vec::new();
+            while received.len() < data.len() {
+                let chunk = crypto::encrypted_read(&mut right_read, &mut recv_transport)
+                    .await
+                    .expect("decrypt chunk");
+                received.extend_from_slice(&chunk);
+            }
+            received
+        };
+
+        let (sent, received) = tokio::join!(send, receive);
+        assert_eq!(sent.expect("send"), data.len() as u64);
+        assert_eq!(progress.last().copied(), Some(data.len() as u64));
+        received
+    }
+
+    #[tokio::test]
+    async fn pipelined_send_matches_serial_send() {
+        let data: Vec<u8> = (0..(3 * CHUNK_SIZE + 123))
+            .map(|i| (i.wrapping_mul(31) % 251) as u8)
+            .collect();
+
+        let serial = round_trip(&data, 1).await;
+        let pipelined = round_trip(&data, 8).await;
+        assert_eq!(serial, data);
+        assert_eq!(pipelined, data);
+    }
+
+    /// Counts the reads `stream_chunks` makes of the wrapped data.
+    struct CountingSource<'a> {
+        data: &'a [u8],
+        reads: Arc<AtomicUsize>,
+    }
+
+    impl tokio::io::AsyncRead for CountingSource<'_> {
+        fn poll_read(
+            mut self: std::pin::Pin<&mut Self>,
+            cx: &mut std::task::Context<'_>,
+            buf: &mut tokio::io::ReadBuf<'_>,
+        ) -> std::task::Poll<std::io::Result<()>> {
+            self.reads.fetch_add(1, Ordering::SeqCst);
+            std::pin::Pin::new(&mut self.data).poll_read(cx, buf)
+        }
+    }
+
+    #[tokio::test]
+    async fn pipeline_reads_and_encrypts_while_a_write_is_stalled() {
+        let chunks = 6;
+        let data: Vec<u8> = (0..chunks * CHUNK_SIZE).map(|i| (i % 251) as u8).collect();
+        let (mut send_transport, mut recv_transport) = transport_pair().await;
+
+        // Far smaller than one frame, so the first write stalls until the
+        // peer starts reading.
+        let (left, right) = tokio::io::duplex(1024);
+        let (_, mut left_write) = tokio::io::split(left);
+        let (mut right_read, _) = tokio::io::split(right);
+        let reads = Arc::new(AtomicUsize::new(0));
+        let mut source = CountingSource {
+            data: &data,
+            reads: reads.clone(),
+        };
+
+        let send = stream_chunks(
+            &mut source,
+            &mut left_write,
+            &mut send_transport,
+            8,
+            Framing {
+                sequenced: true,
+                compress_level: None,
+            },
+            |_| {},
+        );
+        let receive = async {
+            // Every chunk is read and encrypted while the first write is
+            // still blocked, which only works if the stages overlap.
+            tokio::time::timeout(Duration::from_secs(10), async {
+                while reads.load(Ordering::SeqCst) < chunks {
+                    tokio::time::sleep(Duration::from_millis(1)).await;
+                }
+            })
+            .await
+            .expect("source not read ahead of the stalled write");
+
+            let mut received = Vec::new();
+            for index in 0..chunks as u64 {
+                let frame = crypto::encrypted_read(&mut right_read, &mut recv_transport)
+                    .await
+                    .expect("decrypt chunk");
+                assert_eq!(frame[..SEQUENCE_LEN], index.to_le_bytes());
+                received.extend_from_slice(&frame[SEQUENCE_LEN..]);
+            }
+            received
+        };
+        let (sent, received) = tokio::join!(send, receive);
+        assert_eq!(sent.expect("send"), data.len() as u64);
+        assert_eq!(received, data);
+
+        // The real transport state is back: the next message decrypts.
+        crypto::encrypted_write(&mut left_write, &mut send_transport, b"after")
+            .await
+            .expect("write after stream");
+        let after = crypto::encrypted_read(&mut right_read, &mut recv_transport)
+            .await
+            .expect("read after stream");
+        assert_eq!(after, b"after");
+    }
+
+    #[test]
+    fn advertise_mode_selects_ticket_addresses() {
+        let node_id = SecretKey::from_bytes(&[7u8; 32]).public();
+        let relay: RelayUrl = "https://relay.example.com".parse().unwrap();
+        let direct = "192.168.1.20:4000".parse().unwrap();
+        let full = NodeAddr::from_parts(node_id, Some(relay.clone()), [direct]);
+
+        let relay_only = advertised_node_addr(full.clone(), AdvertiseMode::RelayOnly, None);
+        assert_eq!(relay_only.relay_url, Some(relay));
+        assert!(relay_only.direct_addresses.is_empty());
+
+        let direct_only = advertised_node_addr(full.clone(), AdvertiseMode::DirectOnly, None);
+        assert!(direct_only.relay_url.is_none());
+        assert_eq!(direct_only.direct_addresses.len(), 1);
+
+        // Without a relay, relay-only falls back to the full address.
+        let no_relay = NodeAddr::from_parts(node_id, None, [direct]);
+        let fallback = advertised_node_addr(no_relay, AdvertiseMode::RelayOnly, None);
+        assert_eq!(fallback.direct_addresses.len(), 1);
+
+        assert_eq!(
+            "relay-only".parse::<AdvertiseMode>().unwrap(),
+            AdvertiseMode::RelayOnly
+        );
+        assert!("lan".parse::<AdvertiseMode>().is_err());
+    }
+
+    #[tokio::test]
+    async fn memory_buffers_are_prepared_without_disk() {
+        let data = bytes::Bytes::from_static(b"generated report");
+        let prepared = prepare_send_bytes(" report.txt ", data.clone())
+            .await
+            .expect("prepare");
+        assert_eq!(prepared.wire_name, "report.txt");
+        assert_eq!(prepared.file_size, data.len() as u64);
+        assert_eq!(prepared.hash, blake3::hash(&data).to_hex().to_string());
+        assert!(matches!(prepared.source, TransferSource::Memory(_)));
+        assert!(prepare_send_bytes("  ", data).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn verify_file_reports_match_and_mismatch() {
+        let path = std::env::temp_dir().join(format!("p2p-share-verify-{}", std::process::id()));
+        std::fs::write(&path, b"audit me").expect("write");
+        let hash = blake3::hash(b"audit me").to_hex().to_string();
+
+        let report = verify_file(&path, &hash.to_uppercase(), None)
+            .await
+            .expect("verify");
+        assert!(report.matches);
+        assert_eq!(report.size_bytes, 8);
+
+        let report = verify_file(&path, &"0".repeat(64), Some(1))
+            .await
+            .expect("verify");
+        assert!(!report.matches);
+        assert_eq!(report.actual, hash);
+        assert!(verify_file(&path, "abc", None).await.is_err());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    /// Prepare `path` for sending, returning the outcome and the codes of
+    /// any `Error` events reported on the way.
+    async fn prepare_path(
+        path: &Path,
+        options: &SendOptions,
+    ) -> (anyhow::Result<PreparedTransfer>, Vec<String>) {
+        let codes = Arc::new(Mutex::new(Vec::new()));
+        let captured = codes.clone();
+        let sink: SharedSink = Arc::new(move |event| {
+            if let TransferEvent::Error { code, .. } = event {
+                captured.lock().unwrap().push(code);
+            }
+        });
+        let prepared = prepare_send_paths(&[path.to_path_buf()], options, Some(&sink)).await;
+        let codes = codes.lock().unwrap().clone();
+        (prepared, codes)
+    }
+
+    fn unsupported_kind(result: anyhow::Result<PreparedTransfer>) -> &'static str {
+        let err = result.expect_err("unsupported file type");
+        err.downcast_ref::<UnsupportedFileType>()
+            .expect("UnsupportedFileType")
+            .kind
+    }
+
+    #[tokio::test]
+    async fn directories_are_sent_as_archives() {
+        let dir = std::env::temp_dir().join(format!("p2p-share-send-dir-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("create dir");
+        std::fs::write(dir.join("a.txt"), b"alpha").expect("write");
+
+        let (prepared, codes) = prepare_path(&dir, &SendOptions::default()).await;
+        let prepared = prepared.expect("prepare");
+        assert_eq!(prepared.content_kind, TransferContentKind::Bundle);
+        assert_eq!(prepared.item_count, 1);
+        assert!(codes.is_empty());
+
+        if let Some(bundle) = prepared.cleanup_path {
+            let _ = std::fs::remove_file(bundle);
+        }
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn special_files_are_rejected_by_type() {
+        let dir = std::env::temp_dir().join(format!("p2p-share-special-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("create dir");
+        let options = SendOptions::default();
+
+        let fifo = dir.join("pipe");
+        let c_path = std::ffi::CString::new(fifo.as_os_str().as_encoded_bytes()).unwrap();
+        assert_eq!(unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) }, 0);
+        let (result, codes) = prepare_path(&fifo, &options).await;
+        assert_eq!(unsupported_kind(result), "FIFO");
+        assert_eq!(codes, ["unsupported_file_type"]);
+
+        let socket = dir.join("socket");
+        let _listener = std::os::unix::net::UnixListener::bind(&socket).expect("bind");
+        let (result, _) = prepare_path(&socket, &options).await;
+        assert_eq!(unsupported_kind(result), "socket");
+
+        let (result, _) = prepare_path(Path::new("/dev/null"), &options).await;
+        assert_eq!(unsupported_kind(result), "character device");
+
+        let block = std::fs::read_dir("/dev").ok().and_then(|entries| {
+            entries.flatten().map(|entry| entry.path()).find(|path| {
+                use std::os::unix::fs::FileTypeExt;
+                std::fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_block_device())
+            })
+        });
+        if let Some(block) = block {
+            let (result, _) = prepare_path(&block, &options).await;
+            assert_eq!(unsupported_kind(result), "block device");
+        }
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn symlinks_are_only_sent_when_followed() {
+        let dir = std::env::temp_dir().join(format!("p2p-share-symlink-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("create dir");
+        std::fs::write(dir.join("target.txt"), b"linked").expect("write");
+        let link = dir.join("link.txt");
+        let _ = std::fs::remove_file(&link);
+        std::os::unix::fs::symlink(dir.join("target.txt"), &link).expect("symlink");
+
+        let (result, codes) = prepare_path(&link, &SendOptions::default()).await;
+        assert_eq!(unsupported_kind(result), "symbolic link");
+        assert_eq!(codes, ["unsupported_file_type"]);
+
+        let options = SendOptions {
+            follow_symlinks: true,
+            ..SendOptions::default()
+        };
+        let (prepared, codes) = prepare_path(&link, &options).await;
+        let prepared = prepared.expect("prepare");
+        assert_eq!(prepared.wire_name, "link.txt");
+        assert_eq!(prepared.file_size, 6);
+        assert!(codes.is_empty());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn files_deleted_after_hashing_abort_the_send() {
+        let path = std::env::temp_dir().join(format!("p2p-share-vanish-{}", std::process::id()));
+        std::fs::write(&path, b"gone before sending").expect("write");
+        let options = SendOptions::default();
+        let (prepared, _) = prepare_path(&path, &options).await;
+        let prepared = prepared.expect("prepare");
+        std::fs::remove_file(&path).expect("delete");
+
+        let (left, right) = tokio::io::duplex(64 * 1024);
+        let (mut recv_read, mut recv_write) = tokio::io::split(left);
+        let (mut send_read, mut send_write) = tokio::io::split(right);
+        let payload = HandshakePayload::default();
+        let (responder, initiator) = tokio::join!(
+            crypto::handshake_responder(&mut recv_read, &mut recv_write, &[], &payload),
+            crypto::handshake_initiator(&mut send_read, &mut send_write, &[], &payload),
+        );
+        let (mut recv_transport, _, _) = responder.expect("responder handshake");
+        let (mut send_transport, _, _) = initiator.expect("initiator handshake");
+
+        let codes = Arc::new(Mutex::new(Vec::new()));
+        let captured = codes.clone();
+        let sink: SharedSink = Arc::new(move |event| {
+            if let TransferEvent::Error { code, .. } = event {
+                captured.lock().unwrap().push(code);
+            }
+        });
+        let err = send_prepared(
+            &mut send_read,
+            &mut send_write,
+            &mut send_transport,
+            &prepared,
+            None,
+            &options,
+            Some(&sink),
+        )
+        .await
+        .expect_err("source is gone");
+        let err = err
+            .downcast_ref::<SourceUnavailable>()
+            .expect("SourceUnavailable");
+        assert_eq!(err.path, path);
+        assert_eq!(*codes.lock().unwrap(), ["source_unavailable"]);
+
+        let line = crypto::encrypted_read(&mut recv_read, &mut recv_transport)
+            .await
+            .expect("abort line");
+        assert!(String::from_utf8_lossy(&line).starts_with(ABORT_PREFIX));
+    }
+
+    /// Handshake over a duplex, let `receiver` play the receiver's last
+    /// moves, then run `wait_for_done` on the sender side.
+    async fn finish_with<F, Fut>(receiver: F) -> anyhow::Result<()>
+    where
+        F: FnOnce(tokio::io::WriteHalf<tokio::io::DuplexStream>, snow::TransportState) -> Fut,
+        Fut: std::future::Future<Output = ()>,
+    {
+        let (left, right) = tokio::io::duplex(64 * 1024);
+        let (mut recv_read, mut recv_write) = tokio::io::split(left);
+        let (mut send_read, mut send_write) = tokio::io::split(right);
+        let payload = HandshakePayload::default();
+        let (responder, initiator) = tokio::join!(
+            crypto::handshake_responder(&mut send_read, &mut send_write, &[], &payload),
+            crypto::handshake_initiator(&mut recv_read, &mut recv_write, &[], &payload),
+        );
+        let (mut send_transport, _, _) = responder.expect("responder handshake");
+        let (recv_transport, _, _) = initiator.expect("initiator handshake");
+
+        receiver(recv_write, recv_transport).await;
+        wait_for_done(&mut send_read, &mut send_transport).await
+    }
+
+    #[tokio::test]
+    async fn done_followed_by_clean_eof_succeeds() {
+        let result = finish_with(|mut writer, mut transport| async move {
+            crypto::encrypted_write(&mut writer, &mut transport, b"DONE\n")
                 .await
-                .context("failed to connect to receiver")
+                .expect("write done");
+            writer.shutdown().await.expect("finish");
+        })
+        .await;
+        assert!(result.is_ok(), "{:?}", result);
+    }
+
+    #[tokio::test]
+    async fn stream_closed_without_done_is_an_error() {
+        let result = finish_with(|mut writer, _| async move {
+            writer.shutdown().await.expect("finish");
+        })
+        .await;
+        let err = result.expect_err("missing DONE must fail");
+        assert!(format!("{:#}", err).contains("before receiver confirmation"));
+    }
+
+    /// Reader standing in for a QUIC stream whose connection was closed.
+    struct ClosedStream(Option<ReadError>);
+
+    impl tokio::io::AsyncRead for ClosedStream {
+        fn poll_read(
+            mut self: std::pin::Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+            _buf: &mut tokio::io::ReadBuf<'_>,
+        ) -> std::task::Poll<std::io::Result<()>> {
+            let err = self.0.take().expect("read once");
+            std::task::Poll::Ready(Err(err.into()))
         }
     }
+
+    fn application_closed(reason: &'static [u8]) -> ReadError {
+        ReadError::ConnectionLost(ConnectionError::ApplicationClosed(ApplicationClose {
+            error_code: VarInt::from_u32(0),
+            reason: reason.into(),
+        }))
+    }
+
+    #[tokio::test]
+    async fn receiver_closing_before_done_arrives_counts_as_confirmation() {
+        let mut transport = handshake_pair().await;
+        let mut reader = ClosedStream(Some(application_closed(b"done")));
+        assert!(wait_for_done(&mut reader, &mut transport).await.is_ok());
+
+        // Dropping the connection on failure closes it with an empty reason.
+        let mut reader = ClosedStream(Some(application_closed(b"")));
+        assert!(wait_for_done(&mut reader, &mut transport).await.is_err());
+        let mut reader = ClosedStream(Some(ReadError::ConnectionLost(ConnectionError::TimedOut)));
+        assert!(wait_for_done(&mut reader, &mut transport).await.is_err());
+    }
+
+    async fn handshake_pair() -> snow::TransportState {
+        transport_pair().await.0
+    }
+
+    /// The responder's and the initiator's transport state after a
+    /// handshake over an in-memory duplex.
+    async fn transport_pair() -> (snow::TransportState, snow::TransportState) {
+        let (left, right) = tokio::io::duplex(64 * 1024);
+        let (mut left_read, mut left_write) = tokio::io::split(left);
+        let (mut right_read, mut right_write) = tokio::io::split(right);
+        let payload = HandshakePayload::default();
+        let (responder, initiator) = tokio::join!(
+            crypto::handshake_responder(&mut left_read, &mut left_write, &[], &payload),
+            crypto::handshake_initiator(&mut right_read, &mut right_write, &[], &payload),
+        );
+        (
+            responder.expect("responder handshake").0,
+            initiator.expect("initiator handshake").0,
+        )
+    }
+
+    #[tokio::test]
+    async fn a_stalled_receiver_does_not_hold_up_the_group() {
+        let ep = iroh::Endpoint::builder()
+            .relay_mode(iroh::RelayMode::Disabled)
+            .alpns(alpn::supported_alpns(AlpnRole::Forward))
+            .bind()
+            .await
+            .expect("bind");
+        let addr = ep.node_addr().initialized().await;
+        let peer = || async {
+            iroh::Endpoint::builder()
+                .relay_mode(iroh::RelayMode::Disabled)
+                .bind()
+                .await
+                .expect("bind")
+        };
+        let options = SendOptions {
+            wait_for_timeout: Some(Duration::from_millis(300)),
+            ..SendOptions::default()
+        };
+
+        let receivers = async {
+            // Connects first but never opens its stream.
+            let stalled = peer().await;
+            let (stalled_conn, _) = endpoint::connect(&stalled, addr.clone(), AlpnRole::Forward)
+                .await
+                .expect("connect");
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            // Joins twice, which must count once.
+            let ready = peer().await;
+            let mut streams = Vec::new();
+            for _ in 0..2 {
+                let (conn, _) = endpoint::connect(&ready, addr.clone(), AlpnRole::Forward)
+                    .await
+                    .expect("connect");
+                let (mut send, _) = conn.open_bi().await.expect("open stream");
+                send.write_all(b"hello").await.expect("write");
+                streams.push((conn, send));
+            }
+            (stalled, stalled_conn, ready, streams)
+        };
+        let (gathered, _peers) = tokio::time::timeout(Duration::from_secs(10), async {
+            tokio::join!(gather_receivers(&ep, 2, &options, None), receivers)
+        })
+        .await
+        .expect("the group must start without the stalled receiver");
+        assert_eq!(gathered.expect("gather").len(), 1);
+    }
 }
@@ -0,0 +1,109 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+/// Byte counters for the transfer in progress, updated after every chunk.
+/// Share one through [`crate::options::TransferOptions::stats`] and read it
+/// from any thread at any cadence; this is cheaper than following progress
+/// events.
+///
+/// Each field is updated independently, so a reader may briefly see a new
+/// byte count with the previous timestamp. [`TransferStats::snapshot`] is
+/// good enough for dashboards, not for accounting.
+#[derive(Debug, Default)]
+pub struct TransferStats {
+    pub bytes_transferred: AtomicU64,
+    /// Size of the transfer, or 0 before its header is known.
+    pub bytes_total: AtomicU64,
+    /// Unix time in milliseconds when the data transfer began.
+    pub started_at_ms: AtomicU64,
+    /// Unix time in milliseconds of the last update.
+    pub updated_at_ms: AtomicU64,
+}
+
+impl TransferStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reset the counters for a new transfer of `total` bytes.
+    pub(crate) fn begin(&self, total: u64) {
+        let now = unix_millis();
+        self.bytes_transferred.store(0, Ordering::Relaxed);
+        self.bytes_total.store(total, Ordering::Relaxed);
+        self.started_at_ms.store(now, Ordering::Relaxed);
+        self.updated_at_ms.store(now, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record(&self, done: u64) {
+        self.bytes_transferred.store(done, Ordering::Relaxed);
+        self.updated_at_ms.store(unix_millis(), Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> TransferStatsSnapshot {
+        TransferStatsSnapshot {
+            bytes_transferred: self.bytes_transferred.load(Ordering::Relaxed),
+            bytes_total: self.bytes_total.load(Ordering::Relaxed),
+            started_at_ms: self.started_at_ms.load(Ordering::Relaxed),
+            updated_at_ms: self.updated_at_ms.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Plain copy of a [`TransferStats`] at one point in time.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct TransferStatsSnapshot {
+    pub bytes_transferred: u64,
+    pub bytes_total: u64,
+    pub started_at_ms: u64,
+    pub updated_at_ms: u64,
+}
+
+impl TransferStatsSnapshot {
+    /// Average rate since the transfer began, or `None` before any time has
+    /// passed.
+    pub fn bytes_per_sec(&self) -> Option<u64> {
+        let elapsed_ms = self.updated_at_ms.checked_sub(self.started_at_ms)?;
+        if elapsed_ms == 0 {
+            return None;
+        }
+        Some(self.bytes_transferred.saturating_mul(1000) / elapsed_ms)
+    }
+}
+
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|value| value.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TransferStats, TransferStatsSnapshot};
+
+    #[test]
+    fn begin_resets_the_previous_transfer() {
+        let stats = TransferStats::new();
+        stats.begin(100);
+        stats.record(100);
+        stats.begin(50);
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.bytes_transferred, 0);
+        assert_eq!(snapshot.bytes_total, 50);
+        assert!(snapshot.started_at_ms > 0);
+    }
+
+    #[test]
+    fn rate_is_averaged_over_the_elapsed_time() {
+        let snapshot = TransferStatsSnapshot {
+            bytes_transferred: 3000,
+            bytes_total: 6000,
+            started_at_ms: 10_000,
+            updated_at_ms: 12_000,
+        };
+        assert_eq!(snapshot.bytes_per_sec(), Some(1500));
+        assert_eq!(TransferStatsSnapshot::default().bytes_per_sec(), None);
+    }
+}
@@ -0,0 +1,360 @@
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use tokio::net::{TcpListener, TcpSocket, TcpStream};
+
+use crate::crypto;
+use crate::endpoint::ConnectFailed;
+use crate::events::{ConnectionPathKind, TransferEvent};
+
+/// Noise prologue for plain TCP transfers. There is no ALPN to offer or
+/// negotiate, so it binds only the transport; TCP peers from before it
+/// fail the handshake.
+pub(crate) fn noise_prologue() -> Vec<u8> {
+    crypto::prologue("tcp", &[], &[])
+}
+
+/// Socket options for the plain TCP path, see
+/// [`crate::options::TransferOptions::tcp`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TcpTuning {
+    /// Disable Nagle's algorithm so the small control frames (header ack,
+    /// `DONE`) and the tail of each chunk go out without waiting for more
+    /// data. On by default.
+    pub nodelay: bool,
+    /// `SO_SNDBUF` in bytes; `None` keeps the OS default.
+    pub send_buffer: Option<u32>,
+    /// `SO_RCVBUF` in bytes; `None` keeps the OS default.
+    pub recv_buffer: Option<u32>,
+}
+
+impl Default for TcpTuning {
+    fn default() -> Self {
+        Self {
+            nodelay: true,
+            send_buffer: None,
+            recv_buffer: None,
+        }
+    }
+}
+
+impl TcpTuning {
+    fn socket_for(&self, addr: SocketAddr) -> std::io::Result<TcpSocket> {
+        let socket = match addr {
+            SocketAddr::V4(_) => TcpSocket::new_v4()?,
+            SocketAddr::V6(_) => TcpSocket::new_v6()?,
+        };
+        if let Some(size) = self.send_buffer {
+            socket.set_send_buffer_size(size)?;
+        }
+        if let Some(size) = self.recv_buffer {
+            socket.set_recv_buffer_size(size)?;
+        }
+        Ok(socket)
+    }
+
+    /// Apply the per-connection options to an accepted stream. Buffer
+    /// sizes are inherited from the listener.
+    pub(crate) fn apply(&self, stream: &TcpStream) -> std::io::Result<()> {
+        stream.set_nodelay(self.nodelay)
+    }
+}
+
+/// Connect to `addr` with `tuning` applied.
+pub(crate) async fn connect(addr: SocketAddr, tuning: &TcpTuning) -> Result<TcpStream> {
+    let stream = async {
+        let stream = tuning.socket_for(addr)?.connect(addr).await?;
+        tuning.apply(&stream)?;
+        std::io::Result::Ok(stream)
+    }
+    .await
+    .with_context(|| ConnectFailed::to(addr.to_string()))?;
+    Ok(stream)
+}
+
+/// Listen on `addr` with `tuning`'s buffer sizes, which accepted streams
+/// inherit.
+pub(crate) fn listen(addr: SocketAddr, tuning: &TcpTuning) -> Result<TcpListener> {
+    let listen = || {
+        let socket = tuning.socket_for(addr)?;
+        #[cfg(unix)]
+        socket.set_reuseaddr(true)?;
+        socket.bind(addr)?;
+        socket.listen(1024)
+    };
+    listen().with_context(|| format!("failed to listen on {}", addr))
+}
+
+/// Parse an address for the direct TCP path. Accepts IPv4 (`192.168.1.5:9000`),
+/// bracketed IPv6 (`[::1]:9000`) and scoped IPv6 link-local addresses, with
+/// the scope given as an interface name or index (`[fe80::1%eth0]:9000`,
+/// `[fe80::1%2]:9000`).
+pub fn parse_direct_addr(input: &str) -> Result<SocketAddr> {
+    let input = input.trim();
+    if let Some(rest) = input.strip_prefix('[') {
+        let (host, port) = rest
+            .split_once("]:")
+            .with_context(|| format!("invalid address '{}': expected [ipv6]:port", input))?;
+        let (ip, scope) = match host.split_once('%') {
+            Some((ip, scope)) => (ip, Some(scope)),
+            None => (host, None),
+        };
+        let ip: Ipv6Addr = ip.parse().with_context(|| {
+            format!(
+                "invalid address '{}': '{}' is not an IPv6 address",
+                input, ip
+            )
+        })?;
+        let scope_id = match scope {
+            Some(scope) => {
+                parse_scope_id(scope).with_context(|| format!("invalid address '{}'", input))?
+            }
+            None => 0,
+        };
+        return Ok(SocketAddr::V6(SocketAddrV6::new(
+            ip,
+            parse_port(port, input)?,
+            0,
+            scope_id,
+        )));
+    }
+
+    let (host, port) = input
+        .rsplit_once(':')
+        .with_context(|| format!("invalid address '{}': expected ip:port", input))?;
+    if host.contains(':') {
+        bail!(
+            "invalid address '{}': IPv6 addresses must be bracketed, e.g. [fe80::1%eth0]:9000",
+            input
+        );
+    }
+    let ip: Ipv4Addr = host.parse().with_context(|| {
+        format!(
+            "invalid address '{}': '{}' is not an IPv4 address",
+            input, host
+        )
+    })?;
+    Ok(SocketAddr::V4(SocketAddrV4::new(
+        ip,
+        parse_port(port, input)?,
+    )))
+}
+
+/// Like [`parse_direct_addr`], but also accept a host name
+/// (`nas.local:9000`), resolved through DNS to its first address.
+pub async fn resolve_direct_addr(input: &str) -> Result<SocketAddr> {
+    let input = input.trim();
+    let Some((host, port)) = host_name(input) else {
+        return parse_direct_addr(input);
+    };
+    let port = parse_port(port, input)?;
+    tokio::net::lookup_host((host, port))
+        .await
+        .with_context(|| format!("cannot resolve '{}'", host))?
+        .next()
+        .with_context(|| format!("'{}' did not resolve to any address", host))
+}
+
+/// The host and port of `input` when the host is a name rather than an IP
+/// address literal.
+fn host_name(input: &str) -> Option<(&str, &str)> {
+    if input.starts_with('[') {
+        return None;
+    }
+    let (host, port) = input.rsplit_once(':')?;
+    let is_name = host
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+        && host.chars().any(|c| c.is_ascii_alphabetic());
+    is_name.then_some((host, port))
+}
+
+/// Parse a `--tcp-listen` address. A bare `:port` listens on every IPv4
+/// interface; anything else is parsed like [`parse_direct_addr`].
+pub fn parse_listen_addr(input: &str) -> Result<SocketAddr> {
+    let input = input.trim();
+    match input.strip_prefix(':') {
+        Some(port) => Ok(SocketAddr::V4(SocketAddrV4::new(
+            Ipv4Addr::UNSPECIFIED,
+            parse_port(port, input)?,
+        ))),
+        None => parse_direct_addr(input),
+    }
+}
+
+/// Human summary line and event for a plain TCP connection. The path is
+/// always direct; `handshake` is the measured handshake time, used as the
+/// latency.
+pub(crate) fn connection_path(
+    peer: SocketAddr,
+    handshake: Option<Duration>,
+) -> (String, TransferEvent) {
+    let latency_ms = handshake.map(|elapsed| elapsed.as_secs_f64() * 1000.0);
+    let line = match latency_ms {
+        Some(ms) => format!(
+            "Connection path: direct TCP ({}), latency: {:.1}ms",
+            peer, ms
+        ),
+        None => format!("Connection path: direct TCP ({})", peer),
+    };
+    let event = TransferEvent::ConnectionPath {
+        kind: ConnectionPathKind::Direct(peer.to_string()),
+        latency_ms,
+    };
+    (line, event)
+}
+
+fn parse_port(port: &str, input: &str) -> Result<u16> {
+    port.parse()
+        .with_context(|| format!("invalid address '{}': bad port '{}'", input, port))
+}
+
+fn parse_scope_id(scope: &str) -> Result<u32> {
+    if let Ok(index) = scope.parse::<u32>() {
+        return Ok(index);
+    }
+    interface_index(scope).with_context(|| format!("unknown network interface '{}'", scope))
+}
+
+#[cfg(unix)]
+fn interface_index(name: &str) -> Option<u32> {
+    let name = std::ffi::CString::new(name).ok()?;
+    // SAFETY: `name` is a valid NUL-terminated string for the whole call.
+    let index = unsafe { libc::if_nametoindex(name.as_ptr()) };
+    (index != 0).then_some(index)
+}
+
+#[cfg(not(unix))]
+fn interface_index(_name: &str) -> Option<u32> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use std::time::Duration;
+
+    use super::{
+        connect, connection_path, listen, parse_direct_addr, parse_listen_addr,
+        resolve_direct_addr, TcpTuning,
+    };
+    use crate::events::{ConnectionPathKind, TransferEvent};
+
+    #[test]
+    fn ipv4_and_bracketed_ipv6_addresses_parse() {
+        assert_eq!(
+            parse_direct_addr(" 192.168.1.5:9000 ").unwrap(),
+            "192.168.1.5:9000".parse::<SocketAddr>().unwrap()
+        );
+        assert_eq!(
+            parse_direct_addr("[::1]:9000").unwrap(),
+            "[::1]:9000".parse::<SocketAddr>().unwrap()
+        );
+    }
+
+    #[test]
+    fn scoped_ipv6_addresses_keep_their_scope() {
+        let SocketAddr::V6(addr) = parse_direct_addr("[fe80::1%3]:9000").unwrap() else {
+            panic!("expected IPv6");
+        };
+        assert_eq!(addr.scope_id(), 3);
+        assert_eq!(addr.port(), 9000);
+
+        #[cfg(target_os = "linux")]
+        {
+            let SocketAddr::V6(addr) = parse_direct_addr("[fe80::1%lo]:9000").unwrap() else {
+                panic!("expected IPv6");
+            };
+            assert_ne!(addr.scope_id(), 0);
+        }
+        assert!(parse_direct_addr("[fe80::1%no-such-if0]:9000").is_err());
+    }
+
+    #[test]
+    fn malformed_addresses_are_rejected() {
+        for input in [
+            "fe80::1:9000",
+            "[::1]",
+            "[::1]:x",
+            "192.168.1.5",
+            "host:port",
+        ] {
+            assert!(parse_direct_addr(input).is_err(), "{input} should fail");
+        }
+        let err = parse_direct_addr("fe80::1:9000").unwrap_err();
+        assert!(err.to_string().contains("must be bracketed"));
+    }
+
+    #[tokio::test]
+    async fn host_names_are_resolved() {
+        let addr = resolve_direct_addr("localhost:9000").await.unwrap();
+        assert!(addr.ip().is_loopback());
+        assert_eq!(addr.port(), 9000);
+
+        assert_eq!(
+            resolve_direct_addr("[::1]:9000").await.unwrap(),
+            "[::1]:9000".parse::<SocketAddr>().unwrap()
+        );
+        assert!(resolve_direct_addr("localhost:port").await.is_err());
+        assert!(resolve_direct_addr("192.168.1.500:9000").await.is_err());
+    }
+
+    #[test]
+    fn listen_addresses_default_to_all_interfaces() {
+        assert_eq!(
+            parse_listen_addr(":9000").unwrap(),
+            "0.0.0.0:9000".parse::<SocketAddr>().unwrap()
+        );
+        assert_eq!(
+            parse_listen_addr("[::]:9000").unwrap(),
+            "[::]:9000".parse::<SocketAddr>().unwrap()
+        );
+        assert!(parse_listen_addr(":").is_err());
+    }
+
+    #[test]
+    fn tcp_connection_path_is_direct_with_handshake_latency() {
+        let peer: SocketAddr = "192.168.1.5:9000".parse().unwrap();
+        let (line, event) = connection_path(peer, Some(Duration::from_millis(12)));
+        assert_eq!(
+            line,
+            "Connection path: direct TCP (192.168.1.5:9000), latency: 12.0ms"
+        );
+        match event {
+            TransferEvent::ConnectionPath {
+                kind: ConnectionPathKind::Direct(addr),
+                latency_ms,
+            } => {
+                assert_eq!(addr, "192.168.1.5:9000");
+                assert_eq!(latency_ms, Some(12.0));
+            }
+            other => panic!("unexpected event: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn tuned_sockets_disable_nagle_on_both_ends() {
+        let tuning = TcpTuning {
+            send_buffer: Some(256 * 1024),
+            recv_buffer: Some(256 * 1024),
+            ..TcpTuning::default()
+        };
+        let listener = listen("127.0.0.1:0".parse().unwrap(), &tuning).expect("listen");
+        let addr = listener.local_addr().unwrap();
+        let (client, accepted) = tokio::join!(connect(addr, &tuning), listener.accept());
+        let client = client.expect("connect");
+        let (server, _) = accepted.expect("accept");
+        tuning.apply(&server).expect("apply");
+        assert!(client.nodelay().unwrap());
+        assert!(server.nodelay().unwrap());
+
+        let nagle = TcpTuning {
+            nodelay: false,
+            ..TcpTuning::default()
+        };
+        let client = connect(addr, &nagle).await.expect("connect");
+        assert!(!client.nodelay().unwrap());
+    }
+}
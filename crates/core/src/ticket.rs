@@ -1,10 +1,35 @@
 use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use anyhow::{bail, Context, Result};
+use anyhow::{Context, Result};
 use iroh::NodeAddr;
+use serde::{Deserialize, Serialize};
 
 const TICKET_PREFIX: &str = "p2psh";
 
+/// Custom-scheme deep link wrapping a ticket: `p2pshare://t/<ticket>`.
+/// Tapping it on a phone opens the app instead of requiring a paste.
+pub const URI_PREFIX: &str = "p2pshare://t/";
+
+/// Default for [`crate::options::TransferOptions::clock_skew`]: how far the
+/// issuer's clock may be off from ours before an expiring ticket is judged
+/// by it.
+pub const DEFAULT_CLOCK_SKEW: Duration = Duration::from_secs(5 * 60);
+
+/// JSON inside a ticket: the address, plus when an expiring ticket was
+/// issued and for how long. Parsers that predate expiry ignore the extra
+/// fields.
+#[derive(Serialize, Deserialize)]
+struct TicketBody {
+    #[serde(flatten)]
+    addr: NodeAddr,
+    /// Unix time in seconds on the issuer's clock.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    issued_at: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ttl_secs: Option<u64>,
+}
+
 /// Serialize a `NodeAddr` into a compact, copy-pasteable ticket string.
 ///
 /// Format: `p2psh:<base64url-encoded JSON>`
@@ -13,92 +38,429 @@ const TICKET_PREFIX: &str = "p2psh";
 /// a remote peer (Docker/container bridges, loopback, link-local) so the
 /// resulting ticket is as short as possible — important for phone copy-paste.
 pub fn serialize(addr: &NodeAddr) -> Result<String> {
-    let filtered = filter_node_addr(addr);
-    let json = serde_json::to_vec(&filtered).context("failed to serialize node address")?;
+    serialize_with_report(addr).map(|(ticket, _)| ticket)
+}
+
+/// [`serialize`], also reporting which direct addresses were left out and
+/// why, to explain tickets that end up relay-only.
+pub fn serialize_with_report(addr: &NodeAddr) -> Result<(String, FilterReport)> {
+    encode(addr, None)
+}
+
+/// [`serialize_with_report`] for a ticket that expires `ttl` after now.
+/// Only the issuer's clock can enforce that exactly; see [`check_expiry`]
+/// for what a peer makes of it.
+pub fn serialize_expiring(addr: &NodeAddr, ttl: Duration) -> Result<(String, FilterReport)> {
+    encode(addr, Some(ttl))
+}
+
+fn encode(addr: &NodeAddr, ttl: Option<Duration>) -> Result<(String, FilterReport)> {
+    let (filtered, report) = filter_node_addr(addr);
+    let body = TicketBody {
+        addr: filtered,
+        issued_at: ttl.map(|_| unix_secs(SystemTime::now())),
+        ttl_secs: ttl.map(|ttl| ttl.as_secs().max(1)),
+    };
+    let json = serde_json::to_vec(&body).context("failed to serialize node address")?;
     let encoded = data_encoding::BASE64URL_NOPAD.encode(&json);
-    Ok(format!("{}:{}", TICKET_PREFIX, encoded))
+    Ok((format!("{}:{}", TICKET_PREFIX, encoded), report))
+}
+
+/// Why a direct address was left out of a ticket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DropReason {
+    Loopback,
+    LinkLocal,
+    DockerBridge,
+}
+
+impl DropReason {
+    pub fn describe(self) -> &'static str {
+        match self {
+            DropReason::Loopback => "loopback",
+            DropReason::LinkLocal => "link-local",
+            DropReason::DockerBridge => "docker bridge",
+        }
+    }
+}
+
+/// A direct address [`serialize_with_report`] left out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct DroppedAddress {
+    pub addr: SocketAddr,
+    pub reason: DropReason,
+}
+
+/// What [`serialize_with_report`] did with the direct addresses.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct FilterReport {
+    /// Direct addresses that made it into the ticket.
+    pub kept: usize,
+    pub dropped: Vec<DroppedAddress>,
+}
+
+impl FilterReport {
+    /// One line such as `dropped 172.17.0.1:4000 (docker bridge)`, or
+    /// `None` when nothing was dropped.
+    pub fn summary(&self) -> Option<String> {
+        if self.dropped.is_empty() {
+            return None;
+        }
+        let dropped: Vec<_> = self
+            .dropped
+            .iter()
+            .map(|dropped| format!("{} ({})", dropped.addr, dropped.reason.describe()))
+            .collect();
+        let mut line = format!("Ticket: dropped {}", dropped.join(", "));
+        if self.kept == 0 {
+            line.push_str("; no direct addresses left");
+        }
+        Some(line)
+    }
+}
+
+/// Wrap a ticket in its deep-link form.
+pub fn to_uri(ticket: &str) -> String {
+    format!("{}{}", URI_PREFIX, ticket.trim())
+}
+
+/// The bare ticket inside a deep link, or `s` itself (trimmed) when it is
+/// not one.
+pub fn unwrap_uri(s: &str) -> &str {
+    let s = s.trim();
+    match s.get(..URI_PREFIX.len()) {
+        Some(prefix) if prefix.eq_ignore_ascii_case(URI_PREFIX) => &s[URI_PREFIX.len()..],
+        _ => s,
+    }
+}
+
+/// Prefix of one part of a ticket split across several QR codes:
+/// `p2psh-part:<id>:<index>/<count>:<text>`. `id` ties the parts of one
+/// ticket together and `index` counts from 1.
+pub const PART_PREFIX: &str = "p2psh-part";
+
+/// Most parts [`join`] puts back together. The count comes from scanned or
+/// pasted text, so it must not size anything unchecked.
+pub const MAX_PARTS: usize = 64;
+
+/// Split `ticket` into parts of at most `max_len` characters of ticket
+/// text each, to be shown one QR code at a time and put back together
+/// with [`join`].
+pub fn split(ticket: &str, max_len: usize) -> Vec<String> {
+    let ticket = ticket.trim();
+    let hash = blake3::hash(ticket.as_bytes());
+    let id = data_encoding::HEXLOWER.encode(&hash.as_bytes()[..2]);
+    // Tickets are ASCII, so every byte boundary is a char boundary.
+    let chunks: Vec<&[u8]> = ticket.as_bytes().chunks(max_len.max(1)).collect();
+    chunks
+        .iter()
+        .enumerate()
+        .map(|(i, chunk)| {
+            format!(
+                "{}:{}:{}/{}:{}",
+                PART_PREFIX,
+                id,
+                i + 1,
+                chunks.len(),
+                String::from_utf8_lossy(chunk)
+            )
+        })
+        .collect()
+}
+
+/// Whether `s` looks like one part from [`split`].
+pub fn is_part(s: &str) -> bool {
+    s.trim()
+        .get(..PART_PREFIX.len() + 1)
+        .is_some_and(|prefix| prefix.eq_ignore_ascii_case(&format!("{}:", PART_PREFIX)))
+}
+
+/// The ticket the parts from [`split`] were cut from. The parts may come
+/// in any order and repeat, as when a QR code is scanned twice. Fails with
+/// [`InvalidTicket`] when a part is malformed, belongs to another ticket
+/// or is missing.
+pub fn join<S: AsRef<str>>(parts: &[S]) -> Result<String> {
+    let mut id = None;
+    let mut texts: Vec<Option<String>> = Vec::new();
+    for part in parts {
+        let (part_id, index, count, text) = parse_part(part.as_ref()).context(InvalidTicket {
+            reason: "malformed ticket part",
+        })?;
+        if *id.get_or_insert(part_id) != part_id || (!texts.is_empty() && texts.len() != count) {
+            return Err(InvalidTicket {
+                reason: "ticket parts from different tickets",
+            }
+            .into());
+        }
+        texts.resize(count, None);
+        texts[index - 1] = Some(text.to_string());
+    }
+    if texts.is_empty() || texts.iter().any(Option::is_none) {
+        return Err(InvalidTicket {
+            reason: "ticket parts missing; scan every QR code",
+        }
+        .into());
+    }
+    Ok(texts.into_iter().flatten().collect())
 }
 
-/// Deserialize a ticket string back into a `NodeAddr`.
+fn parse_part(part: &str) -> Option<(&str, usize, usize, &str)> {
+    let part = part.trim();
+    if !is_part(part) {
+        return None;
+    }
+    let mut fields = part[PART_PREFIX.len() + 1..].splitn(3, ':');
+    let id = fields.next()?;
+    let (index, count) = fields.next()?.split_once('/')?;
+    let (index, count) = (index.parse().ok()?, count.parse().ok()?);
+    let text = fields.next()?;
+    (count <= MAX_PARTS && (1..=count).contains(&index)).then_some((id, index, count, text))
+}
+
+/// Error for a ticket that cannot be decoded into an address.
+#[derive(Debug)]
+pub struct InvalidTicket {
+    pub reason: &'static str,
+}
+
+impl std::fmt::Display for InvalidTicket {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid ticket: {}", self.reason)
+    }
+}
+
+impl std::error::Error for InvalidTicket {}
+
+/// Deserialize a ticket back into a `NodeAddr`. Accepts both the bare
+/// `p2psh:` form and the deep-link form from [`to_uri`]. Fails with
+/// [`InvalidTicket`].
 pub fn deserialize(ticket: &str) -> Result<NodeAddr> {
-    let data = ticket
+    decode(ticket).map(|body| body.addr)
+}
+
+fn decode(ticket: &str) -> Result<TicketBody> {
+    let data = unwrap_uri(ticket)
         .strip_prefix(&format!("{}:", TICKET_PREFIX))
-        .context("invalid ticket: expected 'p2psh:' prefix")?;
+        .context(InvalidTicket {
+            reason: "expected 'p2psh:' prefix",
+        })?;
     let bytes = data_encoding::BASE64URL_NOPAD
         .decode(data.as_bytes())
-        .context(
-            "invalid ticket: bad base64 encoding (was the ticket truncated during copy-paste?)",
-        )?;
-    let addr: NodeAddr = serde_json::from_slice(&bytes).context(
-        "invalid ticket: corrupt address data (was the ticket truncated during copy-paste?)",
-    )?;
+        .context(InvalidTicket {
+            reason: "bad base64 encoding (was the ticket truncated during copy-paste?)",
+        })?;
+    let body: TicketBody = serde_json::from_slice(&bytes).context(InvalidTicket {
+        reason: "corrupt address data (was the ticket truncated during copy-paste?)",
+    })?;
 
-    if addr.direct_addresses.is_empty() && addr.relay_url.is_none() {
-        bail!("invalid ticket: no addresses or relay URL (was the ticket truncated during copy-paste?)");
+    if body.addr.direct_addresses.is_empty() && body.addr.relay_url.is_none() {
+        return Err(InvalidTicket {
+            reason: "no addresses or relay URL (was the ticket truncated during copy-paste?)",
+        }
+        .into());
     }
 
-    Ok(addr)
+    Ok(body)
+}
+
+/// Error for an expiring ticket that is past its lifetime, by the issuer's
+/// clock or, allowing for clock skew, by ours. Reported as an `Error`
+/// event with code `ticket_expired`.
+#[derive(Debug)]
+pub struct TicketExpired {
+    /// How long the ticket was valid for.
+    pub ttl: Duration,
+}
+
+impl std::fmt::Display for TicketExpired {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "the ticket expired (it was valid for {} s); ask for a new one",
+            self.ttl.as_secs()
+        )
+    }
+}
+
+impl std::error::Error for TicketExpired {}
+
+/// Check an expiring ticket against `now` on our clock, allowing the
+/// issuer's clock to be `skew` off. Returns a warning instead of failing
+/// when the ticket appears issued further than `skew` in the future: the
+/// clocks disagree, so nothing can be said about its age, and the issuer,
+/// which times the ticket on its own clock, still turns it away once it
+/// expired. Tickets without an expiry always pass.
+pub fn check_expiry(ticket: &str, now: SystemTime, skew: Duration) -> Result<Option<String>> {
+    let body = decode(ticket)?;
+    let (Some(issued_at), Some(ttl_secs)) = (body.issued_at, body.ttl_secs) else {
+        return Ok(None);
+    };
+    let now = unix_secs(now);
+    let skew = skew.as_secs();
+    if issued_at > now.saturating_add(skew) {
+        return Ok(Some(format!(
+            "Warning: the ticket was issued {} s in the future by this device's clock; \
+             one of the clocks is off, so its expiry cannot be checked here.",
+            issued_at - now
+        )));
+    }
+    if now > issued_at.saturating_add(ttl_secs).saturating_add(skew) {
+        return Err(TicketExpired {
+            ttl: Duration::from_secs(ttl_secs),
+        }
+        .into());
+    }
+    Ok(None)
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs())
+        .unwrap_or(0)
+}
+
+/// Which transports a ticket lets a peer connect over.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TicketKind {
+    /// Direct addresses only; fails when the peers cannot reach each other.
+    DirectOnly,
+    /// A relay URL only; always connects, but may be slow.
+    RelayOnly,
+    /// Both, so the connection can start on the relay and go direct.
+    Mixed,
+}
+
+impl TicketKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TicketKind::DirectOnly => "direct_only",
+            TicketKind::RelayOnly => "relay_only",
+            TicketKind::Mixed => "mixed",
+        }
+    }
+}
+
+/// What a ticket contains, for showing before connecting.
+#[derive(Debug, Clone, Serialize)]
+pub struct TicketInfo {
+    pub node_id: String,
+    pub relay_url: Option<String>,
+    pub direct_addresses: Vec<String>,
+    pub kind: TicketKind,
+}
+
+/// Parse `ticket` and report which transports it enables.
+pub fn classify(ticket: &str) -> Result<TicketKind> {
+    Ok(kind_of(&deserialize(ticket)?))
+}
+
+/// Parse `ticket` into a [`TicketInfo`].
+pub fn inspect(ticket: &str) -> Result<TicketInfo> {
+    let addr = deserialize(ticket)?;
+    Ok(TicketInfo {
+        node_id: addr.node_id.to_string(),
+        relay_url: addr.relay_url.as_ref().map(|url| url.to_string()),
+        direct_addresses: addr
+            .direct_addresses
+            .iter()
+            .map(|addr| addr.to_string())
+            .collect(),
+        kind: kind_of(&addr),
+    })
+}
+
+fn kind_of(addr: &NodeAddr) -> TicketKind {
+    // `deserialize` guarantees at least one of the two.
+    match (addr.relay_url.is_some(), addr.direct_addresses.is_empty()) {
+        (true, true) => TicketKind::RelayOnly,
+        (true, false) => TicketKind::Mixed,
+        (false, _) => TicketKind::DirectOnly,
+    }
 }
 
 /// Check whether a string looks like an iroh ticket (vs. a plain ip:port address).
 pub fn is_ticket(s: &str) -> bool {
-    let s = s.trim();
+    let s = unwrap_uri(s);
     s.starts_with(&format!("{}:", TICKET_PREFIX))
         || s.starts_with(&format!("{}:", TICKET_PREFIX.to_uppercase()))
 }
 
 /// Build a new `NodeAddr` keeping only addresses that are useful to a remote
 /// peer.  This drops Docker/container bridges, loopback, and link-local.
-fn filter_node_addr(addr: &NodeAddr) -> NodeAddr {
-    let useful: Vec<SocketAddr> = addr
-        .direct_addresses
-        .iter()
-        .copied()
-        .filter(|sa| is_useful_address(sa))
-        .collect();
+fn filter_node_addr(addr: &NodeAddr) -> (NodeAddr, FilterReport) {
+    let mut useful: Vec<SocketAddr> = Vec::new();
+    let mut report = FilterReport::default();
+    for &direct in &addr.direct_addresses {
+        match drop_reason(&direct) {
+            Some(reason) => report.dropped.push(DroppedAddress {
+                addr: direct,
+                reason,
+            }),
+            None => useful.push(direct),
+        }
+    }
+    report.kept = useful.len();
 
-    NodeAddr::from_parts(addr.node_id, addr.relay_url.clone(), useful)
+    (
+        NodeAddr::from_parts(addr.node_id, addr.relay_url.clone(), useful),
+        report,
+    )
 }
 
 /// Heuristic to decide whether a local address is worth advertising to a remote
 /// peer in the ticket.
-fn is_useful_address(addr: &SocketAddr) -> bool {
+pub(crate) fn is_useful_address(addr: &SocketAddr) -> bool {
+    drop_reason(addr).is_none()
+}
+
+/// Why `addr` is not worth advertising, or `None` when it is.
+fn drop_reason(addr: &SocketAddr) -> Option<DropReason> {
     match addr.ip() {
         IpAddr::V4(ip) => {
             // Loopback (127.x.x.x) — useless remotely.
             if ip.is_loopback() {
-                return false;
+                return Some(DropReason::Loopback);
             }
             // Link-local (169.254.x.x) — not routable.
             let o = ip.octets();
             if o[0] == 169 && o[1] == 254 {
-                return false;
+                return Some(DropReason::LinkLocal);
             }
             // Docker / container bridge gateways: 172.16-31.x.1 where
             // the third octet is 0 and fourth is 1.  Real LAN networks on
             // 172.16/12 almost never use x.0.1 as a host address.
             if o[0] == 172 && (16..=31).contains(&o[1]) && o[2] == 0 && o[3] == 1 {
-                return false;
+                return Some(DropReason::DockerBridge);
             }
-            true
+            None
         }
         IpAddr::V6(ip) => {
             if ip.is_loopback() {
-                return false;
+                return Some(DropReason::Loopback);
             }
             // Link-local (fe80::/10)
             let seg = ip.segments();
             if seg[0] & 0xffc0 == 0xfe80 {
-                return false;
+                return Some(DropReason::LinkLocal);
             }
-            true
+            None
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::is_ticket;
+    use iroh::{NodeAddr, SecretKey};
+
+    use std::time::{Duration, SystemTime};
+
+    use super::{
+        check_expiry, classify, deserialize, inspect, is_part, is_ticket, join, serialize,
+        serialize_expiring, serialize_with_report, split, to_uri, unwrap_uri, DropReason,
+        TicketExpired, TicketKind,
+    };
 
     #[test]
     fn ticket_prefix_detection_is_case_insensitive() {
@@ -106,4 +468,156 @@ mod tests {
         assert!(is_ticket("P2PSH:abc"));
         assert!(!is_ticket("127.0.0.1:9000"));
     }
+
+    #[test]
+    fn deep_links_wrap_and_unwrap_tickets() {
+        let node_id = SecretKey::from_bytes(&[3u8; 32]).public();
+        let addr = NodeAddr::from_parts(node_id, None, ["192.168.1.20:4000".parse().unwrap()]);
+        let ticket = serialize(&addr).expect("serialize");
+        let uri = to_uri(&ticket);
+        assert!(uri.starts_with("p2pshare://t/p2psh:"));
+        assert_eq!(unwrap_uri(&format!(" {uri} ")), ticket);
+        assert_eq!(unwrap_uri(&ticket), ticket);
+        assert!(is_ticket(&uri));
+
+        let parsed = deserialize(&uri).expect("uri form");
+        assert_eq!(parsed.node_id, node_id);
+        assert_eq!(deserialize(&ticket).expect("bare form").node_id, node_id);
+    }
+
+    #[test]
+    fn tickets_are_classified_by_transport() {
+        let node_id = SecretKey::from_bytes(&[4u8; 32]).public();
+        let relay: iroh::RelayUrl = "https://relay.example.com".parse().unwrap();
+        let direct = ["192.168.1.20:4000".parse().unwrap()];
+        let ticket = |relay, direct: &[_]| {
+            serialize(&NodeAddr::from_parts(node_id, relay, direct.to_vec())).unwrap()
+        };
+
+        assert_eq!(
+            classify(&ticket(None, &direct)).unwrap(),
+            TicketKind::DirectOnly
+        );
+        assert_eq!(
+            classify(&ticket(Some(relay.clone()), &[])).unwrap(),
+            TicketKind::RelayOnly
+        );
+        let mixed = inspect(&ticket(Some(relay), &direct)).unwrap();
+        assert_eq!(mixed.kind, TicketKind::Mixed);
+        assert_eq!(mixed.direct_addresses, ["192.168.1.20:4000"]);
+        assert_eq!(serde_json::to_value(&mixed).unwrap()["kind"], "mixed");
+        assert!(classify("p2psh:garbage").is_err());
+    }
+
+    #[test]
+    fn dropped_addresses_are_reported_with_a_reason() {
+        let node_id = SecretKey::from_bytes(&[5u8; 32]).public();
+        let relay: iroh::RelayUrl = "https://relay.example.com".parse().unwrap();
+        let direct = [
+            "192.168.1.20:4000",
+            "172.17.0.1:4000",
+            "127.0.0.1:4000",
+            "[fe80::1]:4000",
+        ]
+        .map(|addr| addr.parse().unwrap());
+        let addr = NodeAddr::from_parts(node_id, Some(relay.clone()), direct);
+        let (ticket, report) = serialize_with_report(&addr).unwrap();
+        assert_eq!(ticket, serialize(&addr).unwrap());
+        assert_eq!(report.kept, 1);
+        let reasons: Vec<_> = report.dropped.iter().map(|d| d.reason).collect();
+        assert_eq!(
+            reasons,
+            [
+                DropReason::Loopback,
+                DropReason::DockerBridge,
+                DropReason::LinkLocal
+            ]
+        );
+        let summary = report.summary().unwrap();
+        assert!(
+            summary.contains("172.17.0.1:4000 (docker bridge)"),
+            "{summary}"
+        );
+        assert!(!summary.contains("no direct addresses left"), "{summary}");
+
+        let docker = "172.17.0.1:4000".parse().unwrap();
+        let only_docker = NodeAddr::from_parts(node_id, Some(relay), [docker]);
+        let (ticket, report) = serialize_with_report(&only_docker).unwrap();
+        assert_eq!(classify(&ticket).unwrap(), TicketKind::RelayOnly);
+        assert!(report
+            .summary()
+            .unwrap()
+            .ends_with("no direct addresses left"));
+
+        let (_, report) =
+            serialize_with_report(&NodeAddr::from_parts(node_id, None, [direct[0]])).unwrap();
+        assert_eq!(report.summary(), None);
+    }
+
+    #[test]
+    fn expiring_tickets_tolerate_clock_skew() {
+        let relay: iroh::RelayUrl = "https://relay.example.com".parse().unwrap();
+        let node_id = SecretKey::from_bytes(&[3u8; 32]).public();
+        let addr = NodeAddr::from_parts(node_id, Some(relay), []);
+        let minute = Duration::from_secs(60);
+        let skew = 5 * minute;
+        let (ticket, _) = serialize_expiring(&addr, 10 * minute).unwrap();
+        assert_eq!(deserialize(&ticket).unwrap(), addr);
+        let now = SystemTime::now();
+
+        // Within the lifetime, and past it by less than the skew.
+        assert_eq!(check_expiry(&ticket, now, skew).unwrap(), None);
+        assert_eq!(
+            check_expiry(&ticket, now + 14 * minute, skew).unwrap(),
+            None
+        );
+
+        // Past the lifetime and the skew.
+        let err = check_expiry(&ticket, now + 16 * minute, skew).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<TicketExpired>().unwrap().ttl,
+            10 * minute
+        );
+
+        // Issued ahead of our clock: within the skew it passes quietly,
+        // beyond it with a warning rather than a failure.
+        assert_eq!(check_expiry(&ticket, now - 4 * minute, skew).unwrap(), None);
+        let warning = check_expiry(&ticket, now - 60 * minute, skew).unwrap();
+        assert!(warning.unwrap().contains("in the future"));
+
+        // Tickets without an expiry never expire.
+        let plain = serialize(&addr).unwrap();
+        assert_eq!(
+            check_expiry(&plain, now + 1000 * minute, skew).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn split_tickets_join_back_in_any_order() {
+        let node_id = SecretKey::from_bytes(&[4u8; 32]).public();
+        let addrs = (0..20).map(|i| format!("192.168.1.{i}:4000").parse().unwrap());
+        let addr = NodeAddr::from_parts(node_id, None, addrs);
+        let ticket = to_uri(&serialize(&addr).expect("serialize"));
+
+        let parts = split(&ticket, 100);
+        assert!(parts.len() > 1);
+        assert!(parts.iter().all(|part| is_part(part)));
+        assert!(!is_ticket(&parts[0]) && !is_part(&ticket));
+        assert_eq!(join(&parts).unwrap(), ticket);
+
+        let mut shuffled = parts.clone();
+        shuffled.reverse();
+        shuffled.push(parts[0].clone());
+        assert_eq!(join(&shuffled).unwrap(), ticket);
+        assert_eq!(split(&ticket, 10_000).len(), 1);
+        assert_eq!(join(&split(&ticket, 10_000)).unwrap(), ticket);
+
+        assert!(join(&parts[1..]).is_err());
+        let other = split(&ticket[..ticket.len() - 1], 100);
+        assert!(join(&[parts[0].clone(), other[1].clone()]).is_err());
+        assert!(join(&["p2psh-part:abcd:3/2:xyz"]).is_err());
+        assert!(join(&["p2psh-part:abcd:1/99999999999:xyz"]).is_err());
+        assert!(join::<&str>(&[]).is_err());
+    }
 }
@@ -0,0 +1,529 @@
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use iroh::endpoint::{Connection, ConnectionType, RecvStream, SendStream};
+use iroh::{Endpoint, NodeId, Watcher as _};
+use n0_future::StreamExt;
+use snow::TransportState;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::TcpListener;
+
+use crate::alpn::NegotiatedAlpn;
+use crate::crypto::{self, HandshakePayload};
+use crate::endpoint::{self, AbortOnDrop, RelayPolicy};
+use crate::events::{ConnectionPathKind, TransferEvent, TransferEventSink};
+use crate::options::TransferOptions;
+use crate::qr;
+use crate::tcp::{self, TcpTuning};
+
+type SharedSink = Arc<dyn TransferEventSink>;
+
+/// A way of reaching the peer. The sender and receiver run the handshake
+/// and transfer over whatever [`Link`] a transport establishes, so a new
+/// transport only has to get the two streams up.
+pub(crate) trait Transport {
+    type Reader: AsyncRead + Unpin + Send;
+    type Writer: AsyncWrite + Unpin + Send;
+
+    /// Finish setting up the connection and open the streams the transfer
+    /// runs over.
+    fn establish(
+        self,
+        sink: Option<&SharedSink>,
+    ) -> impl Future<Output = Result<Link<Self::Reader, Self::Writer>>> + Send;
+}
+
+/// Which end of the Noise handshake this side plays: the side that
+/// connected initiates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum HandshakeRole {
+    Initiator,
+    Responder,
+}
+
+/// A connection a [`Transport`] established, ready for [`handshake`].
+pub(crate) struct Link<R, W> {
+    pub reader: R,
+    pub writer: W,
+    pub role: HandshakeRole,
+    /// Noise prologue both sides bind the handshake to.
+    pub prologue: Vec<u8>,
+    /// ALPN the connection negotiated; `None` over plain TCP.
+    pub negotiated: Option<NegotiatedAlpn>,
+    pub path: PathSource,
+}
+
+/// Where the path to the peer is reported from.
+pub(crate) enum PathSource {
+    /// An iroh connection, whose path may change during the transfer and
+    /// is watched until [`PathSource::finish`].
+    Iroh {
+        ep: Endpoint,
+        conn: Connection,
+        node_id: NodeId,
+        watcher: Option<AbortOnDrop>,
+    },
+    /// A plain TCP stream, always direct to `peer`. `latency` is the
+    /// handshake time, measured only on the connecting side.
+    Tcp {
+        peer: SocketAddr,
+        latency: Option<Duration>,
+    },
+}
+
+impl PathSource {
+    /// The peer's node id; `None` over plain TCP.
+    pub fn node_id(&self) -> Option<NodeId> {
+        match self {
+            PathSource::Iroh { node_id, .. } => Some(*node_id),
+            PathSource::Tcp { .. } => None,
+        }
+    }
+
+    /// The peer's address; `None` over iroh, where it can change.
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        match self {
+            PathSource::Iroh { .. } => None,
+            PathSource::Tcp { peer, .. } => Some(*peer),
+        }
+    }
+
+    /// One-line description of the current path, if known.
+    pub fn describe(&self) -> Option<String> {
+        match self {
+            PathSource::Iroh { ep, node_id, .. } => ep
+                .remote_info(*node_id)
+                .map(|info| info.conn_type.to_string()),
+            PathSource::Tcp { peer, .. } => Some(format!("direct TCP ({})", peer)),
+        }
+    }
+
+    /// The current path and its latency in milliseconds, if known.
+    pub fn current(&self) -> (ConnectionPathKind, Option<f64>) {
+        match self {
+            PathSource::Iroh { ep, node_id, .. } => match ep.remote_info(*node_id) {
+                Some(info) => (
+                    path_kind(info.conn_type),
+                    info.latency.map(|d| d.as_secs_f64() * 1000.0),
+                ),
+                None => (ConnectionPathKind::None, None),
+            },
+            PathSource::Tcp { peer, latency } => (
+                ConnectionPathKind::Direct(peer.to_string()),
+                latency.map(|d| d.as_secs_f64() * 1000.0),
+            ),
+        }
+    }
+
+    /// Stop watching the path and print/emit a summary of the final one.
+    /// A TCP path never changes, so it was reported in full after the
+    /// handshake.
+    pub fn finish(&mut self, sink: Option<&SharedSink>) {
+        if let PathSource::Iroh {
+            ep,
+            node_id,
+            watcher,
+            ..
+        } = self
+        {
+            drop(watcher.take());
+            print_conn_summary(ep, *node_id, sink);
+        }
+    }
+
+    /// Wait up to `limit` for the peer to close the connection.
+    pub async fn closed(&self, limit: Duration) {
+        if let PathSource::Iroh { conn, .. } = self {
+            let _ = tokio::time::timeout(limit, conn.closed()).await;
+        }
+    }
+
+    /// Close the connection after a completed transfer. TCP closes when
+    /// the link is dropped.
+    pub fn close(&self) {
+        if let PathSource::Iroh { conn, .. } = self {
+            conn.close(0u8.into(), b"done");
+        }
+    }
+
+    /// Stop watching the path and close the connection after a failed
+    /// transfer, with an empty reason the peer does not mistake for a
+    /// confirmation. TCP closes when the link is dropped.
+    pub fn abort(&mut self) {
+        if let PathSource::Iroh { conn, watcher, .. } = self {
+            drop(watcher.take());
+            conn.close(0u8.into(), b"");
+        }
+    }
+}
+
+/// Error [`IrohTransport`] fails with when the peer did not open its
+/// stream within [`IrohTransport::stream_timeout`].
+#[derive(Debug)]
+pub(crate) struct StreamNotOpened;
+
+impl std::fmt::Display for StreamNotOpened {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("the peer did not open its stream")
+    }
+}
+
+impl std::error::Error for StreamNotOpened {}
+
+/// An iroh connection that is up. Establishing identifies the peer,
+/// enforces the relay rules, starts watching the path and opens the bi
+/// stream, or accepts it when the peer connected.
+pub(crate) struct IrohTransport<'a> {
+    pub ep: &'a Endpoint,
+    pub conn: Connection,
+    pub negotiated: NegotiatedAlpn,
+    pub role: HandshakeRole,
+    pub transfer: &'a TransferOptions,
+    /// How long a responder waits for the peer to open its stream before
+    /// failing with [`StreamNotOpened`]; `None` waits for as long as the
+    /// connection lives.
+    pub stream_timeout: Option<Duration>,
+}
+
+impl Transport for IrohTransport<'_> {
+    type Reader = RecvStream;
+    type Writer = SendStream;
+
+    async fn establish(self, sink: Option<&SharedSink>) -> Result<Link<RecvStream, SendStream>> {
+        let IrohTransport {
+            ep,
+            conn,
+            negotiated,
+            role,
+            transfer,
+            stream_timeout,
+        } = self;
+        let node_id = conn.remote_node_id()?;
+        emit(sink, endpoint::peer_identified(node_id));
+        for event in endpoint::public_addresses(ep) {
+            emit(sink, event);
+        }
+        status(sink, format!("Negotiated protocol: {}", negotiated));
+
+        if let Some(info) = ep.remote_info(node_id) {
+            eprintln!("Connection path: {}", info.conn_type);
+        }
+        if let Err(err) =
+            endpoint::check_relay_pin(ep, &transfer.relay, transfer.strict_host, node_id)
+        {
+            emit(sink, err.event());
+            return Err(err.into());
+        }
+        if let Err(err) =
+            endpoint::check_relay_policy(ep, node_id, transfer.relay_policy, |message| {
+                status(sink, message)
+            })
+            .await
+        {
+            emit(sink, err.event());
+            return Err(err.into());
+        }
+        let watcher = if transfer.no_path_watch {
+            None
+        } else {
+            spawn_conn_type_watcher(ep, &conn, node_id, transfer.relay_policy, sink.cloned())
+        };
+
+        let (writer, reader) = match (role, stream_timeout) {
+            (HandshakeRole::Initiator, _) => conn
+                .open_bi()
+                .await
+                .map_err(|e| anyhow::anyhow!("failed to open bi stream: {}", e))?,
+            (HandshakeRole::Responder, None) => conn
+                .accept_bi()
+                .await
+                .map_err(|e| anyhow::anyhow!("failed to accept bi stream: {}", e))?,
+            (HandshakeRole::Responder, Some(limit)) => {
+                match tokio::time::timeout(limit, conn.accept_bi()).await {
+                    Ok(Ok(streams)) => streams,
+                    Ok(Err(_)) | Err(_) => return Err(StreamNotOpened.into()),
+                }
+            }
+        };
+
+        Ok(Link {
+            reader,
+            writer,
+            role,
+            prologue: negotiated.noise_prologue(),
+            negotiated: Some(negotiated),
+            path: PathSource::Iroh {
+                ep: ep.clone(),
+                conn,
+                node_id,
+                watcher,
+            },
+        })
+    }
+}
+
+/// A plain TCP connection, for LAN transfers that should not touch relays
+/// or STUN.
+pub(crate) enum TcpTransport<'a> {
+    /// Connect to the address; this side initiates the handshake.
+    Dial(SocketAddr, &'a TcpTuning),
+    /// Accept one connection on the listener, which is closed afterwards;
+    /// the peer initiates the handshake.
+    Accept(TcpListener, &'a TcpTuning),
+}
+
+impl Transport for TcpTransport<'_> {
+    type Reader = OwnedReadHalf;
+    type Writer = OwnedWriteHalf;
+
+    async fn establish(
+        self,
+        _sink: Option<&SharedSink>,
+    ) -> Result<Link<OwnedReadHalf, OwnedWriteHalf>> {
+        let (stream, peer, role) = match self {
+            TcpTransport::Dial(addr, tuning) => {
+                let stream = tcp::connect(addr, tuning).await?;
+                let peer = stream.peer_addr().unwrap_or(addr);
+                (stream, peer, HandshakeRole::Initiator)
+            }
+            TcpTransport::Accept(listener, tuning) => {
+                let (stream, peer) = listener
+                    .accept()
+                    .await
+                    .context("failed to accept TCP connection")?;
+                drop(listener);
+                tuning
+                    .apply(&stream)
+                    .context("failed to configure TCP connection")?;
+                (stream, peer, HandshakeRole::Responder)
+            }
+        };
+        let (reader, writer) = stream.into_split();
+        Ok(Link {
+            reader,
+            writer,
+            role,
+            prologue: tcp::noise_prologue(),
+            negotiated: None,
+            path: PathSource::Tcp {
+                peer,
+                latency: None,
+            },
+        })
+    }
+}
+
+/// Run the Noise handshake over `link` in its role and print/emit the
+/// verification code, also as a QR code with
+/// [`TransferOptions::code_qr`]. A TCP link's path is reported here too, as it
+/// cannot change later. Fails with [`Downgraded`](crate::alpn::Downgraded)
+/// when the peer's payload shows an older protocol version was negotiated
+/// than both sides speak.
+/// Returns the transport state and the peer's payload, or `None` when it
+/// sent none.
+pub(crate) async fn handshake<R, W>(
+    link: &mut Link<R, W>,
+    transfer: &TransferOptions,
+    sink: Option<&SharedSink>,
+) -> Result<(TransportState, Option<HandshakePayload>)>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let payload = HandshakePayload::local();
+    let started = Instant::now();
+    let (transport, code, hello) = match link.role {
+        HandshakeRole::Initiator => {
+            crypto::handshake_initiator(
+                &mut link.reader,
+                &mut link.writer,
+                &link.prologue,
+                &payload,
+            )
+            .await?
+        }
+        HandshakeRole::Responder => {
+            crypto::handshake_responder(
+                &mut link.reader,
+                &mut link.writer,
+                &link.prologue,
+                &payload,
+            )
+            .await?
+        }
+    };
+    if let (Some(negotiated), Some(hello)) = (link.negotiated, &hello) {
+        negotiated.check_downgrade(&hello.protocols)?;
+    }
+    // The responder's handshake time includes waiting for the peer, so
+    // only the connecting side reports a latency.
+    if let PathSource::Tcp { latency, .. } = &mut link.path {
+        if link.role == HandshakeRole::Initiator {
+            *latency = Some(started.elapsed());
+        }
+    }
+
+    let code = code.render(transfer.code_format);
+    status(
+        sink,
+        format!("Encryption established. Verification code: {}", code),
+    );
+    if transfer.code_qr {
+        if let Some(code_qr) = qr::code_qr(&code) {
+            eprintln!();
+            eprintln!("  Compare this QR code with the one on the other device:");
+            eprintln!();
+            qr::print(&[code_qr]);
+            eprintln!();
+        }
+    }
+    emit(sink, TransferEvent::HandshakeCode(code));
+    if let PathSource::Tcp { peer, latency } = &link.path {
+        let (path_line, path_event) = tcp::connection_path(*peer, *latency);
+        eprintln!("{}", path_line);
+        emit(sink, path_event);
+    }
+    Ok((transport, hello))
+}
+
+fn emit(sink: Option<&SharedSink>, event: TransferEvent) {
+    if let Some(sink) = sink {
+        sink.on_event(event);
+    }
+}
+
+fn status(sink: Option<&SharedSink>, msg: impl Into<String>) {
+    let msg = msg.into();
+    eprintln!("{}", msg);
+    emit(sink, TransferEvent::Status(msg));
+}
+
+/// Spawn a background task that watches connection type changes and
+/// prints/emits them until the returned guard is dropped.
+fn spawn_conn_type_watcher(
+    ep: &Endpoint,
+    conn: &Connection,
+    node_id: NodeId,
+    relay_policy: RelayPolicy,
+    sink: Option<SharedSink>,
+) -> Option<AbortOnDrop> {
+    let watcher = ep.conn_type(node_id)?;
+    let mut stream = watcher.stream_updates_only();
+    let conn = conn.clone();
+    let handle = tokio::task::spawn(async move {
+        while let Some(conn_type) = stream.next().await {
+            for event in endpoint::enforce_relay_policy(relay_policy, &conn_type, &conn) {
+                if let TransferEvent::Status(message) | TransferEvent::Error { message, .. } =
+                    &event
+                {
+                    eprintln!("{}", message);
+                }
+                emit(sink.as_ref(), event);
+            }
+            match &conn_type {
+                ConnectionType::Direct(addr) => {
+                    eprintln!("Connection upgraded: direct ({})", addr);
+                }
+                ConnectionType::Relay(url) => {
+                    eprintln!("Connection changed: relay ({})", url);
+                }
+                ConnectionType::Mixed(addr, url) => {
+                    eprintln!("Connection changed: mixed (udp: {}, relay: {})", addr, url);
+                }
+                ConnectionType::None => {
+                    eprintln!("Connection path: none (searching...)");
+                }
+            }
+            emit(
+                sink.as_ref(),
+                TransferEvent::ConnectionPath {
+                    kind: path_kind(conn_type),
+                    latency_ms: None,
+                },
+            );
+        }
+    });
+    Some(AbortOnDrop::new(handle))
+}
+
+/// Print/emit a summary of the final connection state.
+fn print_conn_summary(ep: &Endpoint, node_id: NodeId, sink: Option<&SharedSink>) {
+    if let Some(info) = ep.remote_info(node_id) {
+        let latency = info.latency.map(|d| d.as_secs_f64() * 1000.0);
+        let latency_str = latency
+            .map(|d| format!("{:.1}ms", d))
+            .unwrap_or_else(|| "unknown".to_string());
+        eprintln!(
+            "Transfer path: {}, latency: {}",
+            info.conn_type, latency_str
+        );
+
+        emit(
+            sink,
+            TransferEvent::ConnectionPath {
+                kind: path_kind(info.conn_type),
+                latency_ms: latency,
+            },
+        );
+    }
+}
+
+fn path_kind(conn_type: ConnectionType) -> ConnectionPathKind {
+    match conn_type {
+        ConnectionType::Direct(addr) => ConnectionPathKind::Direct(addr.to_string()),
+        ConnectionType::Relay(url) => ConnectionPathKind::Relay(url.to_string()),
+        ConnectionType::Mixed(addr, url) => ConnectionPathKind::Mixed {
+            udp_addr: addr.to_string(),
+            relay_url: url.to_string(),
+        },
+        ConnectionType::None => ConnectionPathKind::None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use super::{handshake, HandshakeRole, PathSource, TcpTransport, Transport};
+    use crate::events::ConnectionPathKind;
+    use crate::options::TransferOptions;
+    use crate::tcp::{self, TcpTuning};
+
+    #[tokio::test]
+    async fn tcp_links_handshake_and_time_the_dialing_side() {
+        let tuning = TcpTuning::default();
+        let transfer = TransferOptions::default();
+        let listener = tcp::listen(SocketAddr::from(([127, 0, 0, 1], 0)), &tuning).unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let accepting = async {
+            let mut link = TcpTransport::Accept(listener, &tuning)
+                .establish(None)
+                .await?;
+            handshake(&mut link, &transfer, None).await?;
+            anyhow::Ok(link)
+        };
+        let dialing = async {
+            let mut link = TcpTransport::Dial(addr, &tuning).establish(None).await?;
+            handshake(&mut link, &transfer, None).await?;
+            anyhow::Ok(link)
+        };
+        let (accepted, dialed) = tokio::join!(accepting, dialing);
+        let (accepted, dialed) = (accepted.unwrap(), dialed.unwrap());
+
+        assert_eq!(accepted.role, HandshakeRole::Responder);
+        assert_eq!(dialed.role, HandshakeRole::Initiator);
+        assert!(matches!(
+            accepted.path,
+            PathSource::Tcp { latency: None, .. }
+        ));
+        assert_eq!(dialed.path.node_id(), None);
+        let (kind, latency_ms) = dialed.path.current();
+        assert!(matches!(kind, ConnectionPathKind::Direct(peer) if peer == addr.to_string()));
+        assert!(latency_ms.is_some());
+    }
+}
@@ -341,11 +341,39 @@ function summarizeEvent(evt: TransferEvent): string {
       return `Handshake: ${typeof evt.value === "string" ? evt.value : "n/a"}`;
     case "progress": {
       const done = typeof evt.done === "number" ? evt.done : 0;
-      const total = typeof evt.total === "number" ? evt.total : 0;
-      return `Progress: ${done}/${total}`;
+      return typeof evt.total === "number" ? `Progress: ${done}/${evt.total}` : `Progress: ${done}`;
     }
     case "connection_path":
       return `Path: ${typeof evt.value === "string" ? evt.value : "unknown"} ${typeof evt.message === "string" ? `(${evt.message})` : ""}`;
+    case "peer_identified":
+      return `Peer: ${typeof evt.message === "string" ? evt.message : "unknown"}`;
+    case "public_address":
+      return `Public address: ${typeof evt.value === "string" ? evt.value : "unknown"}`;
+    case "relay_status":
+      return evt.value === "connected"
+        ? `Relay: ${typeof evt.message === "string" ? evt.message : "connected"}`
+        : "Relay unavailable: LAN only";
+    case "file_started":
+      return `File ${typeof evt.file_index === "number" ? evt.file_index + 1 : "?"}: ${typeof evt.file_name === "string" ? evt.file_name : "file"}`;
+    case "file_progress": {
+      const done = typeof evt.done === "number" ? evt.done : 0;
+      const total = typeof evt.total === "number" ? evt.total : 0;
+      return `File progress: ${done}/${total}`;
+    }
+    case "file_finished":
+      return `File ${typeof evt.file_index === "number" ? evt.file_index + 1 : "?"} done`;
+    case "temp_file":
+      return `Writing to ${typeof evt.value === "string" ? evt.value : "temporary file"}`;
+    case "file_error":
+      return `Skipped ${typeof evt.file_name === "string" ? evt.file_name : "file"}: ${typeof evt.message === "string" ? evt.message : "failed"}`;
+    case "file_skipped":
+      return `Unchanged, skipped ${typeof evt.file_name === "string" ? evt.file_name : "file"}`;
+    case "preview":
+      return `Preview received (${typeof evt.size_bytes === "number" ? evt.size_bytes : 0} bytes)`;
+    case "accept_queue":
+      return `Senders: ${typeof evt.message === "string" ? evt.message : "queue changed"}`;
+    case "estimate":
+      return `Estimated time: ${typeof evt.message === "string" ? evt.message : "unknown"}`;
     case "completed": {
       const itemCount = typeof evt.item_count === "number" ? evt.item_count : 1;
       const isBundle = evt.content_kind === "bundle" || itemCount > 1;
@@ -397,7 +425,14 @@ function handleTransferEvent(evt: TransferEvent): void {
 
     case "progress": {
       const done = typeof evt.done === "number" ? evt.done : 0;
-      const total = typeof evt.total === "number" ? evt.total : 0;
+      if (typeof evt.total !== "number") {
+        // Following a growing file: the size is only known at the end.
+        progressFill.style.width = "0%";
+        progressPercent.textContent = "";
+        progressDetail.textContent = formatBytes(done);
+        break;
+      }
+      const total = evt.total;
       const pct = total > 0 ? Math.min(100, (done / total) * 100) : 0;
       progressFill.style.width = `${pct.toFixed(1)}%`;
       progressPercent.textContent = `${Math.round(pct)}%`;
@@ -413,6 +448,18 @@ function handleTransferEvent(evt: TransferEvent): void {
       break;
     }
 
+    case "relay_status":
+      if (evt.value !== "connected") {
+        connectionText.textContent = "LAN only (relay unavailable)";
+      }
+      break;
+
+    case "file_started":
+      if (typeof evt.file_name === "string") {
+        statusText.textContent = `Transferring ${evt.file_name}`;
+      }
+      break;
+
     case "completed": {
       statusDot.className = "status-dot status-dot--success";
       statusText.textContent = "Transfer complete";
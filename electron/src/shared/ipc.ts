@@ -15,6 +15,7 @@ export interface TransferEventBase {
   message?: string;
   value?: string;
   schema_version?: string;
+  session_id?: number;
 }
 
 export interface TransferEventStatus extends TransferEventBase {
@@ -44,6 +45,71 @@ export interface TransferEventConnectionPath extends TransferEventBase {
   latency_ms?: number;
 }
 
+export interface TransferEventPeerIdentified extends TransferEventBase {
+  kind: "peer_identified";
+  value?: string;
+}
+
+export interface TransferEventPublicAddress extends TransferEventBase {
+  kind: "public_address";
+  value?: string;
+}
+
+export interface TransferEventRelayStatus extends TransferEventBase {
+  kind: "relay_status";
+  value?: "connected" | "unavailable";
+}
+
+export interface TransferEventFileStarted extends TransferEventBase {
+  kind: "file_started";
+  file_index?: number;
+  file_name?: string;
+  size_bytes?: number;
+}
+
+export interface TransferEventFileProgress extends TransferEventBase {
+  kind: "file_progress";
+  file_index?: number;
+  done?: number;
+  total?: number;
+}
+
+export interface TransferEventFileFinished extends TransferEventBase {
+  kind: "file_finished";
+  file_index?: number;
+}
+
+export interface TransferEventTempFile extends TransferEventBase {
+  kind: "temp_file";
+}
+
+export interface TransferEventFileError extends TransferEventBase {
+  kind: "file_error";
+  file_index?: number;
+  file_name?: string;
+}
+
+export interface TransferEventFileSkipped extends TransferEventBase {
+  kind: "file_skipped";
+  file_name?: string;
+}
+
+export interface TransferEventPreview extends TransferEventBase {
+  kind: "preview";
+  size_bytes?: number;
+}
+
+export interface TransferEventAcceptQueue extends TransferEventBase {
+  kind: "accept_queue";
+  queue_depth?: number;
+}
+
+export interface TransferEventEstimate extends TransferEventBase {
+  kind: "estimate";
+  size_bytes?: number;
+  est_secs?: number;
+}
+
 export interface TransferEventCompleted extends TransferEventBase {
   kind: "completed";
   file_name?: string;
@@ -52,10 +118,14 @@ export interface TransferEventCompleted extends TransferEventBase {
   saved_to?: string;
   content_kind?: "file" | "bundle";
   item_count?: number;
+  mime?: string;
+  compression_level?: number;
+  blake3?: string;
 }
 
 export interface TransferEventError extends TransferEventBase {
   kind: "error";
+  partial_path?: string;
 }
 
 export interface TransferEventProcessEnd extends TransferEventBase {
@@ -74,6 +144,18 @@ export type TransferEvent =
   | TransferEventHandshakeCode
   | TransferEventProgress
   | TransferEventConnectionPath
+  | TransferEventRelayStatus
+  | TransferEventFileStarted
+  | TransferEventFileProgress
+  | TransferEventFileFinished
+  | TransferEventTempFile
+  | TransferEventFileError
+  | TransferEventFileSkipped
+  | TransferEventPreview
+  | TransferEventAcceptQueue
+  | TransferEventEstimate
+  | TransferEventPeerIdentified
+  | TransferEventPublicAddress
   | TransferEventCompleted
   | TransferEventError
   | TransferEventProcessEnd